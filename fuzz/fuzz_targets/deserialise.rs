@@ -0,0 +1,29 @@
+#![no_main]
+
+use fast_stream_db::serialisation::{
+    WireFormat, deserialise_packets_with_format, serialise_packets_with_format,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte picks the features, so checksummed and compressed framing is covered too.
+    let Some((&features, data)) = data.split_first() else {
+        return;
+    };
+    let format = WireFormat {
+        max_payload_size: 1 << 20,
+        ..WireFormat::from_features(features.into())
+    };
+
+    // Rejecting the data is fine, as long as it is rejected without panicking.
+    let Ok((packets, consumed)) = deserialise_packets_with_format(data, format) else {
+        return;
+    };
+    assert!(consumed <= data.len());
+
+    // Anything that was read must write back out and read back as the same packets.
+    let reserialised = serialise_packets_with_format(&packets, format).unwrap();
+    let (reparsed, reconsumed) = deserialise_packets_with_format(&reserialised, format).unwrap();
+    assert_eq!(reparsed, packets);
+    assert_eq!(reconsumed, reserialised.len());
+});
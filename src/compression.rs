@@ -0,0 +1,41 @@
+#[cfg(feature = "compression")]
+use std::io::Read;
+
+/// Favours speed, as payloads are compressed on the connection task for every response.
+#[cfg(feature = "compression")]
+const COMPRESSION_LEVEL: i32 = 3;
+
+#[cfg(feature = "compression")]
+pub fn compress(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    Ok(zstd::bulk::compress(data, COMPRESSION_LEVEL)?)
+}
+
+/// Fails unless the data expands to exactly `uncompressed_size` bytes. The output grows as it is
+/// decompressed, so a bogus size can't cause a huge allocation up front.
+#[cfg(feature = "compression")]
+pub fn decompress(data: &[u8], uncompressed_size: usize) -> anyhow::Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    // Reading one byte past the declared size is enough to tell the data expands further.
+    zstd::stream::read::Decoder::with_buffer(data)?
+        .take(uncompressed_size as u64 + 1)
+        .read_to_end(&mut decompressed)?;
+
+    if decompressed.len() != uncompressed_size {
+        return Err(anyhow::anyhow!(
+            "Payload declared {} bytes uncompressed but expanded to {}",
+            uncompressed_size,
+            decompressed.len()
+        ));
+    }
+    Ok(decompressed)
+}
+
+#[cfg(not(feature = "compression"))]
+pub fn compress(_data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    Err(anyhow::anyhow!("Compression support isn't compiled in"))
+}
+
+#[cfg(not(feature = "compression"))]
+pub fn decompress(_data: &[u8], _uncompressed_size: usize) -> anyhow::Result<Vec<u8>> {
+    Err(anyhow::anyhow!("Compression support isn't compiled in"))
+}
@@ -1,3 +1,12 @@
+pub mod compression;
+pub mod metrics;
+pub mod rate_limit;
 pub mod serialisation;
+pub mod server;
 pub mod settings;
+pub mod snapshot;
+pub mod state;
+pub mod text_protocol;
+pub mod tls;
 pub mod utils;
+pub mod wal;
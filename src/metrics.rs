@@ -0,0 +1,229 @@
+use crate::settings::Settings;
+use crate::state::ServerState;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+use tracing::{error, info, warn};
+
+/// Packet IDs are small and dense, so their counters live in a fixed table indexed by ID.
+const TRACKED_PACKET_IDS: usize = 256;
+/// The largest scrape request accepted before the connection is dropped.
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+
+/// Process-wide counters. Gauges derived from the streams themselves are read from
+/// `ServerState` at scrape time, as it already tracks them incrementally.
+pub static METRICS: Metrics = Metrics::new();
+
+pub struct Metrics {
+    active_connections: AtomicU64,
+    packets_processed: [AtomicU64; TRACKED_PACKET_IDS],
+    last_pruned_streams: AtomicU64,
+    pruned_streams: AtomicU64,
+    evicted_streams: AtomicU64,
+}
+
+/// Counts a connection as active until dropped.
+pub struct ConnectionGuard(());
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        METRICS.active_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            active_connections: AtomicU64::new(0),
+            packets_processed: [const { AtomicU64::new(0) }; TRACKED_PACKET_IDS],
+            last_pruned_streams: AtomicU64::new(0),
+            pruned_streams: AtomicU64::new(0),
+            evicted_streams: AtomicU64::new(0),
+        }
+    }
+
+    pub fn track_connection(&'static self) -> ConnectionGuard {
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard(())
+    }
+
+    pub fn active_connections(&self) -> u64 {
+        self.active_connections.load(Ordering::Relaxed)
+    }
+
+    pub fn record_packet(&self, packet_id: u32) {
+        if let Some(counter) = self.packets_processed.get(packet_id as usize) {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn packets_processed(&self, packet_id: u32) -> u64 {
+        self.packets_processed
+            .get(packet_id as usize)
+            .map_or(0, |counter| counter.load(Ordering::Relaxed))
+    }
+
+    pub fn record_prune(&self, deleted_streams: usize) {
+        let deleted_streams = deleted_streams as u64;
+        self.last_pruned_streams
+            .store(deleted_streams, Ordering::Relaxed);
+        self.pruned_streams
+            .fetch_add(deleted_streams, Ordering::Relaxed);
+    }
+
+    pub fn record_eviction(&self, evicted_streams: usize) {
+        self.evicted_streams
+            .fetch_add(evicted_streams as u64, Ordering::Relaxed);
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self, state: &ServerState) -> String {
+        let mut output = String::new();
+
+        write_metric(
+            &mut output,
+            "fsdb_streams",
+            "gauge",
+            "Number of streams currently open.",
+            state.stream_count() as u64,
+        );
+        write_metric(
+            &mut output,
+            "fsdb_buffered_bytes",
+            "gauge",
+            "Bytes buffered across all streams.",
+            state.total_bytes() as u64,
+        );
+        write_metric(
+            &mut output,
+            "fsdb_active_connections",
+            "gauge",
+            "Number of open client connections.",
+            self.active_connections(),
+        );
+        write_metric(
+            &mut output,
+            "fsdb_last_pruned_streams",
+            "gauge",
+            "Streams deleted by the most recent prune cycle.",
+            self.last_pruned_streams.load(Ordering::Relaxed),
+        );
+        write_metric(
+            &mut output,
+            "fsdb_pruned_streams_total",
+            "counter",
+            "Streams deleted for being idle.",
+            self.pruned_streams.load(Ordering::Relaxed),
+        );
+        write_metric(
+            &mut output,
+            "fsdb_evicted_streams_total",
+            "counter",
+            "Streams deleted to get back under the memory soft limit.",
+            self.evicted_streams.load(Ordering::Relaxed),
+        );
+
+        let _ = writeln!(
+            output,
+            "# HELP fsdb_packets_processed_total Client packets processed, by packet ID."
+        );
+        let _ = writeln!(output, "# TYPE fsdb_packets_processed_total counter");
+        for (packet_id, counter) in self.packets_processed.iter().enumerate() {
+            let count = counter.load(Ordering::Relaxed);
+            if count == 0 {
+                continue;
+            }
+            let _ = writeln!(
+                output,
+                "fsdb_packets_processed_total{{packet_id=\"{}\"}} {}",
+                packet_id, count
+            );
+        }
+
+        output
+    }
+}
+
+fn write_metric(output: &mut String, name: &str, metric_type: &str, help: &str, value: u64) {
+    let _ = writeln!(output, "# HELP {} {}", name, help);
+    let _ = writeln!(output, "# TYPE {} {}", name, metric_type);
+    let _ = writeln!(output, "{} {}", name, value);
+}
+
+/// Serves `GET /metrics` over HTTP until the shutdown future resolves.
+pub async fn run_metrics_server(
+    settings: &'static Settings,
+    state: Arc<Mutex<ServerState>>,
+    shutdown: impl Future<Output = ()>,
+) -> anyhow::Result<()> {
+    let addr = format!("{}:{}", settings.tcp_host, settings.metrics_port);
+    let listener = TcpListener::bind(&addr).await?;
+    info!(%addr, "Metrics server listening");
+    tokio::pin!(shutdown);
+
+    loop {
+        let accepted = tokio::select! {
+            _ = &mut shutdown => break,
+            accepted = listener.accept() => accepted,
+        };
+
+        match accepted {
+            Ok((stream, _)) => {
+                let state = Arc::clone(&state);
+                tokio::spawn(async move {
+                    if let Err(e) = handle_metrics_request(stream, state).await {
+                        warn!(error = %e, "Error serving metrics");
+                    }
+                });
+            }
+            Err(e) => {
+                error!(error = %e, "Error accepting metrics connection");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn handle_metrics_request(
+    mut stream: TcpStream,
+    state: Arc<Mutex<ServerState>>,
+) -> anyhow::Result<()> {
+    // Only the request line matters, but the headers are read so the client isn't reset
+    // by unread data when the connection closes.
+    let mut request = Vec::new();
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        if request.len() > MAX_REQUEST_SIZE {
+            return Err(anyhow::anyhow!("Metrics request too large"));
+        }
+
+        let mut temp_buffer = [0u8; 1024];
+        let bytes_read = stream.read(&mut temp_buffer).await?;
+        if bytes_read == 0 {
+            return Ok(());
+        }
+        request.extend_from_slice(&temp_buffer[..bytes_read]);
+    }
+
+    let request_line = request.split(|byte| *byte == b'\n').next().unwrap_or(&[]);
+    let (status, body) = if request_line.starts_with(b"GET /metrics ") {
+        let state_guard = state.lock().await;
+        ("200 OK", METRICS.render(&state_guard))
+    } else {
+        ("404 Not Found", String::new())
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+
+    Ok(())
+}
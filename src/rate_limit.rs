@@ -0,0 +1,52 @@
+use tokio::time::{Duration, Instant};
+
+/// A token bucket limiting how many packets a connection may have handled per second. The bucket
+/// holds one second's worth of tokens, so a client may burst up to the rate after being idle.
+pub struct RateLimiter {
+    /// Tokens added per second, or 0 for no limit.
+    rate: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(packets_per_second: u32) -> Self {
+        let rate = f64::from(packets_per_second);
+        Self {
+            rate,
+            tokens: rate,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.last_refill = now;
+    }
+
+    /// Takes a token, returning false if there are none left.
+    pub fn try_acquire(&mut self) -> bool {
+        if self.rate == 0.0 {
+            return true;
+        }
+
+        self.refill();
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+
+    /// How long until the next token is available.
+    pub fn time_until_available(&mut self) -> Duration {
+        if self.rate == 0.0 {
+            return Duration::ZERO;
+        }
+
+        self.refill();
+        Duration::from_secs_f64(((1.0 - self.tokens) / self.rate).max(0.0))
+    }
+}
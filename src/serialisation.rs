@@ -1,4 +1,161 @@
-pub type Bytes = Vec<u8>;
+use crate::compression;
+
+/// Data carried in packets. Cloning one only bumps a reference count, so data handed out of a
+/// stream can be shared between responses without copying it.
+pub use bytes::Bytes;
+
+/// The newest protocol version the server speaks. Clients that never send `ClientHello` are
+/// assumed to speak `MIN_PROTOCOL_VERSION`.
+pub const PROTOCOL_VERSION: u32 = 2;
+/// Version 2 prefixed every packet with its length, which version 1 clients can't be read without.
+pub const MIN_PROTOCOL_VERSION: u32 = 2;
+
+/// Why a packet could not be read from a buffer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PacketReadError {
+    /// The buffer ends partway through a packet, so more data may still complete it.
+    Incomplete,
+    /// The data can never form a valid packet, whatever follows it.
+    Invalid(String),
+}
+
+impl std::fmt::Display for PacketReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PacketReadError::Incomplete => write!(f, "Incomplete packet"),
+            PacketReadError::Invalid(reason) => write!(f, "Invalid packet: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for PacketReadError {}
+
+/// `ClientNegotiateFeatures` bit for a CRC32 after every packet, in both directions.
+pub const FEATURE_CHECKSUMS: u32 = 1 << 0;
+/// `ClientNegotiateFeatures` bit for zstd compression of the stream payload in enqueue packets,
+/// `ServerStreamContents` and `ServerStreamPush`, in both directions.
+pub const FEATURE_COMPRESSION: u32 = 1 << 1;
+/// Every feature bit the server can enable.
+#[cfg(feature = "compression")]
+pub const SUPPORTED_FEATURES: u32 = FEATURE_CHECKSUMS | FEATURE_COMPRESSION;
+#[cfg(not(feature = "compression"))]
+pub const SUPPORTED_FEATURES: u32 = FEATURE_CHECKSUMS;
+
+/// `ServerError` code for a packet larger than the server will buffer.
+pub const ERROR_PAYLOAD_TOO_LARGE: u32 = 1;
+/// `ServerError` code for a request naming a stream that doesn't exist.
+pub const ERROR_UNKNOWN_STREAM: u32 = 2;
+/// `ServerError` code for a packet the server can't handle.
+pub const ERROR_INVALID_PACKET: u32 = 3;
+/// `ServerError` code for a request that would need more streams than the server allows.
+pub const ERROR_STREAM_LIMIT_REACHED: u32 = 4;
+/// `ServerError` code for a request that would replace a stream that already exists.
+pub const ERROR_STREAM_EXISTS: u32 = 5;
+/// `ServerError` code for a privileged packet sent before authenticating with the admin token.
+pub const ERROR_NOT_AUTHENTICATED: u32 = 6;
+/// `ServerError` code for a request naming a stream group that doesn't exist.
+pub const ERROR_UNKNOWN_GROUP: u32 = 7;
+
+/// `ServerHealth` status of a server that is ready for traffic.
+pub const HEALTH_READY: u32 = 0;
+/// `ServerHealth` status of a server that still works but is near a limit, such as its memory
+/// threshold or connection limit, so new traffic is better routed elsewhere.
+pub const HEALTH_DEGRADED: u32 = 1;
+/// `ServerHealth` status of a server that has begun graceful shutdown.
+pub const HEALTH_SHUTTING_DOWN: u32 = 2;
+
+/// A request that failed without affecting the rest of the connection. The server answers it
+/// with a `ServerError` and carries on, unlike any other error, which closes the connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestError {
+    pub code: u32,
+    pub message: String,
+}
+
+impl RequestError {
+    pub fn new(code: u32, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn unknown_stream(stream_id: u32) -> Self {
+        Self::new(
+            ERROR_UNKNOWN_STREAM,
+            format!("Stream {} doesn't exist", stream_id),
+        )
+    }
+
+    pub fn unknown_group(group_id: u32) -> Self {
+        Self::new(
+            ERROR_UNKNOWN_GROUP,
+            format!("Group {} doesn't exist", group_id),
+        )
+    }
+
+    pub fn to_packet(&self) -> Packet {
+        Packet::ServerError {
+            code: self.code,
+            message: self.message.clone().into(),
+        }
+    }
+}
+
+impl std::fmt::Display for RequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+/// How packets are framed on a connection, as negotiated with `ClientNegotiateFeatures`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WireFormat {
+    /// Whether each packet is followed by a CRC32 of its length prefix, ID and payload.
+    pub checksums: bool,
+    /// Whether stream payloads are zstd compressed, preceded by their uncompressed size.
+    pub compression: bool,
+    /// The most bytes a stream payload may hold once decompressed, 0 meaning unlimited. Without
+    /// it, a small compressed packet could make the server allocate far more than it would ever
+    /// read.
+    pub max_payload_size: usize,
+}
+
+impl WireFormat {
+    pub fn from_features(features: u32) -> Self {
+        Self {
+            checksums: features & FEATURE_CHECKSUMS != 0,
+            compression: features & FEATURE_COMPRESSION != 0,
+            max_payload_size: 0,
+        }
+    }
+}
+
+/// `Predicate` kinds on the wire.
+const PREDICATE_RANGE: u32 = 0;
+const PREDICATE_MODULO: u32 = 1;
+
+/// Which streams a `ClientEnqueueWhere` enqueues to, matched against each stream ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Predicate {
+    /// IDs from `min` to `max`, inclusive.
+    Range { min: u32, max: u32 },
+    /// IDs leaving `remainder` when divided by `divisor`. A divisor of 0 matches nothing.
+    Modulo { divisor: u32, remainder: u32 },
+}
+
+impl Predicate {
+    pub fn matches(&self, stream_id: u32) -> bool {
+        match *self {
+            Predicate::Range { min, max } => (min..=max).contains(&stream_id),
+            Predicate::Modulo { divisor, remainder } => {
+                stream_id.checked_rem(divisor) == Some(remainder)
+            }
+        }
+    }
+}
 
 const PACKET_ID_CLIENT_PING: u32 = 0;
 const PACKET_ID_CLIENT_CREATE_NEW_STREAM: u32 = 1;
@@ -13,9 +170,103 @@ const PACKET_ID_CLIENT_CHECK_STREAM_STATE: u32 = 9;
 const PACKET_ID_SERVER_PONG: u32 = 10;
 const PACKET_ID_SERVER_STREAM_CONTENTS: u32 = 11;
 const PACKET_ID_SERVER_STREAM_STATE: u32 = 12;
+const PACKET_ID_SERVER_BUSY: u32 = 13;
+const PACKET_ID_CLIENT_ENQUEUE_SEQ: u32 = 14;
+const PACKET_ID_SERVER_ENQUEUE_SEQ_RESULT: u32 = 15;
+const PACKET_ID_CLIENT_LIST_STREAMS_BY_ACTIVITY: u32 = 16;
+const PACKET_ID_SERVER_STREAM_ACTIVITY_LIST: u32 = 17;
+const PACKET_ID_CLIENT_SET_STREAM_METADATA: u32 = 18;
+const PACKET_ID_CLIENT_GET_STREAM_METADATA: u32 = 19;
+const PACKET_ID_SERVER_STREAM_METADATA: u32 = 20;
+const PACKET_ID_CLIENT_ENQUEUE_ALL_ACK: u32 = 21;
+const PACKET_ID_CLIENT_ENQUEUE_ALL_EXCEPT_ACK: u32 = 22;
+const PACKET_ID_SERVER_ENQUEUE_ALL_ACK: u32 = 23;
+const PACKET_ID_CLIENT_READ_RANGE: u32 = 24;
+const PACKET_ID_CLIENT_GET_TOTAL_BYTES: u32 = 25;
+const PACKET_ID_SERVER_TOTAL_BYTES: u32 = 26;
+const PACKET_ID_CLIENT_GOODBYE: u32 = 27;
+const PACKET_ID_SERVER_GOODBYE: u32 = 28;
+const PACKET_ID_CLIENT_REGISTER_CONSUMER: u32 = 29;
+const PACKET_ID_CLIENT_FETCH_FROM_CURSOR: u32 = 30;
+const PACKET_ID_CLIENT_ADVANCE_CURSOR: u32 = 31;
+const PACKET_ID_CLIENT_UNREGISTER_CONSUMER: u32 = 32;
+const PACKET_ID_CLIENT_SET_GLOBAL_EXPIRY: u32 = 33;
+const PACKET_ID_CLIENT_SELF_CHECK: u32 = 34;
+const PACKET_ID_SERVER_SELF_CHECK_REPORT: u32 = 35;
+const PACKET_ID_CLIENT_PEEK_STREAM_CONTENTS: u32 = 36;
+const PACKET_ID_CLIENT_REQUEST_STREAM_CONTENTS_WITH_META: u32 = 37;
+const PACKET_ID_SERVER_STREAM_CONTENTS_META: u32 = 38;
+const PACKET_ID_CLIENT_STREAM_LENGTH: u32 = 39;
+const PACKET_ID_SERVER_STREAM_LENGTH: u32 = 40;
+const PACKET_ID_CLIENT_HELLO: u32 = 41;
+const PACKET_ID_SERVER_HELLO: u32 = 42;
+const PACKET_ID_SERVER_ENQUEUE_REJECTED: u32 = 43;
+const PACKET_ID_CLIENT_LIST_STREAMS: u32 = 44;
+const PACKET_ID_SERVER_STREAM_LIST: u32 = 45;
+const PACKET_ID_CLIENT_AWAIT_STREAM_CONTENTS: u32 = 46;
+const PACKET_ID_CLIENT_PREPEND_SINGLE: u32 = 47;
+const PACKET_ID_SERVER_ENQUEUE_ERROR: u32 = 48;
+const PACKET_ID_CLIENT_CREATE_MULTIPLE_STREAMS: u32 = 49;
+const PACKET_ID_SERVER_STREAMS_CREATED: u32 = 50;
+const PACKET_ID_CLIENT_CREATE_NEW_STREAM_WITH_TTL: u32 = 51;
+const PACKET_ID_CLIENT_CLEAR_STREAM: u32 = 52;
+const PACKET_ID_CLIENT_CREATE_NEW_STREAM_NAMED: u32 = 53;
+const PACKET_ID_CLIENT_LOOKUP_STREAM_NAME: u32 = 54;
+const PACKET_ID_SERVER_NAMED_STREAM: u32 = 55;
+const PACKET_ID_CLIENT_ENQUEUE_BATCH: u32 = 56;
+const PACKET_ID_CLIENT_NEGOTIATE_FEATURES: u32 = 57;
+const PACKET_ID_SERVER_FEATURES: u32 = 58;
+const PACKET_ID_CLIENT_REQUEST_STATS: u32 = 59;
+const PACKET_ID_SERVER_STATS: u32 = 60;
+const PACKET_ID_CLIENT_MOVE_STREAM_CONTENTS: u32 = 61;
+const PACKET_ID_SERVER_ERROR: u32 = 62;
+const PACKET_ID_CLIENT_RENAME_STREAM: u32 = 63;
+const PACKET_ID_CLIENT_DRAIN_PREFIX: u32 = 64;
+const PACKET_ID_CLIENT_AUTHENTICATE: u32 = 65;
+const PACKET_ID_SERVER_AUTH_RESULT: u32 = 66;
+const PACKET_ID_CLIENT_FLUSH_ALL: u32 = 67;
+const PACKET_ID_CLIENT_DELETE_ALL: u32 = 68;
+const PACKET_ID_SERVER_FLUSH_RESULT: u32 = 69;
+const PACKET_ID_CLIENT_SUBSCRIBE: u32 = 70;
+const PACKET_ID_SERVER_STREAM_PUSH: u32 = 71;
+const PACKET_ID_CLIENT_UNSUBSCRIBE: u32 = 72;
+const PACKET_ID_CLIENT_WHO_AM_I: u32 = 73;
+const PACKET_ID_SERVER_CONNECTION_INFO: u32 = 74;
+const PACKET_ID_CLIENT_STREAM_INFO: u32 = 75;
+const PACKET_ID_SERVER_STREAM_INFO: u32 = 76;
+const PACKET_ID_CLIENT_TOUCH_STREAM: u32 = 77;
+const PACKET_ID_CLIENT_ENQUEUE_MULTIPLE_EXCEPT: u32 = 78;
+const PACKET_ID_CLIENT_REQUEST_STREAM_CONTENTS_RANGE: u32 = 79;
+const PACKET_ID_CLIENT_HEALTH_CHECK: u32 = 80;
+const PACKET_ID_SERVER_HEALTH: u32 = 81;
+const PACKET_ID_SERVER_STREAM_CREATED: u32 = 82;
+const PACKET_ID_CLIENT_ENQUEUE_SINGLE_ACK: u32 = 83;
+const PACKET_ID_SERVER_ENQUEUE_ACK: u32 = 84;
+const PACKET_ID_CLIENT_ENQUEUE_WHERE: u32 = 85;
+const PACKET_ID_CLIENT_DELETE_MULTIPLE_STREAMS: u32 = 86;
+const PACKET_ID_SERVER_STREAMS_DELETED: u32 = 87;
+const PACKET_ID_CLIENT_CREATE_NEW_STREAM_SIZED: u32 = 88;
+const PACKET_ID_CLIENT_CHECK_STREAM_STATE_MULTIPLE: u32 = 89;
+const PACKET_ID_SERVER_STREAM_STATE_MULTIPLE: u32 = 90;
+const PACKET_ID_CLIENT_FLUSH_STREAM: u32 = 91;
+const PACKET_ID_CLIENT_OPEN_SESSION: u32 = 92;
+const PACKET_ID_SERVER_SESSION_OPENED: u32 = 93;
+const PACKET_ID_CLIENT_RESUME_SESSION: u32 = 94;
+const PACKET_ID_SERVER_SESSION_RESUMED: u32 = 95;
+const PACKET_ID_CLIENT_CREATE_GROUP: u32 = 96;
+const PACKET_ID_SERVER_GROUP_CREATED: u32 = 97;
+const PACKET_ID_CLIENT_ADD_TO_GROUP: u32 = 98;
+const PACKET_ID_CLIENT_REMOVE_FROM_GROUP: u32 = 99;
+const PACKET_ID_CLIENT_DELETE_GROUP: u32 = 100;
+const PACKET_ID_CLIENT_ENQUEUE_ALL_EXCEPT_GROUP: u32 = 101;
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Packet {
-    ClientPing,
+    /// The nonce is optional, and echoed back in the `ServerPong` so pipelined pings can be told
+    /// apart.
+    ClientPing {
+        nonce: Option<u64>,
+    },
     ClientCreateNewStream {
         stream_id: u32,
     },
@@ -46,7 +297,9 @@ pub enum Packet {
     ClientCheckStreamState {
         stream_id: u32,
     },
-    ServerPong,
+    ServerPong {
+        nonce: Option<u64>,
+    },
     ServerStreamContents {
         buffer_data: Bytes,
     },
@@ -54,12 +307,319 @@ pub enum Packet {
         stream_id: u32,
         is_valid: bool,
     },
+    ServerBusy {
+        retry_after_ms: u32,
+    },
+    ClientEnqueueSeq {
+        stream_id: u32,
+        seq: u64,
+        enqueue_data: Bytes,
+    },
+    ServerEnqueueSeqResult {
+        stream_id: u32,
+        seq: u64,
+        applied: bool,
+    },
+    ClientListStreamsByActivity {
+        limit: u32,
+        descending: bool,
+    },
+    ServerStreamActivityList {
+        // Pairs of (stream ID, idle seconds).
+        entries: Vec<(u32, u32)>,
+    },
+    ClientSetStreamMetadata {
+        stream_id: u32,
+        metadata: Bytes,
+    },
+    ClientGetStreamMetadata {
+        stream_id: u32,
+    },
+    ServerStreamMetadata {
+        stream_id: u32,
+        metadata: Bytes,
+    },
+    ClientEnqueueAllAck {
+        enqueue_data: Bytes,
+    },
+    ClientEnqueueAllExceptAck {
+        enqueue_data: Bytes,
+        filter_stream_ids: Vec<u32>,
+    },
+    ServerEnqueueAllAck {
+        stream_count: u32,
+    },
+    ClientReadRange {
+        stream_id: u32,
+        start: u32,
+        end: u32,
+    },
+    ClientGetTotalBytes,
+    ServerTotalBytes {
+        total_bytes: u64,
+        stream_count: u32,
+    },
+    ClientGoodbye,
+    ServerGoodbye,
+    ClientRegisterConsumer {
+        stream_id: u32,
+        consumer_id: u32,
+    },
+    ClientFetchFromCursor {
+        stream_id: u32,
+        consumer_id: u32,
+        max_bytes: u32,
+    },
+    ClientAdvanceCursor {
+        stream_id: u32,
+        consumer_id: u32,
+        byte_count: u32,
+    },
+    ClientUnregisterConsumer {
+        stream_id: u32,
+        consumer_id: u32,
+    },
+    ClientSetGlobalExpiry {
+        seconds: u32,
+    },
+    ClientSelfCheck,
+    ServerSelfCheckReport {
+        // One human-readable description per discrepancy found.
+        issues: Vec<String>,
+    },
+    ClientPeekStreamContents {
+        stream_id: u32,
+        max_bytes: u32,
+    },
+    ClientRequestStreamContentsWithMeta {
+        stream_id: u32,
+    },
+    ServerStreamContentsMeta {
+        stream_id: u32,
+        byte_count: u32,
+        existed: bool,
+    },
+    ClientStreamLength {
+        stream_id: u32,
+    },
+    ServerStreamLength {
+        stream_id: u32,
+        length: u32,
+        exists: bool,
+    },
+    ClientHello {
+        protocol_version: u32,
+    },
+    ServerHello {
+        protocol_version: u32,
+        accepted: bool,
+    },
+    ServerEnqueueRejected {
+        stream_id: u32,
+    },
+    ClientListStreams {
+        offset: u32,
+        limit: u32,
+    },
+    ServerStreamList {
+        total_count: u32,
+        stream_ids: Vec<u32>,
+    },
+    ClientAwaitStreamContents {
+        stream_id: u32,
+        timeout_ms: u32,
+    },
+    ClientPrependSingle {
+        stream_id: u32,
+        enqueue_data: Bytes,
+    },
+    ServerEnqueueError {
+        stream_id: u32,
+    },
+    ClientCreateMultipleStreams {
+        stream_ids: Vec<u32>,
+    },
+    ServerStreamsCreated {
+        existing_stream_ids: Vec<u32>,
+    },
+    ClientCreateNewStreamWithTtl {
+        stream_id: u32,
+        ttl_secs: u32,
+    },
+    ClientClearStream {
+        stream_id: u32,
+    },
+    ClientCreateNewStreamNamed {
+        name: Bytes,
+    },
+    ClientLookupStreamName {
+        name: Bytes,
+    },
+    ServerNamedStream {
+        stream_id: u32,
+        exists: bool,
+    },
+    ClientEnqueueBatch {
+        // Pairs of (stream ID, enqueue data).
+        entries: Vec<(u32, Bytes)>,
+    },
+    ClientNegotiateFeatures {
+        features: u32,
+    },
+    ServerFeatures {
+        features: u32,
+    },
+    ClientRequestStats,
+    ServerStats {
+        stream_count: u32,
+        total_buffered_bytes: u64,
+        uptime_secs: u64,
+    },
+    ClientMoveStreamContents {
+        source_id: u32,
+        dest_id: u32,
+    },
+    ServerError {
+        code: u32,
+        message: Bytes,
+    },
+    ClientRenameStream {
+        old_id: u32,
+        new_id: u32,
+    },
+    ClientDrainPrefix {
+        stream_id: u32,
+        byte_count: u32,
+    },
+    ClientAuthenticate {
+        token: Bytes,
+    },
+    ServerAuthResult {
+        success: bool,
+    },
+    ClientFlushAll,
+    ClientDeleteAll,
+    ServerFlushResult {
+        streams_affected: u32,
+    },
+    ClientSubscribe {
+        stream_id: u32,
+    },
+    ServerStreamPush {
+        stream_id: u32,
+        buffer_data: Bytes,
+    },
+    ClientUnsubscribe {
+        stream_id: u32,
+    },
+    ClientWhoAmI,
+    ServerConnectionInfo {
+        connection_id: u64,
+        peer: Bytes,
+    },
+    ClientStreamInfo {
+        stream_id: u32,
+    },
+    ServerStreamInfo {
+        stream_id: u32,
+        exists: bool,
+        length: u32,
+        idle_secs: u32,
+    },
+    ClientTouchStream {
+        stream_id: u32,
+    },
+    ClientEnqueueMultipleExcept {
+        enqueue_data: Bytes,
+        include_ids: Vec<u32>,
+        exclude_ids: Vec<u32>,
+    },
+    ClientRequestStreamContentsRange {
+        stream_id: u32,
+        start: u32,
+        length: u32,
+    },
+    ClientHealthCheck,
+    ServerHealth {
+        status: u32,
+    },
+    ServerStreamCreated {
+        stream_id: u32,
+        created: bool,
+    },
+    ClientEnqueueSingleAck {
+        stream_id: u32,
+        enqueue_data: Bytes,
+    },
+    ServerEnqueueAck {
+        stream_id: u32,
+        accepted: bool,
+        new_length: u32,
+    },
+    ClientEnqueueWhere {
+        enqueue_data: Bytes,
+        predicate: Predicate,
+    },
+    ClientDeleteMultipleStreams {
+        stream_ids: Vec<u32>,
+    },
+    ServerStreamsDeleted {
+        count: u32,
+    },
+    ClientCreateNewStreamSized {
+        stream_id: u32,
+        initial_capacity: u32,
+    },
+    ClientCheckStreamStateMultiple {
+        stream_ids: Vec<u32>,
+    },
+    /// Whether each requested stream exists, as (stream ID, exists) in the order requested.
+    ServerStreamStateMultiple {
+        results: Vec<(u32, bool)>,
+    },
+    ClientFlushStream {
+        stream_id: u32,
+    },
+    ClientOpenSession,
+    ServerSessionOpened {
+        token: Bytes,
+    },
+    ClientResumeSession {
+        token: Bytes,
+    },
+    /// Whether the session was resumed, and the streams the connection was resubscribed to.
+    ServerSessionResumed {
+        resumed: bool,
+        stream_ids: Vec<u32>,
+    },
+    ClientCreateGroup {
+        group_id: u32,
+    },
+    ServerGroupCreated {
+        group_id: u32,
+        created: bool,
+    },
+    ClientAddToGroup {
+        group_id: u32,
+        stream_id: u32,
+    },
+    ClientRemoveFromGroup {
+        group_id: u32,
+        stream_id: u32,
+    },
+    ClientDeleteGroup {
+        group_id: u32,
+    },
+    ClientEnqueueAllExceptGroup {
+        group_id: u32,
+        enqueue_data: Bytes,
+    },
 }
 
 impl Packet {
-    fn packet_id(&self) -> u32 {
+    pub fn packet_id(&self) -> u32 {
         match self {
-            Packet::ClientPing => PACKET_ID_CLIENT_PING,
+            Packet::ClientPing { .. } => PACKET_ID_CLIENT_PING,
             Packet::ClientCreateNewStream { .. } => PACKET_ID_CLIENT_CREATE_NEW_STREAM,
             Packet::ClientDeleteStream { .. } => PACKET_ID_CLIENT_DELETE_STREAM,
             Packet::ClientEnqueueSingle { .. } => PACKET_ID_CLIENT_ENQUEUE_SINGLE,
@@ -71,41 +631,260 @@ impl Packet {
                 PACKET_ID_CLIENT_REQUEST_STREAM_CONTENTS_NO_CLEAR
             }
             Packet::ClientCheckStreamState { .. } => PACKET_ID_CLIENT_CHECK_STREAM_STATE,
-            Packet::ServerPong => PACKET_ID_SERVER_PONG,
+            Packet::ServerPong { .. } => PACKET_ID_SERVER_PONG,
             Packet::ServerStreamContents { .. } => PACKET_ID_SERVER_STREAM_CONTENTS,
             Packet::ServerStreamState { .. } => PACKET_ID_SERVER_STREAM_STATE,
+            Packet::ServerBusy { .. } => PACKET_ID_SERVER_BUSY,
+            Packet::ClientEnqueueSeq { .. } => PACKET_ID_CLIENT_ENQUEUE_SEQ,
+            Packet::ServerEnqueueSeqResult { .. } => PACKET_ID_SERVER_ENQUEUE_SEQ_RESULT,
+            Packet::ClientListStreamsByActivity { .. } => PACKET_ID_CLIENT_LIST_STREAMS_BY_ACTIVITY,
+            Packet::ServerStreamActivityList { .. } => PACKET_ID_SERVER_STREAM_ACTIVITY_LIST,
+            Packet::ClientSetStreamMetadata { .. } => PACKET_ID_CLIENT_SET_STREAM_METADATA,
+            Packet::ClientGetStreamMetadata { .. } => PACKET_ID_CLIENT_GET_STREAM_METADATA,
+            Packet::ServerStreamMetadata { .. } => PACKET_ID_SERVER_STREAM_METADATA,
+            Packet::ClientEnqueueAllAck { .. } => PACKET_ID_CLIENT_ENQUEUE_ALL_ACK,
+            Packet::ClientEnqueueAllExceptAck { .. } => PACKET_ID_CLIENT_ENQUEUE_ALL_EXCEPT_ACK,
+            Packet::ServerEnqueueAllAck { .. } => PACKET_ID_SERVER_ENQUEUE_ALL_ACK,
+            Packet::ClientReadRange { .. } => PACKET_ID_CLIENT_READ_RANGE,
+            Packet::ClientGetTotalBytes => PACKET_ID_CLIENT_GET_TOTAL_BYTES,
+            Packet::ServerTotalBytes { .. } => PACKET_ID_SERVER_TOTAL_BYTES,
+            Packet::ClientGoodbye => PACKET_ID_CLIENT_GOODBYE,
+            Packet::ServerGoodbye => PACKET_ID_SERVER_GOODBYE,
+            Packet::ClientRegisterConsumer { .. } => PACKET_ID_CLIENT_REGISTER_CONSUMER,
+            Packet::ClientFetchFromCursor { .. } => PACKET_ID_CLIENT_FETCH_FROM_CURSOR,
+            Packet::ClientAdvanceCursor { .. } => PACKET_ID_CLIENT_ADVANCE_CURSOR,
+            Packet::ClientUnregisterConsumer { .. } => PACKET_ID_CLIENT_UNREGISTER_CONSUMER,
+            Packet::ClientSetGlobalExpiry { .. } => PACKET_ID_CLIENT_SET_GLOBAL_EXPIRY,
+            Packet::ClientSelfCheck => PACKET_ID_CLIENT_SELF_CHECK,
+            Packet::ServerSelfCheckReport { .. } => PACKET_ID_SERVER_SELF_CHECK_REPORT,
+            Packet::ClientPeekStreamContents { .. } => PACKET_ID_CLIENT_PEEK_STREAM_CONTENTS,
+            Packet::ClientRequestStreamContentsWithMeta { .. } => {
+                PACKET_ID_CLIENT_REQUEST_STREAM_CONTENTS_WITH_META
+            }
+            Packet::ServerStreamContentsMeta { .. } => PACKET_ID_SERVER_STREAM_CONTENTS_META,
+            Packet::ClientStreamLength { .. } => PACKET_ID_CLIENT_STREAM_LENGTH,
+            Packet::ServerStreamLength { .. } => PACKET_ID_SERVER_STREAM_LENGTH,
+            Packet::ClientHello { .. } => PACKET_ID_CLIENT_HELLO,
+            Packet::ServerHello { .. } => PACKET_ID_SERVER_HELLO,
+            Packet::ServerEnqueueRejected { .. } => PACKET_ID_SERVER_ENQUEUE_REJECTED,
+            Packet::ClientListStreams { .. } => PACKET_ID_CLIENT_LIST_STREAMS,
+            Packet::ServerStreamList { .. } => PACKET_ID_SERVER_STREAM_LIST,
+            Packet::ClientAwaitStreamContents { .. } => PACKET_ID_CLIENT_AWAIT_STREAM_CONTENTS,
+            Packet::ClientPrependSingle { .. } => PACKET_ID_CLIENT_PREPEND_SINGLE,
+            Packet::ServerEnqueueError { .. } => PACKET_ID_SERVER_ENQUEUE_ERROR,
+            Packet::ClientCreateMultipleStreams { .. } => PACKET_ID_CLIENT_CREATE_MULTIPLE_STREAMS,
+            Packet::ServerStreamsCreated { .. } => PACKET_ID_SERVER_STREAMS_CREATED,
+            Packet::ClientCreateNewStreamWithTtl { .. } => {
+                PACKET_ID_CLIENT_CREATE_NEW_STREAM_WITH_TTL
+            }
+            Packet::ClientClearStream { .. } => PACKET_ID_CLIENT_CLEAR_STREAM,
+            Packet::ClientCreateNewStreamNamed { .. } => PACKET_ID_CLIENT_CREATE_NEW_STREAM_NAMED,
+            Packet::ClientLookupStreamName { .. } => PACKET_ID_CLIENT_LOOKUP_STREAM_NAME,
+            Packet::ServerNamedStream { .. } => PACKET_ID_SERVER_NAMED_STREAM,
+            Packet::ClientEnqueueBatch { .. } => PACKET_ID_CLIENT_ENQUEUE_BATCH,
+            Packet::ClientNegotiateFeatures { .. } => PACKET_ID_CLIENT_NEGOTIATE_FEATURES,
+            Packet::ServerFeatures { .. } => PACKET_ID_SERVER_FEATURES,
+            Packet::ClientRequestStats => PACKET_ID_CLIENT_REQUEST_STATS,
+            Packet::ServerStats { .. } => PACKET_ID_SERVER_STATS,
+            Packet::ClientMoveStreamContents { .. } => PACKET_ID_CLIENT_MOVE_STREAM_CONTENTS,
+            Packet::ServerError { .. } => PACKET_ID_SERVER_ERROR,
+            Packet::ClientRenameStream { .. } => PACKET_ID_CLIENT_RENAME_STREAM,
+            Packet::ClientDrainPrefix { .. } => PACKET_ID_CLIENT_DRAIN_PREFIX,
+            Packet::ClientAuthenticate { .. } => PACKET_ID_CLIENT_AUTHENTICATE,
+            Packet::ServerAuthResult { .. } => PACKET_ID_SERVER_AUTH_RESULT,
+            Packet::ClientFlushAll => PACKET_ID_CLIENT_FLUSH_ALL,
+            Packet::ClientDeleteAll => PACKET_ID_CLIENT_DELETE_ALL,
+            Packet::ServerFlushResult { .. } => PACKET_ID_SERVER_FLUSH_RESULT,
+            Packet::ClientSubscribe { .. } => PACKET_ID_CLIENT_SUBSCRIBE,
+            Packet::ServerStreamPush { .. } => PACKET_ID_SERVER_STREAM_PUSH,
+            Packet::ClientUnsubscribe { .. } => PACKET_ID_CLIENT_UNSUBSCRIBE,
+            Packet::ClientWhoAmI => PACKET_ID_CLIENT_WHO_AM_I,
+            Packet::ServerConnectionInfo { .. } => PACKET_ID_SERVER_CONNECTION_INFO,
+            Packet::ClientStreamInfo { .. } => PACKET_ID_CLIENT_STREAM_INFO,
+            Packet::ServerStreamInfo { .. } => PACKET_ID_SERVER_STREAM_INFO,
+            Packet::ClientTouchStream { .. } => PACKET_ID_CLIENT_TOUCH_STREAM,
+            Packet::ClientEnqueueMultipleExcept { .. } => PACKET_ID_CLIENT_ENQUEUE_MULTIPLE_EXCEPT,
+            Packet::ClientRequestStreamContentsRange { .. } => {
+                PACKET_ID_CLIENT_REQUEST_STREAM_CONTENTS_RANGE
+            }
+            Packet::ClientHealthCheck => PACKET_ID_CLIENT_HEALTH_CHECK,
+            Packet::ServerHealth { .. } => PACKET_ID_SERVER_HEALTH,
+            Packet::ServerStreamCreated { .. } => PACKET_ID_SERVER_STREAM_CREATED,
+            Packet::ClientEnqueueSingleAck { .. } => PACKET_ID_CLIENT_ENQUEUE_SINGLE_ACK,
+            Packet::ServerEnqueueAck { .. } => PACKET_ID_SERVER_ENQUEUE_ACK,
+            Packet::ClientEnqueueWhere { .. } => PACKET_ID_CLIENT_ENQUEUE_WHERE,
+            Packet::ClientDeleteMultipleStreams { .. } => PACKET_ID_CLIENT_DELETE_MULTIPLE_STREAMS,
+            Packet::ServerStreamsDeleted { .. } => PACKET_ID_SERVER_STREAMS_DELETED,
+            Packet::ClientCreateNewStreamSized { .. } => PACKET_ID_CLIENT_CREATE_NEW_STREAM_SIZED,
+            Packet::ClientCheckStreamStateMultiple { .. } => {
+                PACKET_ID_CLIENT_CHECK_STREAM_STATE_MULTIPLE
+            }
+            Packet::ServerStreamStateMultiple { .. } => PACKET_ID_SERVER_STREAM_STATE_MULTIPLE,
+            Packet::ClientFlushStream { .. } => PACKET_ID_CLIENT_FLUSH_STREAM,
+            Packet::ClientOpenSession => PACKET_ID_CLIENT_OPEN_SESSION,
+            Packet::ServerSessionOpened { .. } => PACKET_ID_SERVER_SESSION_OPENED,
+            Packet::ClientResumeSession { .. } => PACKET_ID_CLIENT_RESUME_SESSION,
+            Packet::ServerSessionResumed { .. } => PACKET_ID_SERVER_SESSION_RESUMED,
+            Packet::ClientCreateGroup { .. } => PACKET_ID_CLIENT_CREATE_GROUP,
+            Packet::ServerGroupCreated { .. } => PACKET_ID_SERVER_GROUP_CREATED,
+            Packet::ClientAddToGroup { .. } => PACKET_ID_CLIENT_ADD_TO_GROUP,
+            Packet::ClientRemoveFromGroup { .. } => PACKET_ID_CLIENT_REMOVE_FROM_GROUP,
+            Packet::ClientDeleteGroup { .. } => PACKET_ID_CLIENT_DELETE_GROUP,
+            Packet::ClientEnqueueAllExceptGroup { .. } => PACKET_ID_CLIENT_ENQUEUE_ALL_EXCEPT_GROUP,
         }
     }
+
+    /// Queries that only describe a stream, so they don't count as activity and never keep it
+    /// from expiring.
+    pub fn is_read_only_query(&self) -> bool {
+        matches!(
+            self,
+            Packet::ClientCheckStreamState { .. }
+                | Packet::ClientCheckStreamStateMultiple { .. }
+                | Packet::ClientStreamLength { .. }
+                | Packet::ClientStreamInfo { .. }
+                | Packet::ClientGetStreamMetadata { .. }
+        )
+    }
 }
 
 // Writer helper functions
-fn write_stream_into_buffer(buffer: &mut Bytes, stream: &Bytes) {
-    let stream_size = stream.len() as u32;
+
+/// Converts a length or count to the `u32` it's sent as, failing rather than truncating it, as a
+/// truncated length would make the reader misparse everything after it.
+fn wire_length(len: usize) -> anyhow::Result<u32> {
+    u32::try_from(len).map_err(|_| anyhow::anyhow!("Length {} is too large to send", len))
+}
+
+fn write_stream_into_buffer(buffer: &mut Vec<u8>, stream: &[u8]) -> anyhow::Result<()> {
+    let stream_size = wire_length(stream.len())?;
     buffer.extend_from_slice(&stream_size.to_le_bytes());
     buffer.extend_from_slice(stream);
+    Ok(())
+}
+
+/// Writes a stream payload, compressing it if the connection negotiated compression.
+fn write_payload_into_buffer(
+    buffer: &mut Vec<u8>,
+    payload: &[u8],
+    format: WireFormat,
+) -> anyhow::Result<()> {
+    if !format.compression {
+        return write_stream_into_buffer(buffer, payload);
+    }
+
+    let uncompressed_size = wire_length(payload.len())?;
+    let compressed = compression::compress(payload)?;
+    buffer.extend_from_slice(&wire_length(compressed.len())?.to_le_bytes()); // Compressed size.
+    buffer.extend_from_slice(&uncompressed_size.to_le_bytes()); // Uncompressed size.
+    buffer.extend_from_slice(&compressed);
+    Ok(())
 }
 
-fn write_filter_list_into_buffer(buffer: &mut Bytes, filter_list: &Vec<u32>) {
-    let filter_list_size = filter_list.len() as u32;
+fn write_filter_list_into_buffer(
+    buffer: &mut Vec<u8>,
+    filter_list: &Vec<u32>,
+) -> anyhow::Result<()> {
+    let filter_list_size = wire_length(filter_list.len())?;
     buffer.extend_from_slice(&filter_list_size.to_le_bytes());
 
     for filter in filter_list {
         buffer.extend_from_slice(&filter.to_le_bytes());
     }
+    Ok(())
+}
+
+fn write_enqueue_batch_into_buffer(
+    buffer: &mut Vec<u8>,
+    entries: &[(u32, Bytes)],
+    format: WireFormat,
+) -> anyhow::Result<()> {
+    let entry_count = wire_length(entries.len())?;
+    buffer.extend_from_slice(&entry_count.to_le_bytes());
+
+    for (stream_id, enqueue_data) in entries {
+        buffer.extend_from_slice(&stream_id.to_le_bytes());
+        write_payload_into_buffer(buffer, enqueue_data, format)?;
+    }
+    Ok(())
+}
+
+fn write_predicate_into_buffer(buffer: &mut Vec<u8>, predicate: &Predicate) {
+    let (kind, first, second) = match *predicate {
+        Predicate::Range { min, max } => (PREDICATE_RANGE, min, max),
+        Predicate::Modulo { divisor, remainder } => (PREDICATE_MODULO, divisor, remainder),
+    };
+    buffer.extend_from_slice(&kind.to_le_bytes());
+    buffer.extend_from_slice(&first.to_le_bytes());
+    buffer.extend_from_slice(&second.to_le_bytes());
 }
 
-fn write_boolean_into_buffer(buffer: &mut Bytes, value: bool) {
+fn write_boolean_into_buffer(buffer: &mut Vec<u8>, value: bool) {
     // Write boolean as u32 (1 byte value + 3 padding bytes)
     let value = if value { 1u32 } else { 0u32 };
     buffer.extend_from_slice(&value.to_le_bytes());
 }
 
-pub fn write_packet_into_buffer(buffer: &mut Bytes, packet: &Packet) {
+/// Writes the packet prefixed with its length, which covers the packet ID and the payload.
+/// Fails if the packet is too large for its lengths to be sent.
+pub fn write_packet_into_buffer(buffer: &mut Vec<u8>, packet: &Packet) -> anyhow::Result<()> {
+    write_packet_into_buffer_with_format(buffer, packet, WireFormat::default())
+}
+
+/// Leaves the buffer as it was if the packet can't be written, so earlier packets stay intact.
+pub fn write_packet_into_buffer_with_format(
+    buffer: &mut Vec<u8>,
+    packet: &Packet,
+    format: WireFormat,
+) -> anyhow::Result<()> {
+    let length_offset = buffer.len();
+    buffer.extend_from_slice(&0u32.to_le_bytes()); // Packet length, filled in once known.
+
+    let packet_length = write_packet_body_into_buffer(buffer, packet, format)
+        .and_then(|()| wire_length(buffer.len() - length_offset - 4));
+    let packet_length = match packet_length {
+        Ok(packet_length) => packet_length,
+        Err(e) => {
+            buffer.truncate(length_offset);
+            return Err(e);
+        }
+    };
+    buffer[length_offset..length_offset + 4].copy_from_slice(&packet_length.to_le_bytes());
+
+    if format.checksums {
+        let checksum = crc32fast::hash(&buffer[length_offset..]);
+        buffer.extend_from_slice(&checksum.to_le_bytes()); // Checksum.
+    }
+
+    Ok(())
+}
+
+fn write_packet_body_into_buffer(
+    buffer: &mut Vec<u8>,
+    packet: &Packet,
+    format: WireFormat,
+) -> anyhow::Result<()> {
     buffer.extend_from_slice(&packet.packet_id().to_le_bytes());
 
     match packet {
         // Zero-payload, zero-length packets.
-        Packet::ClientPing | Packet::ServerPong => {}
+        Packet::ClientGetTotalBytes
+        | Packet::ClientGoodbye
+        | Packet::ServerGoodbye
+        | Packet::ClientSelfCheck
+        | Packet::ClientRequestStats
+        | Packet::ClientFlushAll
+        | Packet::ClientDeleteAll
+        | Packet::ClientWhoAmI
+        | Packet::ClientHealthCheck
+        | Packet::ClientOpenSession => {}
+
+        // Pings without a nonce keep the payload empty, as it was before nonces existed.
+        Packet::ClientPing { nonce } | Packet::ServerPong { nonce } => {
+            if let Some(nonce) = nonce {
+                buffer.extend_from_slice(&nonce.to_le_bytes()); // Nonce.
+            }
+        }
 
         // Simpler packets with fixed size.
         Packet::ClientCreateNewStream { stream_id } => {
@@ -119,24 +898,24 @@ pub fn write_packet_into_buffer(buffer: &mut Bytes, packet: &Packet) {
             enqueue_data,
         } => {
             buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
-            write_stream_into_buffer(buffer, enqueue_data); // Enqueue data.
+            write_payload_into_buffer(buffer, enqueue_data, format)?; // Enqueue data.
         }
         Packet::ClientEnqueueMultiple {
             enqueue_data,
             filter_stream_ids,
         } => {
-            write_stream_into_buffer(buffer, enqueue_data); // Enqueue data.
-            write_filter_list_into_buffer(buffer, filter_stream_ids); // Filter stream IDs.
+            write_payload_into_buffer(buffer, enqueue_data, format)?; // Enqueue data.
+            write_filter_list_into_buffer(buffer, filter_stream_ids)?; // Filter stream IDs.
         }
         Packet::ClientEnqueueAll { enqueue_data } => {
-            write_stream_into_buffer(buffer, enqueue_data); // Enqueue data.
+            write_payload_into_buffer(buffer, enqueue_data, format)?; // Enqueue data.
         }
         Packet::ClientEnqueueAllExcept {
             enqueue_data,
             filter_stream_ids,
         } => {
-            write_stream_into_buffer(buffer, enqueue_data); // Enqueue data.
-            write_filter_list_into_buffer(buffer, filter_stream_ids); // Filter stream IDs.
+            write_payload_into_buffer(buffer, enqueue_data, format)?; // Enqueue data.
+            write_filter_list_into_buffer(buffer, filter_stream_ids)?; // Filter stream IDs.
         }
         Packet::ClientRequestStreamContents { stream_id } => {
             buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
@@ -148,7 +927,7 @@ pub fn write_packet_into_buffer(buffer: &mut Bytes, packet: &Packet) {
             buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
         }
         Packet::ServerStreamContents { buffer_data } => {
-            write_stream_into_buffer(buffer, buffer_data); // Buffer data.
+            write_payload_into_buffer(buffer, buffer_data, format)?; // Buffer data.
         }
         Packet::ServerStreamState {
             stream_id,
@@ -157,244 +936,1831 @@ pub fn write_packet_into_buffer(buffer: &mut Bytes, packet: &Packet) {
             buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
             write_boolean_into_buffer(buffer, *is_valid); // Is valid.
         }
-    }
-}
-
-// Reader helper functions
-pub struct ReadResult<T> {
-    pub value: T,
-    pub new_offset: usize,
-}
-
-fn read_boolean_from_buffer(buffer: &Bytes, offset: usize) -> ReadResult<bool> {
-    let value = buffer[offset] > 0;
-    let new_offset = offset + 4;
-
-    ReadResult { value, new_offset }
-}
-
-// Not sure how I feel about the results, this whole think kinda relies on trust.
-fn read_stream_from_buffer(buffer: &Bytes, mut offset: usize) -> anyhow::Result<ReadResult<Bytes>> {
-    let stream_size = u32::from_le_bytes(buffer[offset..offset + 4].try_into()?);
-    offset += 4;
-
-    let mut new_buffer = Vec::with_capacity(stream_size as usize);
-    new_buffer.extend_from_slice(&buffer[offset..offset + stream_size as usize]);
-
-    offset += stream_size as usize;
-
-    Ok(ReadResult {
-        value: new_buffer,
-        new_offset: offset,
-    })
-}
-
-fn read_filter_list_from_buffer(
-    buffer: &Bytes,
-    mut offset: usize,
-) -> anyhow::Result<ReadResult<Vec<u32>>> {
-    let filter_list_size = u32::from_le_bytes(buffer[offset..offset + 4].try_into()?);
-    offset += 4; // Skip past the size field
-
-    let mut new_list = Vec::with_capacity(filter_list_size as usize);
-
-    for _ in 0..filter_list_size {
-        let value = u32::from_le_bytes(buffer[offset..offset + 4].try_into()?);
-        new_list.push(value);
-        offset += 4;
-    }
-
-    Ok(ReadResult {
-        value: new_list,
-        new_offset: offset,
-    })
-}
-
-pub fn read_packet_from_buffer(
-    buffer: &Bytes,
-    mut offset: usize,
-) -> anyhow::Result<ReadResult<Packet>> {
-    let packet_id = u32::from_le_bytes(buffer[offset..offset + 4].try_into()?);
-    offset += 4;
-
-    match packet_id {
-        // No payload packets.
-        PACKET_ID_CLIENT_PING => Ok(ReadResult {
-            value: Packet::ClientPing,
-            new_offset: offset,
-        }),
-        PACKET_ID_SERVER_PONG => Ok(ReadResult {
-            value: Packet::ServerPong,
-            new_offset: offset,
-        }),
-        PACKET_ID_CLIENT_CREATE_NEW_STREAM => {
-            let stream_id = u32::from_le_bytes(buffer[offset..offset + 4].try_into()?);
+        Packet::ServerBusy { retry_after_ms } => {
+            buffer.extend_from_slice(&retry_after_ms.to_le_bytes()); // Retry after.
+        }
+        Packet::ClientEnqueueSeq {
+            stream_id,
+            seq,
+            enqueue_data,
+        } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+            buffer.extend_from_slice(&seq.to_le_bytes()); // Sequence.
+            write_payload_into_buffer(buffer, enqueue_data, format)?; // Enqueue data.
+        }
+        Packet::ServerEnqueueSeqResult {
+            stream_id,
+            seq,
+            applied,
+        } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+            buffer.extend_from_slice(&seq.to_le_bytes()); // Sequence.
+            write_boolean_into_buffer(buffer, *applied); // Applied.
+        }
+        Packet::ClientListStreamsByActivity { limit, descending } => {
+            buffer.extend_from_slice(&limit.to_le_bytes()); // Limit.
+            write_boolean_into_buffer(buffer, *descending); // Descending.
+        }
+        Packet::ServerStreamActivityList { entries } => {
+            let entry_count = wire_length(entries.len())?;
+            buffer.extend_from_slice(&entry_count.to_le_bytes()); // Entry count.
+            for (stream_id, idle_secs) in entries {
+                buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+                buffer.extend_from_slice(&idle_secs.to_le_bytes()); // Idle seconds.
+            }
+        }
+        Packet::ClientSetStreamMetadata {
+            stream_id,
+            metadata,
+        } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+            write_stream_into_buffer(buffer, metadata)?; // Metadata.
+        }
+        Packet::ClientGetStreamMetadata { stream_id } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+        }
+        Packet::ServerStreamMetadata {
+            stream_id,
+            metadata,
+        } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+            write_stream_into_buffer(buffer, metadata)?; // Metadata.
+        }
+        Packet::ClientEnqueueAllAck { enqueue_data } => {
+            write_payload_into_buffer(buffer, enqueue_data, format)?; // Enqueue data.
+        }
+        Packet::ClientEnqueueAllExceptAck {
+            enqueue_data,
+            filter_stream_ids,
+        } => {
+            write_payload_into_buffer(buffer, enqueue_data, format)?; // Enqueue data.
+            write_filter_list_into_buffer(buffer, filter_stream_ids)?; // Filter stream IDs.
+        }
+        Packet::ServerEnqueueAllAck { stream_count } => {
+            buffer.extend_from_slice(&stream_count.to_le_bytes()); // Stream count.
+        }
+        Packet::ClientReadRange {
+            stream_id,
+            start,
+            end,
+        } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+            buffer.extend_from_slice(&start.to_le_bytes()); // Start.
+            buffer.extend_from_slice(&end.to_le_bytes()); // End.
+        }
+        Packet::ServerTotalBytes {
+            total_bytes,
+            stream_count,
+        } => {
+            buffer.extend_from_slice(&total_bytes.to_le_bytes()); // Total bytes.
+            buffer.extend_from_slice(&stream_count.to_le_bytes()); // Stream count.
+        }
+        Packet::ClientRegisterConsumer {
+            stream_id,
+            consumer_id,
+        } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+            buffer.extend_from_slice(&consumer_id.to_le_bytes()); // Consumer ID.
+        }
+        Packet::ClientFetchFromCursor {
+            stream_id,
+            consumer_id,
+            max_bytes,
+        } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+            buffer.extend_from_slice(&consumer_id.to_le_bytes()); // Consumer ID.
+            buffer.extend_from_slice(&max_bytes.to_le_bytes()); // Max bytes.
+        }
+        Packet::ClientAdvanceCursor {
+            stream_id,
+            consumer_id,
+            byte_count,
+        } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+            buffer.extend_from_slice(&consumer_id.to_le_bytes()); // Consumer ID.
+            buffer.extend_from_slice(&byte_count.to_le_bytes()); // Byte count.
+        }
+        Packet::ClientUnregisterConsumer {
+            stream_id,
+            consumer_id,
+        } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+            buffer.extend_from_slice(&consumer_id.to_le_bytes()); // Consumer ID.
+        }
+        Packet::ClientSetGlobalExpiry { seconds } => {
+            buffer.extend_from_slice(&seconds.to_le_bytes()); // Expiry in seconds.
+        }
+        Packet::ServerSelfCheckReport { issues } => {
+            let issue_count = wire_length(issues.len())?;
+            buffer.extend_from_slice(&issue_count.to_le_bytes()); // Issue count.
+            for issue in issues {
+                write_stream_into_buffer(buffer, issue.as_bytes())?; // Issue text.
+            }
+        }
+        Packet::ClientPeekStreamContents {
+            stream_id,
+            max_bytes,
+        } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+            buffer.extend_from_slice(&max_bytes.to_le_bytes()); // Max bytes.
+        }
+        Packet::ClientRequestStreamContentsWithMeta { stream_id } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+        }
+        Packet::ServerStreamContentsMeta {
+            stream_id,
+            byte_count,
+            existed,
+        } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+            buffer.extend_from_slice(&byte_count.to_le_bytes()); // Byte count.
+            write_boolean_into_buffer(buffer, *existed); // Existed.
+        }
+        Packet::ClientStreamLength { stream_id } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+        }
+        Packet::ServerStreamLength {
+            stream_id,
+            length,
+            exists,
+        } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+            buffer.extend_from_slice(&length.to_le_bytes()); // Length.
+            write_boolean_into_buffer(buffer, *exists); // Exists.
+        }
+        Packet::ClientHello { protocol_version } => {
+            buffer.extend_from_slice(&protocol_version.to_le_bytes()); // Protocol version.
+        }
+        Packet::ServerHello {
+            protocol_version,
+            accepted,
+        } => {
+            buffer.extend_from_slice(&protocol_version.to_le_bytes()); // Protocol version.
+            write_boolean_into_buffer(buffer, *accepted); // Accepted.
+        }
+        Packet::ServerEnqueueRejected { stream_id } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+        }
+        Packet::ClientListStreams { offset, limit } => {
+            buffer.extend_from_slice(&offset.to_le_bytes()); // Offset.
+            buffer.extend_from_slice(&limit.to_le_bytes()); // Limit.
+        }
+        Packet::ServerStreamList {
+            total_count,
+            stream_ids,
+        } => {
+            buffer.extend_from_slice(&total_count.to_le_bytes()); // Total count.
+            write_filter_list_into_buffer(buffer, stream_ids)?; // Stream IDs.
+        }
+        Packet::ClientAwaitStreamContents {
+            stream_id,
+            timeout_ms,
+        } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+            buffer.extend_from_slice(&timeout_ms.to_le_bytes()); // Timeout.
+        }
+        Packet::ClientPrependSingle {
+            stream_id,
+            enqueue_data,
+        } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+            write_payload_into_buffer(buffer, enqueue_data, format)?; // Enqueue data.
+        }
+        Packet::ServerEnqueueError { stream_id } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+        }
+        Packet::ClientCreateMultipleStreams { stream_ids } => {
+            write_filter_list_into_buffer(buffer, stream_ids)?; // Stream IDs.
+        }
+        Packet::ServerStreamsCreated {
+            existing_stream_ids,
+        } => {
+            write_filter_list_into_buffer(buffer, existing_stream_ids)?; // Existing stream IDs.
+        }
+        Packet::ClientCreateNewStreamWithTtl {
+            stream_id,
+            ttl_secs,
+        } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+            buffer.extend_from_slice(&ttl_secs.to_le_bytes()); // TTL.
+        }
+        Packet::ClientClearStream { stream_id } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+        }
+        Packet::ClientCreateNewStreamNamed { name } => {
+            write_stream_into_buffer(buffer, name)?; // Name.
+        }
+        Packet::ClientLookupStreamName { name } => {
+            write_stream_into_buffer(buffer, name)?; // Name.
+        }
+        Packet::ServerNamedStream { stream_id, exists } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+            write_boolean_into_buffer(buffer, *exists); // Exists.
+        }
+        Packet::ClientEnqueueBatch { entries } => {
+            write_enqueue_batch_into_buffer(buffer, entries, format)?; // Entries.
+        }
+        Packet::ClientNegotiateFeatures { features } => {
+            buffer.extend_from_slice(&features.to_le_bytes()); // Features.
+        }
+        Packet::ServerFeatures { features } => {
+            buffer.extend_from_slice(&features.to_le_bytes()); // Features.
+        }
+        Packet::ServerStats {
+            stream_count,
+            total_buffered_bytes,
+            uptime_secs,
+        } => {
+            buffer.extend_from_slice(&stream_count.to_le_bytes()); // Stream count.
+            buffer.extend_from_slice(&total_buffered_bytes.to_le_bytes()); // Total buffered bytes.
+            buffer.extend_from_slice(&uptime_secs.to_le_bytes()); // Uptime.
+        }
+        Packet::ClientMoveStreamContents { source_id, dest_id } => {
+            buffer.extend_from_slice(&source_id.to_le_bytes()); // Source stream ID.
+            buffer.extend_from_slice(&dest_id.to_le_bytes()); // Destination stream ID.
+        }
+        Packet::ServerError { code, message } => {
+            buffer.extend_from_slice(&code.to_le_bytes()); // Error code.
+            write_stream_into_buffer(buffer, message)?; // Message.
+        }
+        Packet::ClientRenameStream { old_id, new_id } => {
+            buffer.extend_from_slice(&old_id.to_le_bytes()); // Old stream ID.
+            buffer.extend_from_slice(&new_id.to_le_bytes()); // New stream ID.
+        }
+        Packet::ClientDrainPrefix {
+            stream_id,
+            byte_count,
+        } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+            buffer.extend_from_slice(&byte_count.to_le_bytes()); // Byte count.
+        }
+        Packet::ClientAuthenticate { token } => {
+            write_stream_into_buffer(buffer, token)?; // Token.
+        }
+        Packet::ServerAuthResult { success } => {
+            write_boolean_into_buffer(buffer, *success); // Success.
+        }
+        Packet::ServerFlushResult { streams_affected } => {
+            buffer.extend_from_slice(&streams_affected.to_le_bytes()); // Streams affected.
+        }
+        Packet::ClientSubscribe { stream_id } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+        }
+        Packet::ServerStreamPush {
+            stream_id,
+            buffer_data,
+        } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+            write_payload_into_buffer(buffer, buffer_data, format)?; // Buffer data.
+        }
+        Packet::ClientUnsubscribe { stream_id } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+        }
+        Packet::ServerConnectionInfo {
+            connection_id,
+            peer,
+        } => {
+            buffer.extend_from_slice(&connection_id.to_le_bytes()); // Connection ID.
+            write_stream_into_buffer(buffer, peer)?; // Peer.
+        }
+        Packet::ClientStreamInfo { stream_id } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+        }
+        Packet::ServerStreamInfo {
+            stream_id,
+            exists,
+            length,
+            idle_secs,
+        } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+            write_boolean_into_buffer(buffer, *exists); // Exists.
+            buffer.extend_from_slice(&length.to_le_bytes()); // Length.
+            buffer.extend_from_slice(&idle_secs.to_le_bytes()); // Idle seconds.
+        }
+        Packet::ClientTouchStream { stream_id } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+        }
+        Packet::ClientEnqueueMultipleExcept {
+            enqueue_data,
+            include_ids,
+            exclude_ids,
+        } => {
+            write_payload_into_buffer(buffer, enqueue_data, format)?; // Enqueue data.
+            write_filter_list_into_buffer(buffer, include_ids)?; // Included stream IDs.
+            write_filter_list_into_buffer(buffer, exclude_ids)?; // Excluded stream IDs.
+        }
+        Packet::ClientRequestStreamContentsRange {
+            stream_id,
+            start,
+            length,
+        } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+            buffer.extend_from_slice(&start.to_le_bytes()); // Start.
+            buffer.extend_from_slice(&length.to_le_bytes()); // Length.
+        }
+        Packet::ServerHealth { status } => {
+            buffer.extend_from_slice(&status.to_le_bytes()); // Status.
+        }
+        Packet::ServerStreamCreated { stream_id, created } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+            write_boolean_into_buffer(buffer, *created); // Created.
+        }
+        Packet::ClientEnqueueSingleAck {
+            stream_id,
+            enqueue_data,
+        } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+            write_payload_into_buffer(buffer, enqueue_data, format)?; // Enqueue data.
+        }
+        Packet::ServerEnqueueAck {
+            stream_id,
+            accepted,
+            new_length,
+        } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+            write_boolean_into_buffer(buffer, *accepted); // Accepted.
+            buffer.extend_from_slice(&new_length.to_le_bytes()); // New length.
+        }
+        Packet::ClientEnqueueWhere {
+            enqueue_data,
+            predicate,
+        } => {
+            write_payload_into_buffer(buffer, enqueue_data, format)?; // Enqueue data.
+            write_predicate_into_buffer(buffer, predicate); // Predicate.
+        }
+        Packet::ClientDeleteMultipleStreams { stream_ids } => {
+            write_filter_list_into_buffer(buffer, stream_ids)?; // Stream IDs.
+        }
+        Packet::ServerStreamsDeleted { count } => {
+            buffer.extend_from_slice(&count.to_le_bytes()); // Count.
+        }
+        Packet::ClientCreateNewStreamSized {
+            stream_id,
+            initial_capacity,
+        } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+            buffer.extend_from_slice(&initial_capacity.to_le_bytes()); // Initial capacity.
+        }
+        Packet::ClientCheckStreamStateMultiple { stream_ids } => {
+            write_filter_list_into_buffer(buffer, stream_ids)?; // Stream IDs.
+        }
+        Packet::ServerStreamStateMultiple { results } => {
+            let result_count = wire_length(results.len())?;
+            buffer.extend_from_slice(&result_count.to_le_bytes()); // Result count.
+            for (stream_id, is_valid) in results {
+                buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+                write_boolean_into_buffer(buffer, *is_valid); // Is valid.
+            }
+        }
+        Packet::ClientFlushStream { stream_id } => {
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+        }
+        Packet::ServerSessionOpened { token } => {
+            write_stream_into_buffer(buffer, token)?; // Token.
+        }
+        Packet::ClientResumeSession { token } => {
+            write_stream_into_buffer(buffer, token)?; // Token.
+        }
+        Packet::ServerSessionResumed {
+            resumed,
+            stream_ids,
+        } => {
+            write_boolean_into_buffer(buffer, *resumed); // Resumed.
+            write_filter_list_into_buffer(buffer, stream_ids)?; // Stream IDs.
+        }
+        Packet::ClientCreateGroup { group_id } => {
+            buffer.extend_from_slice(&group_id.to_le_bytes()); // Group ID.
+        }
+        Packet::ServerGroupCreated { group_id, created } => {
+            buffer.extend_from_slice(&group_id.to_le_bytes()); // Group ID.
+            write_boolean_into_buffer(buffer, *created); // Created.
+        }
+        Packet::ClientAddToGroup {
+            group_id,
+            stream_id,
+        } => {
+            buffer.extend_from_slice(&group_id.to_le_bytes()); // Group ID.
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+        }
+        Packet::ClientRemoveFromGroup {
+            group_id,
+            stream_id,
+        } => {
+            buffer.extend_from_slice(&group_id.to_le_bytes()); // Group ID.
+            buffer.extend_from_slice(&stream_id.to_le_bytes()); // Stream ID.
+        }
+        Packet::ClientDeleteGroup { group_id } => {
+            buffer.extend_from_slice(&group_id.to_le_bytes()); // Group ID.
+        }
+        Packet::ClientEnqueueAllExceptGroup {
+            group_id,
+            enqueue_data,
+        } => {
+            buffer.extend_from_slice(&group_id.to_le_bytes()); // Group ID.
+            write_payload_into_buffer(buffer, enqueue_data, format)?; // Enqueue data.
+        }
+    }
+
+    Ok(())
+}
+
+// Reader helper functions
+pub struct ReadResult<T> {
+    pub value: T,
+    pub new_offset: usize,
+}
+
+/// Returns `buffer[offset..offset + len]`, or `Incomplete` if the buffer is too short.
+fn buffer_slice(buffer: &[u8], offset: usize, len: usize) -> Result<&[u8], PacketReadError> {
+    let end = offset
+        .checked_add(len)
+        .ok_or_else(|| PacketReadError::Invalid("Length overflows the buffer".to_string()))?;
+    buffer.get(offset..end).ok_or(PacketReadError::Incomplete)
+}
+
+/// Checks that `count` elements of at least `element_size` bytes each can still fit in the
+/// buffer, so a bogus count can't cause a huge allocation up front.
+fn ensure_elements_fit(
+    buffer: &[u8],
+    offset: usize,
+    count: u32,
+    element_size: usize,
+) -> Result<(), PacketReadError> {
+    buffer_slice(buffer, offset, count as usize * element_size)?;
+    Ok(())
+}
+
+/// Booleans occupy 4 bytes on the wire, so all 4 must be present even though only the first
+/// is significant.
+fn read_boolean_from_buffer(buffer: &[u8], offset: usize) -> anyhow::Result<ReadResult<bool>> {
+    let value = buffer_slice(buffer, offset, 4)?[0] > 0;
+    let new_offset = offset + 4;
+
+    Ok(ReadResult { value, new_offset })
+}
+
+// Not sure how I feel about the results, this whole think kinda relies on trust.
+fn read_stream_from_buffer(buffer: &[u8], mut offset: usize) -> anyhow::Result<ReadResult<Bytes>> {
+    let stream_size = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+    offset += 4;
+
+    let new_buffer = Bytes::copy_from_slice(buffer_slice(buffer, offset, stream_size as usize)?);
+
+    offset += stream_size as usize;
+
+    Ok(ReadResult {
+        value: new_buffer,
+        new_offset: offset,
+    })
+}
+
+/// Rejects a declared payload size over the limit before anything is copied or allocated for it.
+fn check_payload_size(payload_size: usize, format: WireFormat) -> anyhow::Result<()> {
+    if format.max_payload_size != 0 && payload_size > format.max_payload_size {
+        return Err(PacketReadError::Invalid(format!(
+            "Payload of {} bytes exceeds the {} byte limit",
+            payload_size, format.max_payload_size
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Reads a stream payload written by `write_payload_into_buffer`.
+fn read_payload_from_buffer(
+    buffer: &[u8],
+    mut offset: usize,
+    format: WireFormat,
+) -> anyhow::Result<ReadResult<Bytes>> {
+    if !format.compression {
+        let payload_size =
+            u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?) as usize;
+        check_payload_size(payload_size, format)?;
+        return read_stream_from_buffer(buffer, offset);
+    }
+
+    let compressed_size = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+    offset += 4;
+    let uncompressed_size =
+        u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?) as usize;
+    offset += 4;
+    let compressed = buffer_slice(buffer, offset, compressed_size as usize)?;
+    offset += compressed_size as usize;
+
+    check_payload_size(uncompressed_size, format)?;
+    let payload = compression::decompress(compressed, uncompressed_size)
+        .map_err(|e| PacketReadError::Invalid(e.to_string()))?;
+
+    Ok(ReadResult {
+        value: payload.into(),
+        new_offset: offset,
+    })
+}
+
+fn read_enqueue_batch_from_buffer(
+    buffer: &[u8],
+    mut offset: usize,
+    format: WireFormat,
+) -> anyhow::Result<ReadResult<Vec<(u32, Bytes)>>> {
+    let entry_count = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+    offset += 4;
+
+    // Every entry takes at least its stream ID and data length.
+    ensure_elements_fit(buffer, offset, entry_count, 8)?;
+    let mut entries = Vec::with_capacity(entry_count as usize);
+
+    for _ in 0..entry_count {
+        let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+        offset += 4;
+        let enqueue_data = read_payload_from_buffer(buffer, offset, format)?;
+        offset = enqueue_data.new_offset;
+        entries.push((stream_id, enqueue_data.value));
+    }
+
+    Ok(ReadResult {
+        value: entries,
+        new_offset: offset,
+    })
+}
+
+fn read_predicate_from_buffer(
+    buffer: &[u8],
+    mut offset: usize,
+) -> anyhow::Result<ReadResult<Predicate>> {
+    let kind = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+    offset += 4;
+    let first = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+    offset += 4;
+    let second = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+    offset += 4;
+
+    let predicate = match kind {
+        PREDICATE_RANGE => Predicate::Range {
+            min: first,
+            max: second,
+        },
+        PREDICATE_MODULO => Predicate::Modulo {
+            divisor: first,
+            remainder: second,
+        },
+        _ => {
+            return Err(
+                PacketReadError::Invalid(format!("Unknown predicate kind: {}", kind)).into(),
+            );
+        }
+    };
+    Ok(ReadResult {
+        value: predicate,
+        new_offset: offset,
+    })
+}
+
+fn read_filter_list_from_buffer(
+    buffer: &[u8],
+    mut offset: usize,
+) -> anyhow::Result<ReadResult<Vec<u32>>> {
+    let filter_list_size = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+    offset += 4; // Skip past the size field
+
+    ensure_elements_fit(buffer, offset, filter_list_size, 4)?;
+    let mut new_list = Vec::with_capacity(filter_list_size as usize);
+
+    for _ in 0..filter_list_size {
+        let value = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+        new_list.push(value);
+        offset += 4;
+    }
+
+    Ok(ReadResult {
+        value: new_list,
+        new_offset: offset,
+    })
+}
+
+/// Reads a length-prefixed packet. The packet is only parsed once all of it has arrived, so a
+/// packet whose fields don't fit its declared length is invalid rather than incomplete.
+pub fn read_packet_from_buffer(buffer: &[u8], offset: usize) -> anyhow::Result<ReadResult<Packet>> {
+    read_packet_from_buffer_with_format(buffer, offset, WireFormat::default())
+}
+
+pub fn read_packet_from_buffer_with_format(
+    buffer: &[u8],
+    mut offset: usize,
+    format: WireFormat,
+) -> anyhow::Result<ReadResult<Packet>> {
+    let frame_start = offset;
+    let packet_length = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?) as usize;
+    offset += 4;
+
+    let packet_buffer = buffer_slice(buffer, offset, packet_length)?;
+    let mut frame_length = 4 + packet_length;
+    // Checked before parsing, so corruption is reported as such rather than as a malformed packet.
+    if format.checksums {
+        let checksum_offset = offset + packet_length;
+        let checksum = u32::from_le_bytes(buffer_slice(buffer, checksum_offset, 4)?.try_into()?);
+        if checksum != crc32fast::hash(&buffer[frame_start..checksum_offset]) {
+            return Err(PacketReadError::Invalid("Packet checksum mismatch".to_string()).into());
+        }
+        frame_length += 4;
+    }
+
+    let packet = match read_packet_body_from_buffer(packet_buffer, 0, format) {
+        Ok(packet) => packet,
+        Err(e) if is_incomplete(&e) => {
+            return Err(PacketReadError::Invalid(
+                "Packet fields exceed its declared length".to_string(),
+            )
+            .into());
+        }
+        Err(e) => return Err(e),
+    };
+    if packet.new_offset != packet_length {
+        return Err(PacketReadError::Invalid(format!(
+            "Packet declared {} bytes but its fields take {}",
+            packet_length, packet.new_offset
+        ))
+        .into());
+    }
+
+    Ok(ReadResult {
+        value: packet.value,
+        new_offset: frame_start + frame_length,
+    })
+}
+
+fn read_packet_body_from_buffer(
+    buffer: &[u8],
+    mut offset: usize,
+    format: WireFormat,
+) -> anyhow::Result<ReadResult<Packet>> {
+    let packet_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+    offset += 4;
+
+    match packet_id {
+        PACKET_ID_CLIENT_PING | PACKET_ID_SERVER_PONG => {
+            // The buffer holds exactly one packet, so any payload is the nonce.
+            let nonce = if buffer.len() > offset {
+                let nonce = u64::from_le_bytes(buffer_slice(buffer, offset, 8)?.try_into()?);
+                offset += 8;
+                Some(nonce)
+            } else {
+                None
+            };
+            let value = if packet_id == PACKET_ID_CLIENT_PING {
+                Packet::ClientPing { nonce }
+            } else {
+                Packet::ServerPong { nonce }
+            };
+            Ok(ReadResult {
+                value,
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_CREATE_NEW_STREAM => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ClientCreateNewStream { stream_id },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_DELETE_STREAM => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ClientDeleteStream { stream_id },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_ENQUEUE_SINGLE => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let enqueue_data = read_payload_from_buffer(buffer, offset, format)?;
+            offset = enqueue_data.new_offset;
+            Ok(ReadResult {
+                value: Packet::ClientEnqueueSingle {
+                    stream_id,
+                    enqueue_data: enqueue_data.value,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_ENQUEUE_MULTIPLE => {
+            let enqueue_data = read_payload_from_buffer(buffer, offset, format)?;
+            offset = enqueue_data.new_offset;
+            let filter_stream_ids = read_filter_list_from_buffer(buffer, offset)?;
+            offset = filter_stream_ids.new_offset;
+            Ok(ReadResult {
+                value: Packet::ClientEnqueueMultiple {
+                    enqueue_data: enqueue_data.value,
+                    filter_stream_ids: filter_stream_ids.value,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_ENQUEUE_ALL => {
+            let enqueue_data = read_payload_from_buffer(buffer, offset, format)?;
+            offset = enqueue_data.new_offset;
+            Ok(ReadResult {
+                value: Packet::ClientEnqueueAll {
+                    enqueue_data: enqueue_data.value,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_ENQUEUE_ALL_EXCEPT => {
+            let enqueue_data = read_payload_from_buffer(buffer, offset, format)?;
+            offset = enqueue_data.new_offset;
+            let filter_stream_ids = read_filter_list_from_buffer(buffer, offset)?;
+            offset = filter_stream_ids.new_offset;
+            Ok(ReadResult {
+                value: Packet::ClientEnqueueAllExcept {
+                    enqueue_data: enqueue_data.value,
+                    filter_stream_ids: filter_stream_ids.value,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_REQUEST_STREAM_CONTENTS => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ClientRequestStreamContents { stream_id },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_REQUEST_STREAM_CONTENTS_NO_CLEAR => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ClientRequestStreamContentsNoClear { stream_id },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_CHECK_STREAM_STATE => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ClientCheckStreamState { stream_id },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_SERVER_STREAM_CONTENTS => {
+            let buffer_data = read_payload_from_buffer(buffer, offset, format)?;
+            offset = buffer_data.new_offset;
+            Ok(ReadResult {
+                value: Packet::ServerStreamContents {
+                    buffer_data: buffer_data.value,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_SERVER_STREAM_STATE => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let is_valid = read_boolean_from_buffer(buffer, offset)?;
+            offset = is_valid.new_offset;
+            Ok(ReadResult {
+                value: Packet::ServerStreamState {
+                    stream_id,
+                    is_valid: is_valid.value,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_SERVER_BUSY => {
+            let retry_after_ms = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ServerBusy { retry_after_ms },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_ENQUEUE_SEQ => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let seq = u64::from_le_bytes(buffer_slice(buffer, offset, 8)?.try_into()?);
+            offset += 8;
+            let enqueue_data = read_payload_from_buffer(buffer, offset, format)?;
+            offset = enqueue_data.new_offset;
+            Ok(ReadResult {
+                value: Packet::ClientEnqueueSeq {
+                    stream_id,
+                    seq,
+                    enqueue_data: enqueue_data.value,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_SERVER_ENQUEUE_SEQ_RESULT => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let seq = u64::from_le_bytes(buffer_slice(buffer, offset, 8)?.try_into()?);
+            offset += 8;
+            let applied = read_boolean_from_buffer(buffer, offset)?;
+            offset = applied.new_offset;
+            Ok(ReadResult {
+                value: Packet::ServerEnqueueSeqResult {
+                    stream_id,
+                    seq,
+                    applied: applied.value,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_LIST_STREAMS_BY_ACTIVITY => {
+            let limit = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let descending = read_boolean_from_buffer(buffer, offset)?;
+            offset = descending.new_offset;
+            Ok(ReadResult {
+                value: Packet::ClientListStreamsByActivity {
+                    limit,
+                    descending: descending.value,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_SERVER_STREAM_ACTIVITY_LIST => {
+            let entry_count = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            ensure_elements_fit(buffer, offset, entry_count, 8)?;
+            let mut entries = Vec::with_capacity(entry_count as usize);
+            for _ in 0..entry_count {
+                let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+                offset += 4;
+                let idle_secs = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+                offset += 4;
+                entries.push((stream_id, idle_secs));
+            }
+            Ok(ReadResult {
+                value: Packet::ServerStreamActivityList { entries },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_SET_STREAM_METADATA => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let metadata = read_stream_from_buffer(buffer, offset)?;
+            offset = metadata.new_offset;
+            Ok(ReadResult {
+                value: Packet::ClientSetStreamMetadata {
+                    stream_id,
+                    metadata: metadata.value,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_GET_STREAM_METADATA => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ClientGetStreamMetadata { stream_id },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_SERVER_STREAM_METADATA => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let metadata = read_stream_from_buffer(buffer, offset)?;
+            offset = metadata.new_offset;
+            Ok(ReadResult {
+                value: Packet::ServerStreamMetadata {
+                    stream_id,
+                    metadata: metadata.value,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_ENQUEUE_ALL_ACK => {
+            let enqueue_data = read_payload_from_buffer(buffer, offset, format)?;
+            offset = enqueue_data.new_offset;
+            Ok(ReadResult {
+                value: Packet::ClientEnqueueAllAck {
+                    enqueue_data: enqueue_data.value,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_ENQUEUE_ALL_EXCEPT_ACK => {
+            let enqueue_data = read_payload_from_buffer(buffer, offset, format)?;
+            offset = enqueue_data.new_offset;
+            let filter_stream_ids = read_filter_list_from_buffer(buffer, offset)?;
+            offset = filter_stream_ids.new_offset;
+            Ok(ReadResult {
+                value: Packet::ClientEnqueueAllExceptAck {
+                    enqueue_data: enqueue_data.value,
+                    filter_stream_ids: filter_stream_ids.value,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_SERVER_ENQUEUE_ALL_ACK => {
+            let stream_count = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ServerEnqueueAllAck { stream_count },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_READ_RANGE => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let start = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let end = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ClientReadRange {
+                    stream_id,
+                    start,
+                    end,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_GET_TOTAL_BYTES => Ok(ReadResult {
+            value: Packet::ClientGetTotalBytes,
+            new_offset: offset,
+        }),
+        PACKET_ID_SERVER_TOTAL_BYTES => {
+            let total_bytes = u64::from_le_bytes(buffer_slice(buffer, offset, 8)?.try_into()?);
+            offset += 8;
+            let stream_count = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ServerTotalBytes {
+                    total_bytes,
+                    stream_count,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_GOODBYE => Ok(ReadResult {
+            value: Packet::ClientGoodbye,
+            new_offset: offset,
+        }),
+        PACKET_ID_SERVER_GOODBYE => Ok(ReadResult {
+            value: Packet::ServerGoodbye,
+            new_offset: offset,
+        }),
+        PACKET_ID_CLIENT_REGISTER_CONSUMER => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let consumer_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ClientRegisterConsumer {
+                    stream_id,
+                    consumer_id,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_FETCH_FROM_CURSOR => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let consumer_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let max_bytes = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ClientFetchFromCursor {
+                    stream_id,
+                    consumer_id,
+                    max_bytes,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_ADVANCE_CURSOR => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let consumer_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let byte_count = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ClientAdvanceCursor {
+                    stream_id,
+                    consumer_id,
+                    byte_count,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_UNREGISTER_CONSUMER => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let consumer_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ClientUnregisterConsumer {
+                    stream_id,
+                    consumer_id,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_SET_GLOBAL_EXPIRY => {
+            let seconds = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ClientSetGlobalExpiry { seconds },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_SELF_CHECK => Ok(ReadResult {
+            value: Packet::ClientSelfCheck,
+            new_offset: offset,
+        }),
+        PACKET_ID_SERVER_SELF_CHECK_REPORT => {
+            let issue_count = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            ensure_elements_fit(buffer, offset, issue_count, 4)?;
+            let mut issues = Vec::with_capacity(issue_count as usize);
+            for _ in 0..issue_count {
+                let issue = read_stream_from_buffer(buffer, offset)?;
+                offset = issue.new_offset;
+                let issue = String::from_utf8(issue.value.to_vec())
+                    .map_err(|e| PacketReadError::Invalid(e.to_string()))?;
+                issues.push(issue);
+            }
+            Ok(ReadResult {
+                value: Packet::ServerSelfCheckReport { issues },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_PEEK_STREAM_CONTENTS => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let max_bytes = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ClientPeekStreamContents {
+                    stream_id,
+                    max_bytes,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_REQUEST_STREAM_CONTENTS_WITH_META => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ClientRequestStreamContentsWithMeta { stream_id },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_SERVER_STREAM_CONTENTS_META => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let byte_count = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let existed = read_boolean_from_buffer(buffer, offset)?;
+            offset = existed.new_offset;
+            Ok(ReadResult {
+                value: Packet::ServerStreamContentsMeta {
+                    stream_id,
+                    byte_count,
+                    existed: existed.value,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_STREAM_LENGTH => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ClientStreamLength { stream_id },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_SERVER_STREAM_LENGTH => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let length = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let exists = read_boolean_from_buffer(buffer, offset)?;
+            offset = exists.new_offset;
+            Ok(ReadResult {
+                value: Packet::ServerStreamLength {
+                    stream_id,
+                    length,
+                    exists: exists.value,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_HELLO => {
+            let protocol_version = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ClientHello { protocol_version },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_SERVER_HELLO => {
+            let protocol_version = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let accepted = read_boolean_from_buffer(buffer, offset)?;
+            offset = accepted.new_offset;
+            Ok(ReadResult {
+                value: Packet::ServerHello {
+                    protocol_version,
+                    accepted: accepted.value,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_SERVER_ENQUEUE_REJECTED => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ServerEnqueueRejected { stream_id },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_LIST_STREAMS => {
+            let list_offset = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let limit = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ClientListStreams {
+                    offset: list_offset,
+                    limit,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_SERVER_STREAM_LIST => {
+            let total_count = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let stream_ids = read_filter_list_from_buffer(buffer, offset)?;
+            offset = stream_ids.new_offset;
+            Ok(ReadResult {
+                value: Packet::ServerStreamList {
+                    total_count,
+                    stream_ids: stream_ids.value,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_AWAIT_STREAM_CONTENTS => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let timeout_ms = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ClientAwaitStreamContents {
+                    stream_id,
+                    timeout_ms,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_PREPEND_SINGLE => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let enqueue_data = read_payload_from_buffer(buffer, offset, format)?;
+            offset = enqueue_data.new_offset;
+            Ok(ReadResult {
+                value: Packet::ClientPrependSingle {
+                    stream_id,
+                    enqueue_data: enqueue_data.value,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_SERVER_ENQUEUE_ERROR => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ServerEnqueueError { stream_id },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_CREATE_MULTIPLE_STREAMS => {
+            let stream_ids = read_filter_list_from_buffer(buffer, offset)?;
+            offset = stream_ids.new_offset;
+            Ok(ReadResult {
+                value: Packet::ClientCreateMultipleStreams {
+                    stream_ids: stream_ids.value,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_SERVER_STREAMS_CREATED => {
+            let existing_stream_ids = read_filter_list_from_buffer(buffer, offset)?;
+            offset = existing_stream_ids.new_offset;
+            Ok(ReadResult {
+                value: Packet::ServerStreamsCreated {
+                    existing_stream_ids: existing_stream_ids.value,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_CREATE_NEW_STREAM_WITH_TTL => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let ttl_secs = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ClientCreateNewStreamWithTtl {
+                    stream_id,
+                    ttl_secs,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_CLEAR_STREAM => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ClientClearStream { stream_id },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_CREATE_NEW_STREAM_NAMED => {
+            let name = read_stream_from_buffer(buffer, offset)?;
+            offset = name.new_offset;
+            Ok(ReadResult {
+                value: Packet::ClientCreateNewStreamNamed { name: name.value },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_LOOKUP_STREAM_NAME => {
+            let name = read_stream_from_buffer(buffer, offset)?;
+            offset = name.new_offset;
+            Ok(ReadResult {
+                value: Packet::ClientLookupStreamName { name: name.value },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_SERVER_NAMED_STREAM => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
             offset += 4;
+            let exists = read_boolean_from_buffer(buffer, offset)?;
+            offset = exists.new_offset;
             Ok(ReadResult {
-                value: Packet::ClientCreateNewStream { stream_id },
+                value: Packet::ServerNamedStream {
+                    stream_id,
+                    exists: exists.value,
+                },
                 new_offset: offset,
             })
         }
-        PACKET_ID_CLIENT_DELETE_STREAM => {
-            let stream_id = u32::from_le_bytes(buffer[offset..offset + 4].try_into()?);
+        PACKET_ID_CLIENT_ENQUEUE_BATCH => {
+            let entries = read_enqueue_batch_from_buffer(buffer, offset, format)?;
+            offset = entries.new_offset;
+            Ok(ReadResult {
+                value: Packet::ClientEnqueueBatch {
+                    entries: entries.value,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_NEGOTIATE_FEATURES => {
+            let features = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
             offset += 4;
             Ok(ReadResult {
-                value: Packet::ClientDeleteStream { stream_id },
+                value: Packet::ClientNegotiateFeatures { features },
                 new_offset: offset,
             })
         }
-        PACKET_ID_CLIENT_ENQUEUE_SINGLE => {
-            let stream_id = u32::from_le_bytes(buffer[offset..offset + 4].try_into()?);
+        PACKET_ID_SERVER_FEATURES => {
+            let features = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
             offset += 4;
-            let enqueue_data = read_stream_from_buffer(buffer, offset)?;
-            offset = enqueue_data.new_offset;
             Ok(ReadResult {
-                value: Packet::ClientEnqueueSingle {
+                value: Packet::ServerFeatures { features },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_REQUEST_STATS => Ok(ReadResult {
+            value: Packet::ClientRequestStats,
+            new_offset: offset,
+        }),
+        PACKET_ID_SERVER_STATS => {
+            let stream_count = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let total_buffered_bytes =
+                u64::from_le_bytes(buffer_slice(buffer, offset, 8)?.try_into()?);
+            offset += 8;
+            let uptime_secs = u64::from_le_bytes(buffer_slice(buffer, offset, 8)?.try_into()?);
+            offset += 8;
+            Ok(ReadResult {
+                value: Packet::ServerStats {
+                    stream_count,
+                    total_buffered_bytes,
+                    uptime_secs,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_MOVE_STREAM_CONTENTS => {
+            let source_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let dest_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ClientMoveStreamContents { source_id, dest_id },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_SERVER_ERROR => {
+            let code = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let message = read_stream_from_buffer(buffer, offset)?;
+            offset = message.new_offset;
+            Ok(ReadResult {
+                value: Packet::ServerError {
+                    code,
+                    message: message.value,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_RENAME_STREAM => {
+            let old_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let new_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ClientRenameStream { old_id, new_id },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_DRAIN_PREFIX => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let byte_count = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ClientDrainPrefix {
                     stream_id,
-                    enqueue_data: enqueue_data.value,
+                    byte_count,
                 },
                 new_offset: offset,
             })
         }
-        PACKET_ID_CLIENT_ENQUEUE_MULTIPLE => {
-            let enqueue_data = read_stream_from_buffer(buffer, offset)?;
+        PACKET_ID_CLIENT_AUTHENTICATE => {
+            let token = read_stream_from_buffer(buffer, offset)?;
+            offset = token.new_offset;
+            Ok(ReadResult {
+                value: Packet::ClientAuthenticate { token: token.value },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_SERVER_AUTH_RESULT => {
+            let success = read_boolean_from_buffer(buffer, offset)?;
+            offset = success.new_offset;
+            Ok(ReadResult {
+                value: Packet::ServerAuthResult {
+                    success: success.value,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_FLUSH_ALL => Ok(ReadResult {
+            value: Packet::ClientFlushAll,
+            new_offset: offset,
+        }),
+        PACKET_ID_CLIENT_DELETE_ALL => Ok(ReadResult {
+            value: Packet::ClientDeleteAll,
+            new_offset: offset,
+        }),
+        PACKET_ID_SERVER_FLUSH_RESULT => {
+            let streams_affected = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ServerFlushResult { streams_affected },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_SUBSCRIBE => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ClientSubscribe { stream_id },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_SERVER_STREAM_PUSH => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let buffer_data = read_payload_from_buffer(buffer, offset, format)?;
+            offset = buffer_data.new_offset;
+            Ok(ReadResult {
+                value: Packet::ServerStreamPush {
+                    stream_id,
+                    buffer_data: buffer_data.value,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_UNSUBSCRIBE => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ClientUnsubscribe { stream_id },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_WHO_AM_I => Ok(ReadResult {
+            value: Packet::ClientWhoAmI,
+            new_offset: offset,
+        }),
+        PACKET_ID_SERVER_CONNECTION_INFO => {
+            let connection_id = u64::from_le_bytes(buffer_slice(buffer, offset, 8)?.try_into()?);
+            offset += 8;
+            let peer = read_stream_from_buffer(buffer, offset)?;
+            offset = peer.new_offset;
+            Ok(ReadResult {
+                value: Packet::ServerConnectionInfo {
+                    connection_id,
+                    peer: peer.value,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_STREAM_INFO => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ClientStreamInfo { stream_id },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_SERVER_STREAM_INFO => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let exists = read_boolean_from_buffer(buffer, offset)?;
+            offset = exists.new_offset;
+            let length = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let idle_secs = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ServerStreamInfo {
+                    stream_id,
+                    exists: exists.value,
+                    length,
+                    idle_secs,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_TOUCH_STREAM => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ClientTouchStream { stream_id },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_ENQUEUE_MULTIPLE_EXCEPT => {
+            let enqueue_data = read_payload_from_buffer(buffer, offset, format)?;
             offset = enqueue_data.new_offset;
-            let filter_stream_ids = read_filter_list_from_buffer(buffer, offset)?;
-            offset = filter_stream_ids.new_offset;
+            let include_ids = read_filter_list_from_buffer(buffer, offset)?;
+            offset = include_ids.new_offset;
+            let exclude_ids = read_filter_list_from_buffer(buffer, offset)?;
+            offset = exclude_ids.new_offset;
             Ok(ReadResult {
-                value: Packet::ClientEnqueueMultiple {
+                value: Packet::ClientEnqueueMultipleExcept {
                     enqueue_data: enqueue_data.value,
-                    filter_stream_ids: filter_stream_ids.value,
+                    include_ids: include_ids.value,
+                    exclude_ids: exclude_ids.value,
                 },
                 new_offset: offset,
             })
         }
-        PACKET_ID_CLIENT_ENQUEUE_ALL => {
-            let enqueue_data = read_stream_from_buffer(buffer, offset)?;
+        PACKET_ID_CLIENT_REQUEST_STREAM_CONTENTS_RANGE => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let start = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let length = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ClientRequestStreamContentsRange {
+                    stream_id,
+                    start,
+                    length,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_HEALTH_CHECK => Ok(ReadResult {
+            value: Packet::ClientHealthCheck,
+            new_offset: offset,
+        }),
+        PACKET_ID_SERVER_HEALTH => {
+            let status = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ServerHealth { status },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_SERVER_STREAM_CREATED => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let created = read_boolean_from_buffer(buffer, offset)?;
+            offset = created.new_offset;
+            Ok(ReadResult {
+                value: Packet::ServerStreamCreated {
+                    stream_id,
+                    created: created.value,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_ENQUEUE_SINGLE_ACK => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let enqueue_data = read_payload_from_buffer(buffer, offset, format)?;
             offset = enqueue_data.new_offset;
             Ok(ReadResult {
-                value: Packet::ClientEnqueueAll {
+                value: Packet::ClientEnqueueSingleAck {
+                    stream_id,
                     enqueue_data: enqueue_data.value,
                 },
                 new_offset: offset,
             })
         }
-        PACKET_ID_CLIENT_ENQUEUE_ALL_EXCEPT => {
-            let enqueue_data = read_stream_from_buffer(buffer, offset)?;
+        PACKET_ID_SERVER_ENQUEUE_ACK => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let accepted = read_boolean_from_buffer(buffer, offset)?;
+            offset = accepted.new_offset;
+            let new_length = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ServerEnqueueAck {
+                    stream_id,
+                    accepted: accepted.value,
+                    new_length,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_ENQUEUE_WHERE => {
+            let enqueue_data = read_payload_from_buffer(buffer, offset, format)?;
             offset = enqueue_data.new_offset;
-            let filter_stream_ids = read_filter_list_from_buffer(buffer, offset)?;
-            offset = filter_stream_ids.new_offset;
+            let predicate = read_predicate_from_buffer(buffer, offset)?;
+            offset = predicate.new_offset;
             Ok(ReadResult {
-                value: Packet::ClientEnqueueAllExcept {
+                value: Packet::ClientEnqueueWhere {
                     enqueue_data: enqueue_data.value,
-                    filter_stream_ids: filter_stream_ids.value,
+                    predicate: predicate.value,
                 },
                 new_offset: offset,
             })
         }
-        PACKET_ID_CLIENT_REQUEST_STREAM_CONTENTS => {
-            let stream_id = u32::from_le_bytes(buffer[offset..offset + 4].try_into()?);
+        PACKET_ID_CLIENT_DELETE_MULTIPLE_STREAMS => {
+            let stream_ids = read_filter_list_from_buffer(buffer, offset)?;
+            offset = stream_ids.new_offset;
+            Ok(ReadResult {
+                value: Packet::ClientDeleteMultipleStreams {
+                    stream_ids: stream_ids.value,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_SERVER_STREAMS_DELETED => {
+            let count = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
             offset += 4;
             Ok(ReadResult {
-                value: Packet::ClientRequestStreamContents { stream_id },
+                value: Packet::ServerStreamsDeleted { count },
                 new_offset: offset,
             })
         }
-        PACKET_ID_CLIENT_REQUEST_STREAM_CONTENTS_NO_CLEAR => {
-            let stream_id = u32::from_le_bytes(buffer[offset..offset + 4].try_into()?);
+        PACKET_ID_CLIENT_CREATE_NEW_STREAM_SIZED => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let initial_capacity = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
             offset += 4;
             Ok(ReadResult {
-                value: Packet::ClientRequestStreamContentsNoClear { stream_id },
+                value: Packet::ClientCreateNewStreamSized {
+                    stream_id,
+                    initial_capacity,
+                },
                 new_offset: offset,
             })
         }
-        PACKET_ID_CLIENT_CHECK_STREAM_STATE => {
-            let stream_id = u32::from_le_bytes(buffer[offset..offset + 4].try_into()?);
+        PACKET_ID_CLIENT_CHECK_STREAM_STATE_MULTIPLE => {
+            let stream_ids = read_filter_list_from_buffer(buffer, offset)?;
+            offset = stream_ids.new_offset;
+            Ok(ReadResult {
+                value: Packet::ClientCheckStreamStateMultiple {
+                    stream_ids: stream_ids.value,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_SERVER_STREAM_STATE_MULTIPLE => {
+            let result_count = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
             offset += 4;
+            ensure_elements_fit(buffer, offset, result_count, 8)?;
+            let mut results = Vec::with_capacity(result_count as usize);
+            for _ in 0..result_count {
+                let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+                offset += 4;
+                let is_valid = read_boolean_from_buffer(buffer, offset)?;
+                offset = is_valid.new_offset;
+                results.push((stream_id, is_valid.value));
+            }
             Ok(ReadResult {
-                value: Packet::ClientCheckStreamState { stream_id },
+                value: Packet::ServerStreamStateMultiple { results },
                 new_offset: offset,
             })
         }
-        PACKET_ID_SERVER_STREAM_CONTENTS => {
-            let buffer_data = read_stream_from_buffer(buffer, offset)?;
-            offset = buffer_data.new_offset;
+        PACKET_ID_CLIENT_FLUSH_STREAM => {
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
             Ok(ReadResult {
-                value: Packet::ServerStreamContents {
-                    buffer_data: buffer_data.value,
+                value: Packet::ClientFlushStream { stream_id },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_OPEN_SESSION => Ok(ReadResult {
+            value: Packet::ClientOpenSession,
+            new_offset: offset,
+        }),
+        PACKET_ID_SERVER_SESSION_OPENED => {
+            let token = read_stream_from_buffer(buffer, offset)?;
+            offset = token.new_offset;
+            Ok(ReadResult {
+                value: Packet::ServerSessionOpened { token: token.value },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_RESUME_SESSION => {
+            let token = read_stream_from_buffer(buffer, offset)?;
+            offset = token.new_offset;
+            Ok(ReadResult {
+                value: Packet::ClientResumeSession { token: token.value },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_SERVER_SESSION_RESUMED => {
+            let resumed = read_boolean_from_buffer(buffer, offset)?;
+            offset = resumed.new_offset;
+            let stream_ids = read_filter_list_from_buffer(buffer, offset)?;
+            offset = stream_ids.new_offset;
+            Ok(ReadResult {
+                value: Packet::ServerSessionResumed {
+                    resumed: resumed.value,
+                    stream_ids: stream_ids.value,
                 },
                 new_offset: offset,
             })
         }
-        PACKET_ID_SERVER_STREAM_STATE => {
-            let stream_id = u32::from_le_bytes(buffer[offset..offset + 4].try_into()?);
+        PACKET_ID_CLIENT_CREATE_GROUP => {
+            let group_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
             offset += 4;
-            let is_valid = read_boolean_from_buffer(buffer, offset);
-            offset = is_valid.new_offset;
             Ok(ReadResult {
-                value: Packet::ServerStreamState {
+                value: Packet::ClientCreateGroup { group_id },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_SERVER_GROUP_CREATED => {
+            let group_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let created = read_boolean_from_buffer(buffer, offset)?;
+            offset = created.new_offset;
+            Ok(ReadResult {
+                value: Packet::ServerGroupCreated {
+                    group_id,
+                    created: created.value,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_ADD_TO_GROUP => {
+            let group_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ClientAddToGroup {
+                    group_id,
                     stream_id,
-                    is_valid: is_valid.value,
                 },
                 new_offset: offset,
             })
         }
-        _ => Err(anyhow::anyhow!("Invalid packet ID: {}", packet_id)),
+        PACKET_ID_CLIENT_REMOVE_FROM_GROUP => {
+            let group_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let stream_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ClientRemoveFromGroup {
+                    group_id,
+                    stream_id,
+                },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_DELETE_GROUP => {
+            let group_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            Ok(ReadResult {
+                value: Packet::ClientDeleteGroup { group_id },
+                new_offset: offset,
+            })
+        }
+        PACKET_ID_CLIENT_ENQUEUE_ALL_EXCEPT_GROUP => {
+            let group_id = u32::from_le_bytes(buffer_slice(buffer, offset, 4)?.try_into()?);
+            offset += 4;
+            let enqueue_data = read_payload_from_buffer(buffer, offset, format)?;
+            offset = enqueue_data.new_offset;
+            Ok(ReadResult {
+                value: Packet::ClientEnqueueAllExceptGroup {
+                    group_id,
+                    enqueue_data: enqueue_data.value,
+                },
+                new_offset: offset,
+            })
+        }
+        _ => Err(PacketReadError::Invalid(format!("Unknown packet ID: {}", packet_id)).into()),
     }
 }
 
-pub fn serialise_packets(packets: &[Packet]) -> Bytes {
-    let mut buffer = Bytes::new();
+pub fn serialise_packets(packets: &[Packet]) -> anyhow::Result<Vec<u8>> {
+    serialise_packets_with_format(packets, WireFormat::default())
+}
+
+pub fn serialise_packets_with_format(
+    packets: &[Packet],
+    format: WireFormat,
+) -> anyhow::Result<Vec<u8>> {
+    let mut buffer = Vec::new();
     for packet in packets {
-        write_packet_into_buffer(&mut buffer, packet);
+        write_packet_into_buffer_with_format(&mut buffer, packet, format)?;
     }
-    buffer
+    Ok(buffer)
 }
 
-pub fn deserialise_packets(buffer: &Bytes) -> anyhow::Result<Vec<Packet>> {
-    let mut packets = Vec::new();
-    let mut offset = 0;
-
-    while offset < buffer.len() {
-        // Check if we have at least 4 bytes for packet_id
-        if buffer.len() - offset < 4 {
-            break; // Not enough data for packet_id
-        }
+/// The total size of the packet at the start of the buffer, including its framing, as declared
+/// by its length prefix. `None` until the length prefix has been received.
+pub fn declared_frame_length(buffer: &[u8], format: WireFormat) -> Option<usize> {
+    let packet_length = u32::from_le_bytes(buffer.get(..4)?.try_into().ok()?) as usize;
+    let checksum_length = if format.checksums { 4 } else { 0 };
+    Some(4 + packet_length + checksum_length)
+}
 
-        match read_packet_from_buffer(buffer, offset) {
-            Ok(result) => {
-                packets.push(result.value);
-                offset = result.new_offset;
-            }
-            Err(_) => {
-                // Partial packet, stop parsing
-                break;
-            }
-        }
-    }
+fn is_incomplete(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<PacketReadError>(),
+        Some(PacketReadError::Incomplete)
+    )
+}
 
+/// Parses every complete packet in the buffer, ignoring a trailing partial one. Fails if the
+/// buffer contains an invalid packet.
+pub fn deserialise_packets(buffer: &[u8]) -> anyhow::Result<Vec<Packet>> {
+    let (packets, _) = deserialise_packets_with_offset(buffer)?;
     Ok(packets)
 }
 
-pub fn deserialise_packets_with_offset(buffer: &Bytes) -> anyhow::Result<(Vec<Packet>, usize)> {
+/// Like `deserialise_packets`, but also returns how many bytes the complete packets took up, so
+/// the caller can keep the rest until more data arrives.
+pub fn deserialise_packets_with_offset(buffer: &[u8]) -> anyhow::Result<(Vec<Packet>, usize)> {
+    deserialise_packets_with_format(buffer, WireFormat::default())
+}
+
+/// Parses packets framed with `format`. Parsing stops after a `ClientNegotiateFeatures`, as the
+/// packets following it may be framed differently.
+pub fn deserialise_packets_with_format(
+    buffer: &[u8],
+    format: WireFormat,
+) -> anyhow::Result<(Vec<Packet>, usize)> {
     let mut packets = Vec::new();
     let mut offset = 0;
 
     while offset < buffer.len() {
-        // Check if we have at least 4 bytes for packet_id
+        // Check if we have at least 4 bytes for the length prefix
         if buffer.len() - offset < 4 {
-            break; // Not enough data for packet_id
+            break; // Not enough data for the length prefix
         }
 
-        match read_packet_from_buffer(buffer, offset) {
+        match read_packet_from_buffer_with_format(buffer, offset, format) {
             Ok(result) => {
+                let changes_format = matches!(result.value, Packet::ClientNegotiateFeatures { .. });
                 packets.push(result.value);
                 offset = result.new_offset;
+                if changes_format {
+                    break;
+                }
             }
-            Err(_) => {
-                // Partial packet, stop parsing
+            Err(e) if is_incomplete(&e) => {
+                // Partial packet, stop parsing and wait for the rest
                 break;
             }
+            Err(e) => return Err(e),
         }
     }
 
@@ -0,0 +1,1639 @@
+use crate::metrics::{METRICS, run_metrics_server};
+use crate::rate_limit::RateLimiter;
+use crate::serialisation::{
+    Bytes, ERROR_INVALID_PACKET, ERROR_NOT_AUTHENTICATED, ERROR_PAYLOAD_TOO_LARGE,
+    MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, Packet, RequestError, SUPPORTED_FEATURES, WireFormat,
+    declared_frame_length, deserialise_packets_with_format, serialise_packets,
+    write_packet_into_buffer_with_format,
+};
+use crate::settings::{ConnectionMode, MissingStreamPolicy, Settings};
+use crate::state::{EnqueueOutcome, MoveOutcome, ServerState, Stream};
+use crate::text_protocol::{TEXT_PROTOCOL_MAGIC, handle_text_connection};
+use crate::tls::{self, TlsAcceptor};
+use crate::utils;
+use anyhow::Context;
+use socket2::{SockRef, TcpKeepalive};
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::{Mutex, Semaphore, mpsc, watch};
+use tokio::task::JoinSet;
+use tokio::time::{Duration, Instant, interval, sleep, timeout, timeout_at};
+use tracing::{Instrument, debug, error, info, info_span, warn};
+
+/// How long a client turned away with `ServerBusy` is asked to wait before retrying.
+const BUSY_RETRY_AFTER_MS: u32 = 1000;
+/// How many TCP connections over the limit can be sent `ServerBusy` at once. Over TLS each reply
+/// waits on a handshake, so past this they're closed without one rather than each getting a task.
+const MAX_BUSY_REPLIES: usize = 16;
+/// How long open connections are given to finish once shutdown starts.
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(10);
+/// The most stream IDs returned in a single `ServerStreamList` page.
+const MAX_LISTED_STREAMS: u32 = 4096;
+/// How many pushes can be queued for a subscribed connection before it's considered stalled and
+/// unsubscribed.
+const PUSH_QUEUE_SIZE: usize = 256;
+/// How many batches of responses a connection's reader can queue before waiting on its writer.
+const RESPONSE_QUEUE_SIZE: usize = 16;
+
+/// Identifies connections in logs, unique across every listener.
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Who a connection is, as logged by the server and reported to the client through
+/// `ClientWhoAmI`, so the two sides' logs can be correlated.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionInfo {
+    /// Unique across every listener, and 0 for packets handled outside a connection.
+    pub id: u64,
+    /// The remote address, or "unix" for UNIX socket connections.
+    pub peer: String,
+}
+
+impl ConnectionInfo {
+    /// Gives the connection the next unused ID.
+    pub fn new(peer: impl Into<String>) -> Self {
+        Self {
+            id: NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed),
+            peer: peer.into(),
+        }
+    }
+}
+
+// Only used when at least one optional packet group is compiled out.
+#[cfg_attr(feature = "admin", allow(dead_code))]
+fn unsupported_packet(feature: &str) -> anyhow::Error {
+    RequestError::new(
+        ERROR_INVALID_PACKET,
+        format!(
+            "Unsupported packet: the server was built without the {} feature",
+            feature
+        ),
+    )
+    .into()
+}
+
+/// State kept for the lifetime of a single connection.
+pub struct ConnectionState {
+    /// The protocol version negotiated through `ClientHello`.
+    pub protocol_version: u32,
+    /// Set once the connection should be closed after the pending responses are written.
+    pub closing: bool,
+    /// A `ClientAwaitStreamContents` waiting on an empty stream, as (stream ID, timeout).
+    pub awaiting_contents: Option<(u32, Duration)>,
+    /// The framing negotiated through `ClientNegotiateFeatures`.
+    pub wire_format: WireFormat,
+    /// Whether the client has sent the admin token, allowing privileged packets.
+    pub authenticated: bool,
+    pub info: ConnectionInfo,
+    /// Where pushes for subscribed streams are sent. Only connections have one, so subscribing
+    /// fails without it.
+    pub push_sender: Option<mpsc::Sender<Packet>>,
+    /// Streams deleted by this connection, kept until the state lock is released so freeing
+    /// their buffers doesn't hold up every other connection.
+    pub released_streams: Vec<Stream>,
+    /// The token of the session this connection holds, released when it closes.
+    pub session_token: Option<Bytes>,
+}
+
+impl Default for ConnectionState {
+    fn default() -> Self {
+        Self {
+            protocol_version: MIN_PROTOCOL_VERSION,
+            closing: false,
+            awaiting_contents: None,
+            wire_format: WireFormat::default(),
+            authenticated: false,
+            push_sender: None,
+            info: ConnectionInfo::default(),
+            released_streams: Vec::new(),
+            session_token: None,
+        }
+    }
+}
+
+fn push_rejections(outcome: &EnqueueOutcome, responses: &mut Vec<Packet>) {
+    for stream_id in &outcome.rejected_stream_ids {
+        responses.push(Packet::ServerEnqueueRejected {
+            stream_id: *stream_id,
+        });
+    }
+    for stream_id in &outcome.missing_stream_ids {
+        responses.push(Packet::ServerEnqueueError {
+            stream_id: *stream_id,
+        });
+    }
+}
+
+pub fn handle_client_packets(
+    state: &mut ServerState,
+    packets: Vec<Packet>,
+) -> anyhow::Result<Vec<Packet>> {
+    let mut connection = ConnectionState::default();
+    let mut responses = Vec::new();
+
+    let mut enqueue_runs = enqueue_runs(&packets);
+    for (index, packet) in packets.into_iter().enumerate() {
+        reserve_enqueue_run(state, &mut enqueue_runs, index);
+        handle_client_packet(state, &mut connection, packet, &mut responses)?;
+        // Waiting needs the connection loop, so a parked await is answered as if it timed out.
+        if let Some((stream_id, _)) = connection.awaiting_contents.take() {
+            let buffer_data = state.fetch_stream_contents(stream_id).unwrap_or_default();
+            responses.push(Packet::ServerStreamContents { buffer_data });
+        }
+    }
+
+    if let Some(token) = connection.session_token.take() {
+        state.release_session(token, connection.authenticated, None);
+    }
+
+    Ok(responses)
+}
+
+/// Finds each run of consecutive `ClientEnqueueSingle`s to the same stream, as (index of its
+/// first packet, stream ID, total bytes).
+fn enqueue_runs(packets: &[Packet]) -> VecDeque<(usize, u32, usize)> {
+    let mut runs = VecDeque::new();
+    let mut start = 0;
+    for run in packets.chunk_by(|previous, packet| match (previous, packet) {
+        (
+            Packet::ClientEnqueueSingle { stream_id, .. },
+            Packet::ClientEnqueueSingle {
+                stream_id: next_stream_id,
+                ..
+            },
+        ) => stream_id == next_stream_id,
+        _ => false,
+    }) {
+        if let [Packet::ClientEnqueueSingle { stream_id, .. }, _, ..] = run {
+            let run_bytes = run
+                .iter()
+                .map(|packet| match packet {
+                    Packet::ClientEnqueueSingle { enqueue_data, .. } => enqueue_data.len(),
+                    _ => 0,
+                })
+                .sum();
+            runs.push_back((start, *stream_id, run_bytes));
+        }
+        start += run.len();
+    }
+    runs
+}
+
+/// Reserves room for the run of enqueues starting at `index`, if there is one, so its data is
+/// appended without growing the buffer again and again. Each enqueue is still handled on its
+/// own, so limits, rejections and pushes apply exactly as before. Reserving only once the run is
+/// reached means a stream created just before it is covered too.
+fn reserve_enqueue_run(
+    state: &mut ServerState,
+    runs: &mut VecDeque<(usize, u32, usize)>,
+    index: usize,
+) {
+    if let Some(&(start, stream_id, run_bytes)) = runs.front()
+        && start == index
+    {
+        runs.pop_front();
+        state.reserve_stream_capacity(stream_id, run_bytes);
+    }
+}
+
+/// Handles a single packet. A `RequestError` is answered with a `ServerError` and leaves the
+/// connection open, while any other error is returned so the connection is closed.
+pub(crate) fn handle_client_packet(
+    state: &mut ServerState,
+    connection: &mut ConnectionState,
+    packet: Packet,
+    responses: &mut Vec<Packet>,
+) -> anyhow::Result<()> {
+    let packet_id = packet.packet_id();
+    if let Err(e) = handle_request(state, connection, packet, responses) {
+        let request_error = e.downcast::<RequestError>()?;
+        debug!(packet_id, error = %request_error, "Request failed");
+        responses.push(request_error.to_packet());
+    }
+
+    if state.is_over_memory_soft_limit() {
+        let evicted_streams = state.remove_streams_over_memory_limit();
+        METRICS.record_eviction(evicted_streams.len());
+        connection.released_streams.extend(evicted_streams);
+    }
+    Ok(())
+}
+
+/// Packets that destroy data, change server-wide behaviour, enumerate every stream or scan the
+/// whole state under the lock, which only clients holding the admin token may send when one is
+/// configured.
+fn is_privileged(packet: &Packet) -> bool {
+    matches!(
+        packet,
+        Packet::ClientDeleteStream { .. }
+            | Packet::ClientDeleteMultipleStreams { .. }
+            | Packet::ClientClearStream { .. }
+            | Packet::ClientListStreams { .. }
+            | Packet::ClientListStreamsByActivity { .. }
+            | Packet::ClientSetGlobalExpiry { .. }
+            | Packet::ClientSelfCheck
+            | Packet::ClientFlushAll
+            | Packet::ClientDeleteAll
+    )
+}
+
+fn handle_request(
+    state: &mut ServerState,
+    connection: &mut ConnectionState,
+    packet: Packet,
+    responses: &mut Vec<Packet>,
+) -> anyhow::Result<()> {
+    if is_privileged(&packet) && state.requires_authentication() && !connection.authenticated {
+        return Err(RequestError::new(
+            ERROR_NOT_AUTHENTICATED,
+            "This packet requires authenticating with ClientAuthenticate first",
+        )
+        .into());
+    }
+
+    match packet {
+        Packet::ClientPing { nonce } => {
+            responses.push(Packet::ServerPong { nonce });
+        }
+        Packet::ClientHello { protocol_version } => {
+            let accepted = (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&protocol_version);
+            if accepted {
+                connection.protocol_version = protocol_version;
+            } else {
+                connection.closing = true;
+            }
+            responses.push(Packet::ServerHello {
+                protocol_version: PROTOCOL_VERSION,
+                accepted,
+            });
+        }
+        Packet::ClientWhoAmI => {
+            responses.push(Packet::ServerConnectionInfo {
+                connection_id: connection.info.id,
+                peer: connection.info.peer.clone().into(),
+            });
+        }
+        Packet::ClientHealthCheck => {
+            responses.push(Packet::ServerHealth {
+                status: state.health_status(METRICS.active_connections()),
+            });
+        }
+        Packet::ClientAuthenticate { token } => {
+            let success = state.check_admin_token(&token);
+            connection.authenticated = success;
+            responses.push(Packet::ServerAuthResult { success });
+        }
+        Packet::ClientOpenSession => {
+            let token = match &connection.session_token {
+                Some(token) => token.clone(),
+                None => state.open_session()?,
+            };
+            connection.session_token = Some(token.clone());
+            responses.push(Packet::ServerSessionOpened { token });
+        }
+        Packet::ClientResumeSession { token } => {
+            // Handing back the session already held lets the connection resume it straight away.
+            if let Some(held_token) = connection.session_token.take() {
+                state.release_session(
+                    held_token,
+                    connection.authenticated,
+                    connection.push_sender.as_ref(),
+                );
+            }
+
+            let Some(session) = state.resume_session(&token) else {
+                responses.push(Packet::ServerSessionResumed {
+                    resumed: false,
+                    stream_ids: Vec::new(),
+                });
+                return Ok(());
+            };
+            connection.authenticated |= session.authenticated;
+            // Streams deleted while the session waited are left out.
+            let stream_ids = match &connection.push_sender {
+                Some(push_sender) => session
+                    .stream_ids
+                    .into_iter()
+                    .filter(|stream_id| state.subscribe(*stream_id, push_sender.clone()).is_ok())
+                    .collect(),
+                None => Vec::new(),
+            };
+            connection.session_token = Some(token);
+            responses.push(Packet::ServerSessionResumed {
+                resumed: true,
+                stream_ids,
+            });
+        }
+        Packet::ClientNegotiateFeatures { features } => {
+            // Unknown bits are left out of the reply, so clients can tell what was enabled.
+            let features = features & SUPPORTED_FEATURES;
+            connection.wire_format = WireFormat {
+                max_payload_size: connection.wire_format.max_payload_size,
+                ..WireFormat::from_features(features)
+            };
+            responses.push(Packet::ServerFeatures { features });
+        }
+        Packet::ClientCreateNewStream { stream_id } => {
+            let created = state.create_new_stream(stream_id)?;
+            responses.push(Packet::ServerStreamCreated { stream_id, created });
+        }
+        Packet::ClientCreateNewStreamWithTtl {
+            stream_id,
+            ttl_secs,
+        } => {
+            let created = state.create_new_stream_with_ttl(stream_id, ttl_secs)?;
+            responses.push(Packet::ServerStreamCreated { stream_id, created });
+        }
+        Packet::ClientCreateNewStreamSized {
+            stream_id,
+            initial_capacity,
+        } => {
+            let created = state.create_new_stream_sized(stream_id, initial_capacity as usize)?;
+            responses.push(Packet::ServerStreamCreated { stream_id, created });
+        }
+        Packet::ClientCreateNewStreamNamed { name } => {
+            let stream_id = state.create_named_stream(&name)?;
+            responses.push(Packet::ServerNamedStream {
+                stream_id,
+                exists: true,
+            });
+        }
+        Packet::ClientLookupStreamName { name } => {
+            let stream_id = state.named_stream_id(&name);
+            responses.push(Packet::ServerNamedStream {
+                stream_id: stream_id.unwrap_or(0),
+                exists: stream_id.is_some(),
+            });
+        }
+        Packet::ClientCreateMultipleStreams { stream_ids } => {
+            let existing_stream_ids = state.create_multiple_streams(&stream_ids)?;
+            responses.push(Packet::ServerStreamsCreated {
+                existing_stream_ids,
+            });
+        }
+        Packet::ClientClearStream { stream_id } => {
+            state.clear_stream(stream_id);
+        }
+        Packet::ClientMoveStreamContents { source_id, dest_id } => {
+            match state.move_stream_contents(source_id, dest_id) {
+                MoveOutcome::Moved { .. } => {}
+                MoveOutcome::Rejected => {
+                    responses.push(Packet::ServerEnqueueRejected { stream_id: dest_id });
+                }
+                MoveOutcome::MissingStream(stream_id) => {
+                    return Err(RequestError::unknown_stream(stream_id).into());
+                }
+            }
+        }
+        Packet::ClientRenameStream { old_id, new_id } => {
+            state.rename_stream(old_id, new_id)?;
+        }
+        Packet::ClientCreateGroup { group_id } => {
+            let created = state.create_group(group_id);
+            responses.push(Packet::ServerGroupCreated { group_id, created });
+        }
+        Packet::ClientDeleteGroup { group_id } => {
+            state.delete_group(group_id);
+        }
+        Packet::ClientAddToGroup {
+            group_id,
+            stream_id,
+        } => {
+            state.add_to_group(group_id, stream_id)?;
+        }
+        Packet::ClientRemoveFromGroup {
+            group_id,
+            stream_id,
+        } => {
+            state.remove_from_group(group_id, stream_id)?;
+        }
+        Packet::ClientSubscribe { stream_id } => {
+            let Some(push_sender) = &connection.push_sender else {
+                return Err(RequestError::new(
+                    ERROR_INVALID_PACKET,
+                    "Subscribing needs a connection to push to",
+                )
+                .into());
+            };
+            state.subscribe(stream_id, push_sender.clone())?;
+        }
+        Packet::ClientFlushStream { stream_id } => {
+            state.flush_stream(stream_id);
+        }
+        Packet::ClientUnsubscribe { stream_id } => {
+            if let Some(push_sender) = &connection.push_sender {
+                state.unsubscribe(stream_id, push_sender);
+            }
+        }
+        Packet::ClientDeleteStream { stream_id } => {
+            connection
+                .released_streams
+                .extend(state.remove_stream(stream_id));
+        }
+        Packet::ClientDeleteMultipleStreams { stream_ids } => {
+            let removed_count = connection.released_streams.len();
+            for stream_id in stream_ids {
+                connection
+                    .released_streams
+                    .extend(state.remove_stream(stream_id));
+            }
+            responses.push(Packet::ServerStreamsDeleted {
+                count: u32::try_from(connection.released_streams.len() - removed_count)
+                    .unwrap_or(u32::MAX),
+            });
+        }
+        Packet::ClientEnqueueSingle {
+            stream_id,
+            enqueue_data,
+        } => {
+            let outcome = state.enqueue_single(stream_id, &enqueue_data)?;
+            push_rejections(&outcome, responses);
+        }
+        Packet::ClientEnqueueSingleAck {
+            stream_id,
+            enqueue_data,
+        } => {
+            let outcome = state.enqueue_single(stream_id, &enqueue_data)?;
+            push_rejections(&outcome, responses);
+            let new_length = state
+                .get_stream(stream_id)
+                .map_or(0, |stream| stream.buffer.len());
+            responses.push(Packet::ServerEnqueueAck {
+                stream_id,
+                accepted: outcome.stream_count == 1,
+                new_length: u32::try_from(new_length).unwrap_or(u32::MAX),
+            });
+        }
+        Packet::ClientPrependSingle {
+            stream_id,
+            enqueue_data,
+        } => {
+            let outcome = state.prepend_single(stream_id, &enqueue_data)?;
+            push_rejections(&outcome, responses);
+        }
+        Packet::ClientEnqueueSeq {
+            stream_id,
+            seq,
+            enqueue_data,
+        } => {
+            if state.missing_stream_policy() == MissingStreamPolicy::Error
+                && !state.stream_exists(stream_id)
+            {
+                responses.push(Packet::ServerEnqueueError { stream_id });
+            }
+            let applied = state.enqueue_seq(stream_id, seq, &enqueue_data);
+            responses.push(Packet::ServerEnqueueSeqResult {
+                stream_id,
+                seq,
+                applied,
+            });
+        }
+        Packet::ClientEnqueueMultiple {
+            enqueue_data,
+            filter_stream_ids,
+        } => {
+            let outcome = state.enqueue_multiple(&filter_stream_ids, &enqueue_data)?;
+            push_rejections(&outcome, responses);
+        }
+        Packet::ClientEnqueueMultipleExcept {
+            enqueue_data,
+            include_ids,
+            exclude_ids,
+        } => {
+            let outcome =
+                state.enqueue_multiple_except(&include_ids, &exclude_ids, &enqueue_data)?;
+            push_rejections(&outcome, responses);
+        }
+        Packet::ClientEnqueueWhere {
+            enqueue_data,
+            predicate,
+        } => {
+            let outcome =
+                state.enqueue_matching(|stream_id| predicate.matches(stream_id), &enqueue_data)?;
+            push_rejections(&outcome, responses);
+        }
+        Packet::ClientEnqueueBatch { entries } => {
+            let outcome = state.enqueue_batch(&entries)?;
+            push_rejections(&outcome, responses);
+        }
+        Packet::ClientEnqueueAll { enqueue_data } => {
+            let outcome = state.enqueue_all(&enqueue_data)?;
+            push_rejections(&outcome, responses);
+        }
+        Packet::ClientEnqueueAllExcept {
+            enqueue_data,
+            filter_stream_ids,
+        } => {
+            let outcome = state.enqueue_all_except(&filter_stream_ids, &enqueue_data)?;
+            push_rejections(&outcome, responses);
+        }
+        Packet::ClientEnqueueAllExceptGroup {
+            group_id,
+            enqueue_data,
+        } => {
+            let outcome = state.enqueue_all_except_group(group_id, &enqueue_data)?;
+            push_rejections(&outcome, responses);
+        }
+        Packet::ClientEnqueueAllAck { enqueue_data } => {
+            let outcome = state.enqueue_all(&enqueue_data)?;
+            push_rejections(&outcome, responses);
+            responses.push(Packet::ServerEnqueueAllAck {
+                stream_count: u32::try_from(outcome.stream_count).unwrap_or(u32::MAX),
+            });
+        }
+        Packet::ClientEnqueueAllExceptAck {
+            enqueue_data,
+            filter_stream_ids,
+        } => {
+            let outcome = state.enqueue_all_except(&filter_stream_ids, &enqueue_data)?;
+            push_rejections(&outcome, responses);
+            responses.push(Packet::ServerEnqueueAllAck {
+                stream_count: u32::try_from(outcome.stream_count).unwrap_or(u32::MAX),
+            });
+        }
+        Packet::ClientRequestStreamContents { stream_id } => {
+            let buffer_data = state.fetch_stream_contents(stream_id).unwrap_or_default();
+            responses.push(Packet::ServerStreamContents { buffer_data });
+        }
+        Packet::ClientAwaitStreamContents {
+            stream_id,
+            timeout_ms,
+        } => {
+            // Answered straight away unless there is an empty stream to wait on.
+            let must_wait = timeout_ms > 0
+                && state
+                    .get_stream(stream_id)
+                    .is_some_and(|stream| stream.buffer.is_empty());
+            if must_wait {
+                connection.awaiting_contents =
+                    Some((stream_id, Duration::from_millis(timeout_ms.into())));
+            } else {
+                let buffer_data = state.fetch_stream_contents(stream_id).unwrap_or_default();
+                responses.push(Packet::ServerStreamContents { buffer_data });
+            }
+        }
+        Packet::ClientRequestStreamContentsWithMeta { stream_id } => {
+            let contents = state.fetch_stream_contents(stream_id);
+            let existed = contents.is_some();
+            let buffer_data = contents.unwrap_or_default();
+            responses.push(Packet::ServerStreamContentsMeta {
+                stream_id,
+                byte_count: u32::try_from(buffer_data.len()).unwrap_or(u32::MAX),
+                existed,
+            });
+            responses.push(Packet::ServerStreamContents { buffer_data });
+        }
+        Packet::ClientRequestStreamContentsNoClear { stream_id } => {
+            let buffer_data = state.fetch_stream_no_clear(stream_id).unwrap_or_default();
+            responses.push(Packet::ServerStreamContents { buffer_data });
+        }
+        Packet::ClientPeekStreamContents {
+            stream_id,
+            max_bytes,
+        } => {
+            let buffer_data = state
+                .peek_stream(stream_id, max_bytes as usize)
+                .unwrap_or_default();
+            responses.push(Packet::ServerStreamContents { buffer_data });
+        }
+        Packet::ClientDrainPrefix {
+            stream_id,
+            byte_count,
+        } => {
+            let buffer_data = state
+                .drain_prefix(stream_id, byte_count as usize)
+                .unwrap_or_default();
+            responses.push(Packet::ServerStreamContents { buffer_data });
+        }
+        Packet::ClientReadRange {
+            stream_id,
+            start,
+            end,
+        } => {
+            let buffer_data = state
+                .read_stream_range(stream_id, start as usize, end as usize)
+                .unwrap_or_default();
+            responses.push(Packet::ServerStreamContents { buffer_data });
+        }
+        Packet::ClientRequestStreamContentsRange {
+            stream_id,
+            start,
+            length,
+        } => {
+            // A peek, like ClientReadRange, so clients can page through a stream in windows.
+            let start = start as usize;
+            let buffer_data = state
+                .read_stream_range(stream_id, start, start.saturating_add(length as usize))
+                .unwrap_or_default();
+            responses.push(Packet::ServerStreamContents { buffer_data });
+        }
+        Packet::ClientRegisterConsumer {
+            stream_id,
+            consumer_id,
+        } => {
+            state.register_consumer(stream_id, consumer_id);
+        }
+        Packet::ClientUnregisterConsumer {
+            stream_id,
+            consumer_id,
+        } => {
+            state.unregister_consumer(stream_id, consumer_id);
+        }
+        Packet::ClientFetchFromCursor {
+            stream_id,
+            consumer_id,
+            max_bytes,
+        } => {
+            let buffer_data = state
+                .fetch_from_cursor(stream_id, consumer_id, max_bytes as usize)
+                .unwrap_or_default();
+            responses.push(Packet::ServerStreamContents { buffer_data });
+        }
+        Packet::ClientAdvanceCursor {
+            stream_id,
+            consumer_id,
+            byte_count,
+        } => {
+            state.advance_cursor(stream_id, consumer_id, byte_count as usize);
+        }
+        Packet::ClientStreamLength { stream_id } => {
+            let length = state
+                .get_stream(stream_id)
+                .map(|stream| stream.buffer.len());
+            responses.push(Packet::ServerStreamLength {
+                stream_id,
+                length: length.map_or(0, |length| u32::try_from(length).unwrap_or(u32::MAX)),
+                exists: length.is_some(),
+            });
+        }
+        Packet::ClientStreamInfo { stream_id } => {
+            let current_timestamp = utils::get_current_timestamp();
+            let response = match state.get_stream(stream_id) {
+                Some(stream) => Packet::ServerStreamInfo {
+                    stream_id,
+                    exists: true,
+                    length: u32::try_from(stream.buffer.len()).unwrap_or(u32::MAX),
+                    // A clock stepping backwards would otherwise make this underflow.
+                    idle_secs: u32::try_from(
+                        current_timestamp.saturating_sub(stream.last_activity),
+                    )
+                    .unwrap_or(u32::MAX),
+                },
+                None => Packet::ServerStreamInfo {
+                    stream_id,
+                    exists: false,
+                    length: 0,
+                    idle_secs: 0,
+                },
+            };
+            responses.push(response);
+        }
+        Packet::ClientGetTotalBytes => {
+            responses.push(Packet::ServerTotalBytes {
+                total_bytes: state.total_bytes() as u64,
+                stream_count: u32::try_from(state.stream_count()).unwrap_or(u32::MAX),
+            });
+        }
+        Packet::ClientRequestStats => {
+            responses.push(Packet::ServerStats {
+                stream_count: u32::try_from(state.stream_count()).unwrap_or(u32::MAX),
+                total_buffered_bytes: state.total_bytes() as u64,
+                uptime_secs: state.uptime().as_secs(),
+            });
+        }
+        Packet::ClientGoodbye => {
+            // Every preceding packet has been handled by now; the connection handler closes
+            // the connection once this is written.
+            connection.closing = true;
+            responses.push(Packet::ServerGoodbye);
+        }
+        // Read-only queries (see `Packet::is_read_only_query`) don't count as activity.
+        Packet::ClientCheckStreamState { stream_id } => {
+            let is_valid = state.stream_exists(stream_id);
+            responses.push(Packet::ServerStreamState {
+                stream_id,
+                is_valid,
+            });
+        }
+        Packet::ClientCheckStreamStateMultiple { stream_ids } => {
+            let results = stream_ids
+                .into_iter()
+                .map(|stream_id| (stream_id, state.stream_exists(stream_id)))
+                .collect();
+            responses.push(Packet::ServerStreamStateMultiple { results });
+        }
+        Packet::ClientTouchStream { stream_id } => {
+            let is_valid = state.touch_stream(stream_id);
+            responses.push(Packet::ServerStreamState {
+                stream_id,
+                is_valid,
+            });
+        }
+        Packet::ClientListStreams { offset, limit } => {
+            let stream_ids =
+                state.list_stream_ids(offset as usize, limit.min(MAX_LISTED_STREAMS) as usize);
+            responses.push(Packet::ServerStreamList {
+                total_count: u32::try_from(state.stream_count()).unwrap_or(u32::MAX),
+                stream_ids,
+            });
+        }
+        #[cfg(feature = "admin")]
+        Packet::ClientListStreamsByActivity { limit, descending } => {
+            let entries = state
+                .list_streams_by_activity(limit as usize, descending)
+                .into_iter()
+                .map(|(stream_id, idle_secs)| {
+                    (stream_id, u32::try_from(idle_secs).unwrap_or(u32::MAX))
+                })
+                .collect();
+            responses.push(Packet::ServerStreamActivityList { entries });
+        }
+        #[cfg(feature = "admin")]
+        Packet::ClientSetGlobalExpiry { seconds } => {
+            state.set_key_expiry(Duration::from_secs(seconds.into()));
+        }
+        #[cfg(feature = "admin")]
+        Packet::ClientSelfCheck => {
+            responses.push(Packet::ServerSelfCheckReport {
+                issues: state.self_check(),
+            });
+        }
+        #[cfg(feature = "admin")]
+        Packet::ClientFlushAll => {
+            let streams_affected = u32::try_from(state.flush_all()).unwrap_or(u32::MAX);
+            responses.push(Packet::ServerFlushResult { streams_affected });
+        }
+        #[cfg(feature = "admin")]
+        Packet::ClientDeleteAll => {
+            let removed_streams = state.remove_all_streams();
+            let streams_affected = u32::try_from(removed_streams.len()).unwrap_or(u32::MAX);
+            connection
+                .released_streams
+                .extend(removed_streams.into_values());
+            responses.push(Packet::ServerFlushResult { streams_affected });
+        }
+        Packet::ClientSetStreamMetadata {
+            stream_id,
+            metadata,
+        } => {
+            state.set_stream_metadata(stream_id, metadata)?;
+        }
+        Packet::ClientGetStreamMetadata { stream_id } => {
+            let metadata = state
+                .get_stream_metadata(stream_id)
+                .cloned()
+                .unwrap_or_default();
+            responses.push(Packet::ServerStreamMetadata {
+                stream_id,
+                metadata,
+            });
+        }
+        #[cfg(not(feature = "admin"))]
+        Packet::ClientListStreamsByActivity { .. }
+        | Packet::ClientSetGlobalExpiry { .. }
+        | Packet::ClientSelfCheck
+        | Packet::ClientFlushAll
+        | Packet::ClientDeleteAll => {
+            return Err(unsupported_packet("admin"));
+        }
+        _ => {
+            return Err(RequestError::new(
+                ERROR_INVALID_PACKET,
+                "Received server packet from client",
+            )
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Responses for the writer to send, with the framing to send them in.
+type QueuedResponses = (Vec<Packet>, WireFormat);
+
+pub async fn handle_connection<S>(
+    mut stream: S,
+    state: Arc<Mutex<ServerState>>,
+    settings: &Settings,
+    info: ConnectionInfo,
+) -> anyhow::Result<()>
+where
+    S: AsyncReadExt + AsyncWriteExt + Unpin,
+{
+    let mut read_buffer = Vec::with_capacity(settings.read_chunk_size);
+    let _connection_guard = METRICS.track_connection();
+    // Reused for every read so it is only allocated and zeroed once per connection.
+    let mut temp_buffer = vec![0u8; settings.read_chunk_size];
+
+    // Only the start of the connection can switch it to the text protocol, so it is checked
+    // before the connection is split into its reader and writer.
+    if settings.text_protocol {
+        while read_buffer.len() < TEXT_PROTOCOL_MAGIC.len()
+            && TEXT_PROTOCOL_MAGIC.starts_with(&read_buffer)
+        {
+            match read_with_idle_timeout(&mut stream, &mut temp_buffer, settings).await {
+                Some(Ok(0)) | None => return Ok(()),
+                Some(Ok(n)) => read_buffer.extend_from_slice(&temp_buffer[..n]),
+                Some(Err(e)) => {
+                    warn!(error = %e, "Error reading from stream");
+                    return Ok(());
+                }
+            }
+        }
+        if read_buffer.starts_with(TEXT_PROTOCOL_MAGIC) {
+            let pending = read_buffer.split_off(TEXT_PROTOCOL_MAGIC.len());
+            return handle_text_connection(stream, state, settings, info, pending).await;
+        }
+    }
+
+    // The writer runs alongside the reader, so a slow write doesn't hold up parsing and
+    // handling. The queue between them is bounded, which means a client that stops reading
+    // still stalls its own requests rather than growing it.
+    let (reader, writer) = tokio::io::split(stream);
+    let (response_sender, response_receiver) = mpsc::channel(RESPONSE_QUEUE_SIZE);
+    // Owned here rather than by the reader, so its session is released however the connection
+    // ends. The push receiver is kept open until then too, so its subscriptions aren't pruned
+    // before the session records them.
+    let (push_sender, mut push_receiver) = mpsc::channel(PUSH_QUEUE_SIZE);
+    let mut connection = ConnectionState {
+        info,
+        wire_format: WireFormat {
+            max_payload_size: settings.payload_limit(),
+            ..WireFormat::default()
+        },
+        push_sender: Some(push_sender),
+        ..ConnectionState::default()
+    };
+    let reader = read_requests(
+        reader,
+        state.clone(),
+        settings,
+        &mut connection,
+        &mut push_receiver,
+        read_buffer,
+        temp_buffer,
+        response_sender,
+    );
+    let writer = write_responses(writer, response_receiver, settings);
+    tokio::pin!(writer);
+
+    let result = tokio::select! {
+        // The reader drops its end of the queue when it finishes, so the writer then sends
+        // whatever is left and closes the connection.
+        result = reader => {
+            let write_result = writer.await;
+            result.and(write_result)
+        }
+        // Only a failed write ends the writer first, leaving nowhere to send responses to.
+        result = &mut writer => result,
+    };
+
+    if let Some(token) = connection.session_token.take() {
+        state.lock().await.release_session(
+            token,
+            connection.authenticated,
+            connection.push_sender.as_ref(),
+        );
+    }
+    result
+}
+
+/// Reads and handles packets, queueing their responses and any pushes for the writer, until the
+/// connection closes.
+#[allow(clippy::too_many_arguments)]
+async fn read_requests<R>(
+    mut reader: R,
+    state: Arc<Mutex<ServerState>>,
+    settings: &Settings,
+    connection: &mut ConnectionState,
+    push_receiver: &mut mpsc::Receiver<Packet>,
+    mut read_buffer: Vec<u8>,
+    mut temp_buffer: Vec<u8>,
+    response_sender: mpsc::Sender<QueuedResponses>,
+) -> anyhow::Result<()>
+where
+    R: AsyncReadExt + Unpin,
+{
+    // Start of the data in read_buffer that hasn't been parsed yet.
+    let mut read_offset = 0;
+    // Packets handled since this connection last let other tasks run.
+    let mut packets_since_yield = 0;
+    let mut rate_limiter = RateLimiter::new(settings.rate_limit);
+    let queue = |responses, format| queue_responses(&response_sender, responses, format);
+
+    // Bytes sniffed for the text protocol are handled before reading any more.
+    let mut pending_read = !read_buffer.is_empty();
+
+    loop {
+        if !pending_read {
+            // The idle timeout restarts with every read or push, so only a connection that has
+            // gone silent is closed, however long it has been open.
+            let read_result = tokio::select! {
+                read_result = read_with_idle_timeout(&mut reader, &mut temp_buffer, settings) => {
+                    match read_result {
+                        Some(result) => result,
+                        None => {
+                            debug!("Closing idle connection");
+                            break;
+                        }
+                    }
+                }
+                // The connection holds a sender itself, so the channel never closes.
+                Some(push) = push_receiver.recv() => {
+                    let mut pushes = vec![push];
+                    while let Ok(push) = push_receiver.try_recv() {
+                        pushes.push(push);
+                    }
+                    queue(pushes, connection.wire_format).await?;
+                    continue;
+                }
+            };
+            let bytes_read = match read_result {
+                Ok(0) => break, // Connection closed
+                Ok(n) => n,
+                Err(e) => {
+                    warn!(error = %e, "Error reading from stream");
+                    break;
+                }
+            };
+
+            read_buffer.extend_from_slice(&temp_buffer[..bytes_read]);
+        }
+        pending_read = false;
+
+        // Try to deserialize packets from the buffer
+        loop {
+            let (packets, consumed_bytes) = match deserialise_packets_with_format(
+                &read_buffer[read_offset..],
+                connection.wire_format,
+            ) {
+                Ok(result) => result,
+                Err(e) => {
+                    // Invalid data can't be skipped reliably, so the connection is closed.
+                    warn!(error = %e, "Error reading packets");
+                    let error = Packet::ServerError {
+                        code: ERROR_INVALID_PACKET,
+                        message: e.to_string().into(),
+                    };
+                    queue(vec![error], connection.wire_format).await?;
+                    return Err(e);
+                }
+            };
+            if packets.is_empty() {
+                // No complete packets yet, keep the data in buffer
+                break;
+            }
+
+            // Process packets, handing responses to the writer whenever the configured amount
+            // has built up so the outgoing queue stays bounded.
+            let mut enqueue_runs = enqueue_runs(&packets);
+            let mut packets = packets.into_iter().enumerate().peekable();
+            while packets.peek().is_some() && !connection.closing {
+                let mut responses = Vec::new();
+                // A negotiation only changes the framing of the packets after its own reply.
+                let response_format = connection.wire_format;
+                let mut state_guard = state.lock().await;
+                while let Some((index, packet)) = packets.next_if(|_| rate_limiter.try_acquire()) {
+                    reserve_enqueue_run(&mut state_guard, &mut enqueue_runs, index);
+                    let packet_id = packet.packet_id();
+                    METRICS.record_packet(packet_id);
+                    if let Err(e) =
+                        handle_client_packet(&mut state_guard, connection, packet, &mut responses)
+                    {
+                        warn!(packet_id, error = %e, "Error handling packet");
+                        return Err(e);
+                    }
+                    // Anything pipelined after a goodbye or a rejected hello is ignored.
+                    packets_since_yield += 1;
+                    if connection.closing
+                        || connection.awaiting_contents.is_some()
+                        || responses.len() >= settings.max_buffered_responses
+                        || packets_since_yield >= settings.max_packets_per_yield
+                    {
+                        break;
+                    }
+                }
+                drop(state_guard); // Release lock before queueing, which may wait on the writer
+                connection.released_streams.clear();
+
+                queue(responses, response_format).await?;
+
+                // Later packets wait for the await to be answered, keeping responses in order.
+                if let Some((stream_id, timeout)) = connection.awaiting_contents.take() {
+                    let response = await_stream_contents(&state, stream_id, timeout).await;
+                    queue(vec![response], connection.wire_format).await?;
+                }
+
+                // A client pipelining many packets would otherwise hold the lock and the runtime
+                // until all of them are handled, stalling every other connection.
+                if packets_since_yield >= settings.max_packets_per_yield {
+                    packets_since_yield = 0;
+                    tokio::task::yield_now().await;
+                }
+
+                // Out of tokens, so wait for the next one without holding the lock. Throttling
+                // here rather than rejecting keeps a well-behaved client's requests intact.
+                let delay = rate_limiter.time_until_available();
+                if !delay.is_zero() && packets.peek().is_some() && !connection.closing {
+                    sleep(delay).await;
+                }
+            }
+
+            if connection.closing {
+                return Ok(());
+            }
+
+            // Skip past consumed bytes
+            if consumed_bytes > 0 {
+                read_offset += consumed_bytes;
+            } else {
+                break;
+            }
+        }
+
+        // Only shift the unparsed bytes to the front once the parsed ones make up at least half
+        // the buffer, so the cost of compacting stays linear in the bytes received.
+        if read_offset * 2 >= read_buffer.len() {
+            read_buffer.drain(..read_offset);
+            read_offset = 0;
+        }
+
+        // Prevent buffer from growing too large. Every complete packet has been handled by now,
+        // so this only holds a partial one. Checking the declared length rejects an oversized
+        // packet as soon as its length prefix arrives, without buffering any of it.
+        if settings.max_read_buffer != 0
+            && let Some(frame_length) =
+                declared_frame_length(&read_buffer[read_offset..], connection.wire_format)
+            && frame_length > settings.max_read_buffer
+        {
+            let message = format!(
+                "Packet of {} bytes exceeds the limit of {} bytes",
+                frame_length, settings.max_read_buffer
+            );
+            warn!(
+                frame_length,
+                "Closing connection sending an oversized packet"
+            );
+            let error = Packet::ServerError {
+                code: ERROR_PAYLOAD_TOO_LARGE,
+                message: message.clone().into(),
+            };
+            queue(vec![error], connection.wire_format).await?;
+            return Err(anyhow::anyhow!(message));
+        }
+    }
+
+    Ok(())
+}
+
+async fn queue_responses(
+    sender: &mpsc::Sender<QueuedResponses>,
+    responses: Vec<Packet>,
+    format: WireFormat,
+) -> anyhow::Result<()> {
+    if responses.is_empty() {
+        return Ok(());
+    }
+    sender
+        .send((responses, format))
+        .await
+        .map_err(|_| anyhow::anyhow!("Connection writer has stopped"))
+}
+
+/// Reads into the buffer, giving up with `None` once the connection has been idle for the
+/// configured timeout.
+async fn read_with_idle_timeout<R>(
+    reader: &mut R,
+    buffer: &mut [u8],
+    settings: &Settings,
+) -> Option<std::io::Result<usize>>
+where
+    R: AsyncReadExt + Unpin,
+{
+    let read = reader.read(buffer);
+    if settings.connection_idle_timeout.is_zero() {
+        Some(read.await)
+    } else {
+        timeout(settings.connection_idle_timeout, read).await.ok()
+    }
+}
+
+/// Writes out the responses queued by the reader until it finishes, then closes the connection.
+/// Batches that have built up in the queue are sent in a single write, up to
+/// `max_buffered_responses`. A client that stops reading would otherwise block the write forever
+/// once its receive window fills, so the connection is given up on after the write timeout.
+async fn write_responses<W>(
+    mut writer: W,
+    mut queue: mpsc::Receiver<QueuedResponses>,
+    settings: &Settings,
+) -> anyhow::Result<()>
+where
+    W: AsyncWriteExt + Unpin,
+{
+    let write_timeout = settings.write_timeout;
+    while let Some(batch) = queue.recv().await {
+        let mut response_data = Vec::new();
+        let mut response_count = encode_responses(&mut response_data, batch)?;
+        while response_count < settings.max_buffered_responses
+            && let Ok(batch) = queue.try_recv()
+        {
+            response_count += encode_responses(&mut response_data, batch)?;
+        }
+
+        let write = async {
+            if let Err(e) = writer.write_all(&response_data).await {
+                warn!(error = %e, "Error writing to stream");
+                return Err(e.into());
+            }
+            if let Err(e) = writer.flush().await {
+                warn!(error = %e, "Error flushing stream");
+                return Err(e.into());
+            }
+            anyhow::Ok(())
+        };
+        if write_timeout.is_zero() {
+            write.await?;
+        } else if timeout(write_timeout, write).await.is_err() {
+            warn!(
+                timeout_secs = write_timeout.as_secs(),
+                "Closing connection: writing responses timed out"
+            );
+            return Err(anyhow::anyhow!(
+                "Writing responses timed out after {:?}",
+                write_timeout
+            ));
+        }
+    }
+
+    // The client may already be gone, in which case there is nothing left to close.
+    let _ = writer.shutdown().await;
+    Ok(())
+}
+
+/// Returns the number of responses encoded.
+fn encode_responses(
+    response_data: &mut Vec<u8>,
+    (responses, format): QueuedResponses,
+) -> anyhow::Result<usize> {
+    for response in &responses {
+        // Only a response holding more than 4 GiB can't be written, in which case the client is
+        // told why instead.
+        if let Err(e) = write_packet_into_buffer_with_format(response_data, response, format) {
+            warn!(packet_id = response.packet_id(), error = %e, "Response is too large to send");
+            let error = Packet::ServerError {
+                code: ERROR_PAYLOAD_TOO_LARGE,
+                message: e.to_string().into(),
+            };
+            write_packet_into_buffer_with_format(response_data, &error, format)?;
+        }
+    }
+    Ok(responses.len())
+}
+
+/// Waits until the stream has data or the timeout elapses, then fetches its contents.
+async fn await_stream_contents(
+    state: &Mutex<ServerState>,
+    stream_id: u32,
+    timeout: Duration,
+) -> Packet {
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        let mut state_guard = state.lock().await;
+        let Some(stream) = state_guard.get_stream(stream_id) else {
+            // Deleted while waiting.
+            return Packet::ServerStreamContents {
+                buffer_data: Bytes::new(),
+            };
+        };
+
+        if !stream.buffer.is_empty() || Instant::now() >= deadline {
+            let buffer_data = state_guard
+                .fetch_stream_contents(stream_id)
+                .unwrap_or_default();
+            return Packet::ServerStreamContents { buffer_data };
+        }
+
+        // Register for the notification before releasing the lock, so an enqueue made in
+        // between isn't missed. Another waiter may still take the data first, hence the loop.
+        let notify = Arc::clone(&stream.notify);
+        let notified = notify.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+        drop(state_guard);
+
+        let _ = timeout_at(deadline, notified).await;
+    }
+}
+
+async fn handle_tcp_connection(
+    stream: TcpStream,
+    state: Arc<Mutex<ServerState>>,
+    settings: &Settings,
+    tls_acceptor: Option<TlsAcceptor>,
+    info: ConnectionInfo,
+) -> anyhow::Result<()> {
+    match tls_acceptor {
+        Some(tls_acceptor) => {
+            let stream = tls::accept(&tls_acceptor, stream).await?;
+            handle_connection(stream, state, settings, info).await
+        }
+        None => handle_connection(stream, state, settings, info).await,
+    }
+}
+
+async fn handle_unix_connection(
+    stream: UnixStream,
+    state: Arc<Mutex<ServerState>>,
+    settings: &Settings,
+    info: ConnectionInfo,
+) -> anyhow::Result<()> {
+    handle_connection(stream, state, settings, info).await
+}
+
+/// Applies the configured socket options to an accepted TCP connection.
+pub fn configure_tcp_stream(stream: &TcpStream, settings: &Settings) -> std::io::Result<()> {
+    stream.set_nodelay(settings.tcp_nodelay)?;
+    if !settings.tcp_keepalive.is_zero() {
+        let keepalive = TcpKeepalive::new().with_time(settings.tcp_keepalive);
+        SockRef::from(stream).set_tcp_keepalive(&keepalive)?;
+    }
+    Ok(())
+}
+
+async fn reject_busy_connection<S>(mut stream: S) -> anyhow::Result<()>
+where
+    S: AsyncWriteExt + Unpin,
+{
+    let response_data = serialise_packets(&[Packet::ServerBusy {
+        retry_after_ms: BUSY_RETRY_AFTER_MS,
+    }])?;
+    stream.write_all(&response_data).await?;
+    stream.shutdown().await?;
+
+    Ok(())
+}
+
+fn connection_permits(settings: &Settings) -> Arc<Semaphore> {
+    // A limit of 0 means unlimited.
+    let max_connections = match settings.max_connections {
+        0 => Semaphore::MAX_PERMITS,
+        max_connections => max_connections,
+    };
+
+    Arc::new(Semaphore::new(max_connections))
+}
+
+/// Prunes expired streams every `period`, which bounds how long a stream can outlive its expiry.
+/// The expiry is read from the state on every run, so changes apply from the next cycle.
+pub async fn cleanup_task(state: Arc<Mutex<ServerState>>, period: Duration) {
+    let mut interval = interval(period);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+    loop {
+        interval.tick().await;
+        let expired_streams = {
+            let mut state = state.lock().await;
+            state.remove_expired_sessions();
+            state.remove_expired_streams()
+        };
+        // Freed only now, so the lock isn't held while the buffers are deallocated.
+        METRICS.record_prune(expired_streams.len());
+        drop(expired_streams);
+    }
+}
+
+/// Runs every configured listener against the same state until all of them have shut down.
+pub async fn run_servers(
+    settings: &'static Settings,
+    state: Arc<Mutex<ServerState>>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> anyhow::Result<()> {
+    // Every listener starts shutting down once the shutdown future resolves.
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let shutdown_state = Arc::clone(&state);
+    tokio::spawn(async move {
+        shutdown.await;
+        // Connections still open during the grace period report it through health checks.
+        shutdown_state.lock().await.begin_shutdown();
+        let _ = shutdown_tx.send(true);
+    });
+
+    let mut listeners = JoinSet::new();
+    for listener in &settings.listeners {
+        let state = Arc::clone(&state);
+        let mut shutdown_rx = shutdown_rx.clone();
+        let shutdown = async move {
+            let _ = shutdown_rx.wait_for(|shutdown| *shutdown).await;
+        };
+
+        match listener {
+            ConnectionMode::Tcp => listeners.spawn(run_tcp_server(settings, state, shutdown)),
+            ConnectionMode::UnixSocket => {
+                listeners.spawn(run_unix_server(settings, state, shutdown))
+            }
+        };
+    }
+
+    // A port of 0 leaves the metrics endpoint disabled.
+    if settings.metrics_port != 0 {
+        let mut shutdown_rx = shutdown_rx.clone();
+        let shutdown = async move {
+            let _ = shutdown_rx.wait_for(|shutdown| *shutdown).await;
+        };
+        listeners.spawn(run_metrics_server(settings, Arc::clone(&state), shutdown));
+    }
+
+    // A listener failing (e.g. to bind) stops the whole server.
+    while let Some(result) = listeners.join_next().await {
+        result??;
+    }
+
+    Ok(())
+}
+
+pub async fn run_tcp_server(
+    settings: &'static Settings,
+    state: Arc<Mutex<ServerState>>,
+    shutdown: impl Future<Output = ()>,
+) -> anyhow::Result<()> {
+    let tls_acceptor = tls::acceptor_from_settings(settings)?;
+    let listeners = bind_tcp_listeners(settings).await?;
+    for listener in &listeners {
+        info!(addr = %listener.local_addr()?, tls = tls_acceptor.is_some(), "TCP server listening");
+    }
+
+    // Each bound address accepts on its own task, feeding one loop so connections on every
+    // address share the same limits and shutdown.
+    let (accepted_tx, mut accepted_rx) = mpsc::channel(1);
+    let mut acceptors = JoinSet::new();
+    for listener in listeners {
+        let accepted_tx = accepted_tx.clone();
+        acceptors.spawn(async move {
+            loop {
+                if accepted_tx.send(listener.accept().await).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    drop(accepted_tx);
+
+    let permits = connection_permits(settings);
+    let busy_permits = Arc::new(Semaphore::new(MAX_BUSY_REPLIES));
+    let mut connections = JoinSet::new();
+    tokio::pin!(shutdown);
+
+    loop {
+        let accepted = tokio::select! {
+            _ = &mut shutdown => break,
+            Some(accepted) = accepted_rx.recv() => accepted,
+        };
+        // Reap finished handlers so the set doesn't grow with every connection.
+        while connections.try_join_next().is_some() {}
+
+        match accepted {
+            Ok((stream, addr)) => {
+                if !settings.is_ip_allowed(addr.ip()) {
+                    warn!(peer = %addr, "Rejecting TCP connection from disallowed address");
+                    continue;
+                }
+                // A socket that can't be tuned still works, just with the OS defaults.
+                if let Err(e) = configure_tcp_stream(&stream, settings) {
+                    warn!(peer = %addr, error = %e, "Error setting TCP socket options");
+                }
+
+                let Ok(permit) = Arc::clone(&permits).try_acquire_owned() else {
+                    let Ok(busy_permit) = Arc::clone(&busy_permits).try_acquire_owned() else {
+                        warn!(peer = %addr, "Closing TCP connection: server busy");
+                        continue;
+                    };
+                    warn!(peer = %addr, "Rejecting TCP connection: server busy");
+                    let tls_acceptor = tls_acceptor.clone();
+                    tokio::spawn(async move {
+                        let _busy_permit = busy_permit;
+                        match tls_acceptor {
+                            Some(tls_acceptor) => {
+                                reject_busy_connection(tls::accept(&tls_acceptor, stream).await?)
+                                    .await
+                            }
+                            None => reject_busy_connection(stream).await,
+                        }
+                    });
+                    continue;
+                };
+
+                let info = ConnectionInfo::new(addr.to_string());
+                let span = info_span!("connection", id = info.id, peer = %info.peer);
+                let state_clone = Arc::clone(&state);
+                let tls_acceptor = tls_acceptor.clone();
+                connections.spawn(
+                    async move {
+                        info!("TCP connection opened");
+                        // The handshake happens here rather than in the accept loop, so a slow
+                        // or failing one only affects its own connection.
+                        match handle_tcp_connection(
+                            stream,
+                            state_clone,
+                            settings,
+                            tls_acceptor,
+                            info,
+                        )
+                        .await
+                        {
+                            Ok(()) => info!("Connection closed"),
+                            Err(e) => warn!(error = %e, "Connection closed with an error"),
+                        }
+                        drop(permit);
+                    }
+                    .instrument(span),
+                );
+            }
+            Err(e) => {
+                error!(error = %e, "Error accepting TCP connection");
+            }
+        }
+    }
+
+    info!("TCP server shutting down");
+    // Aborting the acceptors drops their listeners.
+    acceptors.shutdown().await;
+    drain_connections(connections).await;
+
+    Ok(())
+}
+
+/// Binds every configured TCP address. Under `bind_strict` any failure is returned; otherwise
+/// failed addresses are only logged, as long as at least one is bound.
+async fn bind_tcp_listeners(settings: &Settings) -> anyhow::Result<Vec<TcpListener>> {
+    let mut listeners = Vec::new();
+    for addr in settings.tcp_bind_addrs() {
+        match TcpListener::bind(addr).await {
+            Ok(listener) => listeners.push(listener),
+            Err(e) if settings.bind_strict => {
+                return Err(e).with_context(|| format!("Failed to bind TCP address {}", addr));
+            }
+            Err(e) => warn!(%addr, error = %e, "Failed to bind TCP address, skipping it"),
+        }
+    }
+
+    if listeners.is_empty() {
+        return Err(anyhow::anyhow!("Failed to bind any TCP address"));
+    }
+    Ok(listeners)
+}
+
+/// Binds the UNIX socket, creating its directory if needed and replacing a socket left behind by
+/// a server that is no longer running. Each failure is reported in terms of the configured path,
+/// rather than as a bare OS error.
+fn bind_unix_listener(path: &str, mode: Option<u32>) -> anyhow::Result<UnixListener> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    if let Some(parent) = Path::new(path).parent()
+        && !parent.as_os_str().is_empty()
+        && !parent.exists()
+    {
+        std::fs::create_dir_all(parent).with_context(|| {
+            format!(
+                "Failed to create the directory {} for the UNIX socket",
+                parent.display()
+            )
+        })?;
+        info!(directory = %parent.display(), "Created the UNIX socket directory");
+    }
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if !metadata.file_type().is_socket() => {
+            return Err(anyhow::anyhow!(
+                "{} already exists and is not a socket",
+                path
+            ));
+        }
+        Ok(_) => match std::os::unix::net::UnixStream::connect(path) {
+            Ok(_) => {
+                return Err(anyhow::anyhow!(
+                    "{} is already in use by another running server",
+                    path
+                ));
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+                return Err(anyhow::anyhow!(
+                    "Permission denied accessing the existing socket at {}",
+                    path
+                ));
+            }
+            // Nothing is listening, so the socket was left behind by a server that has exited.
+            Err(_) => std::fs::remove_file(path)
+                .with_context(|| format!("Failed to remove the stale socket at {}", path))?,
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e).with_context(|| format!("Failed to inspect {}", path)),
+    }
+
+    let listener = UnixListener::bind(path).map_err(|e| {
+        let reason = match e.kind() {
+            std::io::ErrorKind::PermissionDenied => "permission denied",
+            std::io::ErrorKind::AddrInUse => "already in use",
+            _ => "failed to bind",
+        };
+        anyhow::Error::new(e).context(format!("UNIX socket {}: {}", path, reason))
+    })?;
+
+    if let Some(mode) = mode {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+            .with_context(|| format!("Failed to set the permissions of {} to {:o}", path, mode))?;
+    }
+
+    Ok(listener)
+}
+
+pub async fn run_unix_server(
+    settings: &'static Settings,
+    state: Arc<Mutex<ServerState>>,
+    shutdown: impl Future<Output = ()>,
+) -> anyhow::Result<()> {
+    let listener = bind_unix_listener(&settings.unix_sock_path, settings.unix_sock_mode)?;
+    info!(path = %settings.unix_sock_path, "UNIX socket server listening");
+    let permits = connection_permits(settings);
+    let mut connections = JoinSet::new();
+    tokio::pin!(shutdown);
+
+    loop {
+        let accepted = tokio::select! {
+            _ = &mut shutdown => break,
+            accepted = listener.accept() => accepted,
+        };
+        // Reap finished handlers so the set doesn't grow with every connection.
+        while connections.try_join_next().is_some() {}
+
+        match accepted {
+            Ok((stream, _)) => {
+                let Ok(permit) = Arc::clone(&permits).try_acquire_owned() else {
+                    warn!("Rejecting UNIX socket connection: server busy");
+                    tokio::spawn(reject_busy_connection(stream));
+                    continue;
+                };
+
+                let info = ConnectionInfo::new("unix");
+                let span = info_span!("connection", id = info.id, peer = %info.peer);
+                let state_clone = Arc::clone(&state);
+                connections.spawn(
+                    async move {
+                        info!("UNIX socket connection opened");
+                        match handle_unix_connection(stream, state_clone, settings, info).await {
+                            Ok(()) => info!("Connection closed"),
+                            Err(e) => warn!(error = %e, "Connection closed with an error"),
+                        }
+                        drop(permit);
+                    }
+                    .instrument(span),
+                );
+            }
+            Err(e) => {
+                error!(error = %e, "Error accepting UNIX connection");
+            }
+        }
+    }
+
+    info!("UNIX socket server shutting down");
+    // Remove the socket straight away so a restarted server can bind while this one drains.
+    drop(listener);
+    let _ = std::fs::remove_file(&settings.unix_sock_path);
+    drain_connections(connections).await;
+
+    Ok(())
+}
+
+/// Waits for open connections to finish, aborting any still open after the grace period.
+async fn drain_connections(mut connections: JoinSet<()>) {
+    let drain = async { while connections.join_next().await.is_some() {} };
+    if timeout(SHUTDOWN_GRACE_PERIOD, drain).await.is_err() {
+        warn!(
+            count = connections.len(),
+            "Aborting connections still open after the shutdown grace period"
+        );
+        connections.shutdown().await;
+    }
+}
+
+/// Resolves once the process receives SIGINT or SIGTERM.
+pub async fn shutdown_signal() {
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(e) => {
+            error!(error = %e, "Error installing SIGTERM handler");
+            let _ = tokio::signal::ctrl_c().await;
+            return;
+        }
+    };
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+    info!("Shutdown signal received");
+}
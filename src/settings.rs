@@ -1,8 +1,7 @@
 use std::env;
-use std::net::IpAddr;
-use std::ops::Deref;
+use std::net::{IpAddr, SocketAddr};
 use std::str::FromStr;
-use std::sync::LazyLock;
+use std::sync::OnceLock;
 use std::time::Duration;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -22,48 +21,589 @@ impl FromStr for ConnectionMode {
     }
 }
 
+impl ConnectionMode {
+    /// Parses a comma-separated list of connection modes, ignoring duplicates.
+    pub fn parse_list(s: &str) -> anyhow::Result<Vec<Self>> {
+        let mut modes = Vec::new();
+        for mode in s.split(',').map(str::trim).filter(|mode| !mode.is_empty()) {
+            let mode = ConnectionMode::from_str(mode)?;
+            if !modes.contains(&mode) {
+                modes.push(mode);
+            }
+        }
+
+        if modes.is_empty() {
+            return Err(anyhow::anyhow!("At least one listener must be enabled"));
+        }
+        Ok(modes)
+    }
+}
+
+/// What to do when an enqueue would take a stream past its maximum size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OverflowPolicy {
+    /// Leave the stream untouched and tell the client with `ServerEnqueueRejected`.
+    Reject,
+    /// Append anyway, dropping the oldest buffered bytes to make room.
+    DropOldest,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Reject" => Ok(OverflowPolicy::Reject),
+            "DropOldest" => Ok(OverflowPolicy::DropOldest),
+            _ => Err(anyhow::anyhow!("Invalid overflow policy: {}", s)),
+        }
+    }
+}
+
+/// What happens to an enqueue naming a stream that doesn't exist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissingStreamPolicy {
+    /// Drop the data silently.
+    Ignore,
+    /// Drop the data and tell the client with `ServerEnqueueError`.
+    Error,
+    /// Create the stream and enqueue to it.
+    Create,
+}
+
+impl FromStr for MissingStreamPolicy {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Ignore" => Ok(MissingStreamPolicy::Ignore),
+            "Error" => Ok(MissingStreamPolicy::Error),
+            "Create" => Ok(MissingStreamPolicy::Create),
+            _ => Err(anyhow::anyhow!("Invalid missing stream policy: {}", s)),
+        }
+    }
+}
+
+/// An IP network in CIDR notation (e.g. `10.0.0.0/8`). A bare address matches only itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IpNetwork {
+    address: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpNetwork {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.address, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpNetwork {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix_len) = match s.split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+            None => (s, None),
+        };
+
+        let address = IpAddr::from_str(address)
+            .map_err(|_| anyhow::anyhow!("Invalid network address: {}", s))?;
+        let max_prefix_len = if address.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix_len| *prefix_len <= max_prefix_len)
+                .ok_or_else(|| anyhow::anyhow!("Invalid network prefix length: {}", s))?,
+            None => max_prefix_len,
+        };
+
+        Ok(Self {
+            address,
+            prefix_len,
+        })
+    }
+}
+
 pub struct Settings {
     pub key_expiry: Duration,
-    pub connection_mode: ConnectionMode,
+    /// How often expired streams are pruned.
+    pub cleanup_interval: Duration,
+    pub listeners: Vec<ConnectionMode>,
     pub unix_sock_path: String,
+    /// Permissions applied to the UNIX socket once bound, or `None` to leave the umask's.
+    pub unix_sock_mode: Option<u32>,
     pub tcp_port: u16,
     pub tcp_host: IpAddr,
+    /// Every address the TCP listener binds, or empty to bind only `tcp_host:tcp_port`.
+    pub tcp_binds: Vec<SocketAddr>,
+    /// Whether failing to bind any one TCP address stops the server, rather than only being
+    /// logged while the others listen.
+    pub bind_strict: bool,
+    pub max_connections: usize,
+    pub allowed_ips: Vec<IpNetwork>,
+    pub max_buffered_responses: usize,
+    pub max_stream_bytes: usize,
+    /// The most streams that may exist at once, or 0 for no limit.
+    pub max_streams: usize,
+    /// Total buffered bytes at which health checks report the server as degraded, or 0 for never.
+    pub degraded_bytes: usize,
+    /// Total buffered bytes past which the least recently active streams are evicted, or 0 for
+    /// never.
+    pub memory_soft_limit: usize,
+    pub overflow_policy: OverflowPolicy,
+    pub missing_stream_policy: MissingStreamPolicy,
+    /// The stream rejected enqueues are appended to, or `None` to drop them.
+    pub dead_letter_stream: Option<u32>,
+    /// The bytes a stream must buffer before an enqueue pushes them to subscribers, or 0 to push
+    /// on every enqueue.
+    pub push_min_bytes: usize,
+    /// How long a closed connection's session can be resumed for, or zero to not offer sessions.
+    pub session_ttl: Duration,
+    pub log_level: String,
+    pub metrics_port: u16,
+    pub read_chunk_size: usize,
+    /// The largest packet a connection may send, including its framing, or 0 for no limit.
+    pub max_read_buffer: usize,
+    /// The largest stream payload a packet may carry once decompressed, or 0 for the same as
+    /// `max_read_buffer`.
+    pub max_payload_size: usize,
+    pub max_packets_per_yield: usize,
+    /// The most packets a connection may have handled per second, or 0 for no limit.
+    pub rate_limit: u32,
+    /// How long a connection may go without sending anything before it is closed, or 0 to never.
+    pub connection_idle_timeout: Duration,
+    /// How long writing responses to a connection may take before it is closed, or 0 to wait
+    /// forever.
+    pub write_timeout: Duration,
+    /// Whether accepted TCP sockets disable Nagle's algorithm, so small replies aren't delayed.
+    pub tcp_nodelay: bool,
+    /// How long an accepted TCP socket may sit idle before keepalive probes are sent, or 0 to
+    /// leave keepalive off.
+    pub tcp_keepalive: Duration,
+    /// Whether connections opening with `TEXT` may use the line-based debugging protocol.
+    pub text_protocol: bool,
+    /// Runtime worker threads, or 0 to handle every connection on the main thread.
+    pub worker_threads: usize,
+    pub snapshot_path: Option<String>,
+    pub snapshot_interval: Duration,
+    /// How long the final snapshot taken on shutdown may take, or 0 to wait for it however long.
+    pub shutdown_snapshot_timeout: Duration,
+    /// Where the write-ahead log is kept, as files named by appending each generation. Requires
+    /// snapshots, which are what let old WAL files be removed.
+    pub wal_path: Option<String>,
+    /// How often the WAL is written and synced to disk.
+    pub wal_sync_interval: Duration,
+    /// PEM certificate chain and private key. TCP connections use TLS when both are set.
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    /// The token clients must send with `ClientAuthenticate` before privileged packets are
+    /// honoured, or `None` to trust every client.
+    pub admin_token: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            key_expiry: Duration::from_secs(150),
+            cleanup_interval: Duration::from_secs(30),
+            listeners: vec![ConnectionMode::UnixSocket],
+            unix_sock_path: "/tmp/fsdb.sock".to_string(),
+            unix_sock_mode: None,
+            tcp_port: 1273,
+            tcp_host: IpAddr::from_str("127.0.0.1").unwrap(),
+            tcp_binds: Vec::new(),
+            bind_strict: true,
+            max_connections: 0,
+            allowed_ips: Vec::new(),
+            max_buffered_responses: 1024,
+            max_stream_bytes: 0,
+            max_streams: 0,
+            degraded_bytes: 0,
+            memory_soft_limit: 0,
+            overflow_policy: OverflowPolicy::Reject,
+            missing_stream_policy: MissingStreamPolicy::Ignore,
+            dead_letter_stream: None,
+            push_min_bytes: 0,
+            session_ttl: Duration::from_secs(30),
+            log_level: "info".to_string(),
+            metrics_port: 0,
+            read_chunk_size: 4096,
+            max_read_buffer: 64 * 1024,
+            max_payload_size: 0,
+            max_packets_per_yield: 1024,
+            rate_limit: 0,
+            connection_idle_timeout: Duration::ZERO,
+            write_timeout: Duration::from_secs(30),
+            tcp_nodelay: true,
+            tcp_keepalive: Duration::ZERO,
+            text_protocol: false,
+            worker_threads: 0,
+            snapshot_path: None,
+            snapshot_interval: Duration::from_secs(60),
+            shutdown_snapshot_timeout: Duration::from_secs(30),
+            wal_path: None,
+            wal_sync_interval: Duration::from_millis(1000),
+            tls_cert: None,
+            tls_key: None,
+            admin_token: None,
+        }
+    }
+}
+
+/// Parses the variable `name` if it is set, naming the variable and value on failure.
+fn parse_var<T: FromStr>(
+    vars: &impl Fn(&str) -> Option<String>,
+    name: &str,
+) -> anyhow::Result<Option<T>>
+where
+    T::Err: std::fmt::Display,
+{
+    let Some(value) = vars(name) else {
+        return Ok(None);
+    };
+
+    let type_name = std::any::type_name::<T>()
+        .rsplit("::")
+        .next()
+        .unwrap_or("value");
+    value
+        .parse::<T>()
+        .map(Some)
+        .map_err(|e| anyhow::anyhow!("{}='{}' is not a valid {}: {}", name, value, type_name, e))
 }
 
 impl Settings {
     pub fn from_env() -> anyhow::Result<Self> {
-        let key_expiry = env::var("FSDB_KEY_EXPIRY")
-            .map(|v| v.parse::<u64>().map(Duration::from_secs))
-            .unwrap_or(Ok(Duration::from_secs(150)))?;
+        Self::from_vars(|name| env::var(name).ok())
+    }
 
-        let connection_mode = env::var("FSDB_CONNECTION_MODE")
-            .map(|v| ConnectionMode::from_str(&v))
-            .unwrap_or(Ok(ConnectionMode::UnixSocket))?;
+    /// Builds the settings from variables looked up by name, validating them together so a
+    /// misconfiguration is reported at startup rather than when a listener is first bound.
+    pub fn from_vars(vars: impl Fn(&str) -> Option<String>) -> anyhow::Result<Self> {
+        let defaults = Self::default();
 
-        let unix_sock_path =
-            env::var("FSDB_UNIX_SOCK_PATH").unwrap_or_else(|_| "/tmp/fsdb.sock".to_string());
+        let key_expiry = parse_var::<u64>(&vars, "FSDB_KEY_EXPIRY")?
+            .map(Duration::from_secs)
+            .unwrap_or(defaults.key_expiry);
 
-        let tcp_port = env::var("FSDB_TCP_PORT")
-            .map(|v| v.parse::<u16>())
-            .unwrap_or(Ok(1273))?;
+        let cleanup_interval = parse_var::<u64>(&vars, "FSDB_CLEANUP_INTERVAL")?
+            .map(Duration::from_secs)
+            .unwrap_or(defaults.cleanup_interval);
+        if cleanup_interval.is_zero() {
+            return Err(anyhow::anyhow!("FSDB_CLEANUP_INTERVAL must be at least 1"));
+        }
+
+        // FSDB_LISTENERS takes precedence over the single-mode FSDB_CONNECTION_MODE.
+        let listeners = match vars("FSDB_LISTENERS") {
+            Some(listeners) => ConnectionMode::parse_list(&listeners)
+                .map_err(|e| anyhow::anyhow!("FSDB_LISTENERS='{}' is invalid: {}", listeners, e))?,
+            None => parse_var::<ConnectionMode>(&vars, "FSDB_CONNECTION_MODE")?
+                .map(|mode| vec![mode])
+                .unwrap_or(defaults.listeners),
+        };
+
+        let unix_sock_path = vars("FSDB_UNIX_SOCK_PATH").unwrap_or(defaults.unix_sock_path);
+        if unix_sock_path.is_empty() && listeners.contains(&ConnectionMode::UnixSocket) {
+            return Err(anyhow::anyhow!(
+                "FSDB_UNIX_SOCK_PATH must not be empty when the UNIX_SOCK listener is enabled"
+            ));
+        }
 
-        let tcp_host = env::var("FSDB_TCP_HOST")
-            .map(|v| IpAddr::from_str(&v))
-            .unwrap_or(Ok(IpAddr::from_str("127.0.0.1").unwrap()))?;
+        let unix_sock_mode = match vars("FSDB_UNIX_SOCK_MODE").filter(|mode| !mode.is_empty()) {
+            Some(mode) => Some(
+                u32::from_str_radix(&mode, 8)
+                    .ok()
+                    .filter(|mode| *mode <= 0o777)
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "FSDB_UNIX_SOCK_MODE='{}' is not a valid octal file mode",
+                            mode
+                        )
+                    })?,
+            ),
+            None => defaults.unix_sock_mode,
+        };
+
+        let tcp_port = parse_var::<u16>(&vars, "FSDB_TCP_PORT")?.unwrap_or(defaults.tcp_port);
+        let tcp_host = parse_var::<IpAddr>(&vars, "FSDB_TCP_HOST")?.unwrap_or(defaults.tcp_host);
+
+        // FSDB_TCP_BINDS takes precedence over the single FSDB_TCP_HOST and FSDB_TCP_PORT.
+        let tcp_binds = match vars("FSDB_TCP_BINDS") {
+            Some(binds) => binds
+                .split(',')
+                .map(str::trim)
+                .filter(|bind| !bind.is_empty())
+                .map(|bind| {
+                    SocketAddr::from_str(bind).map_err(|e| {
+                        anyhow::anyhow!(
+                            "FSDB_TCP_BINDS entry '{}' is not a valid address: {}",
+                            bind,
+                            e
+                        )
+                    })
+                })
+                .collect::<anyhow::Result<Vec<SocketAddr>>>()?,
+            None => defaults.tcp_binds,
+        };
+
+        let allow_ephemeral_port =
+            parse_var::<bool>(&vars, "FSDB_ALLOW_EPHEMERAL_PORT")?.unwrap_or(false);
+        if listeners.contains(&ConnectionMode::Tcp) && !allow_ephemeral_port {
+            if tcp_binds.is_empty() && tcp_port == 0 {
+                return Err(anyhow::anyhow!(
+                    "FSDB_TCP_PORT must not be 0 unless FSDB_ALLOW_EPHEMERAL_PORT=true"
+                ));
+            }
+            if let Some(bind) = tcp_binds.iter().find(|bind| bind.port() == 0) {
+                return Err(anyhow::anyhow!(
+                    "FSDB_TCP_BINDS entry '{}' must not use port 0 unless \
+                     FSDB_ALLOW_EPHEMERAL_PORT=true",
+                    bind
+                ));
+            }
+        }
+
+        let bind_strict =
+            parse_var::<bool>(&vars, "FSDB_BIND_STRICT")?.unwrap_or(defaults.bind_strict);
+
+        let max_connections =
+            parse_var::<usize>(&vars, "FSDB_MAX_CONNECTIONS")?.unwrap_or(defaults.max_connections);
+
+        let allowed_ips = match vars("FSDB_ALLOWED_IPS") {
+            Some(v) => v
+                .split(',')
+                .map(str::trim)
+                .filter(|network| !network.is_empty())
+                .map(IpNetwork::from_str)
+                .collect::<anyhow::Result<Vec<IpNetwork>>>()
+                .map_err(|e| anyhow::anyhow!("FSDB_ALLOWED_IPS='{}' is invalid: {}", v, e))?,
+            None => defaults.allowed_ips,
+        };
+
+        let max_buffered_responses = parse_var::<usize>(&vars, "FSDB_MAX_BUFFERED_RESPONSES")?
+            .unwrap_or(defaults.max_buffered_responses);
+        if max_buffered_responses == 0 {
+            return Err(anyhow::anyhow!(
+                "FSDB_MAX_BUFFERED_RESPONSES must be at least 1"
+            ));
+        }
+
+        let max_stream_bytes = parse_var::<usize>(&vars, "FSDB_MAX_STREAM_BYTES")?
+            .unwrap_or(defaults.max_stream_bytes);
+
+        let max_streams =
+            parse_var::<usize>(&vars, "FSDB_MAX_STREAMS")?.unwrap_or(defaults.max_streams);
+
+        let degraded_bytes =
+            parse_var::<usize>(&vars, "FSDB_DEGRADED_BYTES")?.unwrap_or(defaults.degraded_bytes);
+
+        let memory_soft_limit = parse_var::<usize>(&vars, "FSDB_MEMORY_SOFT_LIMIT")?
+            .unwrap_or(defaults.memory_soft_limit);
+
+        let overflow_policy = parse_var::<OverflowPolicy>(&vars, "FSDB_OVERFLOW_POLICY")?
+            .unwrap_or(defaults.overflow_policy);
+
+        let missing_stream_policy = parse_var::<MissingStreamPolicy>(&vars, "FSDB_STRICT_ENQUEUE")?
+            .unwrap_or(defaults.missing_stream_policy);
+
+        let dead_letter_stream = parse_var::<u32>(&vars, "FSDB_DEAD_LETTER_STREAM")?;
+
+        let push_min_bytes =
+            parse_var::<usize>(&vars, "FSDB_PUSH_MIN_BYTES")?.unwrap_or(defaults.push_min_bytes);
+
+        let session_ttl = parse_var::<u64>(&vars, "FSDB_SESSION_TTL")?
+            .map(Duration::from_secs)
+            .unwrap_or(defaults.session_ttl);
+
+        let log_level = vars("FSDB_LOG_LEVEL").unwrap_or(defaults.log_level);
+
+        let metrics_port =
+            parse_var::<u16>(&vars, "FSDB_METRICS_PORT")?.unwrap_or(defaults.metrics_port);
+
+        let read_chunk_size =
+            parse_var::<usize>(&vars, "FSDB_READ_CHUNK_SIZE")?.unwrap_or(defaults.read_chunk_size);
+        if read_chunk_size == 0 {
+            return Err(anyhow::anyhow!("FSDB_READ_CHUNK_SIZE must be at least 1"));
+        }
+
+        let max_read_buffer =
+            parse_var::<usize>(&vars, "FSDB_MAX_READ_BUFFER")?.unwrap_or(defaults.max_read_buffer);
+
+        let max_payload_size = parse_var::<usize>(&vars, "FSDB_MAX_PAYLOAD_SIZE")?
+            .unwrap_or(defaults.max_payload_size);
+        // A compressed payload can't expand past what the client could have sent uncompressed.
+        if max_read_buffer != 0 && max_payload_size > max_read_buffer {
+            return Err(anyhow::anyhow!(
+                "FSDB_MAX_PAYLOAD_SIZE can't exceed FSDB_MAX_READ_BUFFER"
+            ));
+        }
+
+        let max_packets_per_yield = parse_var::<usize>(&vars, "FSDB_MAX_PACKETS_PER_YIELD")?
+            .unwrap_or(defaults.max_packets_per_yield);
+        if max_packets_per_yield == 0 {
+            return Err(anyhow::anyhow!(
+                "FSDB_MAX_PACKETS_PER_YIELD must be at least 1"
+            ));
+        }
+
+        let rate_limit = parse_var::<u32>(&vars, "FSDB_RATE_LIMIT")?.unwrap_or(defaults.rate_limit);
+
+        let connection_idle_timeout = parse_var::<u64>(&vars, "FSDB_CONNECTION_IDLE_TIMEOUT")?
+            .map(Duration::from_secs)
+            .unwrap_or(defaults.connection_idle_timeout);
+
+        let write_timeout = parse_var::<u64>(&vars, "FSDB_WRITE_TIMEOUT")?
+            .map(Duration::from_secs)
+            .unwrap_or(defaults.write_timeout);
+
+        let tcp_nodelay =
+            parse_var::<bool>(&vars, "FSDB_TCP_NODELAY")?.unwrap_or(defaults.tcp_nodelay);
+
+        let tcp_keepalive = parse_var::<u64>(&vars, "FSDB_TCP_KEEPALIVE")?
+            .map(Duration::from_secs)
+            .unwrap_or(defaults.tcp_keepalive);
+
+        let text_protocol =
+            parse_var::<bool>(&vars, "FSDB_TEXT_PROTOCOL")?.unwrap_or(defaults.text_protocol);
+
+        let worker_threads =
+            parse_var::<usize>(&vars, "FSDB_WORKER_THREADS")?.unwrap_or(defaults.worker_threads);
+
+        let snapshot_path = vars("FSDB_SNAPSHOT_PATH").filter(|path| !path.is_empty());
+
+        let snapshot_interval = parse_var::<u64>(&vars, "FSDB_SNAPSHOT_INTERVAL")?
+            .map(Duration::from_secs)
+            .unwrap_or(defaults.snapshot_interval);
+        if snapshot_interval.is_zero() {
+            return Err(anyhow::anyhow!("FSDB_SNAPSHOT_INTERVAL must be at least 1"));
+        }
+
+        let shutdown_snapshot_timeout = parse_var::<u64>(&vars, "FSDB_SHUTDOWN_SNAPSHOT_TIMEOUT")?
+            .map(Duration::from_secs)
+            .unwrap_or(defaults.shutdown_snapshot_timeout);
+
+        let wal_path = vars("FSDB_WAL_PATH").filter(|path| !path.is_empty());
+        if wal_path.is_some() && snapshot_path.is_none() {
+            return Err(anyhow::anyhow!(
+                "FSDB_WAL_PATH requires FSDB_SNAPSHOT_PATH to be set"
+            ));
+        }
+
+        let wal_sync_interval = parse_var::<u64>(&vars, "FSDB_WAL_SYNC_INTERVAL_MS")?
+            .map(Duration::from_millis)
+            .unwrap_or(defaults.wal_sync_interval);
+        if wal_sync_interval.is_zero() {
+            return Err(anyhow::anyhow!(
+                "FSDB_WAL_SYNC_INTERVAL_MS must be at least 1"
+            ));
+        }
+
+        let tls_cert = vars("FSDB_TLS_CERT").filter(|path| !path.is_empty());
+        let tls_key = vars("FSDB_TLS_KEY").filter(|path| !path.is_empty());
+        if tls_cert.is_some() != tls_key.is_some() {
+            return Err(anyhow::anyhow!(
+                "FSDB_TLS_CERT and FSDB_TLS_KEY must be set together"
+            ));
+        }
+        if tls_cert.is_some() && !cfg!(feature = "tls") {
+            return Err(anyhow::anyhow!(
+                "FSDB_TLS_CERT is set, but the server was built without the tls feature"
+            ));
+        }
+
+        let admin_token = vars("FSDB_ADMIN_TOKEN").filter(|token| !token.is_empty());
 
         Ok(Self {
             key_expiry,
-            connection_mode,
+            cleanup_interval,
+            listeners,
             unix_sock_path,
+            unix_sock_mode,
             tcp_port,
             tcp_host,
+            tcp_binds,
+            bind_strict,
+            max_connections,
+            allowed_ips,
+            max_buffered_responses,
+            max_stream_bytes,
+            max_streams,
+            degraded_bytes,
+            memory_soft_limit,
+            overflow_policy,
+            missing_stream_policy,
+            dead_letter_stream,
+            push_min_bytes,
+            session_ttl,
+            log_level,
+            metrics_port,
+            read_chunk_size,
+            max_read_buffer,
+            max_payload_size,
+            max_packets_per_yield,
+            rate_limit,
+            connection_idle_timeout,
+            write_timeout,
+            tcp_nodelay,
+            tcp_keepalive,
+            text_protocol,
+            worker_threads,
+            snapshot_path,
+            snapshot_interval,
+            shutdown_snapshot_timeout,
+            wal_path,
+            wal_sync_interval,
+            tls_cert,
+            tls_key,
+            admin_token,
         })
     }
 
-    pub fn get() -> &'static Self {
-        static SETTINGS: LazyLock<Settings> =
-            LazyLock::new(|| Settings::from_env().expect("Failed to load settings"));
+    /// The payload limit connections enforce, falling back to the packet limit when unset.
+    pub fn payload_limit(&self) -> usize {
+        if self.max_payload_size == 0 {
+            self.max_read_buffer
+        } else {
+            self.max_payload_size
+        }
+    }
+
+    /// The addresses the TCP listener binds.
+    pub fn tcp_bind_addrs(&self) -> Vec<SocketAddr> {
+        if self.tcp_binds.is_empty() {
+            vec![SocketAddr::new(self.tcp_host, self.tcp_port)]
+        } else {
+            self.tcp_binds.clone()
+        }
+    }
 
-        SETTINGS.deref()
+    /// Whether a TCP peer may connect. An empty allowlist allows everyone.
+    pub fn is_ip_allowed(&self, ip: IpAddr) -> bool {
+        self.allowed_ips.is_empty() || self.allowed_ips.iter().any(|network| network.contains(ip))
+    }
+
+    /// Loads the settings from the environment, returning any configuration error instead of
+    /// panicking. Call once at startup, before anything uses `get`.
+    pub fn init() -> anyhow::Result<&'static Self> {
+        let settings = Settings::from_env()?;
+        Ok(SETTINGS.get_or_init(|| settings))
+    }
+
+    pub fn get() -> &'static Self {
+        SETTINGS.get_or_init(|| Settings::from_env().expect("Failed to load settings"))
     }
 }
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
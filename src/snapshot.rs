@@ -0,0 +1,292 @@
+use crate::serialisation::Bytes;
+use crate::state::{ServerState, Stream};
+use crate::wal::remove_wal_files_before;
+use bytes::BytesMut;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::sync::Notify;
+use tokio::time::{Duration, interval, timeout};
+use tracing::{debug, error, info, warn};
+
+const SNAPSHOT_MAGIC: &[u8; 4] = b"FSDB";
+/// Bumped whenever the layout below changes, so older snapshots are refused rather than misread.
+const SNAPSHOT_VERSION: u32 = 5;
+
+// Layout (little endian):
+//   magic [u8; 4], version u32, WAL generation u64, stream count u32, then for each stream:
+//   stream ID u32, last_activity u64, has_last_seq u8, last_seq u64, base_offset u64,
+//   has_ttl u8, ttl u64, has_name u8, name length u32, name,
+//   metadata length u32, metadata, buffer length u64, buffer,
+//   cursor count u32, then (consumer ID u32, cursor u64) pairs.
+// Then group count u32, and for each group:
+//   group ID u32, member count u32, then the member stream IDs u32.
+
+/// Serialises every stream and group in the state.
+pub fn encode_snapshot(state: &ServerState) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(state.total_bytes() + 64 * state.stream_count());
+    buffer.extend_from_slice(SNAPSHOT_MAGIC);
+    buffer.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+    buffer.extend_from_slice(&state.wal().generation().to_le_bytes());
+    buffer.extend_from_slice(&(state.stream_count() as u32).to_le_bytes());
+
+    for (stream_id, stream) in state.streams() {
+        buffer.extend_from_slice(&stream_id.to_le_bytes());
+        buffer.extend_from_slice(&stream.last_activity.to_le_bytes());
+        buffer.push(stream.last_seq.is_some() as u8);
+        buffer.extend_from_slice(&stream.last_seq.unwrap_or(0).to_le_bytes());
+        buffer.extend_from_slice(&stream.base_offset.to_le_bytes());
+        buffer.push(stream.ttl.is_some() as u8);
+        buffer.extend_from_slice(&stream.ttl.unwrap_or(0).to_le_bytes());
+        let name = stream.name.as_deref().unwrap_or_default();
+        buffer.push(stream.name.is_some() as u8);
+        buffer.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        buffer.extend_from_slice(name);
+        buffer.extend_from_slice(&(stream.metadata.len() as u32).to_le_bytes());
+        buffer.extend_from_slice(&stream.metadata);
+        buffer.extend_from_slice(&(stream.buffer.len() as u64).to_le_bytes());
+        buffer.extend_from_slice(&stream.buffer);
+        buffer.extend_from_slice(&(stream.consumer_cursors.len() as u32).to_le_bytes());
+        for (consumer_id, cursor) in &stream.consumer_cursors {
+            buffer.extend_from_slice(&consumer_id.to_le_bytes());
+            buffer.extend_from_slice(&cursor.to_le_bytes());
+        }
+    }
+
+    buffer.extend_from_slice(&(state.groups().len() as u32).to_le_bytes());
+    for (group_id, members) in state.groups() {
+        buffer.extend_from_slice(&group_id.to_le_bytes());
+        buffer.extend_from_slice(&(members.len() as u32).to_le_bytes());
+        for stream_id in members {
+            buffer.extend_from_slice(&stream_id.to_le_bytes());
+        }
+    }
+
+    buffer
+}
+
+struct SnapshotReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> SnapshotReader<'a> {
+    fn read_bytes(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+        let bytes = self
+            .offset
+            .checked_add(len)
+            .and_then(|end| self.data.get(self.offset..end))
+            .ok_or_else(|| anyhow::anyhow!("Snapshot is truncated"))?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.read_bytes(4)?.try_into()?))
+    }
+
+    fn read_u64(&mut self) -> anyhow::Result<u64> {
+        Ok(u64::from_le_bytes(self.read_bytes(8)?.try_into()?))
+    }
+}
+
+/// Adds every stream and group in the snapshot to the state, replacing those with the same ID,
+/// and moves the state on to the WAL generation that follows the snapshot.
+pub fn decode_snapshot(data: &[u8], state: &mut ServerState) -> anyhow::Result<()> {
+    let mut reader = SnapshotReader { data, offset: 0 };
+
+    if reader.read_bytes(SNAPSHOT_MAGIC.len())? != SNAPSHOT_MAGIC {
+        return Err(anyhow::anyhow!("Not a FastStreamDB snapshot"));
+    }
+    let version = reader.read_u32()?;
+    if version != SNAPSHOT_VERSION {
+        return Err(anyhow::anyhow!(
+            "Unsupported snapshot version {} (expected {})",
+            version,
+            SNAPSHOT_VERSION
+        ));
+    }
+
+    // Decode everything before touching the state, so a corrupt snapshot loads nothing.
+    let wal_generation = reader.read_u64()?;
+    let stream_count = reader.read_u32()?;
+    let mut streams = Vec::new();
+    for _ in 0..stream_count {
+        let stream_id = reader.read_u32()?;
+        let last_activity = reader.read_u64()?;
+        let has_last_seq = reader.read_u8()? != 0;
+        let last_seq = reader.read_u64()?;
+        let base_offset = reader.read_u64()?;
+        let has_ttl = reader.read_u8()? != 0;
+        let ttl = reader.read_u64()?;
+        let has_name = reader.read_u8()? != 0;
+        let name_len = reader.read_u32()? as usize;
+        let name = Bytes::copy_from_slice(reader.read_bytes(name_len)?);
+        let metadata_len = reader.read_u32()? as usize;
+        let metadata = Bytes::copy_from_slice(reader.read_bytes(metadata_len)?);
+        let buffer_len = usize::try_from(reader.read_u64()?)?;
+        let buffer = BytesMut::from(reader.read_bytes(buffer_len)?);
+
+        let cursor_count = reader.read_u32()?;
+        let mut consumer_cursors = HashMap::new();
+        for _ in 0..cursor_count {
+            let consumer_id = reader.read_u32()?;
+            consumer_cursors.insert(consumer_id, reader.read_u64()?);
+        }
+
+        streams.push((
+            stream_id,
+            Stream {
+                buffer,
+                last_activity,
+                last_seq: has_last_seq.then_some(last_seq),
+                metadata,
+                base_offset,
+                consumer_cursors,
+                ttl: has_ttl.then_some(ttl),
+                name: has_name.then_some(name),
+                notify: Arc::new(Notify::new()),
+            },
+        ));
+    }
+    let group_count = reader.read_u32()?;
+    let mut groups = Vec::new();
+    for _ in 0..group_count {
+        let group_id = reader.read_u32()?;
+        let member_count = reader.read_u32()?;
+        let mut members = HashSet::new();
+        for _ in 0..member_count {
+            members.insert(reader.read_u32()?);
+        }
+        groups.push((group_id, members));
+    }
+    if reader.offset != data.len() {
+        return Err(anyhow::anyhow!("Snapshot has trailing data"));
+    }
+
+    for (stream_id, stream) in streams {
+        state.insert_stream(stream_id, stream);
+    }
+    for (group_id, members) in groups {
+        state.insert_group(group_id, members);
+    }
+    state.wal_mut().set_generation(wal_generation);
+
+    Ok(())
+}
+
+/// Writes the snapshot to a temporary file next to `path` and renames it into place, so a crash
+/// mid-write never leaves a partial snapshot behind.
+pub fn write_snapshot(path: &Path, data: &[u8]) -> anyhow::Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+
+    let mut file = std::fs::File::create(&temp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    drop(file);
+
+    std::fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+/// Loads the snapshot at `path` into the state, returning false if there is none.
+pub fn load_snapshot(path: &Path, state: &mut ServerState) -> anyhow::Result<bool> {
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+
+    decode_snapshot(&data, state)?;
+    Ok(true)
+}
+
+/// Encodes a snapshot under the state lock and writes it on a blocking thread, so connections
+/// aren't held up by disk I/O. Once it is written, the WAL files it covers are removed. Returns
+/// the number of streams and buffered bytes saved.
+pub async fn take_snapshot(
+    state: &Mutex<ServerState>,
+    path: &'static str,
+    wal_path: Option<&'static str>,
+) -> anyhow::Result<(usize, usize)> {
+    let (data, wal_generation, streams, bytes) = {
+        let mut state = state.lock().await;
+        // Changes from here on go to a new generation, which the snapshot doesn't include.
+        let wal_generation = state.wal_mut().rotate();
+        (
+            encode_snapshot(&state),
+            wal_generation,
+            state.stream_count(),
+            state.total_bytes(),
+        )
+    };
+
+    let data_len = data.len();
+    tokio::task::spawn_blocking(move || {
+        write_snapshot(Path::new(path), &data)?;
+        if let Some(wal_path) = wal_path {
+            remove_wal_files_before(Path::new(wal_path), wal_generation)?;
+        }
+        anyhow::Ok(())
+    })
+    .await??;
+    debug!(path, bytes = data_len, "Snapshot written");
+    Ok((streams, bytes))
+}
+
+/// Takes a snapshot every `period`.
+pub async fn snapshot_task(
+    state: Arc<Mutex<ServerState>>,
+    path: &'static str,
+    wal_path: Option<&'static str>,
+    period: Duration,
+) {
+    let mut interval = interval(period);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    // The first tick completes immediately, and there is nothing new to save at startup.
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        if let Err(e) = take_snapshot(&state, path, wal_path).await {
+            error!(path, error = %e, "Error writing snapshot");
+        }
+    }
+}
+
+/// Takes a final snapshot once the server has stopped, so a planned restart resumes where it left
+/// off. Gives up after `budget` (zero waits as long as it takes) rather than holding up shutdown
+/// on a huge dataset.
+pub async fn shutdown_snapshot(
+    state: &Mutex<ServerState>,
+    path: &'static str,
+    wal_path: Option<&'static str>,
+    budget: Duration,
+) -> anyhow::Result<()> {
+    let snapshot = take_snapshot(state, path, wal_path);
+    let (streams, bytes) = if budget.is_zero() {
+        snapshot.await?
+    } else {
+        match timeout(budget, snapshot).await {
+            Ok(result) => result?,
+            Err(_) => {
+                warn!(
+                    path,
+                    budget_secs = budget.as_secs(),
+                    "Shutdown snapshot didn't finish in time, changes since the last one may be lost"
+                );
+                return Ok(());
+            }
+        }
+    };
+
+    info!(path, streams, bytes, "Saved snapshot on shutdown");
+    Ok(())
+}
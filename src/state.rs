@@ -0,0 +1,1787 @@
+use crate::serialisation::{
+    Bytes, ERROR_INVALID_PACKET, ERROR_PAYLOAD_TOO_LARGE, ERROR_STREAM_EXISTS,
+    ERROR_STREAM_LIMIT_REACHED, HEALTH_DEGRADED, HEALTH_READY, HEALTH_SHUTTING_DOWN, Packet,
+    RequestError,
+};
+use crate::settings::{MissingStreamPolicy, OverflowPolicy, Settings};
+use crate::utils;
+use crate::wal::{WalLog, WalRecord};
+use bytes::{Buf, BytesMut};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{Notify, mpsc};
+use tracing::warn;
+
+pub const MAX_STREAM_METADATA_SIZE: usize = 256;
+const INITIAL_STREAM_CAPACITY: usize = 1024;
+/// The most a client can have a new stream's buffer pre-allocated with.
+pub const MAX_INITIAL_STREAM_CAPACITY: usize = 1 << 20;
+/// Named streams are given IDs from here upwards, so they stay clear of the low IDs clients
+/// usually pick for themselves.
+pub const FIRST_NAMED_STREAM_ID: u32 = 1 << 31;
+/// Long enough that session tokens can't be guessed.
+const SESSION_TOKEN_SIZE: usize = 16;
+
+pub struct Stream {
+    pub buffer: BytesMut,
+    pub last_activity: u64,
+    /// The highest sequence number applied through a sequenced enqueue, if any.
+    pub last_seq: Option<u64>,
+    /// Opaque client-provided bytes, kept across fetches.
+    pub metadata: Bytes,
+    /// Absolute stream offset of `buffer[0]`, advanced as bytes are removed from the front.
+    pub base_offset: u64,
+    /// Read cursors of registered consumers, as absolute stream offsets.
+    pub consumer_cursors: HashMap<u32, u64>,
+    /// Idle time in seconds after which the stream is pruned, overriding the global key expiry.
+    /// 0 means the stream never expires.
+    pub ttl: Option<u64>,
+    /// The name the stream can be looked up by, if it was created by name.
+    pub name: Option<Bytes>,
+    /// Woken whenever data is appended, for requests waiting on an empty stream. Waiters hold a
+    /// clone of it, so they can be counted without any extra bookkeeping.
+    pub notify: Arc<Notify>,
+}
+
+impl Stream {
+    fn new() -> Self {
+        Self::with_capacity(INITIAL_STREAM_CAPACITY)
+    }
+
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            buffer: BytesMut::with_capacity(capacity),
+            last_activity: utils::get_current_timestamp(),
+            last_seq: None,
+            metadata: Bytes::new(),
+            base_offset: 0,
+            consumer_cursors: HashMap::new(),
+            ttl: None,
+            name: None,
+            notify: Arc::new(Notify::new()),
+        }
+    }
+
+    fn wake_waiters(&self) {
+        // Notifying takes a lock, which isn't worth paying on every append when nobody waits.
+        if Arc::strong_count(&self.notify) > 1 {
+            self.notify.notify_waiters();
+        }
+    }
+
+    /// The consumer's cursor relative to the start of the buffer. Cursors left behind by a
+    /// clearing fetch are treated as pointing at the start of the buffer.
+    fn cursor_position(&self, consumer_id: u32) -> Option<usize> {
+        let cursor = *self.consumer_cursors.get(&consumer_id)?;
+        Some(cursor.saturating_sub(self.base_offset) as usize)
+    }
+
+    /// Appends `data`, applying the overflow policy if the buffer would grow past `max_bytes`
+    /// (0 meaning unlimited). Returns how many bytes were dropped to make room, or `None` if the
+    /// data was rejected.
+    fn append(
+        &mut self,
+        data: &[u8],
+        max_bytes: usize,
+        overflow_policy: OverflowPolicy,
+    ) -> Option<usize> {
+        let new_len = self.buffer.len() + data.len();
+        if max_bytes == 0 || new_len <= max_bytes {
+            self.buffer.extend_from_slice(data);
+            self.wake_waiters();
+            return Some(0);
+        }
+
+        match overflow_policy {
+            OverflowPolicy::Reject => None,
+            OverflowPolicy::DropOldest => {
+                let excess = new_len - max_bytes;
+                if excess >= self.buffer.len() {
+                    // The new data alone fills the stream, so only its tail is kept.
+                    let skipped = excess - self.buffer.len();
+                    self.buffer.clear();
+                    self.buffer.extend_from_slice(&data[skipped..]);
+                } else {
+                    self.buffer.advance(excess);
+                    self.buffer.extend_from_slice(data);
+                }
+                self.base_offset += excess as u64;
+                self.wake_waiters();
+                Some(excess)
+            }
+        }
+    }
+
+    /// Inserts `data` at the front of the buffer, which is O(n) in the buffered bytes. Returns
+    /// false if it would grow the buffer past `max_bytes` (0 meaning unlimited). Dropping the
+    /// oldest bytes would discard the prepended data itself, so the overflow policy doesn't apply.
+    fn prepend(&mut self, data: &[u8], max_bytes: usize) -> bool {
+        if max_bytes != 0 && self.buffer.len() + data.len() > max_bytes {
+            return false;
+        }
+
+        let mut buffer = BytesMut::with_capacity(data.len() + self.buffer.len());
+        buffer.extend_from_slice(data);
+        buffer.extend_from_slice(&self.buffer);
+        self.buffer = buffer;
+        // Consumers keep their place in the existing data, while those that haven't read
+        // anything yet see the prepended bytes first.
+        for cursor in self.consumer_cursors.values_mut() {
+            if *cursor > self.base_offset {
+                *cursor += data.len() as u64;
+            }
+        }
+        self.wake_waiters();
+        true
+    }
+
+    /// Removes the bytes every registered consumer has read past, returning how many were
+    /// removed. Without registered consumers, nothing is removed.
+    fn compact(&mut self) -> usize {
+        let Some(lowest_cursor) = self.consumer_cursors.values().min().copied() else {
+            return 0;
+        };
+
+        let removable =
+            (lowest_cursor.saturating_sub(self.base_offset) as usize).min(self.buffer.len());
+        self.buffer.advance(removable);
+        self.base_offset += removable as u64;
+        removable
+    }
+}
+
+/// The result of moving one stream's contents into another.
+#[derive(Debug, PartialEq, Eq)]
+pub enum MoveOutcome {
+    /// The contents were moved, and this many bytes were dropped from the destination to make
+    /// room under `OverflowPolicy::DropOldest`.
+    Moved { dropped_bytes: usize },
+    /// The destination was full, so neither stream was changed.
+    Rejected,
+    /// The stream with this ID doesn't exist, so neither stream was changed.
+    MissingStream(u32),
+}
+
+/// The result of enqueueing to one or more streams.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct EnqueueOutcome {
+    /// The number of streams the data was appended to.
+    pub stream_count: usize,
+    /// Streams that were full and rejected the data.
+    pub rejected_stream_ids: Vec<u32>,
+    /// Streams that don't exist, reported under `MissingStreamPolicy::Error`.
+    pub missing_stream_ids: Vec<u32>,
+}
+
+/// What a connection hands back to its session when it closes, for the next connection
+/// presenting the token to resume.
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    pub authenticated: bool,
+    /// The streams the connection was subscribed to.
+    pub stream_ids: Vec<u32>,
+    // When the session is forgotten, or None while a connection holds it.
+    expires_at: Option<Instant>,
+}
+
+pub struct ServerState {
+    stream_map: HashMap<u32, Stream>,
+    // Sum of all stream buffer lengths, maintained incrementally.
+    total_bytes: usize,
+    // How long a stream may be idle before it is pruned. Zero means never.
+    key_expiry: Duration,
+    // The most bytes a stream may buffer. Zero means unlimited.
+    max_stream_bytes: usize,
+    // The most streams that may exist at once, or 0 for no limit.
+    max_streams: usize,
+    overflow_policy: OverflowPolicy,
+    missing_stream_policy: MissingStreamPolicy,
+    // IDs of named streams, by name.
+    stream_names: HashMap<Bytes, u32>,
+    // Where the search for a free ID starts when the next named stream is created.
+    next_named_stream_id: u32,
+    started_at: Instant,
+    // The token clients must authenticate with before privileged packets are honoured. None means
+    // every client is trusted.
+    admin_token: Option<Bytes>,
+    // Senders to the connections subscribed to each stream, which are pushed its data as soon as
+    // it's enqueued.
+    subscriptions: HashMap<u32, Vec<mpsc::Sender<Packet>>>,
+    // Total buffered bytes at which the server reports itself degraded. Zero means never.
+    degraded_bytes: usize,
+    // The most connections the server accepts, or 0 for no limit.
+    max_connections: usize,
+    // Set once graceful shutdown has begun.
+    shutting_down: bool,
+    // Changes waiting to be written to the write-ahead log.
+    wal: WalLog,
+    // Where enqueues rejected for being full or naming a missing stream are kept, if anywhere.
+    dead_letter_stream: Option<u32>,
+    // Total buffered bytes past which streams are evicted, 0 meaning unlimited.
+    memory_soft_limit: usize,
+    // Bytes a stream must buffer before enqueues push them to subscribers.
+    push_min_bytes: usize,
+    // Sessions by token, kept apart from the streams as they expire on their own schedule.
+    sessions: HashMap<Bytes, Session>,
+    // How long a released session can be resumed for. Zero means sessions aren't offered.
+    session_ttl: Duration,
+    // The IDs of the streams in each group, by group ID.
+    groups: HashMap<u32, HashSet<u32>>,
+}
+
+impl Default for ServerState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ServerState {
+    pub fn new() -> Self {
+        Self::with_key_expiry(Duration::ZERO)
+    }
+
+    pub fn with_key_expiry(key_expiry: Duration) -> Self {
+        Self {
+            stream_map: HashMap::with_capacity(1024),
+            total_bytes: 0,
+            key_expiry,
+            max_stream_bytes: 0,
+            max_streams: 0,
+            overflow_policy: OverflowPolicy::Reject,
+            missing_stream_policy: MissingStreamPolicy::Ignore,
+            stream_names: HashMap::new(),
+            next_named_stream_id: FIRST_NAMED_STREAM_ID,
+            started_at: Instant::now(),
+            admin_token: None,
+            subscriptions: HashMap::new(),
+            degraded_bytes: 0,
+            max_connections: 0,
+            shutting_down: false,
+            wal: WalLog::default(),
+            dead_letter_stream: None,
+            memory_soft_limit: 0,
+            push_min_bytes: 0,
+            sessions: HashMap::new(),
+            session_ttl: Duration::ZERO,
+            groups: HashMap::new(),
+        }
+    }
+
+    pub fn from_settings(settings: &Settings) -> Self {
+        let mut state = Self::with_key_expiry(settings.key_expiry);
+        state.set_stream_limit(settings.max_stream_bytes, settings.overflow_policy);
+        state.set_max_streams(settings.max_streams);
+        state.set_missing_stream_policy(settings.missing_stream_policy);
+        state.set_health_thresholds(settings.degraded_bytes, settings.max_connections);
+        state.set_dead_letter_stream(settings.dead_letter_stream);
+        state.set_memory_soft_limit(settings.memory_soft_limit);
+        state.set_push_min_bytes(settings.push_min_bytes);
+        state.set_session_ttl(settings.session_ttl);
+        state.set_admin_token(
+            settings
+                .admin_token
+                .as_ref()
+                .map(|token| Bytes::from(token.clone())),
+        );
+        state
+    }
+
+    /// Applies to subsequent enqueues only; streams already past the limit are left as they are.
+    pub fn set_stream_limit(&mut self, max_stream_bytes: usize, overflow_policy: OverflowPolicy) {
+        self.max_stream_bytes = max_stream_bytes;
+        self.overflow_policy = overflow_policy;
+    }
+
+    /// Only stops new streams from being created, so lowering it below the current count deletes
+    /// nothing. As expired streams are pruned, creation is allowed again.
+    pub fn set_max_streams(&mut self, max_streams: usize) {
+        self.max_streams = max_streams;
+    }
+
+    /// Fails if creating `new_stream_count` more streams would exceed the stream limit.
+    fn ensure_stream_capacity(&self, new_stream_count: usize) -> anyhow::Result<()> {
+        if self.max_streams != 0 && self.stream_map.len() + new_stream_count > self.max_streams {
+            return Err(RequestError::new(
+                ERROR_STREAM_LIMIT_REACHED,
+                format!("The limit of {} streams has been reached", self.max_streams),
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// Applies to enqueues naming a single stream or a list of them, not to broadcasts.
+    pub fn set_missing_stream_policy(&mut self, missing_stream_policy: MissingStreamPolicy) {
+        self.missing_stream_policy = missing_stream_policy;
+    }
+
+    pub fn missing_stream_policy(&self) -> MissingStreamPolicy {
+        self.missing_stream_policy
+    }
+
+    /// Sets when health checks report the server as degraded: once `degraded_bytes` are buffered
+    /// or `max_connections` connections are open. Zero disables either check.
+    pub fn set_health_thresholds(&mut self, degraded_bytes: usize, max_connections: usize) {
+        self.degraded_bytes = degraded_bytes;
+        self.max_connections = max_connections;
+    }
+
+    /// Checked after every packet, so lowering it evicts streams on the next one.
+    pub fn set_memory_soft_limit(&mut self, memory_soft_limit: usize) {
+        self.memory_soft_limit = memory_soft_limit;
+    }
+
+    pub fn is_over_memory_soft_limit(&self) -> bool {
+        self.memory_soft_limit != 0 && self.total_bytes > self.memory_soft_limit
+    }
+
+    /// Makes health checks report the server as shutting down from now on.
+    pub fn begin_shutdown(&mut self) {
+        self.shutting_down = true;
+    }
+
+    /// One of the `HEALTH_*` statuses, given how many connections are open.
+    pub fn health_status(&self, active_connections: u64) -> u32 {
+        if self.shutting_down {
+            HEALTH_SHUTTING_DOWN
+        } else if (self.degraded_bytes != 0 && self.total_bytes >= self.degraded_bytes)
+            || (self.max_connections != 0 && active_connections >= self.max_connections as u64)
+        {
+            HEALTH_DEGRADED
+        } else {
+            HEALTH_READY
+        }
+    }
+
+    pub fn set_admin_token(&mut self, admin_token: Option<Bytes>) {
+        self.admin_token = admin_token;
+    }
+
+    /// Whether privileged packets need the connection to have authenticated first.
+    pub fn requires_authentication(&self) -> bool {
+        self.admin_token.is_some()
+    }
+
+    /// Checks a token against the admin token, in time independent of where they first differ so
+    /// the token can't be guessed byte by byte. Any token is accepted when none is configured.
+    pub fn check_admin_token(&self, token: &[u8]) -> bool {
+        let Some(admin_token) = &self.admin_token else {
+            return true;
+        };
+
+        admin_token.len() == token.len()
+            && admin_token
+                .iter()
+                .zip(token)
+                .fold(0, |difference, (a, b)| difference | (a ^ b))
+                == 0
+    }
+
+    pub fn set_dead_letter_stream(&mut self, dead_letter_stream: Option<u32>) {
+        self.dead_letter_stream = dead_letter_stream;
+    }
+
+    /// Keeps data an enqueue to `stream_id` rejected in the dead-letter stream, if there is one,
+    /// as the target stream ID and data length (both `u32`) followed by the data. The stream is
+    /// created if needed. Records that don't fit under its cap are dropped whatever the overflow
+    /// policy, as dropping the oldest bytes would leave a partial record at the front, and data
+    /// rejected by the dead-letter stream itself is never dead-lettered again.
+    fn dead_letter(&mut self, stream_id: u32, data: &[u8]) {
+        let Some(dead_letter_stream) = self.dead_letter_stream else {
+            return;
+        };
+        if stream_id == dead_letter_stream {
+            return;
+        }
+        let Ok(length) = u32::try_from(data.len()) else {
+            return;
+        };
+        if !self.stream_map.contains_key(&dead_letter_stream)
+            && self.create_new_stream(dead_letter_stream).is_err()
+        {
+            return;
+        }
+
+        let mut record = Vec::with_capacity(8 + data.len());
+        record.extend_from_slice(&stream_id.to_le_bytes());
+        record.extend_from_slice(&length.to_le_bytes());
+        record.extend_from_slice(data);
+        let stream = self
+            .stream_map
+            .get_mut(&dead_letter_stream)
+            .expect("Dead-letter stream was just created");
+        if stream
+            .append(&record, self.max_stream_bytes, OverflowPolicy::Reject)
+            .is_none()
+        {
+            return;
+        }
+        self.wal
+            .record_append(dead_letter_stream, &record, 0, stream.base_offset);
+        self.total_bytes += record.len();
+        stream.last_activity = utils::get_current_timestamp();
+        self.push_to_subscribers(dead_letter_stream);
+    }
+
+    /// Applies the missing stream policy to an enqueue of `data` naming a stream that doesn't
+    /// exist, returning whether the stream was created for it.
+    fn handle_missing_stream(
+        &mut self,
+        stream_id: u32,
+        data: &[u8],
+        outcome: &mut EnqueueOutcome,
+    ) -> anyhow::Result<bool> {
+        match self.missing_stream_policy {
+            MissingStreamPolicy::Ignore => Ok(false),
+            MissingStreamPolicy::Error => {
+                outcome.missing_stream_ids.push(stream_id);
+                self.dead_letter(stream_id, data);
+                Ok(false)
+            }
+            MissingStreamPolicy::Create => self.create_new_stream(stream_id),
+        }
+    }
+
+    /// Pushes the stream's data to `sender` from now on, starting with anything already buffered.
+    /// Subscribing the same connection twice has no further effect.
+    pub fn subscribe(
+        &mut self,
+        stream_id: u32,
+        sender: mpsc::Sender<Packet>,
+    ) -> anyhow::Result<()> {
+        if !self.stream_map.contains_key(&stream_id) {
+            return Err(RequestError::unknown_stream(stream_id).into());
+        }
+
+        let subscribers = self.subscriptions.entry(stream_id).or_default();
+        if !subscribers
+            .iter()
+            .any(|subscriber| subscriber.same_channel(&sender))
+        {
+            subscribers.push(sender);
+        }
+        self.push_buffered(stream_id, 0);
+        Ok(())
+    }
+
+    /// Lets enqueues batch small writes into fewer pushes. Subscribing and flushing push whatever
+    /// is buffered regardless.
+    pub fn set_push_min_bytes(&mut self, push_min_bytes: usize) {
+        self.push_min_bytes = push_min_bytes;
+    }
+
+    /// Pushes everything buffered to the stream's subscribers now, rather than waiting for
+    /// enqueues to reach `push_min_bytes`. Data no subscriber can take stays buffered. Returns
+    /// whether the stream exists.
+    pub fn flush_stream(&mut self, stream_id: u32) -> bool {
+        if !self.stream_map.contains_key(&stream_id) {
+            return false;
+        }
+        self.push_buffered(stream_id, 0);
+        true
+    }
+
+    pub fn unsubscribe(&mut self, stream_id: u32, sender: &mpsc::Sender<Packet>) {
+        if let Some(subscribers) = self.subscriptions.get_mut(&stream_id) {
+            subscribers.retain(|subscriber| !subscriber.same_channel(sender));
+            if subscribers.is_empty() {
+                self.subscriptions.remove(&stream_id);
+            }
+        }
+    }
+
+    pub fn set_session_ttl(&mut self, session_ttl: Duration) {
+        self.session_ttl = session_ttl;
+    }
+
+    /// Starts a session held by the calling connection, returning the token that resumes it.
+    pub fn open_session(&mut self) -> anyhow::Result<Bytes> {
+        if self.session_ttl.is_zero() {
+            return Err(RequestError::new(
+                ERROR_INVALID_PACKET,
+                "Sessions are disabled, as FSDB_SESSION_TTL is 0",
+            )
+            .into());
+        }
+
+        let token = Bytes::from(utils::random_bytes(SESSION_TOKEN_SIZE)?);
+        self.sessions.insert(token.clone(), Session::default());
+        Ok(token)
+    }
+
+    /// Keeps the closing connection's authentication and subscriptions under its session, for
+    /// `session_ttl` from now.
+    pub fn release_session(
+        &mut self,
+        token: Bytes,
+        authenticated: bool,
+        push_sender: Option<&mpsc::Sender<Packet>>,
+    ) {
+        let mut stream_ids: Vec<u32> = push_sender
+            .map(|push_sender| {
+                self.subscriptions
+                    .iter()
+                    .filter(|(_, subscribers)| {
+                        subscribers
+                            .iter()
+                            .any(|subscriber| subscriber.same_channel(push_sender))
+                    })
+                    .map(|(stream_id, _)| *stream_id)
+                    .collect()
+            })
+            .unwrap_or_default();
+        stream_ids.sort_unstable();
+
+        self.sessions.insert(
+            token,
+            Session {
+                authenticated,
+                stream_ids,
+                expires_at: Some(Instant::now() + self.session_ttl),
+            },
+        );
+    }
+
+    /// Hands a released, unexpired session to the calling connection, which holds it until it
+    /// releases it again. Sessions still held by another connection can't be resumed.
+    pub fn resume_session(&mut self, token: &[u8]) -> Option<Session> {
+        let session = self.sessions.get_mut(token)?;
+        match session.expires_at {
+            Some(expires_at) if expires_at > Instant::now() => {
+                session.expires_at = None;
+                Some(session.clone())
+            }
+            Some(_) => {
+                self.sessions.remove(token);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Forgets released sessions that weren't resumed in time.
+    pub fn remove_expired_sessions(&mut self) {
+        let now = Instant::now();
+        self.sessions
+            .retain(|_, session| session.expires_at.is_none_or(|expires_at| expires_at > now));
+    }
+
+    /// Called after every enqueue, so pushes wait until the stream holds `push_min_bytes`.
+    fn push_to_subscribers(&mut self, stream_id: u32) {
+        self.push_buffered(stream_id, self.push_min_bytes);
+    }
+
+    /// Moves the stream's buffered data to every subscriber, clearing the buffer, once it holds
+    /// at least `min_bytes`. Subscribers whose connection has closed are dropped, as are those
+    /// too far behind to queue another push, so one stalled connection can't hold up the rest.
+    /// If no subscriber can take the data, it stays buffered.
+    fn push_buffered(&mut self, stream_id: u32, min_bytes: usize) {
+        let Some(subscribers) = self.subscriptions.get_mut(&stream_id) else {
+            return;
+        };
+        subscribers.retain(|subscriber| {
+            if subscriber.is_closed() {
+                return false;
+            }
+            if subscriber.capacity() == 0 {
+                warn!(
+                    stream_id,
+                    "Dropping a subscriber that isn't keeping up with pushes"
+                );
+                return false;
+            }
+            true
+        });
+        if subscribers.is_empty() {
+            self.subscriptions.remove(&stream_id);
+            return;
+        }
+        if self
+            .stream_map
+            .get(&stream_id)
+            .is_none_or(|stream| stream.buffer.is_empty() || stream.buffer.len() < min_bytes)
+        {
+            return;
+        }
+
+        let buffer_data = self
+            .fetch_stream_contents(stream_id)
+            .expect("Stream was just checked");
+        // Pushes are only sent with the state locked, so the capacity checked above still holds.
+        for subscriber in &self.subscriptions[&stream_id] {
+            let _ = subscriber.try_send(Packet::ServerStreamPush {
+                stream_id,
+                buffer_data: buffer_data.clone(),
+            });
+        }
+    }
+
+    /// Time since the state was created, which is when the server started.
+    pub fn uptime(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    pub fn key_expiry(&self) -> Duration {
+        self.key_expiry
+    }
+
+    /// Takes effect from the next prune onwards.
+    pub fn set_key_expiry(&mut self, key_expiry: Duration) {
+        self.key_expiry = key_expiry;
+    }
+
+    /// Leaves an existing stream intact rather than replacing it, so re-creating a stream can't
+    /// lose buffered data, though it still counts as activity. Returns whether the stream was
+    /// created.
+    pub fn create_new_stream(&mut self, stream_id: u32) -> anyhow::Result<bool> {
+        self.create_new_stream_sized(stream_id, INITIAL_STREAM_CAPACITY)
+    }
+
+    /// Like `create_new_stream`, but pre-allocates the buffer for `initial_capacity` bytes, up to
+    /// `MAX_INITIAL_STREAM_CAPACITY` or the stream byte limit. An existing stream is left as is.
+    pub fn create_new_stream_sized(
+        &mut self,
+        stream_id: u32,
+        initial_capacity: usize,
+    ) -> anyhow::Result<bool> {
+        self.create_stream(stream_id, initial_capacity, None, None)
+    }
+
+    /// Creates the stream with its TTL and name set from the start, so the WAL records them
+    /// along with its creation.
+    fn create_stream(
+        &mut self,
+        stream_id: u32,
+        initial_capacity: usize,
+        ttl: Option<u64>,
+        name: Option<&[u8]>,
+    ) -> anyhow::Result<bool> {
+        if let Some(stream) = self.stream_map.get_mut(&stream_id) {
+            stream.last_activity = utils::get_current_timestamp();
+            return Ok(false);
+        }
+        self.ensure_stream_capacity(1)?;
+        let mut initial_capacity = initial_capacity.min(MAX_INITIAL_STREAM_CAPACITY);
+        if self.max_stream_bytes != 0 {
+            initial_capacity = initial_capacity.min(self.max_stream_bytes);
+        }
+        let mut stream = Stream::with_capacity(initial_capacity);
+        stream.ttl = ttl;
+        stream.name = name.map(Bytes::copy_from_slice);
+        self.wal.record(WalRecord::Create {
+            stream_id,
+            ttl,
+            name,
+        });
+        self.insert_stream(stream_id, stream);
+
+        Ok(true)
+    }
+
+    /// Undoes the bookkeeping for a stream that was removed or replaced.
+    fn forget_stream(&mut self, stream: &Stream) {
+        self.total_bytes -= stream.buffer.len();
+        if let Some(name) = &stream.name {
+            self.stream_names.remove(name);
+        }
+    }
+
+    /// Creates a stream that can be looked up by name, giving it an unused ID. If a stream with
+    /// the name already exists it is left intact. Either way, returns the stream's ID.
+    pub fn create_named_stream(&mut self, name: &[u8]) -> anyhow::Result<u32> {
+        if let Some(stream_id) = self.stream_names.get(name) {
+            return Ok(*stream_id);
+        }
+
+        let mut stream_id = self.next_named_stream_id;
+        while self.stream_map.contains_key(&stream_id) {
+            stream_id = stream_id.checked_add(1).unwrap_or(FIRST_NAMED_STREAM_ID);
+            if stream_id == self.next_named_stream_id {
+                return Err(RequestError::new(
+                    ERROR_STREAM_LIMIT_REACHED,
+                    "No stream IDs left for named streams",
+                )
+                .into());
+            }
+        }
+        self.next_named_stream_id = stream_id.checked_add(1).unwrap_or(FIRST_NAMED_STREAM_ID);
+
+        self.create_stream(stream_id, INITIAL_STREAM_CAPACITY, None, Some(name))?;
+
+        Ok(stream_id)
+    }
+
+    pub fn named_stream_id(&self, name: &[u8]) -> Option<u32> {
+        self.stream_names.get(name).copied()
+    }
+
+    /// Like `create_new_stream`, but the stream expires after its own idle time rather than the
+    /// global key expiry. An existing stream keeps its own expiry.
+    pub fn create_new_stream_with_ttl(
+        &mut self,
+        stream_id: u32,
+        ttl_secs: u32,
+    ) -> anyhow::Result<bool> {
+        self.create_stream(
+            stream_id,
+            INITIAL_STREAM_CAPACITY,
+            Some(ttl_secs.into()),
+            None,
+        )
+    }
+
+    /// Creates each stream that doesn't exist yet, leaving existing streams intact. Returns the IDs
+    /// that already existed.
+    /// Fails without creating any if they wouldn't all fit under the stream limit.
+    pub fn create_multiple_streams(&mut self, stream_ids: &[u32]) -> anyhow::Result<Vec<u32>> {
+        let new_stream_ids: HashSet<u32> = stream_ids
+            .iter()
+            .copied()
+            .filter(|stream_id| !self.stream_map.contains_key(stream_id))
+            .collect();
+        self.ensure_stream_capacity(new_stream_ids.len())?;
+
+        let mut existing_stream_ids = Vec::new();
+        for &stream_id in stream_ids {
+            if self.stream_map.contains_key(&stream_id) {
+                existing_stream_ids.push(stream_id);
+            } else {
+                self.create_new_stream(stream_id)?;
+            }
+        }
+
+        Ok(existing_stream_ids)
+    }
+
+    pub fn fetch_stream_contents(&mut self, stream_id: u32) -> Option<Bytes> {
+        let stream = self.stream_map.get_mut(&stream_id)?;
+
+        // The contents are split off rather than copied. Once the caller drops them, the next
+        // enqueue reclaims the same allocation instead of growing a new buffer.
+        let stream_buffer = stream.buffer.split().freeze();
+        stream.base_offset += stream_buffer.len() as u64;
+        self.total_bytes -= stream_buffer.len();
+        if !stream_buffer.is_empty() {
+            self.wal.record(WalRecord::Trim {
+                stream_id,
+                base_offset: stream.base_offset,
+            });
+        }
+
+        stream.last_activity = utils::get_current_timestamp();
+
+        Some(stream_buffer)
+    }
+
+    /// Discards the buffered bytes without returning them, keeping the stream and its capacity.
+    /// Returns whether the stream exists.
+    pub fn clear_stream(&mut self, stream_id: u32) -> bool {
+        let Some(stream) = self.stream_map.get_mut(&stream_id) else {
+            return false;
+        };
+
+        stream.base_offset += stream.buffer.len() as u64;
+        self.total_bytes -= stream.buffer.len();
+        stream.buffer.clear();
+        stream.last_activity = utils::get_current_timestamp();
+        self.wal.record(WalRecord::Trim {
+            stream_id,
+            base_offset: stream.base_offset,
+        });
+
+        true
+    }
+
+    /// Drains the source stream's buffer onto the end of the destination's. Both streams are
+    /// left untouched unless the whole buffer is moved.
+    pub fn move_stream_contents(&mut self, source_id: u32, dest_id: u32) -> MoveOutcome {
+        let now = utils::get_current_timestamp();
+        if source_id == dest_id {
+            return match self.stream_map.get_mut(&source_id) {
+                Some(stream) => {
+                    stream.last_activity = now;
+                    MoveOutcome::Moved { dropped_bytes: 0 }
+                }
+                None => MoveOutcome::MissingStream(source_id),
+            };
+        }
+
+        let [source, dest] = self.stream_map.get_disjoint_mut([&source_id, &dest_id]);
+        let (Some(source), Some(dest)) = (source, dest) else {
+            let missing_id = if self.stream_map.contains_key(&source_id) {
+                dest_id
+            } else {
+                source_id
+            };
+            return MoveOutcome::MissingStream(missing_id);
+        };
+
+        let Some(dropped_bytes) =
+            dest.append(&source.buffer, self.max_stream_bytes, self.overflow_policy)
+        else {
+            return MoveOutcome::Rejected;
+        };
+        self.wal
+            .record_append(dest_id, &source.buffer, dropped_bytes, dest.base_offset);
+        source.base_offset += source.buffer.len() as u64;
+        source.buffer.clear();
+        self.wal.record(WalRecord::Trim {
+            stream_id: source_id,
+            base_offset: source.base_offset,
+        });
+        source.last_activity = now;
+        dest.last_activity = now;
+        self.total_bytes -= dropped_bytes;
+        self.push_to_subscribers(dest_id);
+        MoveOutcome::Moved { dropped_bytes }
+    }
+
+    pub fn fetch_stream_no_clear(&mut self, stream_id: u32) -> Option<Bytes> {
+        let stream = self.stream_map.get_mut(&stream_id)?;
+
+        let stream_buffer = Bytes::copy_from_slice(&stream.buffer);
+        stream.last_activity = utils::get_current_timestamp();
+
+        Some(stream_buffer)
+    }
+
+    /// Removes and returns at most `max_bytes` leading bytes, leaving the rest for later.
+    pub fn drain_prefix(&mut self, stream_id: u32, max_bytes: usize) -> Option<Bytes> {
+        let stream = self.stream_map.get_mut(&stream_id)?;
+
+        let end = max_bytes.min(stream.buffer.len());
+        let contents = stream.buffer.split_to(end).freeze();
+        stream.base_offset += end as u64;
+        self.total_bytes -= end;
+        self.wal.record(WalRecord::Trim {
+            stream_id,
+            base_offset: stream.base_offset,
+        });
+        stream.last_activity = utils::get_current_timestamp();
+
+        Some(contents)
+    }
+
+    /// Returns a copy of at most `max_bytes` leading bytes without clearing.
+    pub fn peek_stream(&mut self, stream_id: u32, max_bytes: usize) -> Option<Bytes> {
+        let stream = self.stream_map.get_mut(&stream_id)?;
+
+        let end = max_bytes.min(stream.buffer.len());
+        let contents = Bytes::copy_from_slice(&stream.buffer[..end]);
+        stream.last_activity = utils::get_current_timestamp();
+
+        Some(contents)
+    }
+
+    /// Returns a copy of `buffer[start..end]` without clearing, clamping `end` to the buffer
+    /// length. Inverted or entirely out-of-range ranges yield an empty buffer.
+    pub fn read_stream_range(&mut self, stream_id: u32, start: usize, end: usize) -> Option<Bytes> {
+        let stream = self.stream_map.get_mut(&stream_id)?;
+
+        let end = end.min(stream.buffer.len());
+        let range = Bytes::copy_from_slice(stream.buffer.get(start..end).unwrap_or_default());
+        stream.last_activity = utils::get_current_timestamp();
+
+        Some(range)
+    }
+
+    /// Registers a consumer with its cursor at the start of the buffer, so it sees everything
+    /// still buffered. Registering an existing consumer keeps its cursor. Returns whether the
+    /// stream exists.
+    pub fn register_consumer(&mut self, stream_id: u32, consumer_id: u32) -> bool {
+        let Some(stream) = self.stream_map.get_mut(&stream_id) else {
+            return false;
+        };
+
+        let cursor = *stream
+            .consumer_cursors
+            .entry(consumer_id)
+            .or_insert(stream.base_offset);
+        self.wal.record(WalRecord::SetCursor {
+            stream_id,
+            consumer_id,
+            cursor,
+        });
+        stream.last_activity = utils::get_current_timestamp();
+        true
+    }
+
+    /// Removing a consumer may release bytes only it had yet to read.
+    pub fn unregister_consumer(&mut self, stream_id: u32, consumer_id: u32) {
+        if let Some(stream) = self.stream_map.get_mut(&stream_id) {
+            if stream.consumer_cursors.remove(&consumer_id).is_some() {
+                self.wal.record(WalRecord::RemoveCursor {
+                    stream_id,
+                    consumer_id,
+                });
+                let removed = stream.compact();
+                self.total_bytes -= removed;
+                if removed > 0 {
+                    self.wal.record(WalRecord::Trim {
+                        stream_id,
+                        base_offset: stream.base_offset,
+                    });
+                }
+            }
+            stream.last_activity = utils::get_current_timestamp();
+        }
+    }
+
+    /// Returns up to `max_bytes` from the consumer's cursor without advancing it. Returns `None`
+    /// if the stream or consumer does not exist.
+    pub fn fetch_from_cursor(
+        &mut self,
+        stream_id: u32,
+        consumer_id: u32,
+        max_bytes: usize,
+    ) -> Option<Bytes> {
+        let stream = self.stream_map.get_mut(&stream_id)?;
+        let start = stream
+            .cursor_position(consumer_id)?
+            .min(stream.buffer.len());
+
+        let end = start.saturating_add(max_bytes).min(stream.buffer.len());
+        let contents = Bytes::copy_from_slice(&stream.buffer[start..end]);
+        stream.last_activity = utils::get_current_timestamp();
+
+        Some(contents)
+    }
+
+    /// Moves the consumer's cursor forward by up to `byte_count`, stopping at the end of the
+    /// buffer, then removes any bytes all consumers have now read.
+    pub fn advance_cursor(&mut self, stream_id: u32, consumer_id: u32, byte_count: usize) {
+        let Some(stream) = self.stream_map.get_mut(&stream_id) else {
+            return;
+        };
+        let Some(position) = stream.cursor_position(consumer_id) else {
+            return;
+        };
+
+        let new_position = position.saturating_add(byte_count).min(stream.buffer.len());
+        let cursor = stream.base_offset + new_position as u64;
+        stream.consumer_cursors.insert(consumer_id, cursor);
+        self.wal.record(WalRecord::SetCursor {
+            stream_id,
+            consumer_id,
+            cursor,
+        });
+        let removed = stream.compact();
+        self.total_bytes -= removed;
+        if removed > 0 {
+            self.wal.record(WalRecord::Trim {
+                stream_id,
+                base_offset: stream.base_offset,
+            });
+        }
+        stream.last_activity = utils::get_current_timestamp();
+    }
+
+    pub fn streams(&self) -> impl Iterator<Item = (u32, &Stream)> {
+        self.stream_map
+            .iter()
+            .map(|(stream_id, stream)| (*stream_id, stream))
+    }
+
+    /// Inserts a fully formed stream, replacing any stream with the same ID.
+    pub fn insert_stream(&mut self, stream_id: u32, stream: Stream) {
+        self.total_bytes += stream.buffer.len();
+        let name = stream.name.clone();
+        if let Some(previous_stream) = self.stream_map.insert(stream_id, stream) {
+            self.forget_stream(&previous_stream);
+        }
+        // A name belongs to one stream at a time, so any other stream holding it loses it.
+        if let Some(name) = name
+            && let Some(previous_id) = self.stream_names.insert(name, stream_id)
+            && previous_id != stream_id
+            && let Some(previous_stream) = self.stream_map.get_mut(&previous_id)
+        {
+            previous_stream.name = None;
+        }
+    }
+
+    /// Moves a stream to a new ID, keeping its contents, activity and everything else about it.
+    /// Fails rather than replace a stream that already has the new ID.
+    pub fn rename_stream(&mut self, old_id: u32, new_id: u32) -> anyhow::Result<()> {
+        if !self.stream_map.contains_key(&old_id) {
+            return Err(RequestError::unknown_stream(old_id).into());
+        }
+        if old_id == new_id {
+            return Ok(());
+        }
+        if self.stream_map.contains_key(&new_id) {
+            return Err(RequestError::new(
+                ERROR_STREAM_EXISTS,
+                format!("Stream {} already exists", new_id),
+            )
+            .into());
+        }
+
+        let stream = self
+            .stream_map
+            .remove(&old_id)
+            .expect("Stream was just checked");
+        if let Some(name) = &stream.name {
+            self.stream_names.insert(name.clone(), new_id);
+        }
+        // Requests waiting on the old ID respond now, as nothing will arrive there any more.
+        stream.wake_waiters();
+        self.stream_map.insert(new_id, stream);
+        self.wal.record(WalRecord::Rename { old_id, new_id });
+        if let Some(subscribers) = self.subscriptions.remove(&old_id) {
+            self.subscriptions.insert(new_id, subscribers);
+        }
+        for members in self.groups.values_mut() {
+            if members.remove(&old_id) {
+                members.insert(new_id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates an empty group, returning false if it already exists.
+    pub fn create_group(&mut self, group_id: u32) -> bool {
+        if self.groups.contains_key(&group_id) {
+            return false;
+        }
+        self.groups.insert(group_id, HashSet::new());
+        self.wal.record(WalRecord::CreateGroup { group_id });
+        true
+    }
+
+    /// Deletes a group, leaving its streams intact. Returns whether it existed.
+    pub fn delete_group(&mut self, group_id: u32) -> bool {
+        if self.groups.remove(&group_id).is_none() {
+            return false;
+        }
+        self.wal.record(WalRecord::DeleteGroup { group_id });
+        true
+    }
+
+    /// Makes the stream a member of the group. A stream can be in any number of groups, and
+    /// leaves all of them when it is deleted.
+    pub fn add_to_group(&mut self, group_id: u32, stream_id: u32) -> anyhow::Result<()> {
+        if !self.stream_map.contains_key(&stream_id) {
+            return Err(RequestError::unknown_stream(stream_id).into());
+        }
+        let members = self
+            .groups
+            .get_mut(&group_id)
+            .ok_or_else(|| RequestError::unknown_group(group_id))?;
+        if members.insert(stream_id) {
+            self.wal.record(WalRecord::AddToGroup {
+                group_id,
+                stream_id,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn remove_from_group(&mut self, group_id: u32, stream_id: u32) -> anyhow::Result<()> {
+        let members = self
+            .groups
+            .get_mut(&group_id)
+            .ok_or_else(|| RequestError::unknown_group(group_id))?;
+        if members.remove(&stream_id) {
+            self.wal.record(WalRecord::RemoveFromGroup {
+                group_id,
+                stream_id,
+            });
+        }
+        Ok(())
+    }
+
+    /// The IDs of the streams in the group, or `None` if it doesn't exist.
+    pub fn group_members(&self, group_id: u32) -> Option<&HashSet<u32>> {
+        self.groups.get(&group_id)
+    }
+
+    pub fn groups(&self) -> &HashMap<u32, HashSet<u32>> {
+        &self.groups
+    }
+
+    /// Inserts a group with its members, replacing any group with the same ID.
+    pub fn insert_group(&mut self, group_id: u32, members: HashSet<u32>) {
+        self.groups.insert(group_id, members);
+    }
+
+    /// Counts as activity on the stream without changing it, delaying its expiry. Returns whether
+    /// the stream exists.
+    pub fn touch_stream(&mut self, stream_id: u32) -> bool {
+        let Some(stream) = self.stream_map.get_mut(&stream_id) else {
+            return false;
+        };
+
+        stream.last_activity = utils::get_current_timestamp();
+        true
+    }
+
+    pub fn get_stream(&self, stream_id: u32) -> Option<&Stream> {
+        self.stream_map.get(&stream_id)
+    }
+
+    /// Callers must not change the buffer's length, as that would bypass byte accounting.
+    pub fn get_stream_mut(&mut self, stream_id: u32) -> Option<&mut Stream> {
+        self.stream_map.get_mut(&stream_id)
+    }
+
+    pub fn set_stream_metadata(&mut self, stream_id: u32, metadata: Bytes) -> anyhow::Result<()> {
+        if metadata.len() > MAX_STREAM_METADATA_SIZE {
+            return Err(RequestError::new(
+                ERROR_PAYLOAD_TOO_LARGE,
+                format!(
+                    "Stream metadata too large: {} bytes (max {})",
+                    metadata.len(),
+                    MAX_STREAM_METADATA_SIZE
+                ),
+            )
+            .into());
+        }
+
+        if let Some(stream) = self.stream_map.get_mut(&stream_id) {
+            self.wal.record(WalRecord::SetMetadata {
+                stream_id,
+                metadata: &metadata,
+            });
+            stream.metadata = metadata;
+            stream.last_activity = utils::get_current_timestamp();
+        }
+        Ok(())
+    }
+
+    pub fn get_stream_metadata(&self, stream_id: u32) -> Option<&Bytes> {
+        self.stream_map
+            .get(&stream_id)
+            .map(|stream| &stream.metadata)
+    }
+
+    /// Makes room for `additional` more bytes in the stream up front, so a burst of enqueues grows
+    /// its buffer at most once. Never reserves past the stream byte limit.
+    pub fn reserve_stream_capacity(&mut self, stream_id: u32, additional: usize) {
+        let Some(stream) = self.stream_map.get_mut(&stream_id) else {
+            return;
+        };
+        let additional = match self.max_stream_bytes {
+            0 => additional,
+            max_bytes => additional.min(max_bytes.saturating_sub(stream.buffer.len())),
+        };
+        stream.buffer.reserve(additional);
+    }
+
+    pub fn stream_exists(&self, stream_id: u32) -> bool {
+        self.stream_map.contains_key(&stream_id)
+    }
+
+    pub fn stream_count(&self) -> usize {
+        self.stream_map.len()
+    }
+
+    pub fn total_bytes(&self) -> usize {
+        self.total_bytes
+    }
+
+    /// Recomputes the total by summing every buffer, which is O(n) in the streams. Only meant
+    /// for checking `total_bytes`, which is kept up to date as streams change.
+    pub fn scanned_total_bytes(&self) -> usize {
+        self.stream_map
+            .values()
+            .map(|stream| stream.buffer.len())
+            .sum()
+    }
+
+    /// Clears every stream's buffer, keeping the streams themselves. Returns how many streams there
+    /// are.
+    pub fn flush_all(&mut self) -> usize {
+        let now = utils::get_current_timestamp();
+        for (stream_id, stream) in self.stream_map.iter_mut() {
+            if !stream.buffer.is_empty() {
+                stream.base_offset += stream.buffer.len() as u64;
+                stream.buffer.clear();
+                self.wal.record(WalRecord::Trim {
+                    stream_id: *stream_id,
+                    base_offset: stream.base_offset,
+                });
+            }
+            stream.last_activity = now;
+        }
+        self.total_bytes = 0;
+        self.stream_map.len()
+    }
+
+    /// Deletes every stream, returning how many there were.
+    pub fn delete_all(&mut self) -> usize {
+        self.remove_all_streams().len()
+    }
+
+    /// Like `delete_all`, but hands the streams back so the caller can free their buffers after
+    /// releasing the state lock.
+    pub fn remove_all_streams(&mut self) -> HashMap<u32, Stream> {
+        let streams = std::mem::replace(&mut self.stream_map, HashMap::with_capacity(1024));
+        for stream in streams.values() {
+            stream.wake_waiters();
+        }
+        self.stream_names.clear();
+        self.subscriptions.clear();
+        for members in self.groups.values_mut() {
+            members.clear();
+        }
+        self.total_bytes = 0;
+        self.wal.record(WalRecord::DeleteAll);
+        streams
+    }
+
+    pub fn delete_stream(&mut self, stream_id: u32) -> anyhow::Result<()> {
+        self.remove_stream(stream_id);
+        Ok(())
+    }
+
+    /// Like `delete_stream`, but hands the stream back so the caller can free its buffer after
+    /// releasing the state lock.
+    pub fn remove_stream(&mut self, stream_id: u32) -> Option<Stream> {
+        let stream = self.stream_map.remove(&stream_id)?;
+        self.forget_stream(&stream);
+        self.wal.record(WalRecord::Delete { stream_id });
+        self.subscriptions.remove(&stream_id);
+        for members in self.groups.values_mut() {
+            members.remove(&stream_id);
+        }
+        // Lets requests waiting on the stream respond now rather than at their timeout.
+        stream.wake_waiters();
+        Some(stream)
+    }
+
+    pub fn enqueue_single(
+        &mut self,
+        stream_id: u32,
+        data: &[u8],
+    ) -> anyhow::Result<EnqueueOutcome> {
+        self.enqueue_multiple(&[stream_id], data)
+    }
+
+    /// Inserts the data at the front of the stream instead of the back. This costs O(n) in the
+    /// bytes already buffered, so it's meant for occasional use such as requeueing data a consumer
+    /// couldn't process, not as a regular enqueue.
+    pub fn prepend_single(
+        &mut self,
+        stream_id: u32,
+        data: &[u8],
+    ) -> anyhow::Result<EnqueueOutcome> {
+        let mut outcome = EnqueueOutcome::default();
+        let stream = match self.stream_map.get_mut(&stream_id) {
+            Some(stream) => stream,
+            None => {
+                if !self.handle_missing_stream(stream_id, data, &mut outcome)? {
+                    return Ok(outcome);
+                }
+                self.stream_map
+                    .get_mut(&stream_id)
+                    .expect("Stream was just created")
+            }
+        };
+
+        if stream.prepend(data, self.max_stream_bytes) {
+            self.total_bytes += data.len();
+            self.wal.record(WalRecord::Prepend { stream_id, data });
+            stream.last_activity = utils::get_current_timestamp();
+            outcome.stream_count = 1;
+            self.push_to_subscribers(stream_id);
+        } else {
+            outcome.rejected_stream_ids.push(stream_id);
+            self.dead_letter(stream_id, data);
+        }
+        Ok(outcome)
+    }
+
+    /// Enqueues the data only if `seq` is strictly greater than the last sequence applied to the
+    /// stream, so retried enqueues are deduplicated. Returns whether the data was enqueued, which
+    /// it also isn't if the stream is full and the data was rejected.
+    pub fn enqueue_seq(&mut self, stream_id: u32, seq: u64, data: &[u8]) -> bool {
+        if self.missing_stream_policy == MissingStreamPolicy::Create
+            && !self.stream_map.contains_key(&stream_id)
+        {
+            let _ = self.create_new_stream(stream_id);
+        }
+        let Some(stream) = self.stream_map.get_mut(&stream_id) else {
+            if self.missing_stream_policy == MissingStreamPolicy::Error {
+                self.dead_letter(stream_id, data);
+            }
+            return false;
+        };
+
+        if stream.last_seq.is_some_and(|last_seq| seq <= last_seq) {
+            return false;
+        }
+
+        let Some(dropped) = stream.append(data, self.max_stream_bytes, self.overflow_policy) else {
+            self.dead_letter(stream_id, data);
+            return false;
+        };
+        stream.last_seq = Some(seq);
+        self.wal
+            .record_append(stream_id, data, dropped, stream.base_offset);
+        self.wal.record(WalRecord::SetSeq { stream_id, seq });
+        self.total_bytes += data.len();
+        self.total_bytes -= dropped;
+        stream.last_activity = utils::get_current_timestamp();
+        self.push_to_subscribers(stream_id);
+        true
+    }
+
+    pub fn enqueue_multiple(
+        &mut self,
+        stream_ids: &[u32],
+        data: &[u8],
+    ) -> anyhow::Result<EnqueueOutcome> {
+        let current_timestamp = utils::get_current_timestamp();
+        let mut outcome = EnqueueOutcome::default();
+        for stream_id in stream_ids {
+            let stream = match self.stream_map.get_mut(stream_id) {
+                Some(stream) => stream,
+                None => {
+                    if !self.handle_missing_stream(*stream_id, data, &mut outcome)? {
+                        continue;
+                    }
+                    self.stream_map
+                        .get_mut(stream_id)
+                        .expect("Stream was just created")
+                }
+            };
+            match stream.append(data, self.max_stream_bytes, self.overflow_policy) {
+                Some(dropped) => {
+                    stream.last_activity = current_timestamp;
+                    self.wal
+                        .record_append(*stream_id, data, dropped, stream.base_offset);
+                    self.total_bytes += data.len();
+                    self.total_bytes -= dropped;
+                    outcome.stream_count += 1;
+                    self.push_to_subscribers(*stream_id);
+                }
+                None => {
+                    outcome.rejected_stream_ids.push(*stream_id);
+                    self.dead_letter(*stream_id, data);
+                }
+            }
+        }
+        Ok(outcome)
+    }
+
+    /// Enqueues to every stream in `include_stream_ids` that isn't also in `exclude_stream_ids`.
+    pub fn enqueue_multiple_except(
+        &mut self,
+        include_stream_ids: &[u32],
+        exclude_stream_ids: &[u32],
+        data: &[u8],
+    ) -> anyhow::Result<EnqueueOutcome> {
+        let exclude_set: HashSet<u32> = exclude_stream_ids.iter().copied().collect();
+        let stream_ids: Vec<u32> = include_stream_ids
+            .iter()
+            .copied()
+            .filter(|stream_id| !exclude_set.contains(stream_id))
+            .collect();
+        self.enqueue_multiple(&stream_ids, data)
+    }
+
+    /// Enqueues to every existing stream whose ID `matches` accepts, in arbitrary order.
+    pub fn enqueue_matching(
+        &mut self,
+        matches: impl Fn(u32) -> bool,
+        data: &[u8],
+    ) -> anyhow::Result<EnqueueOutcome> {
+        let stream_ids: Vec<u32> = self
+            .stream_map
+            .keys()
+            .copied()
+            .filter(|stream_id| matches(*stream_id))
+            .collect();
+        self.enqueue_multiple(&stream_ids, data)
+    }
+
+    /// Appends each entry's data to its own stream, in entry order.
+    pub fn enqueue_batch(&mut self, entries: &[(u32, Bytes)]) -> anyhow::Result<EnqueueOutcome> {
+        let mut outcome = EnqueueOutcome::default();
+        for (stream_id, data) in entries {
+            let entry_outcome = self.enqueue_single(*stream_id, data)?;
+            outcome.stream_count += entry_outcome.stream_count;
+            outcome
+                .rejected_stream_ids
+                .extend(entry_outcome.rejected_stream_ids);
+            outcome
+                .missing_stream_ids
+                .extend(entry_outcome.missing_stream_ids);
+        }
+        Ok(outcome)
+    }
+
+    /// Streams are visited in arbitrary (hash map) order. Only the order of appends within each
+    /// stream is guaranteed, so nothing may rely on the order across streams.
+    pub fn enqueue_all(&mut self, data: &[u8]) -> anyhow::Result<EnqueueOutcome> {
+        self.enqueue_all_except(&[], data)
+    }
+
+    pub fn enqueue_all_except(
+        &mut self,
+        exclude_stream_ids: &[u32],
+        data: &[u8],
+    ) -> anyhow::Result<EnqueueOutcome> {
+        let exclude_set: HashSet<u32> = exclude_stream_ids.iter().copied().collect();
+        let current_timestamp = utils::get_current_timestamp();
+        let mut outcome = EnqueueOutcome::default();
+        for (stream_id, stream) in self.stream_map.iter_mut() {
+            if exclude_set.contains(stream_id) {
+                continue;
+            }
+            match stream.append(data, self.max_stream_bytes, self.overflow_policy) {
+                Some(dropped) => {
+                    stream.last_activity = current_timestamp;
+                    self.wal
+                        .record_append(*stream_id, data, dropped, stream.base_offset);
+                    self.total_bytes += data.len();
+                    self.total_bytes -= dropped;
+                    outcome.stream_count += 1;
+                }
+                None => outcome.rejected_stream_ids.push(*stream_id),
+            }
+        }
+        for stream_id in outcome.rejected_stream_ids.clone() {
+            self.dead_letter(stream_id, data);
+        }
+
+        let subscribed_stream_ids: Vec<u32> = self
+            .subscriptions
+            .keys()
+            .filter(|stream_id| !exclude_set.contains(stream_id))
+            .copied()
+            .collect();
+        for stream_id in subscribed_stream_ids {
+            self.push_to_subscribers(stream_id);
+        }
+        Ok(outcome)
+    }
+
+    /// Like `enqueue_all_except`, excluding the group's members.
+    pub fn enqueue_all_except_group(
+        &mut self,
+        group_id: u32,
+        data: &[u8],
+    ) -> anyhow::Result<EnqueueOutcome> {
+        let members: Vec<u32> = self
+            .groups
+            .get(&group_id)
+            .ok_or_else(|| RequestError::unknown_group(group_id))?
+            .iter()
+            .copied()
+            .collect();
+        self.enqueue_all_except(&members, data)
+    }
+
+    /// Returns up to `limit` stream IDs in ascending order, skipping the first `offset`.
+    pub fn list_stream_ids(&self, offset: usize, limit: usize) -> Vec<u32> {
+        let mut stream_ids = self.stream_map.keys().copied().collect::<Vec<u32>>();
+        stream_ids.sort_unstable();
+
+        stream_ids.into_iter().skip(offset).take(limit).collect()
+    }
+
+    /// Returns up to `limit` streams as (stream ID, idle seconds), ordered from least to most
+    /// recently active, or the reverse if `descending` is set.
+    #[cfg(feature = "admin")]
+    pub fn list_streams_by_activity(&self, limit: usize, descending: bool) -> Vec<(u32, u64)> {
+        let current_timestamp = utils::get_current_timestamp();
+
+        let mut streams = self
+            .stream_map
+            .iter()
+            .map(|(stream_id, stream)| (*stream_id, stream.last_activity))
+            .collect::<Vec<(u32, u64)>>();
+
+        // Ties are broken by stream ID so the order is deterministic.
+        let compare = |a: &(u32, u64), b: &(u32, u64)| {
+            let ordering = a.1.cmp(&b.1).then(a.0.cmp(&b.0));
+            if descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        };
+
+        // Only the first `limit` entries need to be fully sorted.
+        if limit < streams.len() {
+            streams.select_nth_unstable_by(limit, compare);
+            streams.truncate(limit);
+        }
+        streams.sort_unstable_by(compare);
+
+        streams
+            .into_iter()
+            .map(|(stream_id, last_activity)| {
+                (stream_id, current_timestamp.saturating_sub(last_activity))
+            })
+            .collect()
+    }
+
+    /// Verifies the state's invariants by scanning every stream, returning a description of
+    /// each discrepancy found. An empty report means the state is consistent.
+    #[cfg(feature = "admin")]
+    pub fn self_check(&self) -> Vec<String> {
+        let mut issues = Vec::new();
+        let current_timestamp = utils::get_current_timestamp();
+
+        let scanned_total_bytes = self.scanned_total_bytes();
+        if scanned_total_bytes != self.total_bytes {
+            issues.push(format!(
+                "total_bytes is {} but streams hold {} bytes",
+                self.total_bytes, scanned_total_bytes
+            ));
+        }
+
+        for (stream_id, stream) in &self.stream_map {
+            if stream.last_activity > current_timestamp {
+                issues.push(format!(
+                    "stream {} was last active {}s in the future",
+                    stream_id,
+                    stream.last_activity - current_timestamp
+                ));
+            }
+
+            // Cleanup runs periodically, so streams may outlive the expiry by up to that much.
+            let idle_time = current_timestamp.saturating_sub(stream.last_activity);
+            if !self.key_expiry.is_zero() && idle_time > self.key_expiry.as_secs() * 2 {
+                issues.push(format!(
+                    "stream {} has been idle for {}s and should have been pruned",
+                    stream_id, idle_time
+                ));
+            }
+
+            if stream.metadata.len() > MAX_STREAM_METADATA_SIZE {
+                issues.push(format!(
+                    "stream {} has {} bytes of metadata (max {})",
+                    stream_id,
+                    stream.metadata.len(),
+                    MAX_STREAM_METADATA_SIZE
+                ));
+            }
+
+            let end_offset = stream.base_offset + stream.buffer.len() as u64;
+            for (consumer_id, cursor) in &stream.consumer_cursors {
+                if *cursor > end_offset {
+                    issues.push(format!(
+                        "consumer {} on stream {} is at offset {}, past the end at {}",
+                        consumer_id, stream_id, cursor, end_offset
+                    ));
+                }
+            }
+            if let Some(lowest_cursor) = stream.consumer_cursors.values().min()
+                && *lowest_cursor > stream.base_offset
+            {
+                issues.push(format!(
+                    "stream {} still holds {} bytes every consumer has read",
+                    stream_id,
+                    (*lowest_cursor - stream.base_offset).min(stream.buffer.len() as u64)
+                ));
+            }
+        }
+
+        for (group_id, members) in &self.groups {
+            for stream_id in members {
+                if !self.stream_map.contains_key(stream_id) {
+                    issues.push(format!(
+                        "group {} lists stream {}, which doesn't exist",
+                        group_id, stream_id
+                    ));
+                }
+            }
+        }
+
+        issues
+    }
+
+    /// Overwrites the incremental byte counter, for tests exercising the self-check.
+    #[doc(hidden)]
+    pub fn set_total_bytes_unchecked(&mut self, total_bytes: usize) {
+        self.total_bytes = total_bytes;
+    }
+
+    pub fn wal(&self) -> &WalLog {
+        &self.wal
+    }
+
+    pub fn wal_mut(&mut self) -> &mut WalLog {
+        &mut self.wal
+    }
+
+    /// Redoes a change read back from the write-ahead log. Limits and policies were already
+    /// applied when the change was first made, so they aren't checked again, and changes to
+    /// streams that no longer exist are skipped.
+    pub fn apply_wal_record(&mut self, record: WalRecord) {
+        match record {
+            WalRecord::Create {
+                stream_id,
+                ttl,
+                name,
+            } => {
+                if !self.stream_map.contains_key(&stream_id) {
+                    let mut stream = Stream::new();
+                    stream.ttl = ttl;
+                    stream.name = name.map(Bytes::copy_from_slice);
+                    self.insert_stream(stream_id, stream);
+                }
+            }
+            WalRecord::Delete { stream_id } => {
+                self.remove_stream(stream_id);
+            }
+            WalRecord::DeleteAll => {
+                self.remove_all_streams();
+            }
+            WalRecord::Append { stream_id, data } => {
+                if let Some(stream) = self.stream_map.get_mut(&stream_id) {
+                    stream.buffer.extend_from_slice(data);
+                    stream.last_activity = utils::get_current_timestamp();
+                    self.total_bytes += data.len();
+                }
+            }
+            WalRecord::Prepend { stream_id, data } => {
+                if let Some(stream) = self.stream_map.get_mut(&stream_id) {
+                    stream.prepend(data, 0);
+                    stream.last_activity = utils::get_current_timestamp();
+                    self.total_bytes += data.len();
+                }
+            }
+            WalRecord::Trim {
+                stream_id,
+                base_offset,
+            } => {
+                if let Some(stream) = self.stream_map.get_mut(&stream_id)
+                    && base_offset > stream.base_offset
+                {
+                    let removed =
+                        ((base_offset - stream.base_offset) as usize).min(stream.buffer.len());
+                    stream.buffer.advance(removed);
+                    stream.base_offset = base_offset;
+                    stream.last_activity = utils::get_current_timestamp();
+                    self.total_bytes -= removed;
+                }
+            }
+            WalRecord::Rename { old_id, new_id } => {
+                let _ = self.rename_stream(old_id, new_id);
+            }
+            WalRecord::SetMetadata {
+                stream_id,
+                metadata,
+            } => {
+                if let Some(stream) = self.stream_map.get_mut(&stream_id) {
+                    stream.metadata = Bytes::copy_from_slice(metadata);
+                }
+            }
+            WalRecord::SetSeq { stream_id, seq } => {
+                if let Some(stream) = self.stream_map.get_mut(&stream_id) {
+                    stream.last_seq = Some(seq);
+                }
+            }
+            WalRecord::SetCursor {
+                stream_id,
+                consumer_id,
+                cursor,
+            } => {
+                if let Some(stream) = self.stream_map.get_mut(&stream_id) {
+                    stream.consumer_cursors.insert(consumer_id, cursor);
+                }
+            }
+            WalRecord::RemoveCursor {
+                stream_id,
+                consumer_id,
+            } => {
+                if let Some(stream) = self.stream_map.get_mut(&stream_id) {
+                    stream.consumer_cursors.remove(&consumer_id);
+                }
+            }
+            WalRecord::CreateGroup { group_id } => {
+                self.create_group(group_id);
+            }
+            WalRecord::DeleteGroup { group_id } => {
+                self.delete_group(group_id);
+            }
+            WalRecord::AddToGroup {
+                group_id,
+                stream_id,
+            } => {
+                let _ = self.add_to_group(group_id, stream_id);
+            }
+            WalRecord::RemoveFromGroup {
+                group_id,
+                stream_id,
+            } => {
+                let _ = self.remove_from_group(group_id, stream_id);
+            }
+        }
+    }
+
+    // Maintenance functions.
+    /// Deletes every stream idle for longer than the key expiry, returning how many were deleted.
+    pub fn prune_expired_streams(&mut self) -> anyhow::Result<usize> {
+        Ok(self.remove_expired_streams().len())
+    }
+
+    /// Like `prune_expired_streams`, but hands the streams back so the caller can free their
+    /// buffers after releasing the state lock.
+    pub fn remove_expired_streams(&mut self) -> Vec<Stream> {
+        let default_idle_time = self.key_expiry.as_secs();
+        let current_timestamp = utils::get_current_timestamp();
+
+        let expired_streams = self
+            .stream_map
+            .iter()
+            .filter(|(_, stream)| {
+                let idle_time = stream.ttl.unwrap_or(default_idle_time);
+                // A clock stepping backwards leaves last_activity in the future, which counts
+                // as no idle time rather than wrapping around to a huge one.
+                idle_time != 0 && current_timestamp.saturating_sub(stream.last_activity) > idle_time
+            })
+            .map(|(stream_id, _)| *stream_id)
+            .collect::<Vec<u32>>();
+
+        expired_streams
+            .into_iter()
+            .filter_map(|stream_id| self.remove_stream(stream_id))
+            .collect()
+    }
+
+    /// Deletes the least recently active streams until the buffered bytes are back under the
+    /// memory soft limit, handing them back like `remove_expired_streams`. Empty streams free
+    /// nothing, so they are kept, as is the dead letter stream.
+    pub fn remove_streams_over_memory_limit(&mut self) -> Vec<Stream> {
+        if !self.is_over_memory_soft_limit() {
+            return Vec::new();
+        }
+
+        let mut by_activity = self
+            .stream_map
+            .iter()
+            .filter(|(stream_id, stream)| {
+                !stream.buffer.is_empty() && Some(**stream_id) != self.dead_letter_stream
+            })
+            .map(|(stream_id, stream)| (stream.last_activity, *stream_id))
+            .collect::<Vec<_>>();
+        by_activity.sort_unstable();
+
+        let mut evicted_streams = Vec::new();
+        for (_, stream_id) in by_activity {
+            if !self.is_over_memory_soft_limit() {
+                break;
+            }
+            evicted_streams.extend(self.remove_stream(stream_id));
+        }
+        if evicted_streams.is_empty() {
+            return evicted_streams;
+        }
+        warn!(
+            streams = evicted_streams.len(),
+            bytes = evicted_streams
+                .iter()
+                .map(|stream| stream.buffer.len())
+                .sum::<usize>(),
+            "Evicted streams to get back under the memory soft limit"
+        );
+        evicted_streams
+    }
+}
@@ -0,0 +1,262 @@
+//! A line-based text protocol for poking at a running server by hand, e.g. with netcat. Each
+//! command is turned into the packet it stands for and handled like any other, so it is subject
+//! to the same limits (the rate limit included) and authentication. Every command takes the
+//! state lock and a write of its own, so this is for debugging only and never for real traffic.
+
+use crate::metrics::METRICS;
+use crate::rate_limit::RateLimiter;
+use crate::serialisation::{Bytes, Packet};
+use crate::server::{ConnectionInfo, ConnectionState, handle_client_packet};
+use crate::settings::Settings;
+use crate::state::ServerState;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::Mutex;
+use tokio::time::{sleep, timeout};
+
+/// The first bytes of a connection that switch it to the text protocol. Read as a binary length
+/// prefix they would declare a packet of over a gigabyte, so no real binary client sends them.
+pub const TEXT_PROTOCOL_MAGIC: &[u8; 4] = b"TEXT";
+
+const HELP: &str = "Commands: PING [nonce], CREATE <id>, DELETE <id>, ENQUEUE <id> <data>, GET <id>, \
+                    PEEK <id>, LEN <id>, LIST, AUTH <token>, HELP, QUIT";
+
+/// Parses a command line into the packet it stands for.
+pub fn parse_command(line: &str) -> Result<Packet, String> {
+    let (command, arguments) = line.split_once(' ').unwrap_or((line, ""));
+    let stream_id = || {
+        arguments
+            .trim()
+            .parse::<u32>()
+            .map_err(|_| format!("{} takes a stream ID", command.to_uppercase()))
+    };
+
+    let packet = match command.to_uppercase().as_str() {
+        "PING" => Packet::ClientPing {
+            nonce: match arguments.trim() {
+                "" => None,
+                nonce => Some(
+                    nonce
+                        .parse()
+                        .map_err(|_| "PING takes an optional numeric nonce".to_string())?,
+                ),
+            },
+        },
+        "CREATE" => Packet::ClientCreateNewStream {
+            stream_id: stream_id()?,
+        },
+        "DELETE" => Packet::ClientDeleteStream {
+            stream_id: stream_id()?,
+        },
+        "ENQUEUE" => {
+            // Everything after the ID is the data, spaces included.
+            let (stream_id, data) = arguments.split_once(' ').unwrap_or((arguments, ""));
+            Packet::ClientEnqueueSingleAck {
+                stream_id: stream_id
+                    .parse()
+                    .map_err(|_| "ENQUEUE takes a stream ID and data".to_string())?,
+                enqueue_data: Bytes::copy_from_slice(data.as_bytes()),
+            }
+        }
+        "GET" => Packet::ClientRequestStreamContents {
+            stream_id: stream_id()?,
+        },
+        "PEEK" => Packet::ClientPeekStreamContents {
+            stream_id: stream_id()?,
+            max_bytes: u32::MAX,
+        },
+        "LEN" => Packet::ClientStreamLength {
+            stream_id: stream_id()?,
+        },
+        "LIST" => Packet::ClientListStreams {
+            offset: 0,
+            limit: u32::MAX,
+        },
+        "AUTH" => Packet::ClientAuthenticate {
+            token: Bytes::copy_from_slice(arguments.as_bytes()),
+        },
+        "QUIT" => Packet::ClientGoodbye,
+        _ => return Err(format!("Unknown command. {}", HELP)),
+    };
+    Ok(packet)
+}
+
+/// Describes a response as a single line.
+pub fn format_response(response: &Packet) -> String {
+    match response {
+        Packet::ServerPong { nonce: None } => "PONG".to_string(),
+        Packet::ServerPong { nonce: Some(nonce) } => format!("PONG {}", nonce),
+        Packet::ServerGoodbye => "BYE".to_string(),
+        Packet::ServerStreamCreated { created: true, .. } => "OK created".to_string(),
+        Packet::ServerStreamCreated { created: false, .. } => "OK already exists".to_string(),
+        Packet::ServerEnqueueAck {
+            accepted: true,
+            new_length,
+            ..
+        } => format!("OK length={}", new_length),
+        // The reason was already given by a rejection or error of its own.
+        Packet::ServerEnqueueAck {
+            accepted: false, ..
+        } => "NOT ENQUEUED".to_string(),
+        Packet::ServerEnqueueRejected { stream_id } => format!("ERR stream {} is full", stream_id),
+        Packet::ServerEnqueueError { stream_id } => {
+            format!("ERR stream {} doesn't exist", stream_id)
+        }
+        Packet::ServerStreamContents { buffer_data } => {
+            format!("DATA {}", buffer_data.escape_ascii())
+        }
+        Packet::ServerStreamLength {
+            length,
+            exists: true,
+            ..
+        } => format!("LEN {}", length),
+        Packet::ServerStreamLength { exists: false, .. } => "ERR no such stream".to_string(),
+        Packet::ServerStreamList {
+            total_count,
+            stream_ids,
+        } => {
+            let stream_ids = stream_ids
+                .iter()
+                .map(u32::to_string)
+                .collect::<Vec<_>>()
+                .join(" ");
+            format!("STREAMS {} {}", total_count, stream_ids)
+                .trim_end()
+                .to_string()
+        }
+        Packet::ServerAuthResult { success: true } => "OK authenticated".to_string(),
+        Packet::ServerAuthResult { success: false } => "ERR wrong token".to_string(),
+        Packet::ServerError { code, message } => {
+            format!("ERR {} (code {})", message.escape_ascii(), code)
+        }
+        other => format!("{:?}", other),
+    }
+}
+
+/// Serves text commands until the client quits or disconnects. `pending` holds bytes already read
+/// after the magic.
+pub async fn handle_text_connection<S>(
+    mut stream: S,
+    state: Arc<Mutex<ServerState>>,
+    settings: &Settings,
+    info: ConnectionInfo,
+    mut pending: Vec<u8>,
+) -> anyhow::Result<()>
+where
+    S: AsyncReadExt + AsyncWriteExt + Unpin,
+{
+    let mut connection = ConnectionState {
+        info,
+        ..ConnectionState::default()
+    };
+    let mut rate_limiter = RateLimiter::new(settings.rate_limit);
+    let mut temp_buffer = vec![0u8; settings.read_chunk_size];
+    write_reply(
+        &mut stream,
+        "OK text protocol, for debugging only. Send HELP for the commands.\n",
+        settings,
+    )
+    .await?;
+
+    loop {
+        while let Some(line_end) = pending.iter().position(|byte| *byte == b'\n') {
+            let line = String::from_utf8_lossy(&pending[..line_end])
+                .trim()
+                .to_string();
+            pending.drain(..=line_end);
+            if line.is_empty() {
+                continue;
+            }
+            // Every command counts against FSDB_RATE_LIMIT like a binary packet would.
+            while !rate_limiter.try_acquire() {
+                sleep(rate_limiter.time_until_available()).await;
+            }
+
+            let mut reply = String::new();
+            if line.eq_ignore_ascii_case("HELP") {
+                reply.push_str(HELP);
+                reply.push('\n');
+            } else {
+                match parse_command(&line) {
+                    // Binary packets are held to FSDB_MAX_PAYLOAD_SIZE as they're parsed, which
+                    // text commands never are.
+                    Ok(Packet::ClientEnqueueSingleAck { enqueue_data, .. })
+                        if settings.payload_limit() != 0
+                            && enqueue_data.len() > settings.payload_limit() =>
+                    {
+                        reply.push_str(&format!(
+                            "ERR payload of {} bytes exceeds the {} byte limit\n",
+                            enqueue_data.len(),
+                            settings.payload_limit()
+                        ));
+                    }
+                    Ok(packet) => {
+                        let mut responses = Vec::new();
+                        METRICS.record_packet(packet.packet_id());
+                        handle_client_packet(
+                            &mut *state.lock().await,
+                            &mut connection,
+                            packet,
+                            &mut responses,
+                        )?;
+                        connection.released_streams.clear();
+                        // Commands that succeed silently still get an answer.
+                        if responses.is_empty() {
+                            reply.push_str("OK\n");
+                        }
+                        for response in &responses {
+                            reply.push_str(&format_response(response));
+                            reply.push('\n');
+                        }
+                    }
+                    Err(e) => {
+                        reply.push_str("ERR ");
+                        reply.push_str(&e);
+                        reply.push('\n');
+                    }
+                }
+            }
+
+            write_reply(&mut stream, &reply, settings).await?;
+            if connection.closing {
+                stream.shutdown().await?;
+                return Ok(());
+            }
+        }
+
+        if settings.max_read_buffer != 0 && pending.len() > settings.max_read_buffer {
+            write_reply(&mut stream, "ERR line too long\n", settings).await?;
+            return Err(anyhow::anyhow!("Text command exceeds the read limit"));
+        }
+
+        let read = stream.read(&mut temp_buffer);
+        let bytes_read = if settings.connection_idle_timeout.is_zero() {
+            read.await?
+        } else {
+            match timeout(settings.connection_idle_timeout, read).await {
+                Ok(result) => result?,
+                Err(_) => return Ok(()),
+            }
+        };
+        if bytes_read == 0 {
+            return Ok(());
+        }
+        pending.extend_from_slice(&temp_buffer[..bytes_read]);
+    }
+}
+
+async fn write_reply<S>(stream: &mut S, reply: &str, settings: &Settings) -> anyhow::Result<()>
+where
+    S: AsyncWriteExt + Unpin,
+{
+    let write = async {
+        stream.write_all(reply.as_bytes()).await?;
+        stream.flush().await
+    };
+    if settings.write_timeout.is_zero() {
+        write.await?;
+    } else {
+        timeout(settings.write_timeout, write).await??;
+    }
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use crate::settings::Settings;
+use tokio::net::TcpStream;
+#[cfg(feature = "tls")]
+use tokio::time::{Duration, timeout};
+
+#[cfg(feature = "tls")]
+pub use tokio_rustls::TlsAcceptor;
+
+/// Never constructed, so TCP connections are always plaintext without the tls feature.
+#[cfg(not(feature = "tls"))]
+#[derive(Clone)]
+pub enum TlsAcceptor {}
+
+#[cfg(feature = "tls")]
+pub type TlsStream = tokio_rustls::server::TlsStream<TcpStream>;
+
+#[cfg(not(feature = "tls"))]
+pub type TlsStream = TcpStream;
+
+/// How long a client has to complete the handshake, so a stalled one can't hold a connection slot.
+#[cfg(feature = "tls")]
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Builds the acceptor for TCP connections, or `None` if TLS isn't configured.
+#[cfg(feature = "tls")]
+pub fn acceptor_from_settings(settings: &Settings) -> anyhow::Result<Option<TlsAcceptor>> {
+    use anyhow::Context;
+    use std::sync::Arc;
+    use tokio_rustls::rustls::ServerConfig;
+    use tokio_rustls::rustls::pki_types::pem::PemObject;
+    use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+    let (Some(cert_path), Some(key_path)) = (&settings.tls_cert, &settings.tls_key) else {
+        return Ok(None);
+    };
+
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Failed to read TLS certificates from {}", cert_path))?;
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .with_context(|| format!("Failed to read TLS private key from {}", key_path))?;
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .context("Invalid TLS certificate or private key")?;
+
+    Ok(Some(TlsAcceptor::from(Arc::new(config))))
+}
+
+#[cfg(not(feature = "tls"))]
+pub fn acceptor_from_settings(_settings: &Settings) -> anyhow::Result<Option<TlsAcceptor>> {
+    Ok(None)
+}
+
+/// Performs the server side of the handshake.
+#[cfg(feature = "tls")]
+pub async fn accept(acceptor: &TlsAcceptor, stream: TcpStream) -> anyhow::Result<TlsStream> {
+    match timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+        Ok(result) => Ok(result?),
+        Err(_) => Err(anyhow::anyhow!("TLS handshake timed out")),
+    }
+}
+
+#[cfg(not(feature = "tls"))]
+pub async fn accept(acceptor: &TlsAcceptor, _stream: TcpStream) -> anyhow::Result<TlsStream> {
+    match *acceptor {}
+}
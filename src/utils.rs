@@ -1,3 +1,4 @@
+use std::io::Read;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 pub fn get_current_timestamp() -> u64 {
@@ -6,3 +7,10 @@ pub fn get_current_timestamp() -> u64 {
         .unwrap()
         .as_secs()
 }
+
+/// Reads `len` bytes from the OS's random source, for tokens clients mustn't be able to guess.
+pub fn random_bytes(len: usize) -> std::io::Result<Vec<u8>> {
+    let mut bytes = vec![0u8; len];
+    std::fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
@@ -0,0 +1,607 @@
+use crate::state::ServerState;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::time::{Duration, interval};
+use tracing::{debug, error, warn};
+
+const WAL_MAGIC: &[u8; 4] = b"FSDW";
+/// Bumped whenever the layout below changes, so older logs are refused rather than misread.
+const WAL_VERSION: u32 = 3;
+const HEADER_SIZE: usize = 8;
+const RECORD_HEADER_SIZE: usize = 12;
+
+const RECORD_CREATE: u8 = 0;
+const RECORD_DELETE: u8 = 1;
+const RECORD_DELETE_ALL: u8 = 2;
+const RECORD_APPEND: u8 = 3;
+const RECORD_PREPEND: u8 = 4;
+const RECORD_TRIM: u8 = 5;
+const RECORD_RENAME: u8 = 6;
+const RECORD_SET_METADATA: u8 = 7;
+const RECORD_SET_SEQ: u8 = 8;
+const RECORD_SET_CURSOR: u8 = 9;
+const RECORD_REMOVE_CURSOR: u8 = 10;
+const RECORD_CREATE_GROUP: u8 = 11;
+const RECORD_DELETE_GROUP: u8 = 12;
+const RECORD_ADD_TO_GROUP: u8 = 13;
+const RECORD_REMOVE_FROM_GROUP: u8 = 14;
+
+// Each generation is its own file, named by appending `.<generation>` to the WAL path.
+// Layout (little endian):
+//   magic [u8; 4], version u32, then for each record:
+//   payload length u64, payload CRC32 u32, payload.
+// A payload is the record type u8 followed by its fields, with data prefixed by its length u64
+// and optional fields by a has_<field> u8.
+
+/// A change to the streams, recorded as it happens so it can be redone after a crash. Records
+/// describe what happened to the buffers rather than the request that caused it, so replaying
+/// them doesn't depend on limits, policies or subscribers.
+#[derive(Debug, PartialEq, Eq)]
+pub enum WalRecord<'a> {
+    /// Carries what the stream was created with, as a TTL or name is never set on its own.
+    Create {
+        stream_id: u32,
+        ttl: Option<u64>,
+        name: Option<&'a [u8]>,
+    },
+    Delete {
+        stream_id: u32,
+    },
+    DeleteAll,
+    Append {
+        stream_id: u32,
+        data: &'a [u8],
+    },
+    Prepend {
+        stream_id: u32,
+        data: &'a [u8],
+    },
+    /// Everything before the absolute offset `base_offset` was removed from the front of the
+    /// stream, whether it was fetched, drained, cleared or dropped to make room.
+    Trim {
+        stream_id: u32,
+        base_offset: u64,
+    },
+    Rename {
+        old_id: u32,
+        new_id: u32,
+    },
+    SetMetadata {
+        stream_id: u32,
+        metadata: &'a [u8],
+    },
+    /// The last sequence number applied, so retries after a crash are still deduplicated.
+    SetSeq {
+        stream_id: u32,
+        seq: u64,
+    },
+    /// A consumer was registered or moved its cursor to the absolute offset `cursor`.
+    SetCursor {
+        stream_id: u32,
+        consumer_id: u32,
+        cursor: u64,
+    },
+    RemoveCursor {
+        stream_id: u32,
+        consumer_id: u32,
+    },
+    CreateGroup {
+        group_id: u32,
+    },
+    DeleteGroup {
+        group_id: u32,
+    },
+    AddToGroup {
+        group_id: u32,
+        stream_id: u32,
+    },
+    RemoveFromGroup {
+        group_id: u32,
+        stream_id: u32,
+    },
+}
+
+/// Encoded records that haven't been written to disk yet, all from one generation.
+pub struct WalSegment {
+    pub generation: u64,
+    pub data: Vec<u8>,
+}
+
+/// The in-memory side of the WAL, held by the state so every change is recorded under the same
+/// lock that makes it.
+#[derive(Default)]
+pub struct WalLog {
+    enabled: bool,
+    /// Advanced at every snapshot, which covers every earlier generation.
+    generation: u64,
+    segments: Vec<WalSegment>,
+}
+
+impl WalLog {
+    /// Nothing is recorded until the WAL is enabled, which happens after any replay so that
+    /// replayed changes aren't recorded a second time.
+    pub fn enable(&mut self) {
+        self.enabled = true;
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn set_generation(&mut self, generation: u64) {
+        self.generation = generation;
+    }
+
+    /// Starts a new generation, returning it. Must be called under the same lock as encoding the
+    /// snapshot, so the snapshot holds exactly the changes from earlier generations.
+    pub fn rotate(&mut self) -> u64 {
+        self.generation += 1;
+        self.generation
+    }
+
+    pub fn record(&mut self, record: WalRecord) {
+        if !self.enabled {
+            return;
+        }
+
+        if self
+            .segments
+            .last()
+            .is_none_or(|segment| segment.generation != self.generation)
+        {
+            self.segments.push(WalSegment {
+                generation: self.generation,
+                data: Vec::new(),
+            });
+        }
+        let segment = self.segments.last_mut().expect("Segment was just pushed");
+        encode_record(&mut segment.data, &record);
+    }
+
+    /// Records an append, along with the bytes the overflow policy dropped from the front.
+    pub fn record_append(&mut self, stream_id: u32, data: &[u8], dropped: usize, base_offset: u64) {
+        self.record(WalRecord::Append { stream_id, data });
+        if dropped > 0 {
+            self.record(WalRecord::Trim {
+                stream_id,
+                base_offset,
+            });
+        }
+    }
+
+    pub fn take_segments(&mut self) -> Vec<WalSegment> {
+        std::mem::take(&mut self.segments)
+    }
+}
+
+fn encode_record(buffer: &mut Vec<u8>, record: &WalRecord) {
+    let start = buffer.len();
+    buffer.extend_from_slice(&[0; RECORD_HEADER_SIZE]); // Filled in once the payload is known.
+
+    match record {
+        WalRecord::Create {
+            stream_id,
+            ttl,
+            name,
+        } => {
+            buffer.push(RECORD_CREATE);
+            buffer.extend_from_slice(&stream_id.to_le_bytes());
+            buffer.push(ttl.is_some() as u8);
+            buffer.extend_from_slice(&ttl.unwrap_or(0).to_le_bytes());
+            buffer.push(name.is_some() as u8);
+            encode_data(buffer, name.unwrap_or_default());
+        }
+        WalRecord::Delete { stream_id } => {
+            buffer.push(RECORD_DELETE);
+            buffer.extend_from_slice(&stream_id.to_le_bytes());
+        }
+        WalRecord::DeleteAll => buffer.push(RECORD_DELETE_ALL),
+        WalRecord::Append { stream_id, data } | WalRecord::Prepend { stream_id, data } => {
+            let record_type = match record {
+                WalRecord::Append { .. } => RECORD_APPEND,
+                _ => RECORD_PREPEND,
+            };
+            buffer.push(record_type);
+            buffer.extend_from_slice(&stream_id.to_le_bytes());
+            encode_data(buffer, data);
+        }
+        WalRecord::Trim {
+            stream_id,
+            base_offset,
+        } => {
+            buffer.push(RECORD_TRIM);
+            buffer.extend_from_slice(&stream_id.to_le_bytes());
+            buffer.extend_from_slice(&base_offset.to_le_bytes());
+        }
+        WalRecord::Rename { old_id, new_id } => {
+            buffer.push(RECORD_RENAME);
+            buffer.extend_from_slice(&old_id.to_le_bytes());
+            buffer.extend_from_slice(&new_id.to_le_bytes());
+        }
+        WalRecord::SetMetadata {
+            stream_id,
+            metadata,
+        } => {
+            buffer.push(RECORD_SET_METADATA);
+            buffer.extend_from_slice(&stream_id.to_le_bytes());
+            encode_data(buffer, metadata);
+        }
+        WalRecord::SetSeq { stream_id, seq } => {
+            buffer.push(RECORD_SET_SEQ);
+            buffer.extend_from_slice(&stream_id.to_le_bytes());
+            buffer.extend_from_slice(&seq.to_le_bytes());
+        }
+        WalRecord::SetCursor {
+            stream_id,
+            consumer_id,
+            cursor,
+        } => {
+            buffer.push(RECORD_SET_CURSOR);
+            buffer.extend_from_slice(&stream_id.to_le_bytes());
+            buffer.extend_from_slice(&consumer_id.to_le_bytes());
+            buffer.extend_from_slice(&cursor.to_le_bytes());
+        }
+        WalRecord::RemoveCursor {
+            stream_id,
+            consumer_id,
+        } => {
+            buffer.push(RECORD_REMOVE_CURSOR);
+            buffer.extend_from_slice(&stream_id.to_le_bytes());
+            buffer.extend_from_slice(&consumer_id.to_le_bytes());
+        }
+        WalRecord::CreateGroup { group_id } => {
+            buffer.push(RECORD_CREATE_GROUP);
+            buffer.extend_from_slice(&group_id.to_le_bytes());
+        }
+        WalRecord::DeleteGroup { group_id } => {
+            buffer.push(RECORD_DELETE_GROUP);
+            buffer.extend_from_slice(&group_id.to_le_bytes());
+        }
+        WalRecord::AddToGroup {
+            group_id,
+            stream_id,
+        }
+        | WalRecord::RemoveFromGroup {
+            group_id,
+            stream_id,
+        } => {
+            let record_type = match record {
+                WalRecord::AddToGroup { .. } => RECORD_ADD_TO_GROUP,
+                _ => RECORD_REMOVE_FROM_GROUP,
+            };
+            buffer.push(record_type);
+            buffer.extend_from_slice(&group_id.to_le_bytes());
+            buffer.extend_from_slice(&stream_id.to_le_bytes());
+        }
+    }
+
+    let payload_start = start + RECORD_HEADER_SIZE;
+    let payload_len = (buffer.len() - payload_start) as u64;
+    let checksum = crc32fast::hash(&buffer[payload_start..]);
+    buffer[start..start + 8].copy_from_slice(&payload_len.to_le_bytes());
+    buffer[start + 8..payload_start].copy_from_slice(&checksum.to_le_bytes());
+}
+
+fn encode_data(buffer: &mut Vec<u8>, data: &[u8]) {
+    buffer.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    buffer.extend_from_slice(data);
+}
+
+struct WalReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> WalReader<'a> {
+    fn read_bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.offset..self.offset.checked_add(len)?)?;
+        self.offset += len;
+        Some(bytes)
+    }
+
+    fn read_u8(&mut self) -> Option<u8> {
+        Some(self.read_bytes(1)?[0])
+    }
+
+    fn read_u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.read_bytes(4)?.try_into().ok()?))
+    }
+
+    fn read_u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.read_bytes(8)?.try_into().ok()?))
+    }
+
+    fn read_data(&mut self) -> Option<&'a [u8]> {
+        let data_len = usize::try_from(self.read_u64()?).ok()?;
+        self.read_bytes(data_len)
+    }
+
+    /// Reads the next record, or `None` if the rest of the data isn't a whole, intact record.
+    fn read_record(&mut self) -> Option<WalRecord<'a>> {
+        let payload_len = usize::try_from(self.read_u64()?).ok()?;
+        let checksum = self.read_u32()?;
+        let payload = self.read_bytes(payload_len)?;
+        if crc32fast::hash(payload) != checksum {
+            return None;
+        }
+
+        let mut reader = WalReader {
+            data: payload,
+            offset: 0,
+        };
+        let record = match reader.read_u8()? {
+            RECORD_CREATE => {
+                let stream_id = reader.read_u32()?;
+                let has_ttl = reader.read_u8()? != 0;
+                let ttl = reader.read_u64()?;
+                let has_name = reader.read_u8()? != 0;
+                let name = reader.read_data()?;
+                WalRecord::Create {
+                    stream_id,
+                    ttl: has_ttl.then_some(ttl),
+                    name: has_name.then_some(name),
+                }
+            }
+            RECORD_DELETE => WalRecord::Delete {
+                stream_id: reader.read_u32()?,
+            },
+            RECORD_DELETE_ALL => WalRecord::DeleteAll,
+            record_type @ (RECORD_APPEND | RECORD_PREPEND) => {
+                let stream_id = reader.read_u32()?;
+                let data = reader.read_data()?;
+                if record_type == RECORD_APPEND {
+                    WalRecord::Append { stream_id, data }
+                } else {
+                    WalRecord::Prepend { stream_id, data }
+                }
+            }
+            RECORD_TRIM => WalRecord::Trim {
+                stream_id: reader.read_u32()?,
+                base_offset: reader.read_u64()?,
+            },
+            RECORD_RENAME => WalRecord::Rename {
+                old_id: reader.read_u32()?,
+                new_id: reader.read_u32()?,
+            },
+            RECORD_SET_METADATA => WalRecord::SetMetadata {
+                stream_id: reader.read_u32()?,
+                metadata: reader.read_data()?,
+            },
+            RECORD_SET_SEQ => WalRecord::SetSeq {
+                stream_id: reader.read_u32()?,
+                seq: reader.read_u64()?,
+            },
+            RECORD_SET_CURSOR => WalRecord::SetCursor {
+                stream_id: reader.read_u32()?,
+                consumer_id: reader.read_u32()?,
+                cursor: reader.read_u64()?,
+            },
+            RECORD_REMOVE_CURSOR => WalRecord::RemoveCursor {
+                stream_id: reader.read_u32()?,
+                consumer_id: reader.read_u32()?,
+            },
+            RECORD_CREATE_GROUP => WalRecord::CreateGroup {
+                group_id: reader.read_u32()?,
+            },
+            RECORD_DELETE_GROUP => WalRecord::DeleteGroup {
+                group_id: reader.read_u32()?,
+            },
+            RECORD_ADD_TO_GROUP => WalRecord::AddToGroup {
+                group_id: reader.read_u32()?,
+                stream_id: reader.read_u32()?,
+            },
+            RECORD_REMOVE_FROM_GROUP => WalRecord::RemoveFromGroup {
+                group_id: reader.read_u32()?,
+                stream_id: reader.read_u32()?,
+            },
+            _ => return None,
+        };
+        (reader.offset == payload.len()).then_some(record)
+    }
+}
+
+fn generation_path(path: &Path, generation: u64) -> PathBuf {
+    let mut generation_path = path.as_os_str().to_owned();
+    generation_path.push(format!(".{}", generation));
+    PathBuf::from(generation_path)
+}
+
+/// Lists the WAL files for `path` as (generation, path), oldest first.
+fn wal_files(path: &Path) -> anyhow::Result<Vec<(u64, PathBuf)>> {
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let prefix = format!(
+        "{}.",
+        path.file_name()
+            .ok_or_else(|| anyhow::anyhow!("WAL path {} has no file name", path.display()))?
+            .to_string_lossy()
+    );
+
+    let entries = match std::fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        if let Some(generation) = file_name
+            .to_str()
+            .and_then(|name| name.strip_prefix(&prefix))
+            .and_then(|generation| generation.parse::<u64>().ok())
+        {
+            files.push((generation, entry.path()));
+        }
+    }
+    files.sort_unstable();
+    Ok(files)
+}
+
+/// Replays every WAL file from the state's generation onwards, on top of whatever the snapshot
+/// restored, leaving the state at the newest generation found. A record torn by a crash
+/// mid-write ends its file, which is truncated there so new records aren't appended after it.
+/// Returns how many records were replayed.
+pub fn replay_wal(path: &Path, state: &mut ServerState) -> anyhow::Result<usize> {
+    let mut replayed = 0;
+    for (generation, file_path) in wal_files(path)? {
+        // Older generations are already in the snapshot.
+        if generation < state.wal().generation() {
+            continue;
+        }
+
+        let data = std::fs::read(&file_path)?;
+        if data.len() < HEADER_SIZE {
+            // Torn before its header was complete, so it holds no records.
+            write_header(&mut File::create(&file_path)?)?;
+            continue;
+        }
+        if &data[..4] != WAL_MAGIC {
+            return Err(anyhow::anyhow!(
+                "{} is not a FastStreamDB WAL",
+                file_path.display()
+            ));
+        }
+        let version = u32::from_le_bytes(data[4..HEADER_SIZE].try_into()?);
+        if version != WAL_VERSION {
+            return Err(anyhow::anyhow!(
+                "Unsupported WAL version {} in {} (expected {})",
+                version,
+                file_path.display(),
+                WAL_VERSION
+            ));
+        }
+
+        let mut reader = WalReader {
+            data: &data,
+            offset: HEADER_SIZE,
+        };
+        let mut valid_len = reader.offset;
+        while let Some(record) = reader.read_record() {
+            state.apply_wal_record(record);
+            replayed += 1;
+            valid_len = reader.offset;
+        }
+        if valid_len != data.len() {
+            warn!(
+                path = %file_path.display(),
+                discarded_bytes = data.len() - valid_len,
+                "Truncating a torn WAL record"
+            );
+            let file = OpenOptions::new().write(true).open(&file_path)?;
+            file.set_len(valid_len as u64)?;
+            file.sync_all()?;
+        }
+        state.wal_mut().set_generation(generation);
+    }
+
+    Ok(replayed)
+}
+
+/// Removes the WAL files of generations before `generation`, once a snapshot covering them has
+/// been written.
+pub fn remove_wal_files_before(path: &Path, generation: u64) -> anyhow::Result<()> {
+    for (file_generation, file_path) in wal_files(path)? {
+        if file_generation < generation {
+            std::fs::remove_file(file_path)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_header(file: &mut File) -> anyhow::Result<()> {
+    file.write_all(WAL_MAGIC)?;
+    file.write_all(&WAL_VERSION.to_le_bytes())?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// The on-disk side of the WAL, appending records to the file of their generation.
+pub struct WalWriter {
+    path: PathBuf,
+    generation: u64,
+    file: File,
+}
+
+impl WalWriter {
+    /// Opens the file for `generation` for appending, creating it if needed. Replay the WAL
+    /// first, as that truncates any torn record new ones would otherwise follow.
+    pub fn open(path: &Path, generation: u64) -> anyhow::Result<Self> {
+        Ok(Self {
+            path: path.to_owned(),
+            generation,
+            file: Self::open_generation(path, generation)?,
+        })
+    }
+
+    fn open_generation(path: &Path, generation: u64) -> anyhow::Result<File> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(generation_path(path, generation))?;
+        if file.metadata()?.len() == 0 {
+            write_header(&mut file)?;
+        }
+        Ok(file)
+    }
+
+    /// Appends the segments in order, moving on to a new file whenever the generation changes,
+    /// and syncs them to disk.
+    pub fn write_segments(&mut self, segments: &[WalSegment]) -> anyhow::Result<()> {
+        for segment in segments {
+            if segment.generation != self.generation {
+                self.file.sync_data()?;
+                self.file = Self::open_generation(&self.path, segment.generation)?;
+                self.generation = segment.generation;
+            }
+            self.file.write_all(&segment.data)?;
+        }
+        self.file.sync_data()?;
+        Ok(())
+    }
+}
+
+/// Writes out and syncs every change recorded so far. The writer stays locked from taking the
+/// records until they're synced, so concurrent syncs can't write them out of order.
+pub async fn sync_wal(
+    state: &Mutex<ServerState>,
+    writer: &Arc<Mutex<WalWriter>>,
+) -> anyhow::Result<()> {
+    let mut writer = Arc::clone(writer).lock_owned().await;
+    let segments = state.lock().await.wal_mut().take_segments();
+    if segments.is_empty() {
+        return Ok(());
+    }
+
+    let bytes = segments
+        .iter()
+        .map(|segment| segment.data.len())
+        .sum::<usize>();
+    tokio::task::spawn_blocking(move || writer.write_segments(&segments)).await??;
+    debug!(bytes, "WAL synced");
+    Ok(())
+}
+
+/// Syncs the WAL every `period`, which bounds how much acknowledged data a crash can lose:
+/// responses are sent as soon as a change is recorded in memory, not once it is on disk.
+/// Records are written on a blocking thread, so connections aren't held up by disk I/O.
+pub async fn wal_task(
+    state: Arc<Mutex<ServerState>>,
+    writer: Arc<Mutex<WalWriter>>,
+    period: Duration,
+) {
+    let mut interval = interval(period);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+    loop {
+        interval.tick().await;
+        if let Err(e) = sync_wal(&state, &writer).await {
+            error!(error = %e, "Error syncing WAL");
+        }
+    }
+}
@@ -0,0 +1,111 @@
+#![cfg(feature = "admin")]
+
+use fast_stream_db::serialisation::{Bytes, ERROR_NOT_AUTHENTICATED, Packet};
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::state::ServerState;
+use fast_stream_db::utils;
+
+fn list_by_activity(state: &mut ServerState, limit: u32, descending: bool) -> Vec<(u32, u32)> {
+    let responses = handle_client_packets(
+        state,
+        vec![Packet::ClientListStreamsByActivity { limit, descending }],
+    )
+    .unwrap();
+
+    match responses.as_slice() {
+        [Packet::ServerStreamActivityList { entries }] => entries.clone(),
+        _ => panic!("Unexpected responses: {:?}", responses),
+    }
+}
+
+/// Creates streams 1 to 4, last touched 40, 10, 30 and 20 seconds ago respectively.
+fn state_with_staggered_activity() -> ServerState {
+    let mut state = ServerState::new();
+    let now = utils::get_current_timestamp();
+
+    for (stream_id, idle_secs) in [(1, 40), (2, 10), (3, 30), (4, 20)] {
+        state.create_new_stream(stream_id).unwrap();
+        state.get_stream_mut(stream_id).unwrap().last_activity = now - idle_secs;
+    }
+
+    state
+}
+
+fn stream_ids(entries: &[(u32, u32)]) -> Vec<u32> {
+    entries.iter().map(|(stream_id, _)| *stream_id).collect()
+}
+
+#[test]
+fn ascending_lists_least_recently_active_first() {
+    let mut state = state_with_staggered_activity();
+
+    let entries = list_by_activity(&mut state, 10, false);
+
+    assert_eq!(stream_ids(&entries), vec![1, 3, 4, 2]);
+    // Allow for the clock ticking over during the test.
+    assert!(entries[0].1 >= 40 && entries[0].1 <= 41);
+}
+
+#[test]
+fn descending_lists_most_recently_active_first() {
+    let mut state = state_with_staggered_activity();
+
+    let entries = list_by_activity(&mut state, 10, true);
+
+    assert_eq!(stream_ids(&entries), vec![2, 4, 3, 1]);
+}
+
+#[test]
+fn limit_bounds_the_returned_count() {
+    let mut state = state_with_staggered_activity();
+
+    assert_eq!(
+        stream_ids(&list_by_activity(&mut state, 2, false)),
+        vec![1, 3]
+    );
+    assert_eq!(
+        stream_ids(&list_by_activity(&mut state, 2, true)),
+        vec![2, 4]
+    );
+    assert!(list_by_activity(&mut state, 0, false).is_empty());
+}
+
+#[test]
+fn listing_by_activity_needs_the_admin_token() {
+    let mut state = state_with_staggered_activity();
+    state.set_admin_token(Some(Bytes::from_static(b"secret")));
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![Packet::ClientListStreamsByActivity {
+            limit: 10,
+            descending: false,
+        }],
+    )
+    .unwrap();
+    match responses.as_slice() {
+        [Packet::ServerError { code, .. }] => assert_eq!(*code, ERROR_NOT_AUTHENTICATED),
+        _ => panic!("Unexpected responses: {:?}", responses),
+    }
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![
+            Packet::ClientAuthenticate {
+                token: Bytes::from_static(b"secret"),
+            },
+            Packet::ClientListStreamsByActivity {
+                limit: 10,
+                descending: false,
+            },
+        ],
+    )
+    .unwrap();
+    assert!(matches!(
+        responses.as_slice(),
+        [
+            Packet::ServerAuthResult { success: true },
+            Packet::ServerStreamActivityList { entries },
+        ] if entries.len() == 4
+    ));
+}
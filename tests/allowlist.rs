@@ -0,0 +1,91 @@
+mod common;
+
+use common::{connect_tcp, free_tcp_port, leak_settings, new_state};
+use fast_stream_db::server::run_tcp_server;
+use fast_stream_db::settings::{ConnectionMode, IpNetwork, Settings};
+use std::net::IpAddr;
+use std::str::FromStr;
+
+fn ip(s: &str) -> IpAddr {
+    IpAddr::from_str(s).unwrap()
+}
+
+fn settings_allowing(networks: &[&str]) -> Settings {
+    Settings {
+        listeners: vec![ConnectionMode::Tcp],
+        allowed_ips: networks
+            .iter()
+            .map(|network| IpNetwork::from_str(network).unwrap())
+            .collect(),
+        ..Settings::default()
+    }
+}
+
+#[test]
+fn networks_match_addresses_within_their_prefix() {
+    let network = IpNetwork::from_str("10.1.0.0/16").unwrap();
+    assert!(network.contains(ip("10.1.255.3")));
+    assert!(!network.contains(ip("10.2.0.1")));
+
+    let host = IpNetwork::from_str("192.168.0.7").unwrap();
+    assert!(host.contains(ip("192.168.0.7")));
+    assert!(!host.contains(ip("192.168.0.8")));
+
+    let everything = IpNetwork::from_str("0.0.0.0/0").unwrap();
+    assert!(everything.contains(ip("203.0.113.9")));
+
+    let v6 = IpNetwork::from_str("fd00::/8").unwrap();
+    assert!(v6.contains(ip("fd12::1")));
+    assert!(!v6.contains(ip("10.0.0.1")));
+
+    // IPv4 peers seen through a dual-stack socket are matched as IPv4.
+    assert!(network.contains(ip("::ffff:10.1.0.1")));
+}
+
+#[test]
+fn invalid_networks_are_rejected() {
+    assert!(IpNetwork::from_str("10.0.0.0/33").is_err());
+    assert!(IpNetwork::from_str("10.0.0/8").is_err());
+    assert!(IpNetwork::from_str("::/129").is_err());
+    assert!(IpNetwork::from_str("10.0.0.0/abc").is_err());
+}
+
+#[test]
+fn empty_allowlist_allows_everyone() {
+    let settings = settings_allowing(&[]);
+    assert!(settings.is_ip_allowed(ip("198.51.100.1")));
+}
+
+#[tokio::test]
+async fn allowed_address_is_served() {
+    let port = free_tcp_port();
+    let settings = leak_settings(Settings {
+        tcp_port: port,
+        ..settings_allowing(&["127.0.0.0/8"])
+    });
+    tokio::spawn(run_tcp_server(
+        settings,
+        new_state(),
+        std::future::pending(),
+    ));
+
+    let mut client = connect_tcp(port).await;
+    client.sync().await;
+}
+
+#[tokio::test]
+async fn disallowed_address_is_closed() {
+    let port = free_tcp_port();
+    let settings = leak_settings(Settings {
+        tcp_port: port,
+        ..settings_allowing(&["10.0.0.0/8", "::1/128"])
+    });
+    tokio::spawn(run_tcp_server(
+        settings,
+        new_state(),
+        std::future::pending(),
+    ));
+
+    let mut client = connect_tcp(port).await;
+    assert!(client.is_closed().await);
+}
@@ -0,0 +1,111 @@
+use fast_stream_db::serialisation::{Bytes, ERROR_NOT_AUTHENTICATED, Packet};
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::state::ServerState;
+
+fn state_with_token() -> ServerState {
+    let mut state = ServerState::new();
+    state.set_admin_token(Some(Bytes::from_static(b"secret")));
+    state.create_new_stream(1).unwrap();
+    state
+}
+
+fn authenticate(token: &[u8]) -> Packet {
+    Packet::ClientAuthenticate {
+        token: Bytes::copy_from_slice(token),
+    }
+}
+
+#[test]
+fn privileged_packets_need_the_token() {
+    let mut state = state_with_token();
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![Packet::ClientDeleteStream { stream_id: 1 }],
+    )
+    .unwrap();
+
+    match responses.as_slice() {
+        [Packet::ServerError { code, .. }] => assert_eq!(*code, ERROR_NOT_AUTHENTICATED),
+        _ => panic!("Unexpected responses: {:?}", responses),
+    }
+    assert!(state.stream_exists(1));
+}
+
+#[test]
+fn authenticated_connection_may_send_privileged_packets() {
+    let mut state = state_with_token();
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![
+            authenticate(b"secret"),
+            Packet::ClientDeleteStream { stream_id: 1 },
+        ],
+    )
+    .unwrap();
+
+    assert_eq!(responses, vec![Packet::ServerAuthResult { success: true }]);
+    assert!(!state.stream_exists(1));
+}
+
+#[test]
+fn wrong_token_is_refused() {
+    let mut state = state_with_token();
+
+    for token in [&b"secreT"[..], b"secret2", b""] {
+        let responses = handle_client_packets(
+            &mut state,
+            vec![
+                authenticate(token),
+                Packet::ClientClearStream { stream_id: 1 },
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(responses[0], Packet::ServerAuthResult { success: false });
+        assert!(matches!(responses[1], Packet::ServerError { .. }));
+    }
+}
+
+#[test]
+fn unprivileged_packets_are_open_to_everyone() {
+    let mut state = state_with_token();
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![
+            Packet::ClientEnqueueSingle {
+                stream_id: 1,
+                enqueue_data: Bytes::from_static(b"data"),
+            },
+            Packet::ClientRequestStreamContents { stream_id: 1 },
+        ],
+    )
+    .unwrap();
+
+    assert_eq!(
+        responses,
+        vec![Packet::ServerStreamContents {
+            buffer_data: Bytes::from_static(b"data")
+        }]
+    );
+}
+
+#[test]
+fn without_a_token_every_client_is_trusted() {
+    let mut state = ServerState::new();
+    state.create_new_stream(1).unwrap();
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![
+            Packet::ClientDeleteStream { stream_id: 1 },
+            authenticate(b"anything"),
+        ],
+    )
+    .unwrap();
+
+    assert_eq!(responses, vec![Packet::ServerAuthResult { success: true }]);
+    assert!(!state.stream_exists(1));
+}
@@ -0,0 +1,135 @@
+mod common;
+
+use common::{TestClient, new_state};
+use fast_stream_db::serialisation::{Bytes, Packet};
+use std::time::{Duration, Instant};
+
+#[tokio::test]
+async fn returns_immediately_when_data_is_buffered() {
+    let mut client = TestClient::connect(new_state());
+
+    client.create_stream(1).await;
+    client
+        .send(&[
+            Packet::ClientEnqueueSingle {
+                stream_id: 1,
+                enqueue_data: Bytes::from_static(b"ready"),
+            },
+            Packet::ClientAwaitStreamContents {
+                stream_id: 1,
+                timeout_ms: 60_000,
+            },
+        ])
+        .await;
+
+    assert_eq!(
+        client.recv().await,
+        Packet::ServerStreamContents {
+            buffer_data: Bytes::from_static(b"ready")
+        }
+    );
+}
+
+#[tokio::test]
+async fn wakes_up_when_data_is_enqueued() {
+    let state = new_state();
+    let mut consumer = TestClient::connect(state.clone());
+    let mut producer = TestClient::connect(state);
+
+    consumer.create_stream(1).await;
+    consumer.sync().await;
+    let start = Instant::now();
+    consumer
+        .send(&[Packet::ClientAwaitStreamContents {
+            stream_id: 1,
+            timeout_ms: 60_000,
+        }])
+        .await;
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    producer
+        .send(&[Packet::ClientEnqueueSingle {
+            stream_id: 1,
+            enqueue_data: Bytes::from_static(b"pushed"),
+        }])
+        .await;
+
+    assert_eq!(
+        consumer.recv().await,
+        Packet::ServerStreamContents {
+            buffer_data: Bytes::from_static(b"pushed")
+        }
+    );
+    assert!(start.elapsed() < Duration::from_secs(10));
+}
+
+#[tokio::test]
+async fn times_out_with_empty_contents_before_later_packets() {
+    let mut client = TestClient::connect(new_state());
+
+    client.create_stream(1).await;
+    client
+        .send(&[
+            Packet::ClientAwaitStreamContents {
+                stream_id: 1,
+                timeout_ms: 50,
+            },
+            Packet::ClientPing { nonce: None },
+        ])
+        .await;
+
+    assert_eq!(
+        client.recv().await,
+        Packet::ServerStreamContents {
+            buffer_data: Bytes::new()
+        }
+    );
+    assert_eq!(client.recv().await, Packet::ServerPong { nonce: None });
+}
+
+#[tokio::test]
+async fn missing_stream_returns_immediately() {
+    let mut client = TestClient::connect(new_state());
+
+    client
+        .send(&[Packet::ClientAwaitStreamContents {
+            stream_id: 1,
+            timeout_ms: 60_000,
+        }])
+        .await;
+
+    assert_eq!(
+        client.recv().await,
+        Packet::ServerStreamContents {
+            buffer_data: Bytes::new()
+        }
+    );
+}
+
+#[tokio::test]
+async fn deleting_the_stream_ends_the_wait() {
+    let state = new_state();
+    let mut consumer = TestClient::connect(state.clone());
+    let mut other = TestClient::connect(state);
+
+    consumer.create_stream(1).await;
+    consumer.sync().await;
+    let start = Instant::now();
+    consumer
+        .send(&[Packet::ClientAwaitStreamContents {
+            stream_id: 1,
+            timeout_ms: 60_000,
+        }])
+        .await;
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    other
+        .send(&[Packet::ClientDeleteStream { stream_id: 1 }])
+        .await;
+
+    assert_eq!(
+        consumer.recv().await,
+        Packet::ServerStreamContents {
+            buffer_data: Bytes::new()
+        }
+    );
+    assert!(start.elapsed() < Duration::from_secs(10));
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use common::{MockStream, default_settings, leak_settings, new_state};
+use fast_stream_db::serialisation::{Packet, serialise_packets};
+use fast_stream_db::server::{ConnectionInfo, handle_connection};
+use fast_stream_db::settings::Settings;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time::{Duration, sleep};
+
+#[tokio::test]
+async fn slow_reader_is_throttled_rather_than_disconnected() {
+    const PING_COUNT: usize = 100_000;
+    let pong_size = serialise_packets(&[Packet::ServerPong { nonce: None }])
+        .unwrap()
+        .len();
+
+    // Far smaller than either direction's traffic, so both sides fill up and block.
+    let (client, server) = tokio::io::duplex(4096);
+    let server = tokio::spawn(handle_connection(
+        server,
+        new_state(),
+        default_settings(),
+        ConnectionInfo::new("test"),
+    ));
+    let (mut client_reader, mut client_writer) = tokio::io::split(client);
+
+    let writer = tokio::spawn(async move {
+        let pings =
+            serialise_packets(&vec![Packet::ClientPing { nonce: None }; PING_COUNT]).unwrap();
+        client_writer.write_all(&pings).await.unwrap();
+        client_writer.shutdown().await.unwrap();
+    });
+
+    // The client isn't reading, so the server stops reading too once its writes back up.
+    sleep(Duration::from_millis(100)).await;
+    assert!(!writer.is_finished());
+    assert!(!server.is_finished());
+
+    let mut responses = Vec::new();
+    client_reader.read_to_end(&mut responses).await.unwrap();
+
+    writer.await.unwrap();
+    server.await.unwrap().unwrap();
+    assert_eq!(responses.len(), PING_COUNT * pong_size);
+}
+
+#[tokio::test]
+async fn stalled_writes_only_hold_up_handling_once_the_queue_is_full() {
+    let settings = leak_settings(Settings {
+        max_buffered_responses: 1,
+        write_timeout: Duration::from_millis(200),
+        ..Settings::default()
+    });
+    let state = new_state();
+    let mut packets = vec![
+        Packet::ClientPing { nonce: None },
+        Packet::ClientCreateNewStream { stream_id: 1 },
+    ];
+    // Far more responses than the writer's queue holds.
+    packets.extend(std::iter::repeat_n(
+        Packet::ClientPing { nonce: None },
+        1000,
+    ));
+    packets.push(Packet::ClientCreateNewStream { stream_id: 2 });
+    let stream = MockStream::with_stalled_writes(serialise_packets(&packets).unwrap());
+
+    let result = handle_connection(
+        stream,
+        Arc::clone(&state),
+        settings,
+        ConnectionInfo::new("test"),
+    )
+    .await;
+
+    assert!(result.is_err());
+    // Handled while the first pong's write was stuck.
+    assert!(state.lock().await.stream_exists(1));
+    assert!(!state.lock().await.stream_exists(2));
+}
@@ -0,0 +1,65 @@
+use fast_stream_db::serialisation::{Bytes, Packet};
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::state::ServerState;
+
+fn acked_stream_count(state: &mut ServerState, packet: Packet) -> u32 {
+    let responses = handle_client_packets(state, vec![packet]).unwrap();
+
+    match responses.as_slice() {
+        [Packet::ServerEnqueueAllAck { stream_count }] => *stream_count,
+        _ => panic!("Unexpected responses: {:?}", responses),
+    }
+}
+
+#[test]
+fn broadcast_ack_reports_streams_written() {
+    let mut state = ServerState::new();
+    for stream_id in 0..5 {
+        state.create_new_stream(stream_id).unwrap();
+    }
+
+    let stream_count = acked_stream_count(
+        &mut state,
+        Packet::ClientEnqueueAllAck {
+            enqueue_data: Bytes::from_static(b"hi"),
+        },
+    );
+
+    assert_eq!(stream_count, 5);
+    for stream_id in 0..5 {
+        assert_eq!(state.fetch_stream_contents(stream_id).unwrap(), &b"hi"[..]);
+    }
+}
+
+#[test]
+fn broadcast_except_ack_excludes_filtered_streams() {
+    let mut state = ServerState::new();
+    for stream_id in 0..5 {
+        state.create_new_stream(stream_id).unwrap();
+    }
+
+    // Excluding a stream that doesn't exist must not affect the count.
+    let stream_count = acked_stream_count(
+        &mut state,
+        Packet::ClientEnqueueAllExceptAck {
+            enqueue_data: Bytes::from_static(b"hi"),
+            filter_stream_ids: vec![1, 3, 99],
+        },
+    );
+
+    assert_eq!(stream_count, 3);
+}
+
+#[test]
+fn broadcast_ack_reports_an_empty_relay() {
+    let mut state = ServerState::new();
+
+    let stream_count = acked_stream_count(
+        &mut state,
+        Packet::ClientEnqueueAllAck {
+            enqueue_data: Bytes::from_static(b"hi"),
+        },
+    );
+
+    assert_eq!(stream_count, 0);
+}
@@ -0,0 +1,44 @@
+use fast_stream_db::serialisation::{Packet, serialise_packets};
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::state::ServerState;
+use fast_stream_db::utils;
+use std::time::Duration;
+
+#[test]
+fn every_stream_is_reported_in_order_without_counting_as_activity() {
+    let mut state = ServerState::with_key_expiry(Duration::from_secs(60));
+    state.create_new_stream(1).unwrap();
+    state.create_new_stream(3).unwrap();
+    state.get_stream_mut(3).unwrap().last_activity = utils::get_current_timestamp() - 120;
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![Packet::ClientCheckStreamStateMultiple {
+            stream_ids: vec![3, 2, 1, 3],
+        }],
+    )
+    .unwrap();
+    state.prune_expired_streams().unwrap();
+
+    assert_eq!(
+        responses,
+        vec![Packet::ServerStreamStateMultiple {
+            results: vec![(3, true), (2, false), (1, true), (3, true)],
+        }]
+    );
+    assert!(!state.stream_exists(3));
+}
+
+#[test]
+fn results_are_ids_and_four_byte_booleans() {
+    let data = serialise_packets(&[Packet::ServerStreamStateMultiple {
+        results: vec![(7, true)],
+    }])
+    .unwrap();
+
+    assert_eq!(
+        data[8..],
+        [1, 0, 0, 0, 7, 0, 0, 0, 1, 0, 0, 0],
+        "count, then the stream ID and a u32 boolean"
+    );
+}
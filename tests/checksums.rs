@@ -0,0 +1,154 @@
+mod common;
+
+use common::{MockStream, default_settings, new_state};
+use fast_stream_db::serialisation::{
+    Bytes, FEATURE_CHECKSUMS, Packet, PacketReadError, WireFormat, deserialise_packets_with_format,
+    read_packet_from_buffer_with_format, serialise_packets, serialise_packets_with_format,
+};
+use fast_stream_db::server::{ConnectionInfo, handle_connection};
+
+const CHECKSUMS: WireFormat = WireFormat {
+    checksums: true,
+    compression: false,
+    max_payload_size: 0,
+};
+
+fn packets() -> Vec<Packet> {
+    vec![
+        Packet::ClientPing { nonce: None },
+        Packet::ClientEnqueueSingle {
+            stream_id: 1,
+            enqueue_data: Bytes::from_static(b"checked"),
+        },
+        Packet::ServerStreamContents {
+            buffer_data: vec![0xAB; 300].into(),
+        },
+    ]
+}
+
+#[test]
+fn checksummed_packets_round_trip() {
+    let packets = packets();
+    let buffer = serialise_packets_with_format(&packets, CHECKSUMS).unwrap();
+    assert_eq!(
+        buffer.len(),
+        serialise_packets(&packets).unwrap().len() + 4 * packets.len()
+    );
+
+    let (deserialised, consumed_bytes) =
+        deserialise_packets_with_format(&buffer, CHECKSUMS).unwrap();
+
+    assert_eq!(deserialised, packets);
+    assert_eq!(consumed_bytes, buffer.len());
+}
+
+#[test]
+fn missing_checksum_is_incomplete() {
+    let buffer =
+        serialise_packets_with_format(&[Packet::ClientPing { nonce: None }], CHECKSUMS).unwrap();
+
+    let error = read_packet_from_buffer_with_format(&buffer[..buffer.len() - 1], 0, CHECKSUMS)
+        .err()
+        .unwrap();
+
+    assert_eq!(error.downcast_ref(), Some(&PacketReadError::Incomplete));
+}
+
+#[test]
+fn any_corrupted_byte_is_detected() {
+    let buffer = serialise_packets_with_format(&packets()[1..2], CHECKSUMS).unwrap();
+
+    // The length prefix is left alone, as corrupting it makes the packet incomplete instead.
+    for index in 4..buffer.len() {
+        let mut corrupted = buffer.clone();
+        corrupted[index] ^= 0x01;
+
+        let error = read_packet_from_buffer_with_format(&corrupted, 0, CHECKSUMS)
+            .err()
+            .unwrap_or_else(|| panic!("Corrupting byte {} went unnoticed", index));
+        assert_eq!(
+            error.downcast_ref(),
+            Some(&PacketReadError::Invalid(
+                "Packet checksum mismatch".to_string()
+            ))
+        );
+    }
+}
+
+#[tokio::test]
+async fn negotiation_switches_framing_after_its_reply() {
+    let mut input = serialise_packets(&[Packet::ClientNegotiateFeatures {
+        features: FEATURE_CHECKSUMS | 0x8000_0000,
+    }])
+    .unwrap();
+    input.extend_from_slice(
+        &serialise_packets_with_format(
+            &[
+                Packet::ClientCreateNewStream { stream_id: 1 },
+                Packet::ClientEnqueueSingle {
+                    stream_id: 1,
+                    enqueue_data: Bytes::from_static(b"checked"),
+                },
+                Packet::ClientRequestStreamContents { stream_id: 1 },
+            ],
+            CHECKSUMS,
+        )
+        .unwrap(),
+    );
+    let mut stream = MockStream::new(input);
+
+    handle_connection(
+        &mut stream,
+        new_state(),
+        default_settings(),
+        ConnectionInfo::new("test"),
+    )
+    .await
+    .unwrap();
+
+    let mut expected = serialise_packets(&[Packet::ServerFeatures {
+        features: FEATURE_CHECKSUMS,
+    }])
+    .unwrap();
+    expected.extend_from_slice(
+        &serialise_packets_with_format(
+            &[
+                Packet::ServerStreamCreated {
+                    stream_id: 1,
+                    created: true,
+                },
+                Packet::ServerStreamContents {
+                    buffer_data: Bytes::from_static(b"checked"),
+                },
+            ],
+            CHECKSUMS,
+        )
+        .unwrap(),
+    );
+    assert_eq!(stream.flushes.concat(), expected);
+}
+
+#[tokio::test]
+async fn checksum_mismatch_closes_the_connection() {
+    let mut input = serialise_packets(&[Packet::ClientNegotiateFeatures {
+        features: FEATURE_CHECKSUMS,
+    }])
+    .unwrap();
+    let mut corrupted =
+        serialise_packets_with_format(&[Packet::ClientCreateNewStream { stream_id: 1 }], CHECKSUMS)
+            .unwrap();
+    corrupted[8] ^= 0x01;
+    input.extend_from_slice(&corrupted);
+    let state = new_state();
+
+    let result = handle_connection(
+        MockStream::new(input),
+        state.clone(),
+        default_settings(),
+        ConnectionInfo::new("test"),
+    )
+    .await;
+
+    assert!(result.is_err());
+    assert_eq!(state.lock().await.stream_count(), 0);
+}
@@ -0,0 +1,38 @@
+use fast_stream_db::serialisation::{Bytes, Packet};
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::state::ServerState;
+
+#[test]
+fn clearing_discards_data_but_keeps_the_stream() {
+    let mut state = ServerState::new();
+    state.create_new_stream_with_ttl(1, 0).unwrap();
+    state
+        .set_stream_metadata(1, Bytes::from_static(b"metadata"))
+        .unwrap();
+    state.enqueue_single(1, b"stale").unwrap();
+    state.create_new_stream(2).unwrap();
+    state.enqueue_single(2, b"other").unwrap();
+
+    let responses =
+        handle_client_packets(&mut state, vec![Packet::ClientClearStream { stream_id: 1 }])
+            .unwrap();
+
+    assert!(responses.is_empty());
+    let stream = state.get_stream(1).unwrap();
+    assert!(stream.buffer.is_empty());
+    assert_eq!(stream.base_offset, 5);
+    assert_eq!(stream.ttl, Some(0));
+    assert_eq!(stream.metadata, &b"metadata"[..]);
+    assert_eq!(state.total_bytes(), 5);
+
+    state.enqueue_single(1, b"fresh").unwrap();
+    assert_eq!(state.fetch_stream_contents(1).unwrap(), &b"fresh"[..]);
+}
+
+#[test]
+fn clearing_a_missing_stream_does_nothing() {
+    let mut state = ServerState::new();
+
+    assert!(!state.clear_stream(1));
+    assert_eq!(state.stream_count(), 0);
+}
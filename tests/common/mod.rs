@@ -0,0 +1,249 @@
+#![allow(dead_code)]
+
+use fast_stream_db::serialisation::{
+    Bytes, Packet, PacketReadError, read_packet_from_buffer, serialise_packets,
+};
+use fast_stream_db::server::{ConnectionInfo, handle_connection};
+use fast_stream_db::settings::Settings;
+use fast_stream_db::state::ServerState;
+use std::pin::Pin;
+use std::sync::{Arc, LazyLock};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream, ReadBuf};
+use tokio::net::{TcpStream, UnixStream};
+use tokio::sync::Mutex;
+use tokio::time::{Duration, sleep};
+
+/// A protocol-speaking client, by default connected in-memory to a freshly
+/// spawned connection handler.
+pub struct TestClient<S = DuplexStream> {
+    stream: S,
+    // Received bytes not yet returned as a packet.
+    pending: Vec<u8>,
+}
+
+impl TestClient {
+    pub fn connect(state: Arc<Mutex<ServerState>>) -> Self {
+        Self::connect_with_settings(state, default_settings())
+    }
+
+    pub fn connect_with_settings(
+        state: Arc<Mutex<ServerState>>,
+        settings: &'static Settings,
+    ) -> Self {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(async move {
+            let _ = handle_connection(server, state, settings, ConnectionInfo::new("test")).await;
+        });
+
+        Self::from_stream(client)
+    }
+}
+
+impl<S> TestClient<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    pub fn from_stream(stream: S) -> Self {
+        Self {
+            stream,
+            pending: Vec::new(),
+        }
+    }
+
+    pub async fn send(&mut self, packets: &[Packet]) {
+        let data = serialise_packets(packets).unwrap();
+        self.send_raw(&data).await;
+    }
+
+    pub async fn send_raw(&mut self, data: &[u8]) {
+        self.stream.write_all(data).await.unwrap();
+    }
+
+    /// Reads exactly one server packet, waiting for more data while it is incomplete.
+    pub async fn recv(&mut self) -> Packet {
+        loop {
+            match read_packet_from_buffer(&self.pending, 0) {
+                Ok(result) => {
+                    self.pending.drain(..result.new_offset);
+                    return result.value;
+                }
+                Err(e) if e.downcast_ref() == Some(&PacketReadError::Incomplete) => {
+                    let mut chunk = [0u8; 4096];
+                    let bytes_read = self.stream.read(&mut chunk).await.unwrap();
+                    assert!(bytes_read > 0, "Connection closed mid-packet");
+                    self.pending.extend_from_slice(&chunk[..bytes_read]);
+                }
+                Err(e) => panic!("Invalid packet from server: {}", e),
+            }
+        }
+    }
+
+    /// Sends a ping and waits for the pong, guaranteeing every packet sent
+    /// before it has been processed by the server.
+    pub async fn sync(&mut self) {
+        self.send(&[Packet::ClientPing { nonce: None }]).await;
+        match self.recv().await {
+            Packet::ServerPong { .. } => {}
+            _ => panic!("Expected a pong"),
+        }
+    }
+
+    /// Creates a stream that must not exist yet, waiting for the server to confirm it.
+    pub async fn create_stream(&mut self, stream_id: u32) {
+        self.send(&[Packet::ClientCreateNewStream { stream_id }])
+            .await;
+        assert_eq!(
+            self.recv().await,
+            Packet::ServerStreamCreated {
+                stream_id,
+                created: true,
+            }
+        );
+    }
+
+    /// Returns whether the server has closed the connection.
+    pub async fn is_closed(&mut self) -> bool {
+        let mut byte = [0u8; 1];
+        matches!(self.stream.read(&mut byte).await, Ok(0) | Err(_))
+    }
+}
+
+/// A `ClientEnqueueSingle` of `data` to the stream.
+pub fn enqueue(stream_id: u32, data: &[u8]) -> Packet {
+    Packet::ClientEnqueueSingle {
+        stream_id,
+        enqueue_data: Bytes::copy_from_slice(data),
+    }
+}
+
+pub fn default_settings() -> &'static Settings {
+    static SETTINGS: LazyLock<Settings> = LazyLock::new(Settings::default);
+    &SETTINGS
+}
+
+pub fn leak_settings(settings: Settings) -> &'static Settings {
+    Box::leak(Box::new(settings))
+}
+
+pub fn new_state() -> Arc<Mutex<ServerState>> {
+    Arc::new(Mutex::new(ServerState::new()))
+}
+
+/// Connects to a server socket, retrying while the server is still starting up.
+pub async fn connect_unix(path: &str) -> TestClient<UnixStream> {
+    for _ in 0..100 {
+        if let Ok(stream) = UnixStream::connect(path).await {
+            return TestClient::from_stream(stream);
+        }
+        sleep(Duration::from_millis(10)).await;
+    }
+    panic!("Server never started listening on {}", path);
+}
+
+pub async fn connect_tcp(port: u16) -> TestClient<TcpStream> {
+    for _ in 0..100 {
+        if let Ok(stream) = TcpStream::connect(("127.0.0.1", port)).await {
+            return TestClient::from_stream(stream);
+        }
+        sleep(Duration::from_millis(10)).await;
+    }
+    panic!("Server never started listening on port {}", port);
+}
+
+pub fn free_tcp_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+pub fn temp_socket_path(name: &str) -> String {
+    std::env::temp_dir()
+        .join(format!("fsdb-{}-{}.sock", name, std::process::id()))
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// A scripted connection: reads yield `input` followed by EOF, and each flush
+/// records the bytes written since the previous one.
+#[derive(Default)]
+pub struct MockStream {
+    input: Vec<u8>,
+    read_offset: usize,
+    // The most bytes returned by a single read, or unlimited if 0.
+    max_read_size: usize,
+    // Whether writes never complete, like a peer that has stopped reading.
+    stall_writes: bool,
+    unflushed: Vec<u8>,
+    pub flushes: Vec<Vec<u8>>,
+}
+
+impl MockStream {
+    pub fn new(input: Vec<u8>) -> Self {
+        Self {
+            input,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_max_read_size(input: Vec<u8>, max_read_size: usize) -> Self {
+        Self {
+            input,
+            max_read_size,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_stalled_writes(input: Vec<u8>) -> Self {
+        Self {
+            input,
+            stall_writes: true,
+            ..Self::default()
+        }
+    }
+}
+
+impl AsyncRead for MockStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let remaining = &self.input[self.read_offset..];
+        let mut size = remaining.len().min(buf.remaining());
+        if self.max_read_size != 0 {
+            size = size.min(self.max_read_size);
+        }
+        buf.put_slice(&remaining[..size]);
+        self.read_offset += size;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for MockStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        if self.stall_writes {
+            return Poll::Pending;
+        }
+        self.unflushed.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        if !self.unflushed.is_empty() {
+            let flushed = std::mem::take(&mut self.unflushed);
+            self.flushes.push(flushed);
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,249 @@
+#![cfg(feature = "compression")]
+
+mod common;
+
+use common::{MockStream, default_settings, new_state};
+use fast_stream_db::serialisation::{
+    Bytes, FEATURE_COMPRESSION, Packet, PacketReadError, WireFormat,
+    deserialise_packets_with_format, read_packet_from_buffer_with_format, serialise_packets,
+    serialise_packets_with_format,
+};
+use fast_stream_db::server::{ConnectionInfo, handle_connection};
+use std::time::Instant;
+
+const COMPRESSED: WireFormat = WireFormat {
+    checksums: false,
+    compression: true,
+    max_payload_size: 0,
+};
+
+/// Structured log lines like a client might stream.
+fn log_lines(size: usize) -> Vec<u8> {
+    let mut data = Vec::with_capacity(size);
+    let mut line = 0u64;
+    while data.len() < size {
+        data.extend_from_slice(
+            format!(
+                "{{\"ts\":{},\"level\":\"info\",\"user_id\":{},\"event\":\"score_submitted\",\"pp\":{}}}\n",
+                1_700_000_000 + line,
+                line * 7919 % 100_000,
+                line * 31 % 1000
+            )
+            .as_bytes(),
+        );
+        line += 1;
+    }
+    data.truncate(size);
+    data
+}
+
+/// Incompressible bytes from a simple xorshift generator.
+fn random_bytes(size: usize) -> Vec<u8> {
+    let mut state = 0x2545_F491_4F6C_DD1Du64;
+    (0..size)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
+
+fn packets() -> Vec<Packet> {
+    vec![
+        Packet::ClientEnqueueSingle {
+            stream_id: 1,
+            enqueue_data: log_lines(4096).into(),
+        },
+        Packet::ClientEnqueueBatch {
+            entries: vec![(1, Bytes::new()), (2, random_bytes(100).into())],
+        },
+        Packet::ServerStreamContents {
+            buffer_data: vec![0xAB; 10_000].into(),
+        },
+        Packet::ClientCreateNewStream { stream_id: 3 },
+    ]
+}
+
+#[test]
+fn compressed_packets_round_trip() {
+    let packets = packets();
+    let buffer = serialise_packets_with_format(&packets, COMPRESSED).unwrap();
+
+    let (deserialised, consumed_bytes) =
+        deserialise_packets_with_format(&buffer, COMPRESSED).unwrap();
+
+    assert_eq!(deserialised, packets);
+    assert_eq!(consumed_bytes, buffer.len());
+    assert!(buffer.len() < serialise_packets(&packets).unwrap().len() / 4);
+}
+
+#[test]
+fn wrong_uncompressed_size_is_invalid() {
+    let buffer = serialise_packets_with_format(&packets()[..1], COMPRESSED).unwrap();
+
+    // The uncompressed size follows the length prefix, packet ID, stream ID and compressed size.
+    for uncompressed_size in [4095u32, 4097, 0] {
+        let mut corrupted = buffer.clone();
+        corrupted[16..20].copy_from_slice(&uncompressed_size.to_le_bytes());
+
+        let error = read_packet_from_buffer_with_format(&corrupted, 0, COMPRESSED)
+            .err()
+            .unwrap();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(PacketReadError::Invalid(_))
+        ));
+    }
+}
+
+#[test]
+fn absurd_compressed_size_is_invalid() {
+    let mut body = Vec::new();
+    body.extend_from_slice(&3u32.to_le_bytes()); // CLIENT_ENQUEUE_SINGLE.
+    body.extend_from_slice(&1u32.to_le_bytes()); // Stream ID.
+    body.extend_from_slice(&u32::MAX.to_le_bytes()); // Compressed size.
+    body.extend_from_slice(&u32::MAX.to_le_bytes()); // Uncompressed size.
+    body.extend_from_slice(b"short");
+    let mut buffer = (body.len() as u32).to_le_bytes().to_vec();
+    buffer.extend_from_slice(&body);
+
+    let error = read_packet_from_buffer_with_format(&buffer, 0, COMPRESSED)
+        .err()
+        .unwrap();
+
+    assert!(matches!(
+        error.downcast_ref(),
+        Some(PacketReadError::Invalid(_))
+    ));
+}
+
+#[test]
+fn payload_expanding_past_the_limit_is_invalid() {
+    let buffer = serialise_packets_with_format(&packets()[..1], COMPRESSED).unwrap();
+    let limited = WireFormat {
+        max_payload_size: 4095,
+        ..COMPRESSED
+    };
+
+    let error = read_packet_from_buffer_with_format(&buffer, 0, limited)
+        .err()
+        .unwrap();
+
+    assert!(matches!(
+        error.downcast_ref(),
+        Some(PacketReadError::Invalid(_))
+    ));
+    let exact = WireFormat {
+        max_payload_size: 4096,
+        ..COMPRESSED
+    };
+    assert!(read_packet_from_buffer_with_format(&buffer, 0, exact).is_ok());
+}
+
+#[tokio::test]
+async fn negotiated_compression_is_transparent_to_storage() {
+    let enqueue_data = Bytes::from(log_lines(2000));
+    let mut input = serialise_packets(&[Packet::ClientNegotiateFeatures {
+        features: FEATURE_COMPRESSION,
+    }])
+    .unwrap();
+    input.extend_from_slice(
+        &serialise_packets_with_format(
+            &[
+                Packet::ClientCreateNewStream { stream_id: 1 },
+                Packet::ClientEnqueueSingle {
+                    stream_id: 1,
+                    enqueue_data: enqueue_data.clone(),
+                },
+                Packet::ClientPeekStreamContents {
+                    stream_id: 1,
+                    max_bytes: 10,
+                },
+            ],
+            COMPRESSED,
+        )
+        .unwrap(),
+    );
+    let mut stream = MockStream::new(input);
+    let state = new_state();
+
+    handle_connection(
+        &mut stream,
+        state.clone(),
+        default_settings(),
+        ConnectionInfo::new("test"),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        state.lock().await.get_stream(1).unwrap().buffer,
+        enqueue_data
+    );
+    let mut expected = serialise_packets(&[Packet::ServerFeatures {
+        features: FEATURE_COMPRESSION,
+    }])
+    .unwrap();
+    expected.extend_from_slice(
+        &serialise_packets_with_format(
+            &[
+                Packet::ServerStreamCreated {
+                    stream_id: 1,
+                    created: true,
+                },
+                Packet::ServerStreamContents {
+                    buffer_data: enqueue_data.slice(..10),
+                },
+            ],
+            COMPRESSED,
+        )
+        .unwrap(),
+    );
+    assert_eq!(stream.flushes.concat(), expected);
+}
+
+#[test]
+#[ignore = "benchmark, run with --release -- --ignored --nocapture"]
+fn bench_compression() {
+    const PAYLOAD_SIZE: usize = 64 * 1024;
+    const ROUNDS: u32 = 200;
+    let payloads = [
+        ("log lines", log_lines(PAYLOAD_SIZE)),
+        ("random bytes", random_bytes(PAYLOAD_SIZE)),
+        ("zeroes", vec![0; PAYLOAD_SIZE]),
+    ];
+
+    for (name, payload) in payloads {
+        let packets = [Packet::ClientEnqueueSingle {
+            stream_id: 1,
+            enqueue_data: payload.into(),
+        }];
+        let plain_size = serialise_packets(&packets).unwrap().len();
+
+        let start = Instant::now();
+        for _ in 0..ROUNDS {
+            serialise_packets_with_format(&packets, COMPRESSED).unwrap();
+        }
+        let compress_time = start.elapsed() / ROUNDS;
+        let compressed = serialise_packets_with_format(&packets, COMPRESSED).unwrap();
+
+        let start = Instant::now();
+        for _ in 0..ROUNDS {
+            deserialise_packets_with_format(&compressed, COMPRESSED).unwrap();
+        }
+        let decompress_time = start.elapsed() / ROUNDS;
+
+        println!(
+            "{}: {} -> {} bytes ({:.1}x), compress {:?}, decompress {:?} per {} KiB packet",
+            name,
+            plain_size,
+            compressed.len(),
+            plain_size as f64 / compressed.len() as f64,
+            compress_time,
+            decompress_time,
+            PAYLOAD_SIZE / 1024
+        );
+    }
+}
@@ -0,0 +1,92 @@
+mod common;
+
+use common::{TestClient, new_state};
+use fast_stream_db::serialisation::{Bytes, Packet};
+use fast_stream_db::state::ServerState;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+const STREAM_ID: u32 = 1;
+const PRODUCERS: u32 = 4;
+const BATCHES: u32 = 20;
+// Kept small enough that a batch always arrives in a single server read.
+const BATCH_SIZE: u32 = 100;
+
+fn message(producer: u32, sequence: u32) -> Bytes {
+    let mut data = producer.to_le_bytes().to_vec();
+    data.extend_from_slice(&sequence.to_le_bytes());
+    data.into()
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_enqueue_and_fetch_preserve_every_byte_once() {
+    let state = new_state();
+
+    let mut setup = TestClient::connect(state.clone());
+    setup.create_stream(STREAM_ID).await;
+
+    let producers = (0..PRODUCERS)
+        .map(|producer| {
+            let mut client = TestClient::connect(state.clone());
+            tokio::spawn(async move {
+                for batch in 0..BATCHES {
+                    let packets = (0..BATCH_SIZE)
+                        .map(|i| Packet::ClientEnqueueSingle {
+                            stream_id: STREAM_ID,
+                            enqueue_data: message(producer, batch * BATCH_SIZE + i),
+                        })
+                        .collect::<Vec<_>>();
+                    client.send(&packets).await;
+                    client.sync().await;
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut consumer = TestClient::connect(state.clone());
+    let mut fetched = Vec::new();
+    let mut fetch = async |fetched: &mut Vec<u8>| {
+        consumer
+            .send(&[Packet::ClientRequestStreamContents {
+                stream_id: STREAM_ID,
+            }])
+            .await;
+        match consumer.recv().await {
+            Packet::ServerStreamContents { buffer_data } => fetched.extend_from_slice(&buffer_data),
+            _ => panic!("Expected stream contents"),
+        }
+    };
+
+    while !producers.iter().all(|producer| producer.is_finished()) {
+        fetch(&mut fetched).await;
+    }
+    for producer in producers {
+        producer.await.unwrap();
+    }
+    // Drain whatever was enqueued after the last concurrent fetch.
+    fetch(&mut fetched).await;
+
+    let total_messages = (PRODUCERS * BATCHES * BATCH_SIZE) as usize;
+    assert_eq!(fetched.len(), total_messages * 8);
+
+    // Producers interleave freely, but each producer's messages must appear
+    // exactly once and in the order they were sent.
+    let mut next_sequence = vec![0u32; PRODUCERS as usize];
+    for chunk in fetched.chunks_exact(8) {
+        let producer = u32::from_le_bytes(chunk[..4].try_into().unwrap());
+        let sequence = u32::from_le_bytes(chunk[4..].try_into().unwrap());
+        assert_eq!(sequence, next_sequence[producer as usize]);
+        next_sequence[producer as usize] += 1;
+    }
+    assert!(
+        next_sequence
+            .iter()
+            .all(|&count| count == BATCHES * BATCH_SIZE)
+    );
+}
+
+#[test]
+fn shared_state_can_move_between_worker_threads() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Arc<Mutex<ServerState>>>();
+}
@@ -0,0 +1,83 @@
+mod common;
+
+use common::{
+    TestClient, connect_tcp, connect_unix, free_tcp_port, leak_settings, new_state,
+    temp_socket_path,
+};
+use fast_stream_db::serialisation::{Bytes, Packet};
+use fast_stream_db::server::{handle_client_packets, run_servers};
+use fast_stream_db::settings::{ConnectionMode, Settings};
+use fast_stream_db::state::ServerState;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::oneshot;
+
+async fn who_am_i<S>(client: &mut TestClient<S>) -> (u64, String)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    client.send(&[Packet::ClientWhoAmI]).await;
+    match client.recv().await {
+        Packet::ServerConnectionInfo {
+            connection_id,
+            peer,
+        } => (connection_id, String::from_utf8(peer.to_vec()).unwrap()),
+        packet => panic!("Expected connection info, got {:?}", packet),
+    }
+}
+
+#[tokio::test]
+async fn connections_are_given_increasing_ids() {
+    let state = new_state();
+    let mut first = TestClient::connect(state.clone());
+    let mut second = TestClient::connect(state);
+
+    let (first_id, _) = who_am_i(&mut first).await;
+    let (second_id, _) = who_am_i(&mut second).await;
+
+    assert_ne!(first_id, 0);
+    assert!(second_id > first_id);
+    assert_eq!(who_am_i(&mut first).await.0, first_id);
+}
+
+#[tokio::test]
+async fn peer_is_the_remote_address_or_unix() {
+    let path = temp_socket_path("connection-info");
+    let port = free_tcp_port();
+    let settings = leak_settings(Settings {
+        listeners: vec![ConnectionMode::Tcp, ConnectionMode::UnixSocket],
+        unix_sock_path: path.clone(),
+        tcp_port: port,
+        ..Settings::default()
+    });
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let server = tokio::spawn(run_servers(settings, new_state(), async {
+        let _ = shutdown_rx.await;
+    }));
+
+    let mut tcp_client = connect_tcp(port).await;
+    let mut unix_client = connect_unix(&path).await;
+
+    let (_, tcp_peer) = who_am_i(&mut tcp_client).await;
+    let (_, unix_peer) = who_am_i(&mut unix_client).await;
+
+    assert!(tcp_peer.starts_with("127.0.0.1:"), "{}", tcp_peer);
+    assert_eq!(unix_peer, "unix");
+
+    drop((tcp_client, unix_client));
+    shutdown_tx.send(()).unwrap();
+    server.await.unwrap().unwrap();
+}
+
+#[test]
+fn packets_handled_outside_a_connection_have_no_id() {
+    let responses =
+        handle_client_packets(&mut ServerState::new(), vec![Packet::ClientWhoAmI]).unwrap();
+
+    assert_eq!(
+        responses,
+        vec![Packet::ServerConnectionInfo {
+            connection_id: 0,
+            peer: Bytes::new(),
+        }]
+    );
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use common::{connect_unix, leak_settings, new_state, temp_socket_path};
+use fast_stream_db::serialisation::Packet;
+use fast_stream_db::server::run_unix_server;
+use fast_stream_db::settings::{ConnectionMode, Settings};
+
+#[tokio::test]
+async fn rejected_client_receives_server_busy() {
+    let path = temp_socket_path("busy");
+    let settings = leak_settings(Settings {
+        listeners: vec![ConnectionMode::UnixSocket],
+        unix_sock_path: path.clone(),
+        max_connections: 1,
+        ..Settings::default()
+    });
+    tokio::spawn(run_unix_server(
+        settings,
+        new_state(),
+        std::future::pending(),
+    ));
+
+    // Occupy the only slot, confirming the connection was admitted.
+    let mut admitted = connect_unix(&path).await;
+    admitted.sync().await;
+
+    let mut rejected = connect_unix(&path).await;
+    match rejected.recv().await {
+        Packet::ServerBusy { retry_after_ms } => assert!(retry_after_ms > 0),
+        _ => panic!("Expected ServerBusy"),
+    }
+    assert!(rejected.is_closed().await);
+
+    // The admitted client is unaffected.
+    admitted.sync().await;
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn closing_a_connection_frees_its_slot() {
+    let path = temp_socket_path("busy-freed");
+    let settings = leak_settings(Settings {
+        listeners: vec![ConnectionMode::UnixSocket],
+        unix_sock_path: path.clone(),
+        max_connections: 2,
+        ..Settings::default()
+    });
+    tokio::spawn(run_unix_server(
+        settings,
+        new_state(),
+        std::future::pending(),
+    ));
+
+    let mut first = connect_unix(&path).await;
+    first.sync().await;
+    let mut second = connect_unix(&path).await;
+    second.sync().await;
+    let mut third = connect_unix(&path).await;
+    assert!(matches!(third.recv().await, Packet::ServerBusy { .. }));
+
+    // The slot is only released once the handler notices the close, so retry for a while.
+    drop(first);
+    let mut admitted = false;
+    for _ in 0..100 {
+        let mut client = connect_unix(&path).await;
+        client.send(&[Packet::ClientPing { nonce: None }]).await;
+        if matches!(client.recv().await, Packet::ServerPong { .. }) {
+            admitted = true;
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    assert!(admitted);
+
+    second.sync().await;
+    let _ = std::fs::remove_file(&path);
+}
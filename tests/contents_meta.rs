@@ -0,0 +1,60 @@
+use fast_stream_db::serialisation::{Bytes, Packet};
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::state::ServerState;
+
+fn fetch_with_meta(state: &mut ServerState, stream_id: u32) -> Vec<Packet> {
+    handle_client_packets(
+        state,
+        vec![Packet::ClientRequestStreamContentsWithMeta { stream_id }],
+    )
+    .unwrap()
+}
+
+#[test]
+fn meta_precedes_drained_contents() {
+    let mut state = ServerState::new();
+    state.create_new_stream(1).unwrap();
+    state.enqueue_single(1, b"hello").unwrap();
+
+    assert_eq!(
+        fetch_with_meta(&mut state, 1),
+        vec![
+            Packet::ServerStreamContentsMeta {
+                stream_id: 1,
+                byte_count: 5,
+                existed: true,
+            },
+            Packet::ServerStreamContents {
+                buffer_data: Bytes::from_static(b"hello"),
+            },
+        ]
+    );
+    assert!(state.get_stream(1).unwrap().buffer.is_empty());
+}
+
+#[test]
+fn empty_stream_is_distinguished_from_missing_stream() {
+    let mut state = ServerState::new();
+    state.create_new_stream(1).unwrap();
+
+    let empty = fetch_with_meta(&mut state, 1);
+    let missing = fetch_with_meta(&mut state, 2);
+
+    assert_eq!(
+        empty[0],
+        Packet::ServerStreamContentsMeta {
+            stream_id: 1,
+            byte_count: 0,
+            existed: true,
+        }
+    );
+    assert_eq!(
+        missing[0],
+        Packet::ServerStreamContentsMeta {
+            stream_id: 2,
+            byte_count: 0,
+            existed: false,
+        }
+    );
+    assert_eq!(empty[1], missing[1]);
+}
@@ -0,0 +1,49 @@
+use fast_stream_db::serialisation::Packet;
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::state::ServerState;
+
+#[test]
+fn creates_every_stream_and_reports_existing_ones() {
+    let mut state = ServerState::new();
+    state.create_new_stream(2).unwrap();
+    state.enqueue_single(2, b"kept").unwrap();
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![Packet::ClientCreateMultipleStreams {
+            stream_ids: vec![1, 2, 3, 3],
+        }],
+    )
+    .unwrap();
+
+    assert_eq!(
+        responses,
+        vec![Packet::ServerStreamsCreated {
+            existing_stream_ids: vec![2, 3],
+        }]
+    );
+    assert_eq!(state.stream_count(), 3);
+    assert_eq!(state.get_stream(2).unwrap().buffer, &b"kept"[..]);
+    assert_eq!(state.total_bytes(), 4);
+}
+
+#[test]
+fn empty_list_creates_nothing() {
+    let mut state = ServerState::new();
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![Packet::ClientCreateMultipleStreams {
+            stream_ids: Vec::new(),
+        }],
+    )
+    .unwrap();
+
+    assert_eq!(
+        responses,
+        vec![Packet::ServerStreamsCreated {
+            existing_stream_ids: Vec::new(),
+        }]
+    );
+    assert_eq!(state.stream_count(), 0);
+}
@@ -0,0 +1,98 @@
+use fast_stream_db::serialisation::{Bytes, Packet};
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::settings::OverflowPolicy;
+use fast_stream_db::state::{MAX_INITIAL_STREAM_CAPACITY, ServerState};
+
+#[test]
+fn recreating_a_stream_keeps_its_data() {
+    let mut state = ServerState::new();
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![
+            Packet::ClientCreateNewStream { stream_id: 1 },
+            Packet::ClientEnqueueSingle {
+                stream_id: 1,
+                enqueue_data: Bytes::from_static(b"kept"),
+            },
+            Packet::ClientCreateNewStream { stream_id: 1 },
+            Packet::ClientCreateNewStreamWithTtl {
+                stream_id: 1,
+                ttl_secs: 10,
+            },
+            Packet::ClientRequestStreamContents { stream_id: 1 },
+        ],
+    )
+    .unwrap();
+
+    assert_eq!(
+        responses,
+        vec![
+            Packet::ServerStreamCreated {
+                stream_id: 1,
+                created: true,
+            },
+            Packet::ServerStreamCreated {
+                stream_id: 1,
+                created: false,
+            },
+            Packet::ServerStreamCreated {
+                stream_id: 1,
+                created: false,
+            },
+            Packet::ServerStreamContents {
+                buffer_data: Bytes::from_static(b"kept"),
+            },
+        ]
+    );
+    assert_eq!(state.get_stream(1).unwrap().ttl, None);
+}
+
+#[test]
+fn recreating_at_the_stream_limit_succeeds() {
+    let mut state = ServerState::new();
+    state.set_max_streams(1);
+    state.create_new_stream(1).unwrap();
+
+    assert!(!state.create_new_stream(1).unwrap());
+    assert!(state.create_new_stream(2).is_err());
+}
+
+#[test]
+fn sized_streams_preallocate_up_to_the_limits() {
+    let mut state = ServerState::new();
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![
+            Packet::ClientCreateNewStreamSized {
+                stream_id: 1,
+                initial_capacity: 64 * 1024,
+            },
+            Packet::ClientCreateNewStreamSized {
+                stream_id: 2,
+                initial_capacity: 0,
+            },
+            Packet::ClientCreateNewStreamSized {
+                stream_id: 3,
+                initial_capacity: u32::MAX,
+            },
+        ],
+    )
+    .unwrap();
+
+    assert_eq!(responses.len(), 3);
+    assert!(state.get_stream(1).unwrap().buffer.capacity() >= 64 * 1024);
+    assert_eq!(state.get_stream(2).unwrap().buffer.capacity(), 0);
+    assert_eq!(
+        state.get_stream(3).unwrap().buffer.capacity(),
+        MAX_INITIAL_STREAM_CAPACITY
+    );
+
+    state.set_stream_limit(100, OverflowPolicy::Reject);
+    state.create_new_stream_sized(4, 4096).unwrap();
+    assert_eq!(state.get_stream(4).unwrap().buffer.capacity(), 100);
+    // A tiny stream still grows to fit what is enqueued.
+    state.enqueue_single(2, b"grows").unwrap();
+    assert_eq!(&state.get_stream(2).unwrap().buffer[..], b"grows");
+}
@@ -0,0 +1,177 @@
+use fast_stream_db::serialisation::{Bytes, Packet};
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::state::ServerState;
+
+const STREAM_ID: u32 = 1;
+
+fn enqueue(state: &mut ServerState, data: &[u8]) {
+    handle_client_packets(
+        state,
+        vec![Packet::ClientEnqueueSingle {
+            stream_id: STREAM_ID,
+            enqueue_data: Bytes::copy_from_slice(data),
+        }],
+    )
+    .unwrap();
+}
+
+fn fetch(state: &mut ServerState, consumer_id: u32, max_bytes: u32) -> Bytes {
+    let responses = handle_client_packets(
+        state,
+        vec![Packet::ClientFetchFromCursor {
+            stream_id: STREAM_ID,
+            consumer_id,
+            max_bytes,
+        }],
+    )
+    .unwrap();
+
+    match responses.as_slice() {
+        [Packet::ServerStreamContents { buffer_data }] => buffer_data.clone(),
+        _ => panic!("Unexpected responses: {:?}", responses),
+    }
+}
+
+fn advance(state: &mut ServerState, consumer_id: u32, byte_count: u32) {
+    handle_client_packets(
+        state,
+        vec![Packet::ClientAdvanceCursor {
+            stream_id: STREAM_ID,
+            consumer_id,
+            byte_count,
+        }],
+    )
+    .unwrap();
+}
+
+fn state_with_consumers(consumer_ids: &[u32]) -> ServerState {
+    let mut state = ServerState::new();
+    state.create_new_stream(STREAM_ID).unwrap();
+    let packets = consumer_ids
+        .iter()
+        .map(|consumer_id| Packet::ClientRegisterConsumer {
+            stream_id: STREAM_ID,
+            consumer_id: *consumer_id,
+        })
+        .collect();
+    handle_client_packets(&mut state, packets).unwrap();
+    state
+}
+
+#[test]
+fn independent_consumers_each_read_every_byte() {
+    let mut state = state_with_consumers(&[1, 2]);
+    let mut received = [Vec::new(), Vec::new()];
+
+    // Consumer 1 reads 2 bytes per round, consumer 2 reads 5 bytes every third round.
+    for round in 0..30u8 {
+        enqueue(&mut state, &[round]);
+
+        let chunk = fetch(&mut state, 1, 2);
+        advance(&mut state, 1, chunk.len() as u32);
+        received[0].extend(chunk);
+
+        if round % 3 == 2 {
+            let chunk = fetch(&mut state, 2, 5);
+            advance(&mut state, 2, chunk.len() as u32);
+            received[1].extend(chunk);
+        }
+    }
+    for (consumer_id, received) in [1, 2].into_iter().zip(received.iter_mut()) {
+        let rest = fetch(&mut state, consumer_id, u32::MAX);
+        advance(&mut state, consumer_id, rest.len() as u32);
+        received.extend(rest);
+    }
+
+    let expected = (0..30u8).collect::<Vec<u8>>();
+    assert_eq!(received[0], expected);
+    assert_eq!(received[1], expected);
+    assert!(state.get_stream(STREAM_ID).unwrap().buffer.is_empty());
+    assert_eq!(state.total_bytes(), 0);
+}
+
+#[test]
+fn bytes_are_kept_until_every_cursor_passes_them() {
+    let mut state = state_with_consumers(&[1, 2]);
+    enqueue(&mut state, b"abcdef");
+
+    advance(&mut state, 1, 4);
+    assert_eq!(state.get_stream(STREAM_ID).unwrap().buffer, &b"abcdef"[..]);
+
+    advance(&mut state, 2, 2);
+    assert_eq!(state.get_stream(STREAM_ID).unwrap().buffer, &b"cdef"[..]);
+    assert_eq!(state.total_bytes(), 4);
+    assert_eq!(fetch(&mut state, 1, 10), &b"ef"[..]);
+    assert_eq!(fetch(&mut state, 2, 10), &b"cdef"[..]);
+
+    // Dropping the slower consumer releases what only it was waiting on.
+    handle_client_packets(
+        &mut state,
+        vec![Packet::ClientUnregisterConsumer {
+            stream_id: STREAM_ID,
+            consumer_id: 2,
+        }],
+    )
+    .unwrap();
+    assert_eq!(state.get_stream(STREAM_ID).unwrap().buffer, &b"ef"[..]);
+    assert_eq!(state.total_bytes(), 2);
+}
+
+#[test]
+fn fetching_does_not_advance_and_advancing_stops_at_the_end() {
+    let mut state = state_with_consumers(&[1]);
+    enqueue(&mut state, b"hello");
+
+    assert_eq!(fetch(&mut state, 1, 3), &b"hel"[..]);
+    assert_eq!(fetch(&mut state, 1, 3), &b"hel"[..]);
+
+    advance(&mut state, 1, 100);
+    assert!(fetch(&mut state, 1, 100).is_empty());
+
+    enqueue(&mut state, b"world");
+    assert_eq!(fetch(&mut state, 1, 100), &b"world"[..]);
+}
+
+#[test]
+fn late_consumers_start_at_the_buffered_data() {
+    let mut state = state_with_consumers(&[1]);
+    enqueue(&mut state, b"abcd");
+    advance(&mut state, 1, 2);
+
+    handle_client_packets(
+        &mut state,
+        vec![Packet::ClientRegisterConsumer {
+            stream_id: STREAM_ID,
+            consumer_id: 2,
+        }],
+    )
+    .unwrap();
+    assert_eq!(fetch(&mut state, 2, 10), &b"cd"[..]);
+}
+
+#[test]
+fn clearing_fetch_moves_lagging_cursors_forward() {
+    let mut state = state_with_consumers(&[1]);
+    enqueue(&mut state, b"abcd");
+
+    assert_eq!(
+        state.fetch_stream_contents(STREAM_ID).unwrap(),
+        &b"abcd"[..]
+    );
+    assert!(fetch(&mut state, 1, 10).is_empty());
+
+    enqueue(&mut state, b"ef");
+    assert_eq!(fetch(&mut state, 1, 10), &b"ef"[..]);
+    advance(&mut state, 1, 2);
+    assert_eq!(state.total_bytes(), 0);
+}
+
+#[test]
+fn unknown_consumers_read_nothing() {
+    let mut state = state_with_consumers(&[1]);
+    enqueue(&mut state, b"abcd");
+
+    assert!(fetch(&mut state, 7, 10).is_empty());
+    advance(&mut state, 7, 4);
+    assert_eq!(state.get_stream(STREAM_ID).unwrap().buffer, &b"abcd"[..]);
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use common::enqueue;
+use fast_stream_db::serialisation::{Bytes, Packet};
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::settings::{MissingStreamPolicy, OverflowPolicy};
+use fast_stream_db::state::ServerState;
+
+const DEAD_LETTER_STREAM: u32 = 100;
+
+fn dead_letter_state(max_stream_bytes: usize) -> ServerState {
+    let mut state = ServerState::new();
+    state.set_stream_limit(max_stream_bytes, OverflowPolicy::Reject);
+    state.set_dead_letter_stream(Some(DEAD_LETTER_STREAM));
+    state
+}
+
+fn record(stream_id: u32, data: &[u8]) -> Vec<u8> {
+    let mut record = stream_id.to_le_bytes().to_vec();
+    record.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    record.extend_from_slice(data);
+    record
+}
+
+#[test]
+fn overflowed_payload_is_dead_lettered() {
+    let mut state = dead_letter_state(64);
+    state.create_new_stream(1).unwrap();
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![
+            enqueue(1, &[b'a'; 60]),
+            enqueue(1, b"overflow"),
+            enqueue(1, b"1234"),
+        ],
+    )
+    .unwrap();
+
+    assert_eq!(
+        responses,
+        vec![Packet::ServerEnqueueRejected { stream_id: 1 }]
+    );
+    assert_eq!(state.get_stream(1).unwrap().buffer.len(), 64);
+    assert_eq!(
+        state.fetch_stream_contents(DEAD_LETTER_STREAM).unwrap(),
+        record(1, b"overflow")
+    );
+    assert_eq!(state.total_bytes(), state.scanned_total_bytes());
+}
+
+#[test]
+fn missing_stream_payload_is_dead_lettered() {
+    let mut state = dead_letter_state(0);
+    state.set_missing_stream_policy(MissingStreamPolicy::Error);
+
+    handle_client_packets(
+        &mut state,
+        vec![
+            enqueue(7, b"lost"),
+            Packet::ClientEnqueueMultiple {
+                enqueue_data: Bytes::from_static(b"also lost"),
+                filter_stream_ids: vec![8],
+            },
+        ],
+    )
+    .unwrap();
+
+    let mut expected = record(7, b"lost");
+    expected.extend(record(8, b"also lost"));
+    assert_eq!(
+        state.fetch_stream_contents(DEAD_LETTER_STREAM).unwrap(),
+        expected
+    );
+}
+
+#[test]
+fn full_dead_letter_stream_drops_records() {
+    let mut state = dead_letter_state(16);
+    state.create_new_stream(1).unwrap();
+
+    handle_client_packets(
+        &mut state,
+        vec![
+            enqueue(1, &[b'a'; 16]),
+            enqueue(1, b"fits"),
+            // A second record would take the dead-letter stream past its own cap.
+            enqueue(1, b"dropped"),
+            // Rejections by the dead-letter stream itself aren't dead-lettered again.
+            enqueue(DEAD_LETTER_STREAM, &[b'b'; 16]),
+        ],
+    )
+    .unwrap();
+
+    assert_eq!(
+        state.fetch_stream_contents(DEAD_LETTER_STREAM).unwrap(),
+        record(1, b"fits")
+    );
+}
@@ -0,0 +1,48 @@
+use fast_stream_db::serialisation::{Bytes, ERROR_NOT_AUTHENTICATED, Packet};
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::state::ServerState;
+
+#[test]
+fn only_existing_streams_are_counted() {
+    let mut state = ServerState::new();
+    for stream_id in [1, 2, 3] {
+        state.create_new_stream(stream_id).unwrap();
+        state.enqueue_single(stream_id, b"session").unwrap();
+    }
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![Packet::ClientDeleteMultipleStreams {
+            // Naming a stream twice only deletes it once.
+            stream_ids: vec![1, 7, 3, 1, 9],
+        }],
+    )
+    .unwrap();
+
+    assert_eq!(responses, vec![Packet::ServerStreamsDeleted { count: 2 }]);
+    assert!(!state.stream_exists(1));
+    assert!(state.stream_exists(2));
+    assert!(!state.stream_exists(3));
+    assert_eq!(state.total_bytes(), 7);
+}
+
+#[test]
+fn batch_delete_is_privileged() {
+    let mut state = ServerState::new();
+    state.set_admin_token(Some(Bytes::from_static(b"secret")));
+    state.create_new_stream(1).unwrap();
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![Packet::ClientDeleteMultipleStreams {
+            stream_ids: vec![1],
+        }],
+    )
+    .unwrap();
+
+    match responses.as_slice() {
+        [Packet::ServerError { code, .. }] => assert_eq!(*code, ERROR_NOT_AUTHENTICATED),
+        _ => panic!("Unexpected responses: {:?}", responses),
+    }
+    assert!(state.stream_exists(1));
+}
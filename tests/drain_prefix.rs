@@ -0,0 +1,58 @@
+use fast_stream_db::serialisation::Packet;
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::state::ServerState;
+
+const STREAM_ID: u32 = 1;
+
+fn drain_prefix(state: &mut ServerState, stream_id: u32, byte_count: u32) -> Vec<u8> {
+    let responses = handle_client_packets(
+        state,
+        vec![Packet::ClientDrainPrefix {
+            stream_id,
+            byte_count,
+        }],
+    )
+    .unwrap();
+
+    match responses.as_slice() {
+        [Packet::ServerStreamContents { buffer_data }] => buffer_data.to_vec(),
+        _ => panic!("Unexpected responses: {:?}", responses),
+    }
+}
+
+#[test]
+fn length_prefixed_messages_are_consumed_one_at_a_time() {
+    let mut state = ServerState::new();
+    state.create_new_stream(STREAM_ID).unwrap();
+    for message in [&b"first"[..], b"second"] {
+        let mut data = (message.len() as u32).to_le_bytes().to_vec();
+        data.extend_from_slice(message);
+        state.enqueue_single(STREAM_ID, &data).unwrap();
+    }
+
+    for expected in [&b"first"[..], b"second"] {
+        let length = drain_prefix(&mut state, STREAM_ID, 4);
+        let length = u32::from_le_bytes(length.try_into().unwrap());
+        assert_eq!(drain_prefix(&mut state, STREAM_ID, length), expected);
+    }
+
+    assert_eq!(state.total_bytes(), 0);
+    assert_eq!(state.get_stream(STREAM_ID).unwrap().base_offset, 19);
+}
+
+#[test]
+fn short_buffer_is_drained_entirely() {
+    let mut state = ServerState::new();
+    state.create_new_stream(STREAM_ID).unwrap();
+    state.enqueue_single(STREAM_ID, b"abc").unwrap();
+
+    assert_eq!(drain_prefix(&mut state, STREAM_ID, 10), b"abc");
+    assert!(drain_prefix(&mut state, STREAM_ID, 10).is_empty());
+}
+
+#[test]
+fn missing_stream_drains_nothing() {
+    let mut state = ServerState::new();
+
+    assert!(drain_prefix(&mut state, STREAM_ID, 10).is_empty());
+}
@@ -0,0 +1,85 @@
+use fast_stream_db::serialisation::{Bytes, Packet};
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::settings::{MissingStreamPolicy, OverflowPolicy};
+use fast_stream_db::state::ServerState;
+
+fn enqueue_ack(stream_id: u32, data: &'static [u8]) -> Packet {
+    Packet::ClientEnqueueSingleAck {
+        stream_id,
+        enqueue_data: Bytes::from_static(data),
+    }
+}
+
+#[test]
+fn ack_reports_the_new_length() {
+    let mut state = ServerState::new();
+    state.create_new_stream(1).unwrap();
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![enqueue_ack(1, b"hello"), enqueue_ack(1, b" world")],
+    )
+    .unwrap();
+
+    assert_eq!(
+        responses,
+        vec![
+            Packet::ServerEnqueueAck {
+                stream_id: 1,
+                accepted: true,
+                new_length: 5,
+            },
+            Packet::ServerEnqueueAck {
+                stream_id: 1,
+                accepted: true,
+                new_length: 11,
+            },
+        ]
+    );
+    assert_eq!(state.fetch_stream_contents(1).unwrap(), &b"hello world"[..]);
+}
+
+#[test]
+fn rejected_enqueue_is_not_accepted() {
+    let mut state = ServerState::new();
+    state.set_stream_limit(8, OverflowPolicy::Reject);
+    state.create_new_stream(1).unwrap();
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![enqueue_ack(1, b"1234"), enqueue_ack(1, b"56789")],
+    )
+    .unwrap();
+
+    assert_eq!(
+        responses[1..],
+        [
+            Packet::ServerEnqueueRejected { stream_id: 1 },
+            Packet::ServerEnqueueAck {
+                stream_id: 1,
+                accepted: false,
+                new_length: 4,
+            },
+        ]
+    );
+}
+
+#[test]
+fn missing_stream_is_not_accepted() {
+    let mut state = ServerState::new();
+    state.set_missing_stream_policy(MissingStreamPolicy::Error);
+
+    let responses = handle_client_packets(&mut state, vec![enqueue_ack(1, b"data")]).unwrap();
+
+    assert_eq!(
+        responses,
+        vec![
+            Packet::ServerEnqueueError { stream_id: 1 },
+            Packet::ServerEnqueueAck {
+                stream_id: 1,
+                accepted: false,
+                new_length: 0,
+            },
+        ]
+    );
+}
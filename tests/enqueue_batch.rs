@@ -0,0 +1,66 @@
+use fast_stream_db::serialisation::{Bytes, Packet, read_packet_from_buffer, serialise_packets};
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::settings::OverflowPolicy;
+use fast_stream_db::state::ServerState;
+
+#[test]
+fn mixed_size_entries_round_trip() {
+    let packet = Packet::ClientEnqueueBatch {
+        entries: vec![
+            (1, Bytes::new()),
+            (2, vec![0xAB].into()),
+            (3, vec![0xCD; 70_000].into()),
+            (1, Bytes::from_static(b"repeated stream")),
+        ],
+    };
+
+    let data = serialise_packets(std::slice::from_ref(&packet)).unwrap();
+    let result = read_packet_from_buffer(&data, 0).unwrap();
+
+    assert_eq!(result.value, packet);
+    assert_eq!(result.new_offset, data.len());
+}
+
+#[test]
+fn entry_count_past_the_packet_is_invalid() {
+    let mut data = serialise_packets(&[Packet::ClientEnqueueBatch {
+        entries: vec![(1, Bytes::from_static(b"data"))],
+    }])
+    .unwrap();
+    // The entry count follows the length and packet ID.
+    data[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+
+    assert!(read_packet_from_buffer(&data, 0).is_err());
+}
+
+#[test]
+fn entries_are_routed_to_their_own_streams() {
+    let mut state = ServerState::new();
+    state.set_stream_limit(4, OverflowPolicy::Reject);
+    for stream_id in 1..=3 {
+        state.create_new_stream(stream_id).unwrap();
+    }
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![Packet::ClientEnqueueBatch {
+            entries: vec![
+                (1, Bytes::from_static(b"ab")),
+                (2, Bytes::from_static(b"cd")),
+                (1, Bytes::from_static(b"ef")),
+                (3, Bytes::from_static(b"too long")),
+                (9, Bytes::from_static(b"missing")),
+            ],
+        }],
+    )
+    .unwrap();
+
+    assert_eq!(
+        responses,
+        vec![Packet::ServerEnqueueRejected { stream_id: 3 }]
+    );
+    assert_eq!(state.get_stream(1).unwrap().buffer, &b"abef"[..]);
+    assert_eq!(state.get_stream(2).unwrap().buffer, &b"cd"[..]);
+    assert!(state.get_stream(3).unwrap().buffer.is_empty());
+    assert_eq!(state.total_bytes(), 6);
+}
@@ -0,0 +1,100 @@
+mod common;
+
+use common::enqueue;
+use fast_stream_db::serialisation::{Bytes, Packet};
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::settings::OverflowPolicy;
+use fast_stream_db::state::ServerState;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+/// Counts reallocations made by this test binary, so the benchmark can report them.
+struct CountingAllocator;
+
+static REALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        REALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[test]
+fn runs_of_enqueues_keep_their_order_and_limits() {
+    let mut state = ServerState::new();
+    state.set_stream_limit(10, OverflowPolicy::Reject);
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![
+            Packet::ClientCreateNewStream { stream_id: 1 },
+            Packet::ClientCreateNewStream { stream_id: 2 },
+            enqueue(1, b"abc"),
+            enqueue(1, b"def"),
+            enqueue(2, b"x"),
+            enqueue(1, b"ghi"),
+            // Only this one is past the limit, so only it is rejected.
+            enqueue(1, b"jk"),
+            enqueue(1, b"l"),
+            Packet::ClientRequestStreamContents { stream_id: 1 },
+            Packet::ClientRequestStreamContents { stream_id: 2 },
+        ],
+    )
+    .unwrap();
+
+    assert_eq!(
+        responses[2..],
+        [
+            Packet::ServerEnqueueRejected { stream_id: 1 },
+            Packet::ServerStreamContents {
+                buffer_data: Bytes::from_static(b"abcdefghil"),
+            },
+            Packet::ServerStreamContents {
+                buffer_data: Bytes::from_static(b"x"),
+            },
+        ]
+    );
+    assert_eq!(state.total_bytes(), 0);
+}
+
+#[test]
+#[ignore = "benchmark, run with --release -- --ignored --nocapture"]
+fn bench_same_stream_enqueue_burst() {
+    const CYCLES: usize = 10_000;
+    const BURST: usize = 256;
+    static ENQUEUE_DATA: [u8; 64] = [0xAB; 64];
+    let mut state = ServerState::new();
+
+    let reallocations_before = REALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..CYCLES {
+        let mut packets = Vec::with_capacity(BURST + 2);
+        packets.push(Packet::ClientCreateNewStream { stream_id: 1 });
+        packets.extend(std::iter::repeat_n(enqueue(1, &ENQUEUE_DATA), BURST));
+        packets.push(Packet::ClientDeleteStream { stream_id: 1 });
+        handle_client_packets(&mut state, packets).unwrap();
+    }
+    let elapsed = start.elapsed();
+    let reallocations = REALLOCATIONS.load(Ordering::Relaxed) - reallocations_before;
+
+    println!(
+        "{} bursts of {} enqueues in {:?}: {:.2} reallocations per burst",
+        CYCLES,
+        BURST,
+        elapsed,
+        reallocations as f64 / CYCLES as f64
+    );
+}
@@ -0,0 +1,71 @@
+use fast_stream_db::serialisation::{Bytes, Packet};
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::settings::MissingStreamPolicy;
+use fast_stream_db::state::ServerState;
+
+fn state_with_streams(stream_ids: &[u32]) -> ServerState {
+    let mut state = ServerState::new();
+    for &stream_id in stream_ids {
+        state.create_new_stream(stream_id).unwrap();
+    }
+    state
+}
+
+#[test]
+fn only_included_streams_that_arent_excluded_are_enqueued_to() {
+    let mut state = state_with_streams(&[1, 2, 3, 4, 5]);
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![Packet::ClientEnqueueMultipleExcept {
+            enqueue_data: Bytes::from_static(b"group"),
+            include_ids: vec![1, 2, 3, 4],
+            // Overlaps the include list in 2 and 4, while 5 and 6 were never included.
+            exclude_ids: vec![2, 4, 5, 6],
+        }],
+    )
+    .unwrap();
+
+    assert!(responses.is_empty());
+    for (stream_id, expected) in [
+        (1, &b"group"[..]),
+        (2, b""),
+        (3, b"group"),
+        (4, b""),
+        (5, b""),
+    ] {
+        assert_eq!(state.get_stream(stream_id).unwrap().buffer, expected);
+    }
+    assert_eq!(state.total_bytes(), 10);
+}
+
+#[test]
+fn excluding_every_included_stream_enqueues_nothing() {
+    let mut state = state_with_streams(&[1, 2]);
+
+    let outcome = state
+        .enqueue_multiple_except(&[1, 2], &[2, 1], b"nobody")
+        .unwrap();
+
+    assert_eq!(outcome.stream_count, 0);
+    assert_eq!(state.total_bytes(), 0);
+}
+
+#[test]
+fn missing_included_streams_follow_the_missing_stream_policy() {
+    let mut state = state_with_streams(&[1]);
+    state.set_missing_stream_policy(MissingStreamPolicy::Error);
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![Packet::ClientEnqueueMultipleExcept {
+            enqueue_data: Bytes::from_static(b"group"),
+            include_ids: vec![1, 2, 3],
+            exclude_ids: vec![3],
+        }],
+    )
+    .unwrap();
+
+    assert_eq!(responses, vec![Packet::ServerEnqueueError { stream_id: 2 }]);
+    assert_eq!(state.get_stream(1).unwrap().buffer, &b"group"[..]);
+}
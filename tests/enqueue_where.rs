@@ -0,0 +1,87 @@
+use fast_stream_db::serialisation::{Bytes, Packet, Predicate};
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::state::ServerState;
+
+/// Enqueues to streams 0 to 9 where the predicate matches, returning the IDs that received data.
+fn enqueued_stream_ids(predicate: Predicate) -> Vec<u32> {
+    let mut state = ServerState::new();
+    for stream_id in 0..10 {
+        state.create_new_stream(stream_id).unwrap();
+    }
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![Packet::ClientEnqueueWhere {
+            enqueue_data: Bytes::from_static(b"hi"),
+            predicate,
+        }],
+    )
+    .unwrap();
+    assert!(responses.is_empty(), "{:?}", responses);
+
+    (0..10)
+        .filter(|stream_id| !state.get_stream(*stream_id).unwrap().buffer.is_empty())
+        .collect()
+}
+
+#[test]
+fn range_matches_inclusive_bounds() {
+    assert_eq!(
+        enqueued_stream_ids(Predicate::Range { min: 3, max: 6 }),
+        vec![3, 4, 5, 6]
+    );
+    assert_eq!(
+        enqueued_stream_ids(Predicate::Range { min: 7, max: 2 }),
+        Vec::<u32>::new()
+    );
+}
+
+#[test]
+fn range_of_one_id_matches_only_that_stream() {
+    assert_eq!(
+        enqueued_stream_ids(Predicate::Range { min: 4, max: 4 }),
+        vec![4]
+    );
+    // Bounds past every stream match nothing rather than wrapping around.
+    assert_eq!(
+        enqueued_stream_ids(Predicate::Range {
+            min: 10,
+            max: u32::MAX
+        }),
+        Vec::<u32>::new()
+    );
+}
+
+#[test]
+fn modulo_matches_the_remainder() {
+    assert_eq!(
+        enqueued_stream_ids(Predicate::Modulo {
+            divisor: 4,
+            remainder: 1
+        }),
+        vec![1, 5, 9]
+    );
+    assert_eq!(
+        enqueued_stream_ids(Predicate::Modulo {
+            divisor: 0,
+            remainder: 0
+        }),
+        Vec::<u32>::new()
+    );
+}
+
+#[test]
+fn predicate_never_creates_streams() {
+    assert!(!Predicate::Range { min: 0, max: 10 }.matches(11));
+
+    let mut state = ServerState::new();
+    handle_client_packets(
+        &mut state,
+        vec![Packet::ClientEnqueueWhere {
+            enqueue_data: Bytes::from_static(b"hi"),
+            predicate: Predicate::Range { min: 0, max: 10 },
+        }],
+    )
+    .unwrap();
+    assert_eq!(state.stream_count(), 0);
+}
@@ -0,0 +1,61 @@
+#![cfg(feature = "admin")]
+
+use fast_stream_db::serialisation::Packet;
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::state::ServerState;
+use fast_stream_db::utils;
+use std::time::Duration;
+
+fn idle_for(state: &mut ServerState, stream_id: u32, seconds: u64) {
+    state.get_stream_mut(stream_id).unwrap().last_activity =
+        utils::get_current_timestamp() - seconds;
+}
+
+#[test]
+fn lowering_expiry_applies_to_next_prune() {
+    let mut state = ServerState::with_key_expiry(Duration::from_secs(150));
+    for stream_id in 1..=3 {
+        state.create_new_stream(stream_id).unwrap();
+    }
+    idle_for(&mut state, 1, 100);
+    idle_for(&mut state, 2, 40);
+
+    state.prune_expired_streams().unwrap();
+    assert_eq!(state.stream_count(), 3);
+
+    handle_client_packets(
+        &mut state,
+        vec![Packet::ClientSetGlobalExpiry { seconds: 60 }],
+    )
+    .unwrap();
+    assert_eq!(state.key_expiry(), Duration::from_secs(60));
+
+    state.prune_expired_streams().unwrap();
+    assert!(!state.stream_exists(1));
+    assert!(state.stream_exists(2));
+    assert!(state.stream_exists(3));
+
+    handle_client_packets(
+        &mut state,
+        vec![Packet::ClientSetGlobalExpiry { seconds: 30 }],
+    )
+    .unwrap();
+    state.prune_expired_streams().unwrap();
+    assert!(!state.stream_exists(2));
+    assert!(state.stream_exists(3));
+}
+
+#[test]
+fn zero_expiry_never_prunes() {
+    let mut state = ServerState::with_key_expiry(Duration::from_secs(150));
+    state.create_new_stream(1).unwrap();
+    idle_for(&mut state, 1, 1000);
+
+    handle_client_packets(
+        &mut state,
+        vec![Packet::ClientSetGlobalExpiry { seconds: 0 }],
+    )
+    .unwrap();
+    state.prune_expired_streams().unwrap();
+    assert!(state.stream_exists(1));
+}
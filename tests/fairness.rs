@@ -0,0 +1,69 @@
+mod common;
+
+use common::{MockStream, leak_settings, new_state};
+use fast_stream_db::serialisation::{Packet, read_packet_from_buffer, serialise_packets};
+use fast_stream_db::server::{ConnectionInfo, handle_connection};
+use fast_stream_db::settings::Settings;
+
+const ENQUEUE_COUNT: usize = 100_000;
+
+/// Runs a connection pipelining many one-byte enqueues alongside one asking for the length of
+/// the stream being enqueued to, returning the length it saw.
+async fn length_seen_during_enqueues(max_packets_per_yield: usize) -> u32 {
+    let settings = leak_settings(Settings {
+        // Large enough for every enqueue to arrive in a single read.
+        read_chunk_size: 4 * 1024 * 1024,
+        max_packets_per_yield,
+        ..Settings::default()
+    });
+    let state = new_state();
+    state.lock().await.create_new_stream(1).unwrap();
+
+    let enqueues = vec![
+        Packet::ClientEnqueueSingle {
+            stream_id: 1,
+            enqueue_data: vec![0].into(),
+        };
+        ENQUEUE_COUNT
+    ];
+    let enqueuer = tokio::spawn(handle_connection(
+        MockStream::new(serialise_packets(&enqueues).unwrap()),
+        state.clone(),
+        settings,
+        ConnectionInfo::new("test"),
+    ));
+    let reader = tokio::spawn(async move {
+        let mut stream = MockStream::new(
+            serialise_packets(&[Packet::ClientStreamLength { stream_id: 1 }]).unwrap(),
+        );
+        handle_connection(&mut stream, state, settings, ConnectionInfo::new("test"))
+            .await
+            .unwrap();
+        stream.flushes.concat()
+    });
+
+    enqueuer.await.unwrap().unwrap();
+    let response = reader.await.unwrap();
+    match read_packet_from_buffer(&response, 0).unwrap().value {
+        Packet::ServerStreamLength { length, .. } => length,
+        packet => panic!("Expected a stream length, got {:?}", packet),
+    }
+}
+
+#[tokio::test]
+async fn pipelined_packets_let_other_connections_run() {
+    let length = length_seen_during_enqueues(100).await;
+
+    assert!(
+        length > 0 && (length as usize) < ENQUEUE_COUNT,
+        "Length {} wasn't seen mid-pipeline",
+        length
+    );
+}
+
+#[tokio::test]
+async fn packets_under_the_cap_are_not_interrupted() {
+    let length = length_seen_during_enqueues(ENQUEUE_COUNT).await;
+
+    assert_eq!(length as usize, ENQUEUE_COUNT);
+}
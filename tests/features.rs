@@ -0,0 +1,42 @@
+#![cfg(not(feature = "admin"))]
+
+use fast_stream_db::serialisation::{ERROR_INVALID_PACKET, Packet};
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::state::ServerState;
+
+#[test]
+fn admin_packets_are_rejected_without_the_admin_feature() {
+    let admin_packets = [
+        Packet::ClientListStreamsByActivity {
+            limit: 10,
+            descending: false,
+        },
+        Packet::ClientSetGlobalExpiry { seconds: 10 },
+        Packet::ClientSelfCheck,
+        Packet::ClientFlushAll,
+        Packet::ClientDeleteAll,
+    ];
+
+    for packet in admin_packets {
+        let mut state = ServerState::new();
+        let responses = handle_client_packets(&mut state, vec![packet]).unwrap();
+
+        match responses.as_slice() {
+            [Packet::ServerError { code, message }] => {
+                assert_eq!(*code, ERROR_INVALID_PACKET);
+                assert!(String::from_utf8_lossy(message).contains("admin"));
+            }
+            _ => panic!("Unexpected responses: {:?}", responses),
+        }
+    }
+}
+
+#[test]
+fn core_packets_are_still_handled() {
+    let mut state = ServerState::new();
+
+    let responses =
+        handle_client_packets(&mut state, vec![Packet::ClientPing { nonce: None }]).unwrap();
+
+    assert_eq!(responses, vec![Packet::ServerPong { nonce: None }]);
+}
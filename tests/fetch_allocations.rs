@@ -0,0 +1,72 @@
+use fast_stream_db::serialisation::{Packet, serialise_packets};
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::state::ServerState;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+/// Counts allocations made by this test binary, so the benchmark can report them.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(new_size, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[test]
+#[ignore = "benchmark, run with --release -- --ignored --nocapture"]
+fn bench_fetch_heavy_workload() {
+    const CYCLES: usize = 100_000;
+    const STREAM_COUNT: u32 = 64;
+    let mut state = ServerState::new();
+    for stream_id in 0..STREAM_COUNT {
+        state.create_new_stream(stream_id).unwrap();
+    }
+    let enqueue_data = vec![0xAB; 256];
+
+    let allocations_before = ALLOCATIONS.load(Ordering::Relaxed);
+    let bytes_before = ALLOCATED_BYTES.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for cycle in 0..CYCLES {
+        let stream_id = cycle as u32 % STREAM_COUNT;
+        for _ in 0..4 {
+            state.enqueue_single(stream_id, &enqueue_data).unwrap();
+        }
+        let responses = handle_client_packets(
+            &mut state,
+            vec![Packet::ClientRequestStreamContents { stream_id }],
+        )
+        .unwrap();
+        assert_eq!(serialise_packets(&responses).unwrap().len(), 8 + 4 + 1024);
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations_before;
+    let allocated_bytes = ALLOCATED_BYTES.load(Ordering::Relaxed) - bytes_before;
+
+    println!(
+        "{} fetch cycles in {:?}: {:.2} allocations and {} bytes allocated per cycle",
+        CYCLES,
+        elapsed,
+        allocations as f64 / CYCLES as f64,
+        allocated_bytes / CYCLES
+    );
+}
@@ -0,0 +1,83 @@
+mod common;
+
+use common::{MockStream, default_settings, new_state};
+use fast_stream_db::serialisation::{Bytes, Packet, deserialise_packets, serialise_packets};
+use fast_stream_db::server::{ConnectionInfo, handle_client_packets, handle_connection};
+use fast_stream_db::state::ServerState;
+
+#[test]
+fn fetch_moves_contents_out_and_reclaims_the_buffer() {
+    let mut state = ServerState::new();
+    state.create_new_stream(1).unwrap();
+    let data = vec![7u8; 1 << 20];
+    state.enqueue_single(1, &data).unwrap();
+
+    let contents = state.fetch_stream_contents(1).unwrap();
+
+    assert_eq!(contents, data);
+    assert!(state.get_stream(1).unwrap().buffer.is_empty());
+    assert_eq!(state.total_bytes(), 0);
+
+    // Once the fetched contents are dropped, the next enqueue reuses their allocation.
+    drop(contents);
+    state.enqueue_single(1, b"next").unwrap();
+    assert!(state.get_stream(1).unwrap().buffer.capacity() >= data.len());
+    assert_eq!(state.fetch_stream_contents(1).unwrap(), &b"next"[..]);
+}
+
+#[tokio::test]
+async fn fetches_in_one_read_are_answered_in_one_write_without_copies() {
+    let state = new_state();
+    let mut buffers = Vec::new();
+    {
+        let mut state = state.lock().await;
+        for stream_id in 1..=3 {
+            state.create_new_stream(stream_id).unwrap();
+            state
+                .enqueue_single(stream_id, &[stream_id as u8; 100])
+                .unwrap();
+            buffers.push(state.get_stream(stream_id).unwrap().buffer.as_ptr());
+        }
+    }
+
+    // Fetched contents are split off the streams' buffers rather than copied.
+    let fetches = (1..=3)
+        .map(|stream_id| Packet::ClientRequestStreamContents { stream_id })
+        .collect::<Vec<_>>();
+    let responses = handle_client_packets(&mut *state.lock().await, fetches.clone()).unwrap();
+    for (response, buffer) in responses.iter().zip(&buffers) {
+        let Packet::ServerStreamContents { buffer_data } = response else {
+            panic!("Expected stream contents, got {:?}", response);
+        };
+        assert_eq!(buffer_data.as_ptr(), *buffer);
+    }
+
+    // Over a connection, every response to the batch goes out in a single write.
+    for stream_id in 1..=3 {
+        state
+            .lock()
+            .await
+            .enqueue_single(stream_id, b"again")
+            .unwrap();
+    }
+    let mut stream = MockStream::new(serialise_packets(&fetches).unwrap());
+    handle_connection(
+        &mut stream,
+        state,
+        default_settings(),
+        ConnectionInfo::new("test"),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(stream.flushes.len(), 1);
+    assert_eq!(
+        deserialise_packets(&stream.flushes[0]).unwrap(),
+        vec![
+            Packet::ServerStreamContents {
+                buffer_data: Bytes::from_static(b"again"),
+            };
+            3
+        ]
+    );
+}
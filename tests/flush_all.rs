@@ -0,0 +1,74 @@
+#![cfg(feature = "admin")]
+
+use fast_stream_db::serialisation::{Bytes, Packet};
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::state::ServerState;
+
+fn populated_state() -> ServerState {
+    let mut state = ServerState::new();
+    for stream_id in 1..=3 {
+        state.create_new_stream(stream_id).unwrap();
+        state.enqueue_single(stream_id, &[0; 10]).unwrap();
+    }
+    state.create_named_stream(b"named").unwrap();
+    state
+}
+
+fn send(state: &mut ServerState, packet: Packet) -> Packet {
+    let mut responses = handle_client_packets(state, vec![packet]).unwrap();
+
+    assert_eq!(responses.len(), 1, "Unexpected responses: {:?}", responses);
+    responses.remove(0)
+}
+
+#[test]
+fn flush_all_empties_every_stream_but_keeps_them() {
+    let mut state = populated_state();
+
+    assert_eq!(
+        send(&mut state, Packet::ClientFlushAll),
+        Packet::ServerFlushResult {
+            streams_affected: 4
+        }
+    );
+
+    assert_eq!(state.stream_count(), 4);
+    assert_eq!(state.total_bytes(), 0);
+    assert!(state.get_stream(1).unwrap().buffer.is_empty());
+    assert_eq!(state.get_stream(1).unwrap().base_offset, 10);
+    assert!(state.named_stream_id(b"named").is_some());
+}
+
+#[test]
+fn delete_all_removes_every_stream() {
+    let mut state = populated_state();
+
+    assert_eq!(
+        send(&mut state, Packet::ClientDeleteAll),
+        Packet::ServerFlushResult {
+            streams_affected: 4
+        }
+    );
+
+    assert_eq!(state.stream_count(), 0);
+    assert_eq!(state.total_bytes(), 0);
+    assert!(state.named_stream_id(b"named").is_none());
+    assert_eq!(
+        send(&mut state, Packet::ClientDeleteAll),
+        Packet::ServerFlushResult {
+            streams_affected: 0
+        }
+    );
+}
+
+#[test]
+fn flushing_needs_the_admin_token_when_one_is_set() {
+    let mut state = populated_state();
+    state.set_admin_token(Some(Bytes::from_static(b"secret")));
+
+    assert!(matches!(
+        send(&mut state, Packet::ClientDeleteAll),
+        Packet::ServerError { .. }
+    ));
+    assert_eq!(state.stream_count(), 4);
+}
@@ -0,0 +1,49 @@
+mod common;
+
+use common::{TestClient, new_state};
+use fast_stream_db::serialisation::{Bytes, Packet};
+
+#[tokio::test]
+async fn goodbye_is_acknowledged_after_preceding_enqueues() {
+    let state = new_state();
+    let mut client = TestClient::connect(state.clone());
+
+    client.create_stream(1).await;
+    client
+        .send(&[
+            Packet::ClientEnqueueSingle {
+                stream_id: 1,
+                enqueue_data: Bytes::from_static(b"hello"),
+            },
+            Packet::ClientEnqueueAll {
+                enqueue_data: Bytes::from_static(b" world"),
+            },
+            Packet::ClientGoodbye,
+        ])
+        .await;
+
+    assert_eq!(client.recv().await, Packet::ServerGoodbye);
+    assert_eq!(
+        state.lock().await.fetch_stream_contents(1).unwrap(),
+        &b"hello world"[..]
+    );
+    assert!(client.is_closed().await);
+}
+
+#[tokio::test]
+async fn packets_after_goodbye_are_ignored() {
+    let state = new_state();
+    let mut client = TestClient::connect(state.clone());
+
+    client
+        .send(&[
+            Packet::ClientGoodbye,
+            Packet::ClientCreateNewStream { stream_id: 1 },
+            Packet::ClientPing { nonce: None },
+        ])
+        .await;
+
+    assert_eq!(client.recv().await, Packet::ServerGoodbye);
+    assert!(client.is_closed().await);
+    assert!(!state.lock().await.stream_exists(1));
+}
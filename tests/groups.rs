@@ -0,0 +1,177 @@
+use fast_stream_db::serialisation::{Bytes, ERROR_UNKNOWN_GROUP, ERROR_UNKNOWN_STREAM, Packet};
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::state::ServerState;
+use std::collections::HashSet;
+
+const SPECTATORS: u32 = 10;
+
+/// Streams 1 to 4, with 2 and 3 in the spectators group.
+fn state_with_group() -> ServerState {
+    let mut state = ServerState::new();
+    for stream_id in 1..=4 {
+        state.create_new_stream(stream_id).unwrap();
+    }
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![
+            Packet::ClientCreateGroup {
+                group_id: SPECTATORS,
+            },
+            Packet::ClientAddToGroup {
+                group_id: SPECTATORS,
+                stream_id: 2,
+            },
+            Packet::ClientAddToGroup {
+                group_id: SPECTATORS,
+                stream_id: 3,
+            },
+        ],
+    )
+    .unwrap();
+    assert_eq!(
+        responses,
+        vec![Packet::ServerGroupCreated {
+            group_id: SPECTATORS,
+            created: true,
+        }]
+    );
+    state
+}
+
+fn buffer(state: &ServerState, stream_id: u32) -> &[u8] {
+    &state.get_stream(stream_id).unwrap().buffer
+}
+
+fn error_code(responses: &[Packet]) -> u32 {
+    match responses {
+        [Packet::ServerError { code, .. }] => *code,
+        _ => panic!("Unexpected responses: {:?}", responses),
+    }
+}
+
+#[test]
+fn broadcast_reaches_only_streams_outside_the_group() {
+    let mut state = state_with_group();
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![Packet::ClientEnqueueAllExceptGroup {
+            group_id: SPECTATORS,
+            enqueue_data: Bytes::from_static(b"lobby"),
+        }],
+    )
+    .unwrap();
+
+    assert!(responses.is_empty(), "{:?}", responses);
+    assert_eq!(buffer(&state, 1), b"lobby");
+    assert_eq!(buffer(&state, 2), b"");
+    assert_eq!(buffer(&state, 3), b"");
+    assert_eq!(buffer(&state, 4), b"lobby");
+    assert_eq!(state.total_bytes(), 10);
+}
+
+#[test]
+fn removed_members_receive_broadcasts_again() {
+    let mut state = state_with_group();
+
+    handle_client_packets(
+        &mut state,
+        vec![
+            Packet::ClientRemoveFromGroup {
+                group_id: SPECTATORS,
+                stream_id: 3,
+            },
+            Packet::ClientEnqueueAllExceptGroup {
+                group_id: SPECTATORS,
+                enqueue_data: Bytes::from_static(b"x"),
+            },
+        ],
+    )
+    .unwrap();
+
+    assert_eq!(buffer(&state, 2), b"");
+    assert_eq!(buffer(&state, 3), b"x");
+}
+
+#[test]
+fn unknown_groups_and_streams_are_rejected() {
+    let mut state = state_with_group();
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![Packet::ClientEnqueueAllExceptGroup {
+            group_id: 99,
+            enqueue_data: Bytes::from_static(b"x"),
+        }],
+    )
+    .unwrap();
+    assert_eq!(error_code(&responses), ERROR_UNKNOWN_GROUP);
+    assert_eq!(state.total_bytes(), 0);
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![Packet::ClientAddToGroup {
+            group_id: 99,
+            stream_id: 1,
+        }],
+    )
+    .unwrap();
+    assert_eq!(error_code(&responses), ERROR_UNKNOWN_GROUP);
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![Packet::ClientAddToGroup {
+            group_id: SPECTATORS,
+            stream_id: 99,
+        }],
+    )
+    .unwrap();
+    assert_eq!(error_code(&responses), ERROR_UNKNOWN_STREAM);
+}
+
+#[test]
+fn existing_groups_are_left_intact() {
+    let mut state = state_with_group();
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![Packet::ClientCreateGroup {
+            group_id: SPECTATORS,
+        }],
+    )
+    .unwrap();
+
+    assert_eq!(
+        responses,
+        vec![Packet::ServerGroupCreated {
+            group_id: SPECTATORS,
+            created: false,
+        }]
+    );
+    assert_eq!(
+        state.group_members(SPECTATORS),
+        Some(&HashSet::from([2, 3]))
+    );
+}
+
+#[test]
+fn membership_follows_deletes_and_renames() {
+    let mut state = state_with_group();
+
+    state.delete_stream(2).unwrap();
+    state.rename_stream(3, 30).unwrap();
+    assert_eq!(state.group_members(SPECTATORS), Some(&HashSet::from([30])));
+
+    state.delete_all();
+    assert_eq!(state.group_members(SPECTATORS), Some(&HashSet::new()));
+
+    handle_client_packets(
+        &mut state,
+        vec![Packet::ClientDeleteGroup {
+            group_id: SPECTATORS,
+        }],
+    )
+    .unwrap();
+    assert_eq!(state.group_members(SPECTATORS), None);
+}
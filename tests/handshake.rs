@@ -0,0 +1,55 @@
+mod common;
+
+use common::{TestClient, new_state};
+use fast_stream_db::serialisation::{PROTOCOL_VERSION, Packet};
+
+#[tokio::test]
+async fn supported_version_is_accepted() {
+    let mut client = TestClient::connect(new_state());
+
+    client
+        .send(&[Packet::ClientHello {
+            protocol_version: PROTOCOL_VERSION,
+        }])
+        .await;
+
+    assert_eq!(
+        client.recv().await,
+        Packet::ServerHello {
+            protocol_version: PROTOCOL_VERSION,
+            accepted: true,
+        }
+    );
+    client.sync().await;
+}
+
+#[tokio::test]
+async fn unsupported_version_is_rejected_and_closed() {
+    for protocol_version in [0, PROTOCOL_VERSION + 1] {
+        let mut client = TestClient::connect(new_state());
+
+        client
+            .send(&[
+                Packet::ClientHello { protocol_version },
+                Packet::ClientPing { nonce: None },
+            ])
+            .await;
+
+        assert_eq!(
+            client.recv().await,
+            Packet::ServerHello {
+                protocol_version: PROTOCOL_VERSION,
+                accepted: false,
+            }
+        );
+        // The pipelined ping is never answered.
+        assert!(client.is_closed().await);
+    }
+}
+
+#[tokio::test]
+async fn handshake_is_optional() {
+    let mut client = TestClient::connect(new_state());
+
+    client.sync().await;
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use common::{connect_unix, leak_settings, new_state, temp_socket_path};
+use fast_stream_db::serialisation::{HEALTH_DEGRADED, HEALTH_READY, HEALTH_SHUTTING_DOWN, Packet};
+use fast_stream_db::server::{handle_client_packets, run_servers};
+use fast_stream_db::settings::{ConnectionMode, Settings};
+use fast_stream_db::state::ServerState;
+use tokio::sync::oneshot;
+use tokio::time::{Duration, timeout};
+
+#[test]
+fn health_check_reports_a_fresh_server_as_ready() {
+    let mut state = ServerState::new();
+
+    let responses = handle_client_packets(&mut state, vec![Packet::ClientHealthCheck]).unwrap();
+
+    assert_eq!(
+        responses,
+        vec![Packet::ServerHealth {
+            status: HEALTH_READY
+        }]
+    );
+}
+
+#[test]
+fn buffering_past_the_threshold_is_degraded() {
+    let mut state = ServerState::new();
+    state.set_health_thresholds(100, 0);
+    state.create_new_stream(1).unwrap();
+    state.enqueue_single(1, &[0; 99]).unwrap();
+    assert_eq!(state.health_status(0), HEALTH_READY);
+
+    state.enqueue_single(1, &[0]).unwrap();
+    assert_eq!(state.health_status(0), HEALTH_DEGRADED);
+
+    state.fetch_stream_contents(1).unwrap();
+    assert_eq!(state.health_status(0), HEALTH_READY);
+}
+
+#[test]
+fn reaching_the_connection_limit_is_degraded() {
+    let mut state = ServerState::new();
+    state.set_health_thresholds(0, 10);
+
+    assert_eq!(state.health_status(9), HEALTH_READY);
+    assert_eq!(state.health_status(10), HEALTH_DEGRADED);
+}
+
+#[test]
+fn zero_thresholds_never_degrade() {
+    let mut state = ServerState::new();
+    state.create_new_stream(1).unwrap();
+    state.enqueue_single(1, &[0; 1000]).unwrap();
+
+    assert_eq!(state.health_status(u64::MAX), HEALTH_READY);
+}
+
+#[test]
+fn shutting_down_outranks_degraded() {
+    let mut state = ServerState::new();
+    state.set_health_thresholds(0, 1);
+    state.begin_shutdown();
+
+    assert_eq!(state.health_status(5), HEALTH_SHUTTING_DOWN);
+}
+
+#[tokio::test]
+async fn open_connections_see_the_shutdown() {
+    let path = temp_socket_path("health");
+    let settings = leak_settings(Settings {
+        listeners: vec![ConnectionMode::UnixSocket],
+        unix_sock_path: path.clone(),
+        ..Settings::default()
+    });
+    let state = new_state();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let server = tokio::spawn(run_servers(settings, state.clone(), async {
+        let _ = shutdown_rx.await;
+    }));
+    let mut client = connect_unix(&path).await;
+    client.send(&[Packet::ClientHealthCheck]).await;
+    assert_eq!(
+        client.recv().await,
+        Packet::ServerHealth {
+            status: HEALTH_READY
+        }
+    );
+
+    shutdown_tx.send(()).unwrap();
+    while state.lock().await.health_status(0) != HEALTH_SHUTTING_DOWN {
+        tokio::task::yield_now().await;
+    }
+    client.send(&[Packet::ClientHealthCheck]).await;
+    assert_eq!(
+        client.recv().await,
+        Packet::ServerHealth {
+            status: HEALTH_SHUTTING_DOWN
+        }
+    );
+
+    drop(client);
+    timeout(Duration::from_secs(5), server)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+}
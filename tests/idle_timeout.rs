@@ -0,0 +1,34 @@
+mod common;
+
+use common::{TestClient, leak_settings, new_state};
+use fast_stream_db::settings::Settings;
+use tokio::time::{Duration, Instant, sleep, timeout};
+
+fn idle_timeout_settings() -> &'static Settings {
+    leak_settings(Settings {
+        connection_idle_timeout: Duration::from_secs(1),
+        ..Settings::default()
+    })
+}
+
+#[tokio::test]
+async fn silent_connection_is_closed() {
+    let mut client = TestClient::connect_with_settings(new_state(), idle_timeout_settings());
+
+    let closed = timeout(Duration::from_secs(5), client.is_closed()).await;
+
+    assert_eq!(closed, Ok(true));
+}
+
+#[tokio::test]
+async fn active_connection_outlives_the_timeout() {
+    let mut client = TestClient::connect_with_settings(new_state(), idle_timeout_settings());
+
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_millis(1500) {
+        client.sync().await;
+        sleep(Duration::from_millis(300)).await;
+    }
+
+    client.sync().await;
+}
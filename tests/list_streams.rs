@@ -0,0 +1,100 @@
+use fast_stream_db::serialisation::{Bytes, ERROR_NOT_AUTHENTICATED, Packet};
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::state::ServerState;
+
+fn list_streams(state: &mut ServerState, offset: u32, limit: u32) -> Packet {
+    let mut responses =
+        handle_client_packets(state, vec![Packet::ClientListStreams { offset, limit }]).unwrap();
+
+    assert_eq!(responses.len(), 1, "Unexpected responses: {:?}", responses);
+    responses.remove(0)
+}
+
+#[test]
+fn pages_through_streams_in_ascending_order() {
+    let mut state = ServerState::new();
+    for stream_id in [5, 1, 9, 3, 7] {
+        state.create_new_stream(stream_id).unwrap();
+    }
+
+    assert_eq!(
+        list_streams(&mut state, 0, 2),
+        Packet::ServerStreamList {
+            total_count: 5,
+            stream_ids: vec![1, 3],
+        }
+    );
+    assert_eq!(
+        list_streams(&mut state, 2, 2),
+        Packet::ServerStreamList {
+            total_count: 5,
+            stream_ids: vec![5, 7],
+        }
+    );
+    assert_eq!(
+        list_streams(&mut state, 4, 2),
+        Packet::ServerStreamList {
+            total_count: 5,
+            stream_ids: vec![9],
+        }
+    );
+    assert_eq!(
+        list_streams(&mut state, 10, 2),
+        Packet::ServerStreamList {
+            total_count: 5,
+            stream_ids: Vec::new(),
+        }
+    );
+}
+
+#[test]
+fn page_size_is_capped() {
+    let mut state = ServerState::new();
+    for stream_id in 0..5000 {
+        state.create_new_stream(stream_id).unwrap();
+    }
+
+    let Packet::ServerStreamList {
+        total_count,
+        stream_ids,
+    } = list_streams(&mut state, 0, u32::MAX)
+    else {
+        panic!("Expected a stream list");
+    };
+
+    assert_eq!(total_count, 5000);
+    assert_eq!(stream_ids, (0..4096).collect::<Vec<u32>>());
+}
+
+#[test]
+fn listing_needs_the_admin_token() {
+    let mut state = ServerState::new();
+    state.create_new_stream(1).unwrap();
+    state.set_admin_token(Some(Bytes::from_static(b"secret")));
+
+    match list_streams(&mut state, 0, 10) {
+        Packet::ServerError { code, .. } => assert_eq!(code, ERROR_NOT_AUTHENTICATED),
+        response => panic!("Unexpected response: {:?}", response),
+    }
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![
+            Packet::ClientAuthenticate {
+                token: Bytes::from_static(b"secret"),
+            },
+            Packet::ClientListStreams {
+                offset: 0,
+                limit: 10,
+            },
+        ],
+    )
+    .unwrap();
+    assert_eq!(
+        responses[1],
+        Packet::ServerStreamList {
+            total_count: 1,
+            stream_ids: vec![1],
+        }
+    );
+}
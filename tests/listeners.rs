@@ -0,0 +1,74 @@
+mod common;
+
+use common::{
+    connect_tcp, connect_unix, free_tcp_port, leak_settings, new_state, temp_socket_path,
+};
+use fast_stream_db::serialisation::{Bytes, Packet};
+use fast_stream_db::server::run_servers;
+use fast_stream_db::settings::{ConnectionMode, Settings};
+use std::path::Path;
+use tokio::sync::oneshot;
+use tokio::time::{Duration, timeout};
+
+#[test]
+fn listener_lists_are_parsed() {
+    assert_eq!(
+        ConnectionMode::parse_list("TCP,UNIX_SOCK").unwrap(),
+        vec![ConnectionMode::Tcp, ConnectionMode::UnixSocket]
+    );
+    assert_eq!(
+        ConnectionMode::parse_list(" UNIX_SOCK , TCP, UNIX_SOCK,").unwrap(),
+        vec![ConnectionMode::UnixSocket, ConnectionMode::Tcp]
+    );
+    assert!(ConnectionMode::parse_list("").is_err());
+    assert!(ConnectionMode::parse_list("TCP,UDP").is_err());
+}
+
+#[tokio::test]
+async fn tcp_and_unix_listeners_share_state() {
+    let path = temp_socket_path("listeners");
+    let port = free_tcp_port();
+    let settings = leak_settings(Settings {
+        listeners: vec![ConnectionMode::Tcp, ConnectionMode::UnixSocket],
+        unix_sock_path: path.clone(),
+        tcp_port: port,
+        ..Settings::default()
+    });
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let server = tokio::spawn(run_servers(settings, new_state(), async {
+        let _ = shutdown_rx.await;
+    }));
+
+    let mut tcp_client = connect_tcp(port).await;
+    let mut unix_client = connect_unix(&path).await;
+
+    tcp_client.create_stream(1).await;
+    tcp_client
+        .send(&[Packet::ClientEnqueueSingle {
+            stream_id: 1,
+            enqueue_data: Bytes::from_static(b"over tcp"),
+        }])
+        .await;
+    tcp_client.sync().await;
+
+    unix_client
+        .send(&[Packet::ClientRequestStreamContents { stream_id: 1 }])
+        .await;
+    assert_eq!(
+        unix_client.recv().await,
+        Packet::ServerStreamContents {
+            buffer_data: Bytes::from_static(b"over tcp")
+        }
+    );
+
+    // Both listeners stop on the same shutdown.
+    drop(tcp_client);
+    drop(unix_client);
+    shutdown_tx.send(()).unwrap();
+    timeout(Duration::from_secs(5), server)
+        .await
+        .expect("Servers did not shut down")
+        .unwrap()
+        .unwrap();
+    assert!(!Path::new(&path).exists());
+}
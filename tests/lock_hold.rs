@@ -0,0 +1,82 @@
+use fast_stream_db::state::ServerState;
+use fast_stream_db::utils;
+use std::time::{Duration, Instant};
+
+fn populated_state(stream_count: u32, stream_size: usize) -> ServerState {
+    let mut state = ServerState::new();
+    let data = vec![0xAB; stream_size];
+    for stream_id in 1..=stream_count {
+        state.create_new_stream(stream_id).unwrap();
+        state.enqueue_single(stream_id, &data).unwrap();
+    }
+    state
+}
+
+#[test]
+fn removed_stream_is_handed_back_with_its_data() {
+    let mut state = populated_state(2, 10);
+
+    let stream = state.remove_stream(1).unwrap();
+
+    assert_eq!(stream.buffer, vec![0xAB; 10]);
+    assert!(state.get_stream(1).is_none());
+    assert_eq!(state.total_bytes(), 10);
+    assert!(state.remove_stream(1).is_none());
+}
+
+#[test]
+fn removing_all_streams_hands_every_one_back() {
+    let mut state = populated_state(3, 10);
+
+    let streams = state.remove_all_streams();
+
+    assert_eq!(streams.len(), 3);
+    assert_eq!(state.stream_count(), 0);
+    assert_eq!(state.total_bytes(), 0);
+}
+
+#[test]
+fn expired_streams_are_handed_back() {
+    let mut state = ServerState::with_key_expiry(Duration::from_secs(60));
+    state.create_new_stream(1).unwrap();
+    state.enqueue_single(1, &[0xAB; 10]).unwrap();
+    state.create_new_stream(2).unwrap();
+    state.get_stream_mut(1).unwrap().last_activity = utils::get_current_timestamp() - 120;
+
+    let streams = state.remove_expired_streams();
+
+    assert_eq!(streams.len(), 1);
+    assert_eq!(streams[0].buffer, vec![0xAB; 10]);
+    assert!(state.get_stream(1).is_none());
+    assert_eq!(state.stream_count(), 1);
+}
+
+#[test]
+#[ignore = "benchmark, run with --release -- --ignored --nocapture"]
+fn bench_delete_all_lock_hold() {
+    const STREAM_COUNT: u32 = 2_000;
+    // Above glibc's mmap threshold, so each buffer is returned to the OS when freed.
+    const STREAM_SIZE: usize = 256 * 1024;
+
+    let mut state = populated_state(STREAM_COUNT, STREAM_SIZE);
+    let start = Instant::now();
+    state.delete_all();
+    let freed_under_lock = start.elapsed();
+
+    let mut state = populated_state(STREAM_COUNT, STREAM_SIZE);
+    let start = Instant::now();
+    let streams = state.remove_all_streams();
+    let deferred = start.elapsed();
+    let start = Instant::now();
+    drop(streams);
+    let freed_after = start.elapsed();
+
+    println!(
+        "deleting {} streams of {} KiB: lock held {:?} freeing inside it, {:?} deferring ({:?} freeing after)",
+        STREAM_COUNT,
+        STREAM_SIZE / 1024,
+        freed_under_lock,
+        deferred,
+        freed_after
+    );
+}
@@ -0,0 +1,100 @@
+mod common;
+
+use common::{TestClient, new_state};
+use fast_stream_db::serialisation::{Bytes, ERROR_INVALID_PACKET, Packet, serialise_packets};
+use tokio::time::{Duration, timeout};
+
+#[tokio::test]
+async fn packets_sent_a_byte_at_a_time_are_reassembled() {
+    let mut client = TestClient::connect(new_state());
+
+    let data = serialise_packets(&[
+        Packet::ClientCreateNewStream { stream_id: 1 },
+        Packet::ClientEnqueueSingle {
+            stream_id: 1,
+            enqueue_data: Bytes::from_static(b"hello"),
+        },
+        Packet::ClientRequestStreamContents { stream_id: 1 },
+    ])
+    .unwrap();
+    for byte in data {
+        client.send_raw(&[byte]).await;
+        tokio::task::yield_now().await;
+    }
+
+    assert_eq!(
+        client.recv().await,
+        Packet::ServerStreamCreated {
+            stream_id: 1,
+            created: true,
+        }
+    );
+    assert_eq!(
+        client.recv().await,
+        Packet::ServerStreamContents {
+            buffer_data: Bytes::from_static(b"hello")
+        }
+    );
+}
+
+#[tokio::test]
+async fn truncated_packet_keeps_the_connection_open() {
+    let mut client = TestClient::connect(new_state());
+
+    let data = serialise_packets(&[Packet::ClientEnqueueSingle {
+        stream_id: 1,
+        enqueue_data: vec![0; 32].into(),
+    }])
+    .unwrap();
+    client.send_raw(&data[..data.len() - 1]).await;
+    client.send_raw(&data[data.len() - 1..]).await;
+
+    client.sync().await;
+}
+
+#[tokio::test]
+async fn unknown_packet_id_is_reported_and_closes_the_connection() {
+    let mut client = TestClient::connect(new_state());
+
+    let mut data = 4u32.to_le_bytes().to_vec(); // Packet length.
+    data.extend_from_slice(&u32::MAX.to_le_bytes()); // Packet ID.
+    client.send_raw(&data).await;
+
+    match client.recv().await {
+        Packet::ServerError { code, .. } => assert_eq!(code, ERROR_INVALID_PACKET),
+        packet => panic!("Expected an error, got {:?}", packet),
+    }
+    assert!(client.is_closed().await);
+}
+
+#[tokio::test]
+async fn frame_too_short_for_a_packet_id_closes_the_connection() {
+    let mut client = TestClient::connect(new_state());
+
+    // A declared length of 0 leaves no room for a packet ID, so the frame can never be parsed.
+    // Waiting for more data would re-parse the same bytes forever.
+    client.send_raw(&0u32.to_le_bytes()).await;
+
+    let closed = timeout(Duration::from_secs(5), async {
+        match client.recv().await {
+            Packet::ServerError { code, .. } => assert_eq!(code, ERROR_INVALID_PACKET),
+            packet => panic!("Expected an error, got {:?}", packet),
+        }
+        client.is_closed().await
+    })
+    .await;
+    assert_eq!(closed, Ok(true));
+}
+
+#[tokio::test]
+async fn server_packet_from_client_is_reported_without_closing() {
+    let mut client = TestClient::connect(new_state());
+
+    client.send(&[Packet::ServerPong { nonce: None }]).await;
+
+    match client.recv().await {
+        Packet::ServerError { code, .. } => assert_eq!(code, ERROR_INVALID_PACKET),
+        packet => panic!("Expected an error, got {:?}", packet),
+    }
+    client.sync().await;
+}
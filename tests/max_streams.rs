@@ -0,0 +1,82 @@
+use fast_stream_db::serialisation::{ERROR_STREAM_LIMIT_REACHED, Packet, RequestError};
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::state::ServerState;
+use fast_stream_db::utils;
+use std::time::Duration;
+
+fn assert_limit_reached(result: anyhow::Result<impl std::fmt::Debug>) {
+    let error = result.unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<RequestError>().unwrap().code,
+        ERROR_STREAM_LIMIT_REACHED
+    );
+}
+
+#[test]
+fn creation_resumes_once_a_stream_is_pruned() {
+    let mut state = ServerState::with_key_expiry(Duration::from_secs(60));
+    state.set_max_streams(3);
+    for stream_id in 1..=3 {
+        state.create_new_stream(stream_id).unwrap();
+    }
+
+    assert_limit_reached(state.create_new_stream(4));
+    assert_eq!(state.stream_count(), 3);
+
+    state.get_stream_mut(1).unwrap().last_activity = utils::get_current_timestamp() - 120;
+    state.prune_expired_streams().unwrap();
+
+    state.create_new_stream(4).unwrap();
+    assert_eq!(state.stream_count(), 3);
+}
+
+#[test]
+fn existing_streams_can_be_recreated_at_the_limit() {
+    let mut state = ServerState::new();
+    state.set_max_streams(1);
+    state.create_new_stream(1).unwrap();
+
+    state.create_new_stream(1).unwrap();
+    assert_eq!(state.create_multiple_streams(&[1]).unwrap(), vec![1]);
+}
+
+#[test]
+fn batch_creation_is_all_or_nothing() {
+    let mut state = ServerState::new();
+    state.set_max_streams(3);
+    state.create_new_stream(1).unwrap();
+
+    assert_limit_reached(state.create_multiple_streams(&[1, 2, 3, 4]));
+    assert_eq!(state.stream_count(), 1);
+
+    // Repeated IDs only count once.
+    state.create_multiple_streams(&[1, 2, 3, 3]).unwrap();
+    assert_eq!(state.stream_count(), 3);
+}
+
+#[test]
+fn limit_is_reported_to_the_client() {
+    let mut state = ServerState::new();
+    state.set_max_streams(1);
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![
+            Packet::ClientCreateNewStream { stream_id: 1 },
+            Packet::ClientCreateNewStream { stream_id: 2 },
+        ],
+    )
+    .unwrap();
+
+    match responses.as_slice() {
+        [
+            Packet::ServerStreamCreated {
+                stream_id: 1,
+                created: true,
+            },
+            Packet::ServerError { code, .. },
+        ] => assert_eq!(*code, ERROR_STREAM_LIMIT_REACHED),
+        responses => panic!("Expected a creation then an error, got {:?}", responses),
+    }
+    assert!(!state.stream_exists(2));
+}
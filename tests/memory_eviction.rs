@@ -0,0 +1,97 @@
+mod common;
+
+use common::enqueue;
+use fast_stream_db::serialisation::Packet;
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::state::ServerState;
+use fast_stream_db::utils;
+
+/// Streams 1 to 4 holding 10 bytes each, with stream 1 the most recently active and stream 4
+/// the least.
+fn filled_state(memory_soft_limit: usize) -> ServerState {
+    let mut state = ServerState::new();
+    state.set_memory_soft_limit(memory_soft_limit);
+    let current_timestamp = utils::get_current_timestamp();
+    for stream_id in 1..=4 {
+        state.create_new_stream(stream_id).unwrap();
+        state.enqueue_single(stream_id, b"0123456789").unwrap();
+        state.get_stream_mut(stream_id).unwrap().last_activity =
+            current_timestamp - 100 * stream_id as u64;
+    }
+    state
+}
+
+#[test]
+fn least_recently_active_streams_are_evicted_first() {
+    let mut state = filled_state(45);
+    // Enqueueing counts as activity, so stream 3 becomes the most recent.
+    handle_client_packets(&mut state, vec![enqueue(3, b"0123456789")]).unwrap();
+
+    assert!(state.stream_exists(1));
+    assert!(state.stream_exists(2));
+    assert!(state.stream_exists(3));
+    assert!(!state.stream_exists(4));
+    assert_eq!(state.total_bytes(), 40);
+
+    // Further over the limit, the next oldest goes before the one just written to.
+    handle_client_packets(&mut state, vec![enqueue(3, b"0123456789")]).unwrap();
+
+    assert!(state.stream_exists(1));
+    assert!(!state.stream_exists(2));
+    assert!(state.stream_exists(3));
+    assert_eq!(state.total_bytes(), 40);
+    assert_eq!(state.total_bytes(), state.scanned_total_bytes());
+}
+
+#[test]
+fn idle_empty_streams_survive_eviction() {
+    let mut state = filled_state(25);
+    state.create_new_stream(5).unwrap();
+    state.get_stream_mut(5).unwrap().last_activity = 0;
+
+    handle_client_packets(
+        &mut state,
+        vec![Packet::ClientCreateNewStream { stream_id: 6 }],
+    )
+    .unwrap();
+
+    // Streams 4 and 3 held the oldest bytes, while the older stream 5 and the new 6 hold none.
+    assert!(state.stream_exists(5));
+    assert!(state.stream_exists(6));
+    assert!(!state.stream_exists(4));
+    assert!(!state.stream_exists(3));
+    assert_eq!(state.total_bytes(), 20);
+}
+
+#[test]
+fn dead_letter_stream_is_never_evicted() {
+    let mut state = filled_state(15);
+    state.set_dead_letter_stream(Some(4));
+
+    handle_client_packets(&mut state, vec![enqueue(1, b"0123456789")]).unwrap();
+
+    assert!(state.stream_exists(4));
+    assert!(!state.stream_exists(3));
+    assert!(!state.stream_exists(2));
+    // Even the stream just written to goes before the older dead letter stream.
+    assert!(!state.stream_exists(1));
+    assert_eq!(state.total_bytes(), 10);
+}
+
+#[test]
+fn nothing_is_evicted_under_the_limit() {
+    let mut state = filled_state(50);
+    handle_client_packets(&mut state, vec![enqueue(1, b"0123456789")]).unwrap();
+
+    assert_eq!(state.stream_count(), 4);
+    assert_eq!(state.total_bytes(), 50);
+}
+
+#[test]
+fn zero_limit_never_evicts() {
+    let mut state = filled_state(0);
+    handle_client_packets(&mut state, vec![enqueue(1, &[0; 1000])]).unwrap();
+
+    assert_eq!(state.stream_count(), 4);
+    assert!(state.remove_streams_over_memory_limit().is_empty());
+}
@@ -0,0 +1,80 @@
+use fast_stream_db::serialisation::{Bytes, ERROR_PAYLOAD_TOO_LARGE, Packet};
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::state::{MAX_STREAM_METADATA_SIZE, ServerState};
+
+const STREAM_ID: u32 = 3;
+
+fn get_metadata(state: &mut ServerState, stream_id: u32) -> Vec<u8> {
+    let responses =
+        handle_client_packets(state, vec![Packet::ClientGetStreamMetadata { stream_id }]).unwrap();
+
+    match responses.as_slice() {
+        [Packet::ServerStreamMetadata { metadata, .. }] => metadata.to_vec(),
+        _ => panic!("Unexpected responses: {:?}", responses),
+    }
+}
+
+fn set_metadata(state: &mut ServerState, metadata: &[u8]) -> Vec<Packet> {
+    handle_client_packets(
+        state,
+        vec![Packet::ClientSetStreamMetadata {
+            stream_id: STREAM_ID,
+            metadata: Bytes::copy_from_slice(metadata),
+        }],
+    )
+    .unwrap()
+}
+
+#[test]
+fn metadata_can_be_set_read_and_overwritten() {
+    let mut state = ServerState::new();
+    state.create_new_stream(STREAM_ID).unwrap();
+    assert!(get_metadata(&mut state, STREAM_ID).is_empty());
+
+    set_metadata(&mut state, b"lobby:osu!");
+    assert_eq!(get_metadata(&mut state, STREAM_ID), b"lobby:osu!");
+
+    set_metadata(&mut state, b"owner:1000");
+    assert_eq!(get_metadata(&mut state, STREAM_ID), b"owner:1000");
+}
+
+#[test]
+fn metadata_survives_fetches() {
+    let mut state = ServerState::new();
+    state.create_new_stream(STREAM_ID).unwrap();
+    set_metadata(&mut state, b"label");
+    state.enqueue_single(STREAM_ID, b"data").unwrap();
+
+    state.fetch_stream_contents(STREAM_ID).unwrap();
+
+    assert_eq!(get_metadata(&mut state, STREAM_ID), b"label");
+}
+
+#[test]
+fn oversized_metadata_is_rejected() {
+    let mut state = ServerState::new();
+    state.create_new_stream(STREAM_ID).unwrap();
+    set_metadata(&mut state, b"kept");
+
+    assert!(set_metadata(&mut state, &vec![0; MAX_STREAM_METADATA_SIZE]).is_empty());
+    assert!(matches!(
+        set_metadata(&mut state, &vec![0; MAX_STREAM_METADATA_SIZE + 1]).as_slice(),
+        [Packet::ServerError {
+            code: ERROR_PAYLOAD_TOO_LARGE,
+            ..
+        }]
+    ));
+    assert_eq!(
+        get_metadata(&mut state, STREAM_ID),
+        vec![0; MAX_STREAM_METADATA_SIZE]
+    );
+}
+
+#[test]
+fn missing_stream_has_empty_metadata() {
+    let mut state = ServerState::new();
+
+    set_metadata(&mut state, b"ignored");
+
+    assert!(get_metadata(&mut state, STREAM_ID).is_empty());
+}
@@ -0,0 +1,91 @@
+mod common;
+
+use common::{TestClient, free_tcp_port, leak_settings, new_state};
+use fast_stream_db::metrics::{METRICS, run_metrics_server};
+use fast_stream_db::settings::Settings;
+use fast_stream_db::state::ServerState;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{Duration, sleep};
+
+async fn http_get(port: u16, path: &str) -> String {
+    for _ in 0..100 {
+        if let Ok(mut stream) = TcpStream::connect(("127.0.0.1", port)).await {
+            let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            return response;
+        }
+        sleep(Duration::from_millis(10)).await;
+    }
+    panic!("Metrics server never started listening on port {}", port);
+}
+
+#[test]
+fn stream_gauges_come_from_the_state() {
+    let mut state = ServerState::new();
+    state.create_new_stream(1).unwrap();
+    state.create_new_stream(2).unwrap();
+    state.enqueue_single(1, b"hello").unwrap();
+
+    let output = METRICS.render(&state);
+
+    assert!(output.contains("\nfsdb_streams 2\n"));
+    assert!(output.contains("\nfsdb_buffered_bytes 5\n"));
+}
+
+#[test]
+fn prune_cycles_are_exported() {
+    METRICS.record_prune(3);
+
+    let output = METRICS.render(&ServerState::new());
+
+    assert!(output.contains("\nfsdb_last_pruned_streams 3\n"));
+    assert!(output.contains("\nfsdb_pruned_streams_total 3\n"));
+}
+
+#[tokio::test]
+async fn endpoint_serves_connection_and_packet_counts() {
+    let state = new_state();
+    let port = free_tcp_port();
+    let settings = leak_settings(Settings {
+        metrics_port: port,
+        ..Settings::default()
+    });
+    tokio::spawn(run_metrics_server(
+        settings,
+        state.clone(),
+        std::future::pending(),
+    ));
+
+    let mut client = TestClient::connect(state);
+    client.create_stream(1).await;
+    client.sync().await;
+
+    let response = http_get(port, "/metrics").await;
+
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("\nfsdb_streams 1\n"));
+    assert!(response.contains("fsdb_packets_processed_total{packet_id=\"1\"}"));
+    assert!(METRICS.active_connections() >= 1);
+    assert!(METRICS.packets_processed(1) >= 1);
+}
+
+#[tokio::test]
+async fn unknown_paths_are_not_found() {
+    let port = free_tcp_port();
+    let settings = leak_settings(Settings {
+        metrics_port: port,
+        ..Settings::default()
+    });
+    tokio::spawn(run_metrics_server(
+        settings,
+        new_state(),
+        std::future::pending(),
+    ));
+
+    let response = http_get(port, "/").await;
+
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+}
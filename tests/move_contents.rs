@@ -0,0 +1,142 @@
+mod common;
+
+use common::{TestClient, new_state};
+use fast_stream_db::serialisation::{Bytes, ERROR_UNKNOWN_STREAM, Packet};
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::settings::OverflowPolicy;
+use fast_stream_db::state::ServerState;
+
+#[test]
+fn contents_are_appended_to_the_destination() {
+    let mut state = ServerState::new();
+    state.create_new_stream(1).unwrap();
+    state.enqueue_single(1, b" session").unwrap();
+    state.create_new_stream(2).unwrap();
+    state.enqueue_single(2, b"durable").unwrap();
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![Packet::ClientMoveStreamContents {
+            source_id: 1,
+            dest_id: 2,
+        }],
+    )
+    .unwrap();
+
+    assert!(responses.is_empty());
+    assert!(state.get_stream(1).unwrap().buffer.is_empty());
+    assert_eq!(state.get_stream(1).unwrap().base_offset, 8);
+    assert_eq!(state.get_stream(2).unwrap().buffer, &b"durable session"[..]);
+    assert_eq!(state.total_bytes(), 15);
+}
+
+#[test]
+fn missing_streams_are_reported_without_changing_anything() {
+    let mut state = ServerState::new();
+    state.create_new_stream(1).unwrap();
+    state.enqueue_single(1, b"data").unwrap();
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![
+            Packet::ClientMoveStreamContents {
+                source_id: 1,
+                dest_id: 2,
+            },
+            Packet::ClientMoveStreamContents {
+                source_id: 3,
+                dest_id: 1,
+            },
+        ],
+    )
+    .unwrap();
+
+    assert_eq!(
+        responses,
+        vec![
+            Packet::ServerError {
+                code: ERROR_UNKNOWN_STREAM,
+                message: Bytes::from_static(b"Stream 2 doesn't exist"),
+            },
+            Packet::ServerError {
+                code: ERROR_UNKNOWN_STREAM,
+                message: Bytes::from_static(b"Stream 3 doesn't exist"),
+            },
+        ]
+    );
+    assert_eq!(state.get_stream(1).unwrap().buffer, &b"data"[..]);
+}
+
+#[test]
+fn full_destination_rejects_the_move() {
+    let mut state = ServerState::new();
+    state.set_stream_limit(8, OverflowPolicy::Reject);
+    state.create_new_stream(1).unwrap();
+    state.enqueue_single(1, b"12345").unwrap();
+    state.create_new_stream(2).unwrap();
+    state.enqueue_single(2, b"6789").unwrap();
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![Packet::ClientMoveStreamContents {
+            source_id: 1,
+            dest_id: 2,
+        }],
+    )
+    .unwrap();
+
+    assert_eq!(
+        responses,
+        vec![Packet::ServerEnqueueRejected { stream_id: 2 }]
+    );
+    assert_eq!(state.get_stream(1).unwrap().buffer, &b"12345"[..]);
+    assert_eq!(state.get_stream(2).unwrap().buffer, &b"6789"[..]);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_moves_never_lose_or_duplicate_bytes() {
+    const MOVES: usize = 200;
+    let state = new_state();
+    {
+        let mut state = state.lock().await;
+        state.create_new_stream(1).unwrap();
+        state.enqueue_single(1, &vec![0xAB; 1000]).unwrap();
+        state.create_new_stream(2).unwrap();
+    }
+
+    let movers = [(1, 2), (2, 1)]
+        .into_iter()
+        .map(|(source_id, dest_id)| {
+            let mut client = TestClient::connect(state.clone());
+            tokio::spawn(async move {
+                for _ in 0..MOVES {
+                    client
+                        .send(&[Packet::ClientMoveStreamContents { source_id, dest_id }])
+                        .await;
+                    client.sync().await;
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut observer = TestClient::connect(state.clone());
+    while !movers.iter().all(|mover| mover.is_finished()) {
+        observer
+            .send(&[
+                Packet::ClientStreamLength { stream_id: 1 },
+                Packet::ClientStreamLength { stream_id: 2 },
+            ])
+            .await;
+        let mut total = 0;
+        for _ in 0..2 {
+            match observer.recv().await {
+                Packet::ServerStreamLength { length, .. } => total += length,
+                _ => panic!("Expected a stream length"),
+            }
+        }
+        assert_eq!(total, 1000);
+    }
+    for mover in movers {
+        mover.await.unwrap();
+    }
+}
@@ -0,0 +1,128 @@
+use fast_stream_db::serialisation::{Bytes, Packet};
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::snapshot::{decode_snapshot, encode_snapshot};
+use fast_stream_db::state::{FIRST_NAMED_STREAM_ID, ServerState};
+
+fn name(name: &str) -> Bytes {
+    Bytes::copy_from_slice(name.as_bytes())
+}
+
+fn lookup(state: &mut ServerState, stream_name: &str) -> Packet {
+    let mut responses = handle_client_packets(
+        state,
+        vec![Packet::ClientLookupStreamName {
+            name: name(stream_name),
+        }],
+    )
+    .unwrap();
+
+    assert_eq!(responses.len(), 1, "Unexpected responses: {:?}", responses);
+    responses.remove(0)
+}
+
+#[test]
+fn named_streams_are_addressed_by_their_id() {
+    let mut state = ServerState::new();
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![Packet::ClientCreateNewStreamNamed {
+            name: name("user:42:messages"),
+        }],
+    )
+    .unwrap();
+    let [Packet::ServerNamedStream { stream_id, exists }] = responses[..] else {
+        panic!("Expected a named stream, got {:?}", responses);
+    };
+    assert!(exists);
+    assert_eq!(stream_id, FIRST_NAMED_STREAM_ID);
+    assert_eq!(
+        lookup(&mut state, "user:42:messages"),
+        Packet::ServerNamedStream {
+            stream_id,
+            exists: true,
+        }
+    );
+
+    state.enqueue_single(stream_id, b"hello").unwrap();
+    assert_eq!(
+        state.fetch_stream_contents(stream_id).unwrap(),
+        &b"hello"[..]
+    );
+}
+
+#[test]
+fn recreating_a_name_keeps_the_existing_stream() {
+    let mut state = ServerState::new();
+    let stream_id = state.create_named_stream(&name("queue")).unwrap();
+    state.enqueue_single(stream_id, b"kept").unwrap();
+
+    assert_eq!(
+        state.create_named_stream(&name("queue")).unwrap(),
+        stream_id
+    );
+    assert_eq!(state.get_stream(stream_id).unwrap().buffer, &b"kept"[..]);
+    assert_ne!(
+        state.create_named_stream(&name("other")).unwrap(),
+        stream_id
+    );
+}
+
+#[test]
+fn ids_in_use_are_skipped() {
+    let mut state = ServerState::new();
+    state.create_new_stream(FIRST_NAMED_STREAM_ID).unwrap();
+    state.create_new_stream(FIRST_NAMED_STREAM_ID + 1).unwrap();
+
+    assert_eq!(
+        state.create_named_stream(&name("queue")).unwrap(),
+        FIRST_NAMED_STREAM_ID + 2
+    );
+}
+
+#[test]
+fn names_are_forgotten_with_their_stream() {
+    let mut state = ServerState::new();
+    let stream_id = state.create_named_stream(&name("deleted")).unwrap();
+
+    state.delete_stream(stream_id).unwrap();
+
+    assert_eq!(
+        lookup(&mut state, "deleted"),
+        Packet::ServerNamedStream {
+            stream_id: 0,
+            exists: false,
+        }
+    );
+}
+
+#[test]
+fn recreating_a_named_stream_keeps_its_name() {
+    let mut state = ServerState::new();
+    let stream_id = state.create_named_stream(&name("queue")).unwrap();
+
+    state.create_new_stream(stream_id).unwrap();
+
+    assert_eq!(
+        lookup(&mut state, "queue"),
+        Packet::ServerNamedStream {
+            stream_id,
+            exists: true,
+        }
+    );
+}
+
+#[test]
+fn names_survive_a_snapshot() {
+    let mut state = ServerState::new();
+    let stream_id = state.create_named_stream(&name("queue")).unwrap();
+
+    let mut restored = ServerState::new();
+    decode_snapshot(&encode_snapshot(&state), &mut restored).unwrap();
+
+    assert_eq!(restored.named_stream_id(&name("queue")), Some(stream_id));
+    assert_eq!(
+        restored.create_named_stream(&name("queue")).unwrap(),
+        stream_id
+    );
+}
@@ -0,0 +1,59 @@
+use fast_stream_db::serialisation::Packet;
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::state::ServerState;
+
+// Broadcasts visit streams in hash map order, which is deliberately left
+// unspecified. What must hold regardless is that every stream sees the
+// appends in the order they were sent.
+#[test]
+fn per_stream_append_order_is_preserved_by_broadcasts() {
+    let mut state = ServerState::new();
+    let stream_ids = (0..64).collect::<Vec<u32>>();
+    for stream_id in &stream_ids {
+        state.create_new_stream(*stream_id).unwrap();
+    }
+
+    let mut packets = Vec::new();
+    for round in 0..16u8 {
+        packets.push(Packet::ClientEnqueueAll {
+            enqueue_data: vec![round, 0].into(),
+        });
+        packets.push(Packet::ClientEnqueueSingle {
+            stream_id: u32::from(round),
+            enqueue_data: vec![round, 1].into(),
+        });
+        packets.push(Packet::ClientEnqueueAllExcept {
+            enqueue_data: vec![round, 2].into(),
+            filter_stream_ids: vec![u32::from(round) + 1],
+        });
+        packets.push(Packet::ClientEnqueueMultiple {
+            enqueue_data: vec![round, 3].into(),
+            filter_stream_ids: vec![u32::from(round) + 2, u32::from(round) + 3],
+        });
+    }
+    handle_client_packets(&mut state, packets).unwrap();
+
+    for stream_id in stream_ids {
+        let mut expected = Vec::new();
+        for round in 0..16u8 {
+            let round_id = u32::from(round);
+            expected.extend_from_slice(&[round, 0]);
+            if stream_id == round_id {
+                expected.extend_from_slice(&[round, 1]);
+            }
+            if stream_id != round_id + 1 {
+                expected.extend_from_slice(&[round, 2]);
+            }
+            if stream_id == round_id + 2 || stream_id == round_id + 3 {
+                expected.extend_from_slice(&[round, 3]);
+            }
+        }
+
+        assert_eq!(
+            state.fetch_stream_contents(stream_id).unwrap(),
+            expected,
+            "Stream {} saw appends out of order",
+            stream_id
+        );
+    }
+}
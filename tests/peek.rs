@@ -0,0 +1,63 @@
+use fast_stream_db::serialisation::Packet;
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::state::ServerState;
+
+const STREAM_ID: u32 = 1;
+
+fn peek(state: &mut ServerState, stream_id: u32, max_bytes: u32) -> Vec<u8> {
+    let responses = handle_client_packets(
+        state,
+        vec![Packet::ClientPeekStreamContents {
+            stream_id,
+            max_bytes,
+        }],
+    )
+    .unwrap();
+
+    match responses.as_slice() {
+        [Packet::ServerStreamContents { buffer_data }] => buffer_data.to_vec(),
+        _ => panic!("Unexpected responses: {:?}", responses),
+    }
+}
+
+fn state_with_contents(contents: &[u8]) -> ServerState {
+    let mut state = ServerState::new();
+    state.create_new_stream(STREAM_ID).unwrap();
+    state.enqueue_single(STREAM_ID, contents).unwrap();
+    state
+}
+
+#[test]
+fn peek_returns_leading_bytes_without_consuming() {
+    let mut state = state_with_contents(b"0123456789");
+
+    assert_eq!(peek(&mut state, STREAM_ID, 4), b"0123");
+    assert_eq!(peek(&mut state, STREAM_ID, 4), b"0123");
+
+    assert_eq!(
+        state.fetch_stream_contents(STREAM_ID).unwrap(),
+        &b"0123456789"[..]
+    );
+}
+
+#[test]
+fn peek_larger_than_buffer_returns_everything() {
+    let mut state = state_with_contents(b"abc");
+
+    assert_eq!(peek(&mut state, STREAM_ID, 3), b"abc");
+    assert_eq!(peek(&mut state, STREAM_ID, u32::MAX), b"abc");
+}
+
+#[test]
+fn zero_byte_peek_is_empty() {
+    let mut state = state_with_contents(b"abc");
+
+    assert!(peek(&mut state, STREAM_ID, 0).is_empty());
+}
+
+#[test]
+fn peek_of_missing_stream_is_empty() {
+    let mut state = ServerState::new();
+
+    assert!(peek(&mut state, STREAM_ID, 10).is_empty());
+}
@@ -0,0 +1,43 @@
+mod common;
+
+use common::{TestClient, new_state};
+use fast_stream_db::serialisation::{Packet, serialise_packets};
+
+#[tokio::test]
+async fn pipelined_pings_echo_their_nonces_in_order() {
+    let mut client = TestClient::connect(new_state());
+
+    client
+        .send(&[
+            Packet::ClientPing { nonce: Some(1) },
+            Packet::ClientPing { nonce: None },
+            Packet::ClientPing {
+                nonce: Some(u64::MAX),
+            },
+        ])
+        .await;
+
+    assert_eq!(client.recv().await, Packet::ServerPong { nonce: Some(1) });
+    assert_eq!(client.recv().await, Packet::ServerPong { nonce: None });
+    assert_eq!(
+        client.recv().await,
+        Packet::ServerPong {
+            nonce: Some(u64::MAX)
+        }
+    );
+}
+
+#[test]
+fn pings_without_a_nonce_keep_an_empty_payload() {
+    // Length prefix and packet ID only, as before nonces were added.
+    assert_eq!(
+        serialise_packets(&[Packet::ClientPing { nonce: None }]).unwrap(),
+        [4, 0, 0, 0, 0, 0, 0, 0]
+    );
+    assert_eq!(
+        serialise_packets(&[Packet::ServerPong { nonce: Some(7) }])
+            .unwrap()
+            .len(),
+        16
+    );
+}
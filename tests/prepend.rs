@@ -0,0 +1,90 @@
+mod common;
+
+use common::enqueue;
+use fast_stream_db::serialisation::{Bytes, Packet};
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::settings::OverflowPolicy;
+use fast_stream_db::state::ServerState;
+
+fn prepend(stream_id: u32, data: &[u8]) -> Packet {
+    Packet::ClientPrependSingle {
+        stream_id,
+        enqueue_data: Bytes::copy_from_slice(data),
+    }
+}
+
+#[test]
+fn prepended_data_is_drained_first() {
+    let mut state = ServerState::new();
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![
+            Packet::ClientCreateNewStream { stream_id: 1 },
+            enqueue(1, b"second"),
+            enqueue(1, b"third"),
+            prepend(1, b"first"),
+            Packet::ClientRequestStreamContents { stream_id: 1 },
+        ],
+    )
+    .unwrap();
+
+    assert_eq!(
+        responses,
+        vec![
+            Packet::ServerStreamCreated {
+                stream_id: 1,
+                created: true,
+            },
+            Packet::ServerStreamContents {
+                buffer_data: Bytes::from_static(b"firstsecondthird")
+            }
+        ]
+    );
+    assert_eq!(state.total_bytes(), 0);
+}
+
+#[test]
+fn prepend_to_missing_stream_is_ignored() {
+    let mut state = ServerState::new();
+
+    let responses = handle_client_packets(&mut state, vec![prepend(1, b"data")]).unwrap();
+
+    assert!(responses.is_empty());
+    assert_eq!(state.total_bytes(), 0);
+}
+
+#[test]
+fn prepend_past_the_limit_is_rejected_whatever_the_policy() {
+    for overflow_policy in [OverflowPolicy::Reject, OverflowPolicy::DropOldest] {
+        let mut state = ServerState::new();
+        state.set_stream_limit(8, overflow_policy);
+        state.create_new_stream(1).unwrap();
+
+        let responses =
+            handle_client_packets(&mut state, vec![enqueue(1, b"abcde"), prepend(1, b"wxyz")])
+                .unwrap();
+
+        assert_eq!(
+            responses,
+            vec![Packet::ServerEnqueueRejected { stream_id: 1 }]
+        );
+        assert_eq!(state.get_stream(1).unwrap().buffer, &b"abcde"[..]);
+        assert_eq!(state.total_bytes(), 5);
+    }
+}
+
+#[test]
+fn consumers_keep_their_place_in_existing_data() {
+    let mut state = ServerState::new();
+    state.create_new_stream(1).unwrap();
+    state.register_consumer(1, 1);
+    state.register_consumer(1, 2);
+    state.enqueue_single(1, b"abcdef").unwrap();
+    state.advance_cursor(1, 2, 3);
+
+    state.prepend_single(1, b"xy").unwrap();
+
+    assert_eq!(state.fetch_from_cursor(1, 1, 64).unwrap(), &b"xyabcdef"[..]);
+    assert_eq!(state.fetch_from_cursor(1, 2, 64).unwrap(), &b"def"[..]);
+}
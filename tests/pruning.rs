@@ -0,0 +1,29 @@
+use fast_stream_db::state::ServerState;
+use fast_stream_db::utils;
+use std::time::Duration;
+
+#[test]
+fn future_activity_survives_prune() {
+    let mut state = ServerState::with_key_expiry(Duration::from_secs(60));
+    state.create_new_stream(1).unwrap();
+    state.get_stream_mut(1).unwrap().last_activity = utils::get_current_timestamp() + 3600;
+
+    state.prune_expired_streams().unwrap();
+
+    assert!(state.stream_exists(1));
+}
+
+#[test]
+fn stale_streams_are_pruned_alongside_future_ones() {
+    let mut state = ServerState::with_key_expiry(Duration::from_secs(60));
+    state.create_new_stream(1).unwrap();
+    state.create_new_stream(2).unwrap();
+    let current_timestamp = utils::get_current_timestamp();
+    state.get_stream_mut(1).unwrap().last_activity = current_timestamp + 3600;
+    state.get_stream_mut(2).unwrap().last_activity = current_timestamp - 120;
+
+    state.prune_expired_streams().unwrap();
+
+    assert!(state.stream_exists(1));
+    assert!(!state.stream_exists(2));
+}
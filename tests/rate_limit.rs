@@ -0,0 +1,49 @@
+mod common;
+
+use common::{TestClient, leak_settings, new_state};
+use fast_stream_db::rate_limit::RateLimiter;
+use fast_stream_db::serialisation::Packet;
+use fast_stream_db::settings::Settings;
+use std::time::Instant;
+
+#[test]
+fn bucket_allows_a_burst_of_one_seconds_worth() {
+    let mut limiter = RateLimiter::new(10);
+
+    for _ in 0..10 {
+        assert!(limiter.try_acquire());
+    }
+    assert!(!limiter.try_acquire());
+    assert!(!limiter.time_until_available().is_zero());
+}
+
+#[test]
+fn zero_rate_is_unlimited() {
+    let mut limiter = RateLimiter::new(0);
+
+    for _ in 0..10_000 {
+        assert!(limiter.try_acquire());
+    }
+}
+
+#[tokio::test]
+async fn packets_beyond_the_limit_are_delayed() {
+    const RATE: u32 = 200;
+    let settings = leak_settings(Settings {
+        rate_limit: RATE,
+        ..Settings::default()
+    });
+    let mut client = TestClient::connect_with_settings(new_state(), settings);
+
+    // A burst of RATE is allowed straight away, and the rest trickle in at RATE per second.
+    let start = Instant::now();
+    client
+        .send(&vec![Packet::ClientPing { nonce: None }; 2 * RATE as usize])
+        .await;
+    for _ in 0..2 * RATE {
+        assert_eq!(client.recv().await, Packet::ServerPong { nonce: None });
+    }
+    let elapsed = start.elapsed();
+
+    assert!(elapsed.as_millis() >= 900, "Finished in {:?}", elapsed);
+}
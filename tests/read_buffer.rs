@@ -0,0 +1,128 @@
+mod common;
+
+use common::{MockStream, default_settings, leak_settings, new_state};
+use fast_stream_db::serialisation::{Bytes, Packet, serialise_packets};
+use fast_stream_db::server::{ConnectionInfo, handle_connection};
+use fast_stream_db::settings::Settings;
+use std::time::Instant;
+
+#[tokio::test]
+async fn packets_straddling_reads_are_reassembled() {
+    // Enqueues of varying sizes, so packets keep landing across read boundaries.
+    let mut packets = vec![Packet::ClientCreateNewStream { stream_id: 1 }];
+    let mut expected = Vec::new();
+    for size in 0..400 {
+        let enqueue_data = vec![(size % 251) as u8; size];
+        expected.extend_from_slice(&enqueue_data);
+        packets.push(Packet::ClientEnqueueSingle {
+            stream_id: 1,
+            enqueue_data: enqueue_data.into(),
+        });
+    }
+    let state = new_state();
+    let mut stream = MockStream::new(serialise_packets(&packets).unwrap());
+
+    handle_connection(
+        &mut stream,
+        state.clone(),
+        default_settings(),
+        ConnectionInfo::new("test"),
+    )
+    .await
+    .unwrap();
+
+    let state = state.lock().await;
+    assert_eq!(state.get_stream(1).unwrap().buffer, expected);
+}
+
+#[tokio::test]
+async fn read_chunks_smaller_than_a_packet_are_reassembled() {
+    let settings = leak_settings(Settings {
+        read_chunk_size: 3,
+        ..Settings::default()
+    });
+    let packets = [
+        Packet::ClientCreateNewStream { stream_id: 1 },
+        Packet::ClientEnqueueSingle {
+            stream_id: 1,
+            enqueue_data: Bytes::from_static(b"split across many reads"),
+        },
+    ];
+    let state = new_state();
+    let mut stream = MockStream::new(serialise_packets(&packets).unwrap());
+
+    handle_connection(
+        &mut stream,
+        state.clone(),
+        settings,
+        ConnectionInfo::new("test"),
+    )
+    .await
+    .unwrap();
+
+    let state = state.lock().await;
+    assert_eq!(
+        state.get_stream(1).unwrap().buffer,
+        &b"split across many reads"[..]
+    );
+}
+
+#[tokio::test]
+#[ignore = "benchmark, run with --release -- --ignored --nocapture"]
+async fn bench_many_tiny_packets() {
+    const PING_COUNT: usize = 1_000_000;
+    let input = serialise_packets(&vec![Packet::ClientPing { nonce: None }; PING_COUNT]).unwrap();
+
+    let mut stream = MockStream::new(input);
+    let start = Instant::now();
+    handle_connection(
+        &mut stream,
+        new_state(),
+        default_settings(),
+        ConnectionInfo::new("test"),
+    )
+    .await
+    .unwrap();
+    let elapsed = start.elapsed();
+
+    let pong_size = serialise_packets(&[Packet::ServerPong { nonce: None }])
+        .unwrap()
+        .len();
+    let pong_count = stream.flushes.iter().map(Vec::len).sum::<usize>() / pong_size;
+    assert_eq!(pong_count, PING_COUNT);
+    println!(
+        "{} pings in {:?} ({:?} per ping)",
+        PING_COUNT,
+        elapsed,
+        elapsed / PING_COUNT as u32
+    );
+}
+
+#[tokio::test]
+#[ignore = "benchmark, run with --release -- --ignored --nocapture"]
+async fn bench_small_reads() {
+    const PING_COUNT: usize = 1_000_000;
+    // Two pings per read, as a client sending many small requests would arrive.
+    const READ_SIZE: usize = 16;
+    let input = serialise_packets(&vec![Packet::ClientPing { nonce: None }; PING_COUNT]).unwrap();
+    let read_count = input.len() / READ_SIZE;
+
+    let mut stream = MockStream::with_max_read_size(input, READ_SIZE);
+    let start = Instant::now();
+    handle_connection(
+        &mut stream,
+        new_state(),
+        default_settings(),
+        ConnectionInfo::new("test"),
+    )
+    .await
+    .unwrap();
+    let elapsed = start.elapsed();
+
+    println!(
+        "{} reads in {:?} ({:?} per read)",
+        read_count,
+        elapsed,
+        elapsed / read_count as u32
+    );
+}
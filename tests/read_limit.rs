@@ -0,0 +1,100 @@
+mod common;
+
+use common::{TestClient, leak_settings, new_state};
+use fast_stream_db::serialisation::{ERROR_PAYLOAD_TOO_LARGE, Packet};
+use fast_stream_db::settings::Settings;
+
+fn limited_settings(max_read_buffer: usize) -> &'static Settings {
+    leak_settings(Settings {
+        max_read_buffer,
+        ..Settings::default()
+    })
+}
+
+#[tokio::test]
+async fn oversized_packet_is_rejected_with_an_error() {
+    let mut client = TestClient::connect_with_settings(new_state(), limited_settings(1024));
+
+    // Only the length prefix is sent, which is enough for the server to refuse the packet.
+    client.send_raw(&100_000u32.to_le_bytes()).await;
+
+    match client.recv().await {
+        Packet::ServerError { code, message } => {
+            assert_eq!(code, ERROR_PAYLOAD_TOO_LARGE);
+            assert!(
+                String::from_utf8(message.to_vec())
+                    .unwrap()
+                    .contains("100004")
+            );
+        }
+        packet => panic!("Expected an error, got {:?}", packet),
+    }
+    assert!(client.is_closed().await);
+}
+
+#[tokio::test]
+async fn large_packets_within_the_limit_are_accepted() {
+    let state = new_state();
+    let mut client = TestClient::connect_with_settings(state.clone(), limited_settings(256 * 1024));
+
+    client.create_stream(1).await;
+    client
+        .send(&[Packet::ClientEnqueueSingle {
+            stream_id: 1,
+            enqueue_data: vec![7; 200 * 1024].into(),
+        }])
+        .await;
+    client.sync().await;
+
+    assert_eq!(state.lock().await.total_bytes(), 200 * 1024);
+}
+
+#[tokio::test]
+async fn oversized_payload_is_rejected_before_it_arrives() {
+    let settings = leak_settings(Settings {
+        max_payload_size: 16,
+        ..Settings::default()
+    });
+    let mut client = TestClient::connect_with_settings(new_state(), settings);
+
+    // Declares a payload far over the limit, but sends only a few bytes of it.
+    let mut body = Vec::new();
+    body.extend_from_slice(&3u32.to_le_bytes()); // CLIENT_ENQUEUE_SINGLE.
+    body.extend_from_slice(&1u32.to_le_bytes()); // Stream ID.
+    body.extend_from_slice(&u32::MAX.to_le_bytes()); // Data size.
+    body.extend_from_slice(b"short");
+    let mut frame = (body.len() as u32).to_le_bytes().to_vec();
+    frame.extend_from_slice(&body);
+    client.send_raw(&frame).await;
+
+    match client.recv().await {
+        Packet::ServerError { message, .. } => assert!(
+            String::from_utf8(message.to_vec())
+                .unwrap()
+                .contains("16 byte limit")
+        ),
+        packet => panic!("Expected an error, got {:?}", packet),
+    }
+    assert!(client.is_closed().await);
+}
+
+#[tokio::test]
+async fn payloads_up_to_the_limit_are_accepted() {
+    let state = new_state();
+    let settings = leak_settings(Settings {
+        max_payload_size: 16,
+        ..Settings::default()
+    });
+    let mut client = TestClient::connect_with_settings(state.clone(), settings);
+
+    client.create_stream(1).await;
+    client
+        .send(&[Packet::ClientEnqueueSingle {
+            stream_id: 1,
+            enqueue_data: vec![7; 16].into(),
+        }])
+        .await;
+    client.sync().await;
+
+    assert_eq!(state.lock().await.total_bytes(), 16);
+}
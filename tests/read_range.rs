@@ -0,0 +1,120 @@
+use fast_stream_db::serialisation::Packet;
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::state::ServerState;
+
+const STREAM_ID: u32 = 1;
+
+fn read_range(state: &mut ServerState, start: u32, end: u32) -> Vec<u8> {
+    let responses = handle_client_packets(
+        state,
+        vec![Packet::ClientReadRange {
+            stream_id: STREAM_ID,
+            start,
+            end,
+        }],
+    )
+    .unwrap();
+
+    match responses.as_slice() {
+        [Packet::ServerStreamContents { buffer_data }] => buffer_data.to_vec(),
+        _ => panic!("Unexpected responses: {:?}", responses),
+    }
+}
+
+fn request_range(state: &mut ServerState, start: u32, length: u32) -> Vec<u8> {
+    let responses = handle_client_packets(
+        state,
+        vec![Packet::ClientRequestStreamContentsRange {
+            stream_id: STREAM_ID,
+            start,
+            length,
+        }],
+    )
+    .unwrap();
+
+    match responses.as_slice() {
+        [Packet::ServerStreamContents { buffer_data }] => buffer_data.to_vec(),
+        _ => panic!("Unexpected responses: {:?}", responses),
+    }
+}
+
+fn state_with_contents(contents: &[u8]) -> ServerState {
+    let mut state = ServerState::new();
+    state.create_new_stream(STREAM_ID).unwrap();
+    state.enqueue_single(STREAM_ID, contents).unwrap();
+    state
+}
+
+#[test]
+fn normal_range_is_returned_without_clearing() {
+    let mut state = state_with_contents(b"0123456789");
+
+    assert_eq!(read_range(&mut state, 2, 5), b"234");
+    assert_eq!(read_range(&mut state, 0, 10), b"0123456789");
+    assert_eq!(read_range(&mut state, 4, 4), b"");
+
+    assert_eq!(
+        state.fetch_stream_contents(STREAM_ID).unwrap(),
+        &b"0123456789"[..]
+    );
+}
+
+#[test]
+fn inverted_range_is_empty() {
+    let mut state = state_with_contents(b"0123456789");
+
+    assert_eq!(read_range(&mut state, 5, 2), b"");
+}
+
+#[test]
+fn range_past_the_end_is_clamped() {
+    let mut state = state_with_contents(b"0123456789");
+
+    assert_eq!(read_range(&mut state, 7, 100), b"789");
+    assert_eq!(read_range(&mut state, 10, 20), b"");
+    assert_eq!(read_range(&mut state, 50, 60), b"");
+    assert_eq!(read_range(&mut state, 0, u32::MAX), b"0123456789");
+}
+
+#[test]
+fn missing_stream_is_empty() {
+    let mut state = ServerState::new();
+
+    assert_eq!(read_range(&mut state, 0, 10), b"");
+}
+
+#[test]
+fn windows_page_through_the_stream_without_clearing() {
+    let mut state = state_with_contents(b"0123456789");
+
+    assert_eq!(request_range(&mut state, 0, 4), b"0123");
+    assert_eq!(request_range(&mut state, 4, 4), b"4567");
+    assert_eq!(request_range(&mut state, 8, 4), b"89");
+    assert_eq!(request_range(&mut state, 3, 0), b"");
+
+    assert_eq!(state.get_stream(STREAM_ID).unwrap().buffer.len(), 10);
+}
+
+#[test]
+fn window_starting_past_the_end_is_empty() {
+    let mut state = state_with_contents(b"0123456789");
+
+    assert_eq!(request_range(&mut state, 10, 5), b"");
+    assert_eq!(request_range(&mut state, 50, 5), b"");
+    assert_eq!(request_range(&mut state, u32::MAX, u32::MAX), b"");
+}
+
+#[test]
+fn window_longer_than_the_remaining_bytes_is_clamped() {
+    let mut state = state_with_contents(b"0123456789");
+
+    assert_eq!(request_range(&mut state, 6, 100), b"6789");
+    assert_eq!(request_range(&mut state, 1, u32::MAX), b"123456789");
+}
+
+#[test]
+fn window_of_a_missing_stream_is_empty() {
+    let mut state = ServerState::new();
+
+    assert_eq!(request_range(&mut state, 0, 10), b"");
+}
@@ -0,0 +1,74 @@
+use fast_stream_db::serialisation::{ERROR_STREAM_EXISTS, ERROR_UNKNOWN_STREAM, Packet};
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::state::ServerState;
+
+fn rename(state: &mut ServerState, old_id: u32, new_id: u32) -> Vec<Packet> {
+    handle_client_packets(state, vec![Packet::ClientRenameStream { old_id, new_id }]).unwrap()
+}
+
+fn error_code(responses: &[Packet]) -> u32 {
+    match responses {
+        [Packet::ServerError { code, .. }] => *code,
+        _ => panic!("Unexpected responses: {:?}", responses),
+    }
+}
+
+#[test]
+fn renamed_stream_keeps_its_contents() {
+    let mut state = ServerState::new();
+    state.create_new_stream(1).unwrap();
+    state.enqueue_single(1, b"pending").unwrap();
+    state.get_stream_mut(1).unwrap().last_activity = 1234;
+
+    assert!(rename(&mut state, 1, 2).is_empty());
+
+    assert!(!state.stream_exists(1));
+    let stream = state.get_stream(2).unwrap();
+    assert_eq!(stream.buffer, &b"pending"[..]);
+    assert_eq!(stream.last_activity, 1234);
+    assert_eq!(state.total_bytes(), 7);
+}
+
+#[test]
+fn renaming_onto_an_existing_stream_is_refused() {
+    let mut state = ServerState::new();
+    state.create_new_stream(1).unwrap();
+    state.enqueue_single(1, b"one").unwrap();
+    state.create_new_stream(2).unwrap();
+    state.enqueue_single(2, b"two").unwrap();
+
+    assert_eq!(error_code(&rename(&mut state, 1, 2)), ERROR_STREAM_EXISTS);
+
+    assert_eq!(state.get_stream(1).unwrap().buffer, &b"one"[..]);
+    assert_eq!(state.get_stream(2).unwrap().buffer, &b"two"[..]);
+}
+
+#[test]
+fn renaming_a_missing_stream_is_refused() {
+    let mut state = ServerState::new();
+
+    assert_eq!(error_code(&rename(&mut state, 1, 2)), ERROR_UNKNOWN_STREAM);
+    assert_eq!(state.stream_count(), 0);
+}
+
+#[test]
+fn renaming_a_stream_to_itself_does_nothing() {
+    let mut state = ServerState::new();
+    state.create_new_stream(1).unwrap();
+    state.enqueue_single(1, b"data").unwrap();
+
+    assert!(rename(&mut state, 1, 1).is_empty());
+
+    assert_eq!(state.get_stream(1).unwrap().buffer, &b"data"[..]);
+}
+
+#[test]
+fn named_stream_lookup_follows_the_rename() {
+    let mut state = ServerState::new();
+    let name = b"session".to_vec();
+    let stream_id = state.create_named_stream(&name).unwrap();
+
+    assert!(rename(&mut state, stream_id, 5).is_empty());
+
+    assert_eq!(state.named_stream_id(&name), Some(5));
+}
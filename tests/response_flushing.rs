@@ -0,0 +1,63 @@
+mod common;
+
+use common::{MockStream, leak_settings, new_state};
+use fast_stream_db::serialisation::{Packet, serialise_packets};
+use fast_stream_db::server::{ConnectionInfo, handle_connection};
+use fast_stream_db::settings::Settings;
+
+// Length prefix and packet ID.
+const PONG_SIZE: usize = 8;
+
+#[tokio::test]
+async fn responses_are_flushed_at_the_threshold() {
+    let settings = leak_settings(Settings {
+        max_buffered_responses: 4,
+        ..Settings::default()
+    });
+    let mut stream =
+        MockStream::new(serialise_packets(&vec![Packet::ClientPing { nonce: None }; 10]).unwrap());
+
+    handle_connection(
+        &mut stream,
+        new_state(),
+        settings,
+        ConnectionInfo::new("test"),
+    )
+    .await
+    .unwrap();
+
+    let flush_sizes = stream.flushes.iter().map(Vec::len).collect::<Vec<_>>();
+    assert_eq!(
+        flush_sizes,
+        vec![4 * PONG_SIZE, 4 * PONG_SIZE, 2 * PONG_SIZE]
+    );
+}
+
+#[tokio::test]
+async fn packets_without_responses_do_not_count_towards_the_threshold() {
+    let settings = leak_settings(Settings {
+        max_buffered_responses: 2,
+        ..Settings::default()
+    });
+    let state = new_state();
+    state.lock().await.create_new_stream(1).unwrap();
+    let mut packets = Vec::new();
+    for _ in 0..3 {
+        packets.push(Packet::ClientPing { nonce: None });
+        packets.extend(std::iter::repeat_n(
+            Packet::ClientEnqueueSingle {
+                stream_id: 1,
+                enqueue_data: vec![1, 2, 3].into(),
+            },
+            5,
+        ));
+    }
+    let mut stream = MockStream::new(serialise_packets(&packets).unwrap());
+
+    handle_connection(&mut stream, state, settings, ConnectionInfo::new("test"))
+        .await
+        .unwrap();
+
+    let flush_sizes = stream.flushes.iter().map(Vec::len).collect::<Vec<_>>();
+    assert_eq!(flush_sizes, vec![2 * PONG_SIZE, PONG_SIZE]);
+}
@@ -0,0 +1,109 @@
+#![cfg(feature = "admin")]
+
+use fast_stream_db::serialisation::{Bytes, ERROR_NOT_AUTHENTICATED, Packet};
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::state::ServerState;
+
+fn self_check(state: &mut ServerState) -> Vec<String> {
+    let responses = handle_client_packets(state, vec![Packet::ClientSelfCheck]).unwrap();
+
+    match responses.as_slice() {
+        [Packet::ServerSelfCheckReport { issues }] => issues.clone(),
+        _ => panic!("Unexpected responses: {:?}", responses),
+    }
+}
+
+fn populated_state() -> ServerState {
+    let mut state = ServerState::new();
+    for stream_id in 1..=3 {
+        state.create_new_stream(stream_id).unwrap();
+    }
+    handle_client_packets(
+        &mut state,
+        vec![
+            Packet::ClientEnqueueAll {
+                enqueue_data: vec![0; 16].into(),
+            },
+            Packet::ClientRegisterConsumer {
+                stream_id: 1,
+                consumer_id: 1,
+            },
+            Packet::ClientAdvanceCursor {
+                stream_id: 1,
+                consumer_id: 1,
+                byte_count: 4,
+            },
+        ],
+    )
+    .unwrap();
+    state.fetch_stream_contents(2).unwrap();
+    state
+}
+
+#[test]
+fn consistent_state_reports_no_issues() {
+    let mut state = populated_state();
+
+    assert_eq!(self_check(&mut state), Vec::<String>::new());
+}
+
+#[test]
+fn detects_corrupted_byte_counter() {
+    let mut state = populated_state();
+    let total_bytes = state.total_bytes();
+    state.set_total_bytes_unchecked(total_bytes + 7);
+
+    let issues = self_check(&mut state);
+
+    assert_eq!(issues.len(), 1, "Unexpected issues: {:?}", issues);
+    assert!(
+        issues[0].contains("total_bytes"),
+        "Unexpected issue: {}",
+        issues[0]
+    );
+}
+
+#[test]
+fn detects_cursor_past_the_end() {
+    let mut state = populated_state();
+    let stream = state.get_stream_mut(3).unwrap();
+    stream.consumer_cursors.insert(9, stream.base_offset + 100);
+
+    let issues = self_check(&mut state);
+
+    assert!(
+        issues.iter().any(|issue| issue.contains("consumer 9")),
+        "Unexpected issues: {:?}",
+        issues
+    );
+}
+
+#[test]
+fn self_check_needs_the_admin_token() {
+    let mut state = populated_state();
+    state.set_admin_token(Some(Bytes::from_static(b"secret")));
+
+    let responses = handle_client_packets(&mut state, vec![Packet::ClientSelfCheck]).unwrap();
+    match responses.as_slice() {
+        [Packet::ServerError { code, .. }] => assert_eq!(*code, ERROR_NOT_AUTHENTICATED),
+        _ => panic!("Unexpected responses: {:?}", responses),
+    }
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![
+            Packet::ClientAuthenticate {
+                token: Bytes::from_static(b"secret"),
+            },
+            Packet::ClientSelfCheck,
+        ],
+    )
+    .unwrap();
+    assert_eq!(
+        responses,
+        vec![
+            Packet::ServerAuthResult { success: true },
+            Packet::ServerSelfCheckReport { issues: Vec::new() },
+        ]
+    );
+}
@@ -0,0 +1,73 @@
+use fast_stream_db::serialisation::{Bytes, Packet};
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::state::ServerState;
+
+const STREAM_ID: u32 = 7;
+
+fn enqueue_seq(state: &mut ServerState, seq: u64, data: &[u8]) -> bool {
+    let responses = handle_client_packets(
+        state,
+        vec![Packet::ClientEnqueueSeq {
+            stream_id: STREAM_ID,
+            seq,
+            enqueue_data: Bytes::copy_from_slice(data),
+        }],
+    )
+    .unwrap();
+
+    match responses.as_slice() {
+        [
+            Packet::ServerEnqueueSeqResult {
+                stream_id: STREAM_ID,
+                seq: reply_seq,
+                applied,
+            },
+        ] if *reply_seq == seq => *applied,
+        _ => panic!("Unexpected responses: {:?}", responses),
+    }
+}
+
+fn state_with_stream() -> ServerState {
+    let mut state = ServerState::new();
+    state.create_new_stream(STREAM_ID).unwrap();
+    state
+}
+
+#[test]
+fn in_order_sequences_are_applied() {
+    let mut state = state_with_stream();
+
+    assert!(enqueue_seq(&mut state, 0, b"a"));
+    assert!(enqueue_seq(&mut state, 1, b"b"));
+    assert!(enqueue_seq(&mut state, 5, b"c"));
+
+    assert_eq!(state.fetch_stream_contents(STREAM_ID).unwrap(), &b"abc"[..]);
+}
+
+#[test]
+fn duplicate_sequence_is_skipped() {
+    let mut state = state_with_stream();
+
+    assert!(enqueue_seq(&mut state, 1, b"a"));
+    assert!(!enqueue_seq(&mut state, 1, b"a"));
+
+    assert_eq!(state.fetch_stream_contents(STREAM_ID).unwrap(), &b"a"[..]);
+}
+
+#[test]
+fn out_of_order_sequence_is_skipped() {
+    let mut state = state_with_stream();
+
+    assert!(enqueue_seq(&mut state, 5, b"a"));
+    assert!(!enqueue_seq(&mut state, 4, b"b"));
+    assert!(enqueue_seq(&mut state, 6, b"c"));
+
+    assert_eq!(state.fetch_stream_contents(STREAM_ID).unwrap(), &b"ac"[..]);
+}
+
+#[test]
+fn missing_stream_is_not_applied() {
+    let mut state = ServerState::new();
+
+    assert!(!enqueue_seq(&mut state, 1, b"a"));
+}
@@ -0,0 +1,540 @@
+use fast_stream_db::serialisation::{
+    Bytes, Packet, PacketReadError, Predicate, deserialise_packets,
+    deserialise_packets_with_offset, read_packet_from_buffer, serialise_packets,
+    write_packet_into_buffer,
+};
+
+fn all_packets() -> Vec<Packet> {
+    vec![
+        Packet::ClientPing { nonce: None },
+        Packet::ClientCreateNewStream { stream_id: 1 },
+        Packet::ClientDeleteStream { stream_id: 2 },
+        Packet::ClientEnqueueSingle {
+            stream_id: 3,
+            enqueue_data: Bytes::from_static(b"single"),
+        },
+        Packet::ClientEnqueueMultiple {
+            enqueue_data: Bytes::from_static(b"multiple"),
+            filter_stream_ids: vec![1, 2, 3],
+        },
+        Packet::ClientEnqueueAll {
+            enqueue_data: Bytes::from_static(b"all"),
+        },
+        Packet::ClientEnqueueAllExcept {
+            enqueue_data: Bytes::from_static(b"all except"),
+            filter_stream_ids: vec![4],
+        },
+        Packet::ClientRequestStreamContents { stream_id: 5 },
+        Packet::ClientRequestStreamContentsNoClear { stream_id: 6 },
+        Packet::ClientCheckStreamState { stream_id: 7 },
+        Packet::ServerPong { nonce: None },
+        Packet::ServerStreamContents {
+            buffer_data: Bytes::from_static(b"contents"),
+        },
+        Packet::ServerStreamState {
+            stream_id: 8,
+            is_valid: true,
+        },
+        Packet::ServerBusy {
+            retry_after_ms: 1000,
+        },
+        Packet::ClientEnqueueSeq {
+            stream_id: 9,
+            seq: u64::MAX,
+            enqueue_data: Bytes::from_static(b"seq"),
+        },
+        Packet::ServerEnqueueSeqResult {
+            stream_id: 9,
+            seq: 1 << 40,
+            applied: false,
+        },
+        Packet::ClientListStreamsByActivity {
+            limit: 10,
+            descending: true,
+        },
+        Packet::ServerStreamActivityList {
+            entries: vec![(1, 30), (2, 0)],
+        },
+        Packet::ClientSetStreamMetadata {
+            stream_id: 10,
+            metadata: Bytes::from_static(b"metadata"),
+        },
+        Packet::ClientGetStreamMetadata { stream_id: 10 },
+        Packet::ServerStreamMetadata {
+            stream_id: 10,
+            metadata: Bytes::new(),
+        },
+        Packet::ClientEnqueueAllAck {
+            enqueue_data: Bytes::from_static(b"ack"),
+        },
+        Packet::ClientEnqueueAllExceptAck {
+            enqueue_data: Bytes::from_static(b"ack except"),
+            filter_stream_ids: Vec::new(),
+        },
+        Packet::ServerEnqueueAllAck { stream_count: 12 },
+        Packet::ClientReadRange {
+            stream_id: 11,
+            start: 2,
+            end: 8,
+        },
+        Packet::ClientGetTotalBytes,
+        Packet::ServerTotalBytes {
+            total_bytes: 1 << 33,
+            stream_count: 4,
+        },
+        Packet::ClientGoodbye,
+        Packet::ServerGoodbye,
+        Packet::ClientRegisterConsumer {
+            stream_id: 12,
+            consumer_id: 1,
+        },
+        Packet::ClientFetchFromCursor {
+            stream_id: 12,
+            consumer_id: 1,
+            max_bytes: 64,
+        },
+        Packet::ClientAdvanceCursor {
+            stream_id: 12,
+            consumer_id: 1,
+            byte_count: 16,
+        },
+        Packet::ClientUnregisterConsumer {
+            stream_id: 12,
+            consumer_id: 1,
+        },
+        Packet::ClientSetGlobalExpiry { seconds: 45 },
+        Packet::ClientSelfCheck,
+        Packet::ServerSelfCheckReport {
+            issues: vec!["first issue".to_string(), String::new()],
+        },
+        Packet::ServerSelfCheckReport { issues: Vec::new() },
+        Packet::ClientPeekStreamContents {
+            stream_id: 13,
+            max_bytes: 4,
+        },
+        Packet::ClientRequestStreamContentsWithMeta { stream_id: 14 },
+        Packet::ServerStreamContentsMeta {
+            stream_id: 14,
+            byte_count: 9,
+            existed: true,
+        },
+        Packet::ServerStreamContentsMeta {
+            stream_id: 15,
+            byte_count: 0,
+            existed: false,
+        },
+        Packet::ClientStreamLength { stream_id: 16 },
+        Packet::ServerStreamLength {
+            stream_id: 16,
+            length: 1024,
+            exists: true,
+        },
+        Packet::ClientHello {
+            protocol_version: 1,
+        },
+        Packet::ServerHello {
+            protocol_version: 1,
+            accepted: false,
+        },
+        Packet::ServerEnqueueRejected { stream_id: 17 },
+        Packet::ClientListStreams {
+            offset: 2,
+            limit: 100,
+        },
+        Packet::ServerStreamList {
+            total_count: 3,
+            stream_ids: vec![18, 19, 20],
+        },
+        Packet::ClientAwaitStreamContents {
+            stream_id: 21,
+            timeout_ms: 500,
+        },
+        Packet::ClientPrependSingle {
+            stream_id: 22,
+            enqueue_data: Bytes::from_static(b"prepend"),
+        },
+        Packet::ServerEnqueueError { stream_id: 23 },
+        Packet::ClientCreateMultipleStreams {
+            stream_ids: vec![24, 25],
+        },
+        Packet::ServerStreamsCreated {
+            existing_stream_ids: vec![26],
+        },
+        Packet::ClientCreateNewStreamWithTtl {
+            stream_id: 27,
+            ttl_secs: 28,
+        },
+        Packet::ClientClearStream { stream_id: 29 },
+        Packet::ClientCreateNewStreamNamed {
+            name: Bytes::from_static(b"user:42:messages"),
+        },
+        Packet::ClientLookupStreamName {
+            name: Bytes::from_static(b"user:42:messages"),
+        },
+        Packet::ServerNamedStream {
+            stream_id: 30,
+            exists: true,
+        },
+        Packet::ClientEnqueueBatch {
+            entries: vec![(31, Bytes::from_static(b"batch")), (32, Bytes::new())],
+        },
+        Packet::ServerFeatures { features: 34 },
+        Packet::ClientRequestStats,
+        Packet::ServerStats {
+            stream_count: 35,
+            total_buffered_bytes: 36,
+            uptime_secs: 37,
+        },
+        Packet::ClientMoveStreamContents {
+            source_id: 38,
+            dest_id: 39,
+        },
+        Packet::ServerError {
+            code: 40,
+            message: Bytes::from_static(b"error"),
+        },
+        Packet::ClientRenameStream {
+            old_id: 41,
+            new_id: 42,
+        },
+        Packet::ClientDrainPrefix {
+            stream_id: 43,
+            byte_count: 44,
+        },
+        Packet::ClientAuthenticate {
+            token: Bytes::from_static(b"token"),
+        },
+        Packet::ServerAuthResult { success: true },
+        Packet::ClientFlushAll,
+        Packet::ClientDeleteAll,
+        Packet::ServerFlushResult {
+            streams_affected: 45,
+        },
+        Packet::ClientSubscribe { stream_id: 46 },
+        Packet::ServerStreamPush {
+            stream_id: 47,
+            buffer_data: Bytes::from_static(b"pushed"),
+        },
+        Packet::ClientUnsubscribe { stream_id: 48 },
+        Packet::ClientWhoAmI,
+        Packet::ServerConnectionInfo {
+            connection_id: 49,
+            peer: Bytes::from_static(b"127.0.0.1:5000"),
+        },
+        Packet::ClientStreamInfo { stream_id: 50 },
+        Packet::ServerStreamInfo {
+            stream_id: 51,
+            exists: true,
+            length: 52,
+            idle_secs: 53,
+        },
+        Packet::ClientTouchStream { stream_id: 54 },
+        Packet::ClientEnqueueMultipleExcept {
+            enqueue_data: Bytes::from_static(b"group"),
+            include_ids: vec![55, 56, 57],
+            exclude_ids: vec![56],
+        },
+        Packet::ClientRequestStreamContentsRange {
+            stream_id: 58,
+            start: 100,
+            length: 50,
+        },
+        Packet::ClientHealthCheck,
+        Packet::ServerHealth { status: 2 },
+        Packet::ServerStreamCreated {
+            stream_id: 59,
+            created: true,
+        },
+        Packet::ClientEnqueueSingleAck {
+            stream_id: 60,
+            enqueue_data: Bytes::from_static(b"acked"),
+        },
+        Packet::ServerEnqueueAck {
+            stream_id: 60,
+            accepted: false,
+            new_length: 4096,
+        },
+        Packet::ClientEnqueueWhere {
+            enqueue_data: Bytes::from_static(b"where"),
+            predicate: Predicate::Range { min: 61, max: 62 },
+        },
+        Packet::ClientEnqueueWhere {
+            enqueue_data: Bytes::new(),
+            predicate: Predicate::Modulo {
+                divisor: 63,
+                remainder: 1,
+            },
+        },
+        Packet::ClientDeleteMultipleStreams {
+            stream_ids: vec![64, 65],
+        },
+        Packet::ServerStreamsDeleted { count: 66 },
+        Packet::ClientCreateNewStreamSized {
+            stream_id: 67,
+            initial_capacity: 68,
+        },
+        Packet::ClientPing { nonce: Some(69) },
+        Packet::ServerPong {
+            nonce: Some(u64::MAX),
+        },
+        Packet::ClientCheckStreamStateMultiple {
+            stream_ids: vec![70, 71],
+        },
+        Packet::ServerStreamStateMultiple {
+            results: vec![(72, true), (73, false)],
+        },
+        Packet::ClientFlushStream { stream_id: 74 },
+        Packet::ClientOpenSession,
+        Packet::ServerSessionOpened {
+            token: Bytes::from_static(b"session"),
+        },
+        Packet::ClientResumeSession {
+            token: Bytes::from_static(b"session"),
+        },
+        Packet::ServerSessionResumed {
+            resumed: true,
+            stream_ids: vec![75, 76],
+        },
+        Packet::ClientCreateGroup { group_id: 77 },
+        Packet::ServerGroupCreated {
+            group_id: 78,
+            created: true,
+        },
+        Packet::ClientAddToGroup {
+            group_id: 79,
+            stream_id: 80,
+        },
+        Packet::ClientRemoveFromGroup {
+            group_id: 81,
+            stream_id: 82,
+        },
+        Packet::ClientDeleteGroup { group_id: 83 },
+        Packet::ClientEnqueueAllExceptGroup {
+            group_id: 84,
+            enqueue_data: Bytes::from_static(b"not spectators"),
+        },
+        // Parsing stops after a negotiation, so it has to come last.
+        Packet::ClientNegotiateFeatures { features: 33 },
+    ]
+}
+
+#[test]
+fn every_packet_type_round_trips() {
+    let packets = all_packets();
+    let buffer = serialise_packets(&packets).unwrap();
+
+    let (deserialised, consumed_bytes) = deserialise_packets_with_offset(&buffer).unwrap();
+
+    assert_eq!(deserialised, packets);
+    assert_eq!(consumed_bytes, buffer.len());
+}
+
+#[test]
+fn partial_trailing_packet_is_left_unparsed() {
+    let packets = all_packets();
+    let mut buffer = serialise_packets(&packets).unwrap();
+    let complete_length = buffer.len();
+    buffer
+        .extend_from_slice(&serialise_packets(&[Packet::ClientPing { nonce: None }]).unwrap()[..5]);
+
+    let (deserialised, consumed_bytes) = deserialise_packets_with_offset(&buffer).unwrap();
+    assert_eq!(deserialised, packets);
+    assert_eq!(consumed_bytes, complete_length);
+
+    assert_eq!(deserialise_packets(&buffer).unwrap(), packets);
+}
+
+fn read_error(buffer: &[u8]) -> PacketReadError {
+    let error = read_packet_from_buffer(buffer, 0)
+        .err()
+        .expect("Expected the read to fail");
+    error
+        .downcast_ref::<PacketReadError>()
+        .cloned()
+        .unwrap_or_else(|| panic!("Unexpected error: {}", error))
+}
+
+#[test]
+fn every_truncated_packet_is_incomplete() {
+    for packet in all_packets() {
+        let buffer = serialise_packets(std::slice::from_ref(&packet)).unwrap();
+
+        for length in 0..buffer.len() {
+            assert_eq!(
+                read_error(&buffer[..length]),
+                PacketReadError::Incomplete,
+                "{:?} truncated to {} bytes",
+                packet,
+                length
+            );
+        }
+    }
+}
+
+#[test]
+fn byte_at_a_time_prefixes_yield_only_complete_packets() {
+    let packets = all_packets();
+    let buffer = serialise_packets(&packets).unwrap();
+
+    let mut packet_ends = Vec::new();
+    for packet in &packets {
+        let previous_end = packet_ends.last().copied().unwrap_or(0);
+        packet_ends.push(
+            previous_end
+                + serialise_packets(std::slice::from_ref(packet))
+                    .unwrap()
+                    .len(),
+        );
+    }
+
+    for length in 0..=buffer.len() {
+        let (deserialised, consumed_bytes) =
+            deserialise_packets_with_offset(&buffer[..length]).unwrap();
+
+        let complete_count = packet_ends.iter().filter(|end| **end <= length).count();
+        assert_eq!(deserialised, packets[..complete_count]);
+        assert_eq!(
+            consumed_bytes,
+            packet_ends[..complete_count].last().copied().unwrap_or(0)
+        );
+    }
+}
+
+#[test]
+fn booleans_round_trip_in_exactly_four_bytes() {
+    for is_valid in [true, false] {
+        let packet = Packet::ServerStreamState {
+            stream_id: 1,
+            is_valid,
+        };
+        let buffer = serialise_packets(std::slice::from_ref(&packet)).unwrap();
+
+        // Length prefix, packet ID, stream ID and the boolean.
+        assert_eq!(buffer.len(), 16);
+        assert_eq!(buffer[12..], [is_valid as u8, 0, 0, 0]);
+
+        let result = read_packet_from_buffer(&buffer, 0).unwrap();
+        assert_eq!(result.value, packet);
+        assert_eq!(result.new_offset, buffer.len());
+    }
+}
+
+/// Prefixes a hand-built packet ID and payload with its length.
+fn frame(body: &[u8]) -> Vec<u8> {
+    let mut buffer = (body.len() as u32).to_le_bytes().to_vec();
+    buffer.extend_from_slice(body);
+    buffer
+}
+
+#[test]
+fn oversized_packet_length_is_incomplete() {
+    let mut buffer = 1000u32.to_le_bytes().to_vec(); // Packet length.
+    buffer.extend_from_slice(&0u32.to_le_bytes()); // CLIENT_PING.
+
+    assert_eq!(read_error(&buffer), PacketReadError::Incomplete);
+}
+
+#[test]
+fn oversized_field_length_is_invalid() {
+    let mut body = Vec::new();
+    body.extend_from_slice(&3u32.to_le_bytes()); // CLIENT_ENQUEUE_SINGLE.
+    body.extend_from_slice(&1u32.to_le_bytes()); // Stream ID.
+    body.extend_from_slice(&u32::MAX.to_le_bytes()); // Data size.
+    body.extend_from_slice(b"short");
+
+    assert!(matches!(
+        read_error(&frame(&body)),
+        PacketReadError::Invalid(_)
+    ));
+}
+
+#[test]
+fn oversized_batch_entry_length_is_invalid() {
+    let mut body = Vec::new();
+    body.extend_from_slice(&56u32.to_le_bytes()); // CLIENT_ENQUEUE_BATCH.
+    body.extend_from_slice(&1u32.to_le_bytes()); // Entry count.
+    body.extend_from_slice(&1u32.to_le_bytes()); // Stream ID.
+    body.extend_from_slice(&u32::MAX.to_le_bytes()); // Data size.
+    body.extend_from_slice(b"short");
+
+    assert!(matches!(
+        read_error(&frame(&body)),
+        PacketReadError::Invalid(_)
+    ));
+}
+
+#[test]
+fn oversized_field_count_is_invalid() {
+    let mut body = Vec::new();
+    body.extend_from_slice(&4u32.to_le_bytes()); // CLIENT_ENQUEUE_MULTIPLE.
+    body.extend_from_slice(&0u32.to_le_bytes()); // Data size.
+    body.extend_from_slice(&u32::MAX.to_le_bytes()); // Filter list size.
+
+    assert!(matches!(
+        read_error(&frame(&body)),
+        PacketReadError::Invalid(_)
+    ));
+}
+
+#[test]
+fn trailing_bytes_within_a_packet_are_invalid() {
+    let mut body = Vec::new();
+    body.extend_from_slice(&0u32.to_le_bytes()); // CLIENT_PING.
+    body.extend_from_slice(b"extra");
+
+    assert!(matches!(
+        read_error(&frame(&body)),
+        PacketReadError::Invalid(_)
+    ));
+}
+
+#[test]
+fn unknown_packet_id_is_invalid() {
+    let buffer = frame(&u32::MAX.to_le_bytes());
+
+    assert!(matches!(read_error(&buffer), PacketReadError::Invalid(_)));
+    assert!(deserialise_packets_with_offset(&buffer).is_err());
+}
+
+#[test]
+fn invalid_packet_after_valid_ones_is_an_error() {
+    let mut buffer = serialise_packets(&[Packet::ClientPing { nonce: None }]).unwrap();
+    buffer.extend_from_slice(&frame(&u32::MAX.to_le_bytes()));
+
+    assert!(deserialise_packets_with_offset(&buffer).is_err());
+}
+
+#[test]
+#[cfg(target_pointer_width = "64")]
+fn lengths_too_large_for_a_u32_are_an_error() {
+    // Zeroed allocations are only backed by memory once touched, and the length is rejected
+    // before any of the data is copied.
+    let enqueue_data = vec![0u8; u32::MAX as usize + 1];
+    let packet = Packet::ClientEnqueueSingle {
+        stream_id: 1,
+        enqueue_data: enqueue_data.into(),
+    };
+    let mut buffer = serialise_packets(&[Packet::ClientPing { nonce: None }]).unwrap();
+    let ping_length = buffer.len();
+
+    let error = write_packet_into_buffer(&mut buffer, &packet).unwrap_err();
+
+    assert!(error.to_string().contains("too large"), "{}", error);
+    assert_eq!(buffer.len(), ping_length);
+    assert!(serialise_packets(&[packet]).is_err());
+}
+
+#[test]
+fn unknown_predicate_kind_is_invalid() {
+    let mut body = Vec::new();
+    body.extend_from_slice(&85u32.to_le_bytes()); // CLIENT_ENQUEUE_WHERE.
+    body.extend_from_slice(&0u32.to_le_bytes()); // Data size.
+    body.extend_from_slice(&2u32.to_le_bytes()); // Predicate kind.
+    body.extend_from_slice(&0u32.to_le_bytes());
+    body.extend_from_slice(&0u32.to_le_bytes());
+
+    assert!(matches!(
+        read_error(&frame(&body)),
+        PacketReadError::Invalid(_)
+    ));
+}
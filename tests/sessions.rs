@@ -0,0 +1,181 @@
+mod common;
+
+use common::TestClient;
+use fast_stream_db::serialisation::{Bytes, ERROR_INVALID_PACKET, Packet};
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::state::ServerState;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::sleep;
+
+fn state_with_sessions(session_ttl: Duration) -> ServerState {
+    let mut state = ServerState::new();
+    state.set_admin_token(Some(Bytes::from_static(b"secret")));
+    state.set_session_ttl(session_ttl);
+    state
+}
+
+fn open_session(state: &mut ServerState) -> Bytes {
+    let responses = handle_client_packets(state, vec![Packet::ClientOpenSession]).unwrap();
+    match responses.as_slice() {
+        [Packet::ServerSessionOpened { token }] => token.clone(),
+        _ => panic!("Unexpected responses: {:?}", responses),
+    }
+}
+
+/// Resumes the session, retrying while the connection that held it is still closing.
+async fn resume_session(client: &mut TestClient, token: &Bytes) -> Vec<u32> {
+    for _ in 0..100 {
+        client
+            .send(&[Packet::ClientResumeSession {
+                token: token.clone(),
+            }])
+            .await;
+        match client.recv().await {
+            Packet::ServerSessionResumed {
+                resumed: true,
+                stream_ids,
+            } => return stream_ids,
+            Packet::ServerSessionResumed { resumed: false, .. } => {
+                sleep(Duration::from_millis(10)).await
+            }
+            response => panic!("Unexpected response: {:?}", response),
+        }
+    }
+    panic!("The session was never released");
+}
+
+#[tokio::test]
+async fn resuming_restores_authentication_and_subscriptions() {
+    let state = Arc::new(Mutex::new(state_with_sessions(Duration::from_secs(30))));
+    let mut client = TestClient::connect(state.clone());
+
+    client.create_stream(1).await;
+    client
+        .send(&[
+            Packet::ClientAuthenticate {
+                token: Bytes::from_static(b"secret"),
+            },
+            Packet::ClientSubscribe { stream_id: 1 },
+            Packet::ClientOpenSession,
+        ])
+        .await;
+    assert_eq!(
+        client.recv().await,
+        Packet::ServerAuthResult { success: true }
+    );
+    let token = match client.recv().await {
+        Packet::ServerSessionOpened { token } => token,
+        response => panic!("Unexpected response: {:?}", response),
+    };
+    drop(client);
+
+    let mut client = TestClient::connect(state.clone());
+    assert_eq!(resume_session(&mut client, &token).await, vec![1]);
+
+    let mut producer = TestClient::connect(state.clone());
+    producer
+        .send(&[Packet::ClientEnqueueSingle {
+            stream_id: 1,
+            enqueue_data: Bytes::from_static(b"event"),
+        }])
+        .await;
+    assert_eq!(
+        client.recv().await,
+        Packet::ServerStreamPush {
+            stream_id: 1,
+            buffer_data: Bytes::from_static(b"event"),
+        }
+    );
+
+    // Privileged, so only answered if the authentication came back too.
+    client
+        .send(&[Packet::ClientListStreams {
+            offset: 0,
+            limit: 10,
+        }])
+        .await;
+    assert_eq!(
+        client.recv().await,
+        Packet::ServerStreamList {
+            total_count: 1,
+            stream_ids: vec![1],
+        }
+    );
+}
+
+#[tokio::test]
+async fn held_sessions_cant_be_resumed() {
+    let state = Arc::new(Mutex::new(state_with_sessions(Duration::from_secs(30))));
+    let mut holder = TestClient::connect(state.clone());
+    let mut other = TestClient::connect(state);
+
+    holder.send(&[Packet::ClientOpenSession]).await;
+    let token = match holder.recv().await {
+        Packet::ServerSessionOpened { token } => token,
+        response => panic!("Unexpected response: {:?}", response),
+    };
+    other.send(&[Packet::ClientResumeSession { token }]).await;
+
+    assert_eq!(
+        other.recv().await,
+        Packet::ServerSessionResumed {
+            resumed: false,
+            stream_ids: Vec::new(),
+        }
+    );
+}
+
+#[test]
+fn expired_and_unknown_sessions_cant_be_resumed() {
+    let mut state = state_with_sessions(Duration::from_millis(10));
+    let token = open_session(&mut state);
+    std::thread::sleep(Duration::from_millis(20));
+
+    for token in [token, Bytes::from_static(b"unknown")] {
+        let responses =
+            handle_client_packets(&mut state, vec![Packet::ClientResumeSession { token }]).unwrap();
+        assert_eq!(
+            responses,
+            vec![Packet::ServerSessionResumed {
+                resumed: false,
+                stream_ids: Vec::new(),
+            }]
+        );
+    }
+}
+
+#[test]
+fn sessions_can_be_resumed_repeatedly() {
+    let mut state = state_with_sessions(Duration::from_secs(30));
+    let token = open_session(&mut state);
+
+    for _ in 0..2 {
+        let responses = handle_client_packets(
+            &mut state,
+            vec![Packet::ClientResumeSession {
+                token: token.clone(),
+            }],
+        )
+        .unwrap();
+        assert_eq!(
+            responses,
+            vec![Packet::ServerSessionResumed {
+                resumed: true,
+                stream_ids: Vec::new(),
+            }]
+        );
+    }
+}
+
+#[test]
+fn sessions_are_disabled_without_a_ttl() {
+    let mut state = state_with_sessions(Duration::ZERO);
+
+    let responses = handle_client_packets(&mut state, vec![Packet::ClientOpenSession]).unwrap();
+    match responses.as_slice() {
+        [Packet::ServerError { code, .. }] => assert_eq!(*code, ERROR_INVALID_PACKET),
+        _ => panic!("Unexpected responses: {:?}", responses),
+    }
+}
@@ -0,0 +1,169 @@
+use fast_stream_db::settings::{ConnectionMode, Settings};
+use std::collections::HashMap;
+use std::time::Duration;
+
+fn settings_from(vars: &[(&str, &str)]) -> anyhow::Result<Settings> {
+    let vars = vars
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect::<HashMap<String, String>>();
+    Settings::from_vars(|name| vars.get(name).cloned())
+}
+
+fn error_from(vars: &[(&str, &str)]) -> String {
+    match settings_from(vars) {
+        Ok(_) => panic!("Expected {:?} to be rejected", vars),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn unset_variables_use_the_defaults() {
+    let settings = settings_from(&[]).unwrap();
+    let defaults = Settings::default();
+
+    assert_eq!(settings.tcp_port, defaults.tcp_port);
+    assert_eq!(settings.tcp_host, defaults.tcp_host);
+    assert_eq!(settings.listeners, defaults.listeners);
+}
+
+#[test]
+fn malformed_values_name_the_variable_and_value() {
+    let error = error_from(&[("FSDB_TCP_PORT", "abc")]);
+    assert!(
+        error.starts_with("FSDB_TCP_PORT='abc' is not a valid u16"),
+        "{}",
+        error
+    );
+
+    let error = error_from(&[("FSDB_TCP_HOST", "")]);
+    assert!(
+        error.starts_with("FSDB_TCP_HOST='' is not a valid IpAddr"),
+        "{}",
+        error
+    );
+
+    let error = error_from(&[("FSDB_CONNECTION_MODE", "UDP")]);
+    assert!(error.starts_with("FSDB_CONNECTION_MODE='UDP'"), "{}", error);
+}
+
+#[test]
+fn port_zero_must_be_explicitly_allowed() {
+    let error = error_from(&[("FSDB_LISTENERS", "TCP"), ("FSDB_TCP_PORT", "0")]);
+    assert!(error.contains("FSDB_ALLOW_EPHEMERAL_PORT"), "{}", error);
+
+    let settings = settings_from(&[
+        ("FSDB_LISTENERS", "TCP"),
+        ("FSDB_TCP_PORT", "0"),
+        ("FSDB_ALLOW_EPHEMERAL_PORT", "true"),
+    ])
+    .unwrap();
+    assert_eq!(settings.tcp_port, 0);
+
+    // The TCP port doesn't matter when there is no TCP listener.
+    let settings =
+        settings_from(&[("FSDB_LISTENERS", "UNIX_SOCK"), ("FSDB_TCP_PORT", "0")]).unwrap();
+    assert_eq!(settings.listeners, vec![ConnectionMode::UnixSocket]);
+}
+
+#[test]
+fn empty_unix_socket_path_is_rejected_in_unix_mode() {
+    let error = error_from(&[("FSDB_UNIX_SOCK_PATH", "")]);
+    assert!(error.contains("FSDB_UNIX_SOCK_PATH"), "{}", error);
+
+    settings_from(&[("FSDB_LISTENERS", "TCP"), ("FSDB_UNIX_SOCK_PATH", "")]).unwrap();
+}
+
+#[test]
+fn cleanup_interval_must_be_positive() {
+    let settings = settings_from(&[("FSDB_CLEANUP_INTERVAL", "5")]).unwrap();
+    assert_eq!(settings.cleanup_interval, Duration::from_secs(5));
+
+    let error = error_from(&[("FSDB_CLEANUP_INTERVAL", "0")]);
+    assert!(error.contains("FSDB_CLEANUP_INTERVAL"), "{}", error);
+}
+
+#[test]
+fn unix_socket_mode_is_parsed_as_octal() {
+    let settings = settings_from(&[("FSDB_UNIX_SOCK_MODE", "660")]).unwrap();
+    assert_eq!(settings.unix_sock_mode, Some(0o660));
+
+    for mode in ["689", "1777", "rw-rw----"] {
+        let error = error_from(&[("FSDB_UNIX_SOCK_MODE", mode)]);
+        assert!(error.contains("FSDB_UNIX_SOCK_MODE"), "{}", error);
+    }
+}
+
+#[test]
+fn tls_certificate_and_key_must_be_set_together() {
+    let error = error_from(&[("FSDB_TLS_CERT", "cert.pem")]);
+    assert!(error.contains("FSDB_TLS_KEY"), "{}", error);
+
+    let error = error_from(&[("FSDB_TLS_KEY", "key.pem")]);
+    assert!(error.contains("FSDB_TLS_CERT"), "{}", error);
+}
+
+#[test]
+fn wal_requires_snapshots() {
+    let error = error_from(&[("FSDB_WAL_PATH", "/tmp/fsdb.wal")]);
+    assert!(error.contains("FSDB_SNAPSHOT_PATH"), "{}", error);
+
+    let settings = settings_from(&[
+        ("FSDB_WAL_PATH", "/tmp/fsdb.wal"),
+        ("FSDB_SNAPSHOT_PATH", "/tmp/fsdb.snapshot"),
+        ("FSDB_WAL_SYNC_INTERVAL_MS", "50"),
+    ])
+    .unwrap();
+    assert_eq!(settings.wal_sync_interval, Duration::from_millis(50));
+}
+
+#[test]
+fn tcp_binds_are_parsed_as_a_list() {
+    let settings = settings_from(&[("FSDB_TCP_BINDS", "0.0.0.0:1273, 127.0.0.1:2000,")]).unwrap();
+    assert_eq!(
+        settings.tcp_bind_addrs(),
+        vec![
+            "0.0.0.0:1273".parse().unwrap(),
+            "127.0.0.1:2000".parse().unwrap()
+        ]
+    );
+
+    // Without it, the single host and port are bound.
+    let settings = settings_from(&[("FSDB_TCP_PORT", "2001")]).unwrap();
+    assert_eq!(
+        settings.tcp_bind_addrs(),
+        vec!["127.0.0.1:2001".parse().unwrap()]
+    );
+
+    let error = error_from(&[("FSDB_TCP_BINDS", "127.0.0.1:2000,localhost:2001")]);
+    assert!(
+        error.starts_with("FSDB_TCP_BINDS entry 'localhost:2001' is not a valid address"),
+        "{}",
+        error
+    );
+
+    let error = error_from(&[
+        ("FSDB_LISTENERS", "TCP"),
+        ("FSDB_TCP_BINDS", "127.0.0.1:2000,127.0.0.1:0"),
+    ]);
+    assert!(error.contains("FSDB_ALLOW_EPHEMERAL_PORT"), "{}", error);
+}
+
+#[test]
+fn payload_limit_defaults_to_the_packet_limit() {
+    let settings = settings_from(&[("FSDB_MAX_READ_BUFFER", "4096")]).unwrap();
+    assert_eq!(settings.payload_limit(), 4096);
+
+    let settings = settings_from(&[
+        ("FSDB_MAX_READ_BUFFER", "4096"),
+        ("FSDB_MAX_PAYLOAD_SIZE", "1024"),
+    ])
+    .unwrap();
+    assert_eq!(settings.payload_limit(), 1024);
+
+    let error = error_from(&[
+        ("FSDB_MAX_READ_BUFFER", "4096"),
+        ("FSDB_MAX_PAYLOAD_SIZE", "4097"),
+    ]);
+    assert!(error.contains("FSDB_MAX_PAYLOAD_SIZE"), "{}", error);
+}
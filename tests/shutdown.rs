@@ -0,0 +1,49 @@
+mod common;
+
+use common::{connect_unix, leak_settings, new_state, temp_socket_path};
+use fast_stream_db::server::run_unix_server;
+use fast_stream_db::settings::{ConnectionMode, Settings};
+use std::path::Path;
+use tokio::net::UnixStream;
+use tokio::sync::oneshot;
+use tokio::time::{Duration, timeout};
+
+#[tokio::test]
+async fn shutdown_lets_open_connections_finish_and_removes_the_socket() {
+    let path = temp_socket_path("shutdown");
+    let settings = leak_settings(Settings {
+        listeners: vec![ConnectionMode::UnixSocket],
+        unix_sock_path: path.clone(),
+        ..Settings::default()
+    });
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let server = tokio::spawn(run_unix_server(settings, new_state(), async {
+        let _ = shutdown_rx.await;
+    }));
+
+    let mut client = connect_unix(&path).await;
+    client.sync().await;
+
+    shutdown_tx.send(()).unwrap();
+
+    // The socket goes away as soon as the server stops accepting.
+    timeout(Duration::from_secs(5), async {
+        while Path::new(&path).exists() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("Socket file was not removed");
+    assert!(UnixStream::connect(&path).await.is_err());
+
+    // The open connection is still served until the client leaves.
+    client.sync().await;
+    assert!(!server.is_finished());
+    drop(client);
+
+    timeout(Duration::from_secs(5), server)
+        .await
+        .expect("Server did not shut down")
+        .unwrap()
+        .unwrap();
+}
@@ -0,0 +1,124 @@
+use fast_stream_db::serialisation::Bytes;
+use fast_stream_db::snapshot::{
+    decode_snapshot, encode_snapshot, load_snapshot, shutdown_snapshot, write_snapshot,
+};
+use fast_stream_db::state::ServerState;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+fn populated_state() -> ServerState {
+    let mut state = ServerState::new();
+    state.create_new_stream(1).unwrap();
+    state.enqueue_seq(1, 7, b"sequenced");
+    state
+        .set_stream_metadata(1, Bytes::from_static(b"metadata"))
+        .unwrap();
+    state.register_consumer(1, 3);
+    state.advance_cursor(1, 3, 4);
+
+    state.create_new_stream_with_ttl(2, 30).unwrap();
+    state.create_new_stream(3).unwrap();
+    state.enqueue_single(3, &vec![0xAB; 5000]).unwrap();
+
+    state.create_group(10);
+    state.add_to_group(10, 1).unwrap();
+    state.add_to_group(10, 3).unwrap();
+    state.create_group(11);
+    state
+}
+
+fn temp_snapshot_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("fsdb-{}-{}.snapshot", name, std::process::id()))
+}
+
+#[test]
+fn snapshot_round_trips_every_stream() {
+    let original = populated_state();
+
+    let mut restored = ServerState::new();
+    decode_snapshot(&encode_snapshot(&original), &mut restored).unwrap();
+
+    assert_eq!(restored.stream_count(), 3);
+    assert_eq!(restored.total_bytes(), original.total_bytes());
+    for (stream_id, stream) in original.streams() {
+        let restored_stream = restored.get_stream(stream_id).unwrap();
+        assert_eq!(restored_stream.buffer, stream.buffer);
+        assert_eq!(restored_stream.last_activity, stream.last_activity);
+        assert_eq!(restored_stream.last_seq, stream.last_seq);
+        assert_eq!(restored_stream.metadata, stream.metadata);
+        assert_eq!(restored_stream.base_offset, stream.base_offset);
+        assert_eq!(restored_stream.consumer_cursors, stream.consumer_cursors);
+        assert_eq!(restored_stream.ttl, stream.ttl);
+        assert_eq!(restored_stream.name, stream.name);
+    }
+    assert_eq!(restored.groups(), original.groups());
+}
+
+#[test]
+fn snapshot_is_written_and_loaded_from_disk() {
+    let path = temp_snapshot_path("round-trip");
+    let original = populated_state();
+
+    write_snapshot(&path, &encode_snapshot(&original)).unwrap();
+    let mut restored = ServerState::new();
+    let loaded = load_snapshot(&path, &mut restored).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(loaded);
+    assert_eq!(restored.stream_count(), 3);
+    assert_eq!(restored.total_bytes(), original.total_bytes());
+}
+
+#[test]
+fn missing_snapshot_loads_nothing() {
+    let mut state = ServerState::new();
+
+    let loaded = load_snapshot(&temp_snapshot_path("missing"), &mut state).unwrap();
+
+    assert!(!loaded);
+    assert_eq!(state.stream_count(), 0);
+}
+
+#[test]
+fn corrupt_snapshot_is_rejected_without_loading_anything() {
+    let snapshot = encode_snapshot(&populated_state());
+
+    for length in 0..snapshot.len() {
+        let mut state = ServerState::new();
+        assert!(decode_snapshot(&snapshot[..length], &mut state).is_err());
+        assert_eq!(state.stream_count(), 0);
+    }
+
+    let mut trailing = snapshot.clone();
+    trailing.push(0);
+    assert!(decode_snapshot(&trailing, &mut ServerState::new()).is_err());
+}
+
+#[test]
+fn unknown_snapshot_version_is_rejected() {
+    let mut snapshot = encode_snapshot(&ServerState::new());
+    snapshot[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+
+    let error = decode_snapshot(&snapshot, &mut ServerState::new()).unwrap_err();
+
+    assert!(error.to_string().contains("version"));
+}
+
+#[tokio::test]
+async fn shutdown_snapshot_saves_every_stream() {
+    let path = temp_snapshot_path("shutdown");
+    let path: &'static str = path.to_str().unwrap().to_string().leak();
+    let state = Mutex::new(populated_state());
+
+    shutdown_snapshot(&state, path, None, Duration::from_secs(10))
+        .await
+        .unwrap();
+    let mut restored = ServerState::new();
+    let loaded = load_snapshot(std::path::Path::new(path), &mut restored).unwrap();
+    std::fs::remove_file(path).unwrap();
+
+    assert!(loaded);
+    assert_eq!(restored.stream_count(), 3);
+    assert_eq!(restored.total_bytes(), state.lock().await.total_bytes());
+}
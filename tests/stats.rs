@@ -0,0 +1,40 @@
+use fast_stream_db::serialisation::Packet;
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::state::ServerState;
+
+fn stats(state: &mut ServerState) -> Packet {
+    let mut responses = handle_client_packets(state, vec![Packet::ClientRequestStats]).unwrap();
+
+    assert_eq!(responses.len(), 1, "Unexpected responses: {:?}", responses);
+    responses.remove(0)
+}
+
+#[test]
+fn stats_track_streams_and_buffered_bytes() {
+    let mut state = ServerState::new();
+    assert_eq!(
+        stats(&mut state),
+        Packet::ServerStats {
+            stream_count: 0,
+            total_buffered_bytes: 0,
+            uptime_secs: 0,
+        }
+    );
+
+    state.create_new_stream(1).unwrap();
+    state.create_new_stream(2).unwrap();
+    state.enqueue_single(1, &[0; 100]).unwrap();
+    state.enqueue_single(2, &[0; 20]).unwrap();
+    state.fetch_stream_contents(2).unwrap();
+
+    let Packet::ServerStats {
+        stream_count,
+        total_buffered_bytes,
+        ..
+    } = stats(&mut state)
+    else {
+        panic!("Expected stats");
+    };
+    assert_eq!(stream_count, 2);
+    assert_eq!(total_buffered_bytes, 100);
+}
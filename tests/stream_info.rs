@@ -0,0 +1,66 @@
+use fast_stream_db::serialisation::Packet;
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::state::ServerState;
+use fast_stream_db::utils;
+
+fn stream_info(state: &mut ServerState, stream_id: u32) -> Packet {
+    let mut responses =
+        handle_client_packets(state, vec![Packet::ClientStreamInfo { stream_id }]).unwrap();
+    assert_eq!(responses.len(), 1);
+    responses.remove(0)
+}
+
+#[test]
+fn reports_length_and_idle_time_without_touching_the_stream() {
+    let mut state = ServerState::new();
+    state.create_new_stream(1).unwrap();
+    state.enqueue_single(1, b"stale").unwrap();
+    let last_activity = utils::get_current_timestamp() - 30;
+    state.get_stream_mut(1).unwrap().last_activity = last_activity;
+
+    match stream_info(&mut state, 1) {
+        Packet::ServerStreamInfo {
+            stream_id,
+            exists,
+            length,
+            idle_secs,
+        } => {
+            assert_eq!(stream_id, 1);
+            assert!(exists);
+            assert_eq!(length, 5);
+            assert!((30..=31).contains(&idle_secs), "{}", idle_secs);
+        }
+        packet => panic!("Expected stream info, got {:?}", packet),
+    }
+    assert_eq!(state.get_stream(1).unwrap().last_activity, last_activity);
+}
+
+#[test]
+fn activity_in_the_future_counts_as_no_idle_time() {
+    let mut state = ServerState::new();
+    state.create_new_stream(1).unwrap();
+    state.get_stream_mut(1).unwrap().last_activity = utils::get_current_timestamp() + 3600;
+
+    assert_eq!(
+        stream_info(&mut state, 1),
+        Packet::ServerStreamInfo {
+            stream_id: 1,
+            exists: true,
+            length: 0,
+            idle_secs: 0,
+        }
+    );
+}
+
+#[test]
+fn missing_streams_are_reported_as_not_existing() {
+    assert_eq!(
+        stream_info(&mut ServerState::new(), 1),
+        Packet::ServerStreamInfo {
+            stream_id: 1,
+            exists: false,
+            length: 0,
+            idle_secs: 0,
+        }
+    );
+}
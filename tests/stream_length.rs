@@ -0,0 +1,50 @@
+use fast_stream_db::serialisation::Packet;
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::state::ServerState;
+
+fn stream_length(state: &mut ServerState, stream_id: u32) -> Packet {
+    let mut responses =
+        handle_client_packets(state, vec![Packet::ClientStreamLength { stream_id }]).unwrap();
+
+    assert_eq!(responses.len(), 1, "Unexpected responses: {:?}", responses);
+    responses.remove(0)
+}
+
+#[test]
+fn length_tracks_buffer_without_consuming() {
+    let mut state = ServerState::new();
+    state.create_new_stream(1).unwrap();
+    state.enqueue_single(1, &[0; 12]).unwrap();
+
+    let expected = Packet::ServerStreamLength {
+        stream_id: 1,
+        length: 12,
+        exists: true,
+    };
+    assert_eq!(stream_length(&mut state, 1), expected);
+    assert_eq!(stream_length(&mut state, 1), expected);
+
+    state.fetch_stream_contents(1).unwrap();
+    assert_eq!(
+        stream_length(&mut state, 1),
+        Packet::ServerStreamLength {
+            stream_id: 1,
+            length: 0,
+            exists: true,
+        }
+    );
+}
+
+#[test]
+fn unknown_stream_reports_not_existing() {
+    let mut state = ServerState::new();
+
+    assert_eq!(
+        stream_length(&mut state, 7),
+        Packet::ServerStreamLength {
+            stream_id: 7,
+            length: 0,
+            exists: false,
+        }
+    );
+}
@@ -0,0 +1,154 @@
+use fast_stream_db::serialisation::{Bytes, Packet};
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::settings::OverflowPolicy;
+use fast_stream_db::state::ServerState;
+
+fn limited_state(max_stream_bytes: usize, overflow_policy: OverflowPolicy) -> ServerState {
+    let mut state = ServerState::new();
+    state.set_stream_limit(max_stream_bytes, overflow_policy);
+    for stream_id in 1..=2 {
+        state.create_new_stream(stream_id).unwrap();
+    }
+    state
+}
+
+fn enqueue_single(state: &mut ServerState, stream_id: u32, data: &[u8]) -> Vec<Packet> {
+    handle_client_packets(
+        state,
+        vec![Packet::ClientEnqueueSingle {
+            stream_id,
+            enqueue_data: Bytes::copy_from_slice(data),
+        }],
+    )
+    .unwrap()
+}
+
+fn contents(state: &ServerState, stream_id: u32) -> &[u8] {
+    &state.get_stream(stream_id).unwrap().buffer
+}
+
+#[test]
+fn reject_policy_leaves_full_streams_untouched() {
+    let mut state = limited_state(8, OverflowPolicy::Reject);
+
+    assert!(enqueue_single(&mut state, 1, b"abcde").is_empty());
+    assert!(enqueue_single(&mut state, 1, b"fgh").is_empty());
+    assert_eq!(
+        enqueue_single(&mut state, 1, b"i"),
+        vec![Packet::ServerEnqueueRejected { stream_id: 1 }]
+    );
+
+    assert_eq!(contents(&state, 1), b"abcdefgh");
+    assert_eq!(state.total_bytes(), 8);
+}
+
+#[test]
+fn reject_policy_reports_each_full_stream() {
+    let mut state = limited_state(4, OverflowPolicy::Reject);
+    enqueue_single(&mut state, 1, b"abc");
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![Packet::ClientEnqueueAllAck {
+            enqueue_data: Bytes::from_static(b"xy"),
+        }],
+    )
+    .unwrap();
+
+    assert_eq!(
+        responses,
+        vec![
+            Packet::ServerEnqueueRejected { stream_id: 1 },
+            Packet::ServerEnqueueAllAck { stream_count: 1 },
+        ]
+    );
+    assert_eq!(contents(&state, 1), b"abc");
+    assert_eq!(contents(&state, 2), b"xy");
+    assert_eq!(state.total_bytes(), 5);
+}
+
+#[test]
+fn reject_policy_does_not_advance_sequence() {
+    let mut state = limited_state(2, OverflowPolicy::Reject);
+
+    assert!(state.enqueue_seq(1, 1, b"ab"));
+    assert!(!state.enqueue_seq(1, 2, b"c"));
+    state.fetch_stream_contents(1).unwrap();
+    assert!(state.enqueue_seq(1, 2, b"c"));
+}
+
+#[test]
+fn drop_oldest_policy_keeps_newest_bytes() {
+    let mut state = limited_state(8, OverflowPolicy::DropOldest);
+
+    for chunk in [b"abcd", b"efgh", b"ijkl"] {
+        assert!(enqueue_single(&mut state, 1, chunk).is_empty());
+    }
+
+    assert_eq!(contents(&state, 1), b"efghijkl");
+    assert_eq!(state.total_bytes(), 8);
+}
+
+#[test]
+fn drop_oldest_policy_truncates_oversized_data() {
+    let mut state = limited_state(4, OverflowPolicy::DropOldest);
+    enqueue_single(&mut state, 1, b"ab");
+
+    handle_client_packets(
+        &mut state,
+        vec![Packet::ClientEnqueueMultiple {
+            enqueue_data: Bytes::from_static(b"0123456789"),
+            filter_stream_ids: vec![1, 2],
+        }],
+    )
+    .unwrap();
+
+    assert_eq!(contents(&state, 1), b"6789");
+    assert_eq!(contents(&state, 2), b"6789");
+    assert_eq!(state.total_bytes(), 8);
+}
+
+#[test]
+fn drop_oldest_policy_moves_lagging_cursors_forward() {
+    let mut state = limited_state(4, OverflowPolicy::DropOldest);
+    handle_client_packets(
+        &mut state,
+        vec![
+            Packet::ClientRegisterConsumer {
+                stream_id: 1,
+                consumer_id: 1,
+            },
+            Packet::ClientEnqueueSingle {
+                stream_id: 1,
+                enqueue_data: Bytes::from_static(b"abcdef"),
+            },
+        ],
+    )
+    .unwrap();
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![Packet::ClientFetchFromCursor {
+            stream_id: 1,
+            consumer_id: 1,
+            max_bytes: 10,
+        }],
+    )
+    .unwrap();
+
+    assert_eq!(
+        responses,
+        vec![Packet::ServerStreamContents {
+            buffer_data: Bytes::from_static(b"cdef")
+        }]
+    );
+}
+
+#[test]
+fn unlimited_by_default() {
+    let mut state = ServerState::new();
+    state.create_new_stream(1).unwrap();
+
+    assert!(enqueue_single(&mut state, 1, &vec![0; 1 << 20]).is_empty());
+    assert_eq!(state.total_bytes(), 1 << 20);
+}
@@ -0,0 +1,70 @@
+use fast_stream_db::serialisation::Packet;
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::state::ServerState;
+use fast_stream_db::utils;
+use std::time::Duration;
+
+fn idle_for(state: &mut ServerState, stream_id: u32, seconds: u64) {
+    state.get_stream_mut(stream_id).unwrap().last_activity =
+        utils::get_current_timestamp() - seconds;
+}
+
+#[test]
+fn streams_expire_by_their_own_ttl() {
+    let mut state = ServerState::with_key_expiry(Duration::from_secs(150));
+    handle_client_packets(
+        &mut state,
+        vec![
+            Packet::ClientCreateNewStream { stream_id: 1 },
+            Packet::ClientCreateNewStreamWithTtl {
+                stream_id: 2,
+                ttl_secs: 10,
+            },
+            Packet::ClientCreateNewStreamWithTtl {
+                stream_id: 3,
+                ttl_secs: 3600,
+            },
+            Packet::ClientCreateNewStreamWithTtl {
+                stream_id: 4,
+                ttl_secs: 0,
+            },
+        ],
+    )
+    .unwrap();
+    for stream_id in 1..=4 {
+        idle_for(&mut state, stream_id, 200);
+    }
+
+    assert_eq!(state.prune_expired_streams().unwrap(), 2);
+    assert!(!state.stream_exists(1));
+    assert!(!state.stream_exists(2));
+    assert!(state.stream_exists(3));
+    assert!(state.stream_exists(4));
+}
+
+#[test]
+fn ttl_applies_without_a_global_expiry() {
+    let mut state = ServerState::new();
+    state.create_new_stream(1).unwrap();
+    state.create_new_stream_with_ttl(2, 10).unwrap();
+    idle_for(&mut state, 1, 1000);
+    idle_for(&mut state, 2, 20);
+
+    state.prune_expired_streams().unwrap();
+
+    assert!(state.stream_exists(1));
+    assert!(!state.stream_exists(2));
+}
+
+#[test]
+fn recreating_a_stream_keeps_its_ttl() {
+    let mut state = ServerState::with_key_expiry(Duration::from_secs(150));
+    state.create_new_stream_with_ttl(1, 0).unwrap();
+    assert!(!state.create_new_stream(1).unwrap());
+    assert!(!state.create_new_stream_with_ttl(1, 100).unwrap());
+    idle_for(&mut state, 1, 200);
+
+    state.prune_expired_streams().unwrap();
+
+    assert!(state.stream_exists(1));
+}
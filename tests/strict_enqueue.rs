@@ -0,0 +1,121 @@
+use fast_stream_db::serialisation::{Bytes, Packet};
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::settings::MissingStreamPolicy;
+use fast_stream_db::state::ServerState;
+
+fn state_with_policy(missing_stream_policy: MissingStreamPolicy) -> ServerState {
+    let mut state = ServerState::new();
+    state.set_missing_stream_policy(missing_stream_policy);
+    state.create_new_stream(1).unwrap();
+    state
+}
+
+fn enqueues_to_missing_streams() -> Vec<Packet> {
+    vec![
+        Packet::ClientEnqueueSingle {
+            stream_id: 2,
+            enqueue_data: Bytes::from_static(b"single"),
+        },
+        Packet::ClientEnqueueMultiple {
+            enqueue_data: Bytes::from_static(b"multiple"),
+            filter_stream_ids: vec![1, 3],
+        },
+        Packet::ClientPrependSingle {
+            stream_id: 4,
+            enqueue_data: Bytes::from_static(b"prepend"),
+        },
+    ]
+}
+
+#[test]
+fn ignore_drops_enqueues_silently() {
+    let mut state = state_with_policy(MissingStreamPolicy::Ignore);
+
+    let responses = handle_client_packets(&mut state, enqueues_to_missing_streams()).unwrap();
+
+    assert!(responses.is_empty());
+    assert_eq!(state.stream_count(), 1);
+    assert_eq!(state.get_stream(1).unwrap().buffer, &b"multiple"[..]);
+}
+
+#[test]
+fn error_reports_each_missing_stream() {
+    let mut state = state_with_policy(MissingStreamPolicy::Error);
+
+    let responses = handle_client_packets(&mut state, enqueues_to_missing_streams()).unwrap();
+
+    assert_eq!(
+        responses,
+        vec![
+            Packet::ServerEnqueueError { stream_id: 2 },
+            Packet::ServerEnqueueError { stream_id: 3 },
+            Packet::ServerEnqueueError { stream_id: 4 },
+        ]
+    );
+    assert_eq!(state.stream_count(), 1);
+    assert_eq!(state.get_stream(1).unwrap().buffer, &b"multiple"[..]);
+}
+
+#[test]
+fn create_enqueues_to_new_streams() {
+    let mut state = state_with_policy(MissingStreamPolicy::Create);
+
+    let responses = handle_client_packets(&mut state, enqueues_to_missing_streams()).unwrap();
+
+    assert!(responses.is_empty());
+    assert_eq!(state.stream_count(), 4);
+    assert_eq!(state.get_stream(2).unwrap().buffer, &b"single"[..]);
+    assert_eq!(state.get_stream(3).unwrap().buffer, &b"multiple"[..]);
+    assert_eq!(state.get_stream(4).unwrap().buffer, &b"prepend"[..]);
+    assert_eq!(state.total_bytes(), 6 + 8 + 8 + 7);
+}
+
+#[test]
+fn sequenced_enqueues_follow_the_policy() {
+    let seq_enqueue = || Packet::ClientEnqueueSeq {
+        stream_id: 2,
+        seq: 1,
+        enqueue_data: Bytes::from_static(b"seq"),
+    };
+
+    let mut state = state_with_policy(MissingStreamPolicy::Error);
+    assert_eq!(
+        handle_client_packets(&mut state, vec![seq_enqueue()]).unwrap(),
+        vec![
+            Packet::ServerEnqueueError { stream_id: 2 },
+            Packet::ServerEnqueueSeqResult {
+                stream_id: 2,
+                seq: 1,
+                applied: false,
+            },
+        ]
+    );
+
+    let mut state = state_with_policy(MissingStreamPolicy::Create);
+    assert_eq!(
+        handle_client_packets(&mut state, vec![seq_enqueue()]).unwrap(),
+        vec![Packet::ServerEnqueueSeqResult {
+            stream_id: 2,
+            seq: 1,
+            applied: true,
+        }]
+    );
+    assert_eq!(state.get_stream(2).unwrap().buffer, &b"seq"[..]);
+}
+
+#[test]
+fn broadcasts_ignore_the_policy() {
+    let mut state = state_with_policy(MissingStreamPolicy::Error);
+
+    let responses = handle_client_packets(
+        &mut state,
+        vec![Packet::ClientEnqueueAllExcept {
+            enqueue_data: Bytes::from_static(b"all"),
+            filter_stream_ids: vec![9],
+        }],
+    )
+    .unwrap();
+
+    assert!(responses.is_empty());
+    assert_eq!(state.stream_count(), 1);
+}
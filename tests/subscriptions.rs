@@ -0,0 +1,213 @@
+mod common;
+
+use common::{TestClient, new_state};
+use fast_stream_db::serialisation::{Bytes, ERROR_UNKNOWN_STREAM, Packet};
+use fast_stream_db::state::ServerState;
+use tokio::sync::mpsc;
+
+#[tokio::test]
+async fn enqueued_data_is_pushed_to_subscribers() {
+    let state = new_state();
+    let mut subscriber = TestClient::connect(state.clone());
+    let mut producer = TestClient::connect(state.clone());
+
+    subscriber.create_stream(1).await;
+    subscriber
+        .send(&[Packet::ClientSubscribe { stream_id: 1 }])
+        .await;
+    subscriber.sync().await;
+    producer
+        .send(&[Packet::ClientEnqueueSingle {
+            stream_id: 1,
+            enqueue_data: Bytes::from_static(b"event"),
+        }])
+        .await;
+
+    assert_eq!(
+        subscriber.recv().await,
+        Packet::ServerStreamPush {
+            stream_id: 1,
+            buffer_data: Bytes::from_static(b"event"),
+        }
+    );
+    assert!(state.lock().await.get_stream(1).unwrap().buffer.is_empty());
+    assert_eq!(state.lock().await.total_bytes(), 0);
+}
+
+#[tokio::test]
+async fn buffered_data_is_pushed_on_subscribing() {
+    let mut client = TestClient::connect(new_state());
+
+    client.create_stream(1).await;
+    client
+        .send(&[
+            Packet::ClientEnqueueSingle {
+                stream_id: 1,
+                enqueue_data: Bytes::from_static(b"backlog"),
+            },
+            Packet::ClientSubscribe { stream_id: 1 },
+        ])
+        .await;
+
+    assert_eq!(
+        client.recv().await,
+        Packet::ServerStreamPush {
+            stream_id: 1,
+            buffer_data: Bytes::from_static(b"backlog"),
+        }
+    );
+}
+
+#[tokio::test]
+async fn every_subscriber_gets_a_copy() {
+    let state = new_state();
+    let mut subscribers = [
+        TestClient::connect(state.clone()),
+        TestClient::connect(state.clone()),
+    ];
+    let mut producer = TestClient::connect(state);
+
+    producer.create_stream(1).await;
+    producer.sync().await;
+    for subscriber in &mut subscribers {
+        subscriber
+            .send(&[Packet::ClientSubscribe { stream_id: 1 }])
+            .await;
+        subscriber.sync().await;
+    }
+    producer
+        .send(&[Packet::ClientEnqueueAll {
+            enqueue_data: Bytes::from_static(b"fanout"),
+        }])
+        .await;
+
+    for subscriber in &mut subscribers {
+        assert_eq!(
+            subscriber.recv().await,
+            Packet::ServerStreamPush {
+                stream_id: 1,
+                buffer_data: Bytes::from_static(b"fanout"),
+            }
+        );
+    }
+}
+
+#[tokio::test]
+async fn subscribing_to_a_missing_stream_fails() {
+    let mut client = TestClient::connect(new_state());
+
+    client
+        .send(&[Packet::ClientSubscribe { stream_id: 1 }])
+        .await;
+
+    match client.recv().await {
+        Packet::ServerError { code, .. } => assert_eq!(code, ERROR_UNKNOWN_STREAM),
+        packet => panic!("Expected an error, got {:?}", packet),
+    }
+}
+
+#[tokio::test]
+async fn unsubscribing_stops_pushes() {
+    let state = new_state();
+    let mut client = TestClient::connect(state.clone());
+
+    client.create_stream(1).await;
+    client
+        .send(&[
+            Packet::ClientSubscribe { stream_id: 1 },
+            Packet::ClientUnsubscribe { stream_id: 1 },
+            Packet::ClientEnqueueSingle {
+                stream_id: 1,
+                enqueue_data: Bytes::from_static(b"kept"),
+            },
+        ])
+        .await;
+    client.sync().await;
+
+    assert_eq!(
+        state.lock().await.get_stream(1).unwrap().buffer,
+        &b"kept"[..]
+    );
+}
+
+#[test]
+fn stalled_and_closed_subscribers_are_dropped() {
+    let mut state = ServerState::new();
+    state.create_new_stream(1).unwrap();
+    let (stalled_sender, _stalled_receiver) = mpsc::channel(1);
+    let (closed_sender, closed_receiver) = mpsc::channel(1);
+    state.subscribe(1, stalled_sender).unwrap();
+    state.subscribe(1, closed_sender).unwrap();
+    drop(closed_receiver);
+
+    // The first push fills the stalled subscriber's queue, so it's dropped on the second.
+    state.enqueue_single(1, b"first").unwrap();
+    state.enqueue_single(1, b"second").unwrap();
+
+    assert_eq!(state.get_stream(1).unwrap().buffer, &b"second"[..]);
+    assert_eq!(state.total_bytes(), 6);
+}
+
+#[tokio::test]
+async fn flushing_pushes_data_below_the_push_threshold() {
+    let state = new_state();
+    state.lock().await.set_push_min_bytes(16);
+    let mut subscriber = TestClient::connect(state.clone());
+    let mut producer = TestClient::connect(state.clone());
+
+    subscriber.create_stream(1).await;
+    subscriber
+        .send(&[Packet::ClientSubscribe { stream_id: 1 }])
+        .await;
+    subscriber.sync().await;
+    // Too small to be pushed on its own, so it waits in the stream.
+    producer
+        .send(&[Packet::ClientEnqueueSingle {
+            stream_id: 1,
+            enqueue_data: Bytes::from_static(b"message"),
+        }])
+        .await;
+    producer.sync().await;
+    assert_eq!(
+        state.lock().await.get_stream(1).unwrap().buffer,
+        &b"message"[..]
+    );
+
+    producer
+        .send(&[Packet::ClientFlushStream { stream_id: 1 }])
+        .await;
+
+    assert_eq!(
+        subscriber.recv().await,
+        Packet::ServerStreamPush {
+            stream_id: 1,
+            buffer_data: Bytes::from_static(b"message"),
+        }
+    );
+    assert_eq!(state.lock().await.total_bytes(), 0);
+}
+
+#[test]
+fn enqueues_push_once_the_threshold_is_reached() {
+    let mut state = ServerState::new();
+    state.set_push_min_bytes(8);
+    state.create_new_stream(1).unwrap();
+    let (sender, mut receiver) = mpsc::channel(4);
+    state.subscribe(1, sender).unwrap();
+
+    state.enqueue_single(1, b"1234").unwrap();
+    assert!(receiver.try_recv().is_err());
+    state.enqueue_single(1, b"5678").unwrap();
+
+    assert_eq!(
+        receiver.try_recv().unwrap(),
+        Packet::ServerStreamPush {
+            stream_id: 1,
+            buffer_data: Bytes::from_static(b"12345678"),
+        }
+    );
+    // Flushing an empty stream or one nobody subscribes to sends nothing.
+    assert!(state.flush_stream(1));
+    assert!(receiver.try_recv().is_err());
+    assert!(!state.flush_stream(2));
+}
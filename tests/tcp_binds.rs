@@ -0,0 +1,96 @@
+mod common;
+
+use common::{connect_tcp, free_tcp_port, leak_settings, new_state};
+use fast_stream_db::serialisation::{Bytes, Packet};
+use fast_stream_db::server::run_tcp_server;
+use fast_stream_db::settings::Settings;
+use std::net::SocketAddr;
+
+fn loopback(port: u16) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], port))
+}
+
+#[tokio::test]
+async fn every_bind_shares_the_state() {
+    let (first_port, second_port) = (free_tcp_port(), free_tcp_port());
+    let settings = leak_settings(Settings {
+        tcp_binds: vec![loopback(first_port), loopback(second_port)],
+        ..Settings::default()
+    });
+    tokio::spawn(run_tcp_server(
+        settings,
+        new_state(),
+        std::future::pending(),
+    ));
+
+    let mut first_client = connect_tcp(first_port).await;
+    let mut second_client = connect_tcp(second_port).await;
+    first_client.create_stream(1).await;
+    first_client
+        .send(&[Packet::ClientEnqueueSingle {
+            stream_id: 1,
+            enqueue_data: Bytes::from_static(b"shared"),
+        }])
+        .await;
+    first_client.sync().await;
+
+    second_client
+        .send(&[Packet::ClientRequestStreamContents { stream_id: 1 }])
+        .await;
+    assert_eq!(
+        second_client.recv().await,
+        Packet::ServerStreamContents {
+            buffer_data: Bytes::from_static(b"shared")
+        }
+    );
+}
+
+#[tokio::test]
+async fn strict_bind_failure_names_the_address() {
+    let taken = std::net::TcpListener::bind(loopback(0)).unwrap();
+    let taken_addr = taken.local_addr().unwrap();
+    let settings = leak_settings(Settings {
+        tcp_binds: vec![loopback(free_tcp_port()), taken_addr],
+        ..Settings::default()
+    });
+
+    let error = run_tcp_server(settings, new_state(), std::future::pending())
+        .await
+        .unwrap_err();
+    assert!(
+        format!("{:#}", error).contains(&taken_addr.to_string()),
+        "{:#}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn lenient_bind_failure_keeps_the_other_addresses() {
+    let taken = std::net::TcpListener::bind(loopback(0)).unwrap();
+    let port = free_tcp_port();
+    let settings = leak_settings(Settings {
+        tcp_binds: vec![taken.local_addr().unwrap(), loopback(port)],
+        bind_strict: false,
+        ..Settings::default()
+    });
+    tokio::spawn(run_tcp_server(
+        settings,
+        new_state(),
+        std::future::pending(),
+    ));
+
+    let mut client = connect_tcp(port).await;
+    client.sync().await;
+
+    // With nothing bound there is nothing to serve, strict or not.
+    let settings = leak_settings(Settings {
+        tcp_binds: vec![taken.local_addr().unwrap()],
+        bind_strict: false,
+        ..Settings::default()
+    });
+    assert!(
+        run_tcp_server(settings, new_state(), std::future::pending())
+            .await
+            .is_err()
+    );
+}
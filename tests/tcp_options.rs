@@ -0,0 +1,44 @@
+use fast_stream_db::server::configure_tcp_stream;
+use fast_stream_db::settings::Settings;
+use socket2::SockRef;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+
+/// Accepts a single loopback connection, returning the server's end of it.
+async fn accepted_stream() -> TcpStream {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let _client = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    listener.accept().await.unwrap().0
+}
+
+#[tokio::test]
+async fn nodelay_is_on_and_keepalive_off_by_default() {
+    let stream = accepted_stream().await;
+
+    configure_tcp_stream(&stream, &Settings::default()).unwrap();
+
+    assert!(stream.nodelay().unwrap());
+    assert!(!SockRef::from(&stream).keepalive().unwrap());
+}
+
+#[tokio::test]
+async fn configured_options_are_applied() {
+    let stream = accepted_stream().await;
+    let settings = Settings {
+        tcp_nodelay: false,
+        tcp_keepalive: Duration::from_secs(42),
+        ..Settings::default()
+    };
+
+    configure_tcp_stream(&stream, &settings).unwrap();
+
+    let socket = SockRef::from(&stream);
+    assert!(!stream.nodelay().unwrap());
+    assert!(socket.keepalive().unwrap());
+    assert_eq!(
+        socket.tcp_keepalive_time().unwrap(),
+        Duration::from_secs(42)
+    );
+}
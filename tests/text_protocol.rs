@@ -0,0 +1,164 @@
+mod common;
+
+use common::{TestClient, leak_settings, new_state};
+use fast_stream_db::serialisation::{Bytes, ERROR_NOT_AUTHENTICATED, Packet};
+use fast_stream_db::server::{ConnectionInfo, handle_connection};
+use fast_stream_db::settings::Settings;
+use fast_stream_db::state::ServerState;
+use fast_stream_db::text_protocol::parse_command;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::Mutex;
+
+/// Sends the input over a fresh connection and returns every line the server replies with
+/// before closing it.
+async fn text_session(settings: &'static Settings, input: &str) -> Vec<String> {
+    text_session_with_state(new_state(), settings, input).await
+}
+
+async fn text_session_with_state(
+    state: Arc<Mutex<ServerState>>,
+    settings: &'static Settings,
+    input: &str,
+) -> Vec<String> {
+    let (mut client, server) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move {
+        let _ = handle_connection(server, state, settings, ConnectionInfo::new("test")).await;
+    });
+
+    client.write_all(input.as_bytes()).await.unwrap();
+    let mut output = String::new();
+    client.read_to_string(&mut output).await.unwrap();
+    output.lines().map(str::to_string).collect()
+}
+
+fn text_settings() -> &'static Settings {
+    leak_settings(Settings {
+        text_protocol: true,
+        ..Settings::default()
+    })
+}
+
+#[test]
+fn commands_map_to_packets() {
+    assert_eq!(
+        parse_command("enqueue 7 two words"),
+        Ok(Packet::ClientEnqueueSingleAck {
+            stream_id: 7,
+            enqueue_data: Bytes::from_static(b"two words"),
+        })
+    );
+    assert_eq!(
+        parse_command("GET 7"),
+        Ok(Packet::ClientRequestStreamContents { stream_id: 7 })
+    );
+    assert!(parse_command("GET seven").is_err());
+    assert!(parse_command("FROB 1").is_err());
+}
+
+#[tokio::test]
+async fn text_session_drives_the_state() {
+    let lines = text_session(
+        text_settings(),
+        "TEXT\r\nCREATE 42\nENQUEUE 42 hello world\nLEN 42\n\nLIST\nGET 42\nGET nothing\nQUIT\nPING\n",
+    )
+    .await;
+
+    assert_eq!(
+        lines[1..],
+        [
+            "OK created",
+            "OK length=11",
+            "LEN 11",
+            "STREAMS 1 42",
+            "DATA hello world",
+            "ERR GET takes a stream ID",
+            "BYE",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn binary_clients_are_unaffected() {
+    let mut client = TestClient::connect_with_settings(new_state(), text_settings());
+
+    client.create_stream(1).await;
+    client.sync().await;
+}
+
+#[tokio::test]
+async fn text_protocol_is_off_by_default() {
+    let lines = text_session(leak_settings(Settings::default()), "TEXT\nPING\n").await;
+
+    assert!(
+        lines.iter().all(|line| !line.contains("PONG")),
+        "{:?}",
+        lines
+    );
+}
+
+#[tokio::test]
+async fn text_commands_are_rate_limited() {
+    const RATE: u32 = 20;
+    let settings = leak_settings(Settings {
+        text_protocol: true,
+        rate_limit: RATE,
+        ..Settings::default()
+    });
+
+    // A burst of RATE is allowed straight away, and the rest trickle in at RATE per second.
+    let start = std::time::Instant::now();
+    let lines = text_session(
+        settings,
+        &format!("TEXT\n{}QUIT\n", "PING\n".repeat(2 * RATE as usize)),
+    )
+    .await;
+    let elapsed = start.elapsed();
+
+    assert_eq!(
+        lines.iter().filter(|line| *line == "PONG").count(),
+        2 * RATE as usize
+    );
+    assert!(elapsed.as_millis() >= 900, "Finished in {:?}", elapsed);
+}
+
+#[tokio::test]
+async fn oversized_enqueues_are_rejected() {
+    let settings = leak_settings(Settings {
+        text_protocol: true,
+        max_payload_size: 4,
+        ..Settings::default()
+    });
+
+    let lines = text_session(settings, "TEXT\nCREATE 1\nENQUEUE 1 hello\nLEN 1\nQUIT\n").await;
+
+    assert_eq!(
+        lines[1..],
+        [
+            "OK created",
+            "ERR payload of 5 bytes exceeds the 4 byte limit",
+            "LEN 0",
+            "BYE",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn listing_needs_the_admin_token() {
+    let mut state = ServerState::new();
+    state.set_admin_token(Some(Bytes::from_static(b"secret")));
+
+    let lines = text_session_with_state(
+        Arc::new(Mutex::new(state)),
+        text_settings(),
+        "TEXT\nCREATE 1\nLIST\nAUTH secret\nLIST\nQUIT\n",
+    )
+    .await;
+
+    assert!(
+        lines[2].ends_with(&format!("(code {})", ERROR_NOT_AUTHENTICATED)),
+        "{:?}",
+        lines
+    );
+    assert_eq!(lines[3..], ["OK authenticated", "STREAMS 1 1", "BYE"]);
+}
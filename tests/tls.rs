@@ -0,0 +1,131 @@
+#![cfg(feature = "tls")]
+
+mod common;
+
+use common::{TestClient, connect_tcp, free_tcp_port, leak_settings, new_state};
+use fast_stream_db::serialisation::{Bytes, Packet, serialise_packets};
+use fast_stream_db::server::run_tcp_server;
+use fast_stream_db::settings::{ConnectionMode, Settings};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{Duration, timeout};
+use tokio_rustls::TlsConnector;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName};
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+
+const CERT_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/tls-cert.pem");
+const KEY_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/tls-key.pem");
+
+fn start_tls_server() -> u16 {
+    start_tls_server_with_limit(0)
+}
+
+fn start_tls_server_with_limit(max_connections: usize) -> u16 {
+    let port = free_tcp_port();
+    let settings = leak_settings(Settings {
+        listeners: vec![ConnectionMode::Tcp],
+        tcp_port: port,
+        tls_cert: Some(CERT_PATH.to_string()),
+        tls_key: Some(KEY_PATH.to_string()),
+        max_connections,
+        ..Settings::default()
+    });
+    tokio::spawn(run_tcp_server(
+        settings,
+        new_state(),
+        std::future::pending(),
+    ));
+
+    port
+}
+
+async fn connect_tls(port: u16) -> TestClient<TlsStream<TcpStream>> {
+    let mut roots = RootCertStore::empty();
+    roots
+        .add(CertificateDer::from_pem_file(CERT_PATH).unwrap())
+        .unwrap();
+    let config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    // Waits for the server to start listening.
+    connect_tcp(port).await;
+    let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    let stream = TlsConnector::from(Arc::new(config))
+        .connect(ServerName::try_from("localhost").unwrap(), stream)
+        .await
+        .unwrap();
+
+    TestClient::from_stream(stream)
+}
+
+#[tokio::test]
+async fn tls_clients_are_served() {
+    let port = start_tls_server();
+
+    let mut client = connect_tls(port).await;
+    client.create_stream(1).await;
+    client
+        .send(&[
+            Packet::ClientEnqueueSingle {
+                stream_id: 1,
+                enqueue_data: Bytes::from_static(b"encrypted"),
+            },
+            Packet::ClientRequestStreamContents { stream_id: 1 },
+        ])
+        .await;
+
+    assert_eq!(
+        client.recv().await,
+        Packet::ServerStreamContents {
+            buffer_data: Bytes::from_static(b"encrypted"),
+        }
+    );
+}
+
+#[tokio::test]
+async fn failed_handshake_leaves_the_server_accepting() {
+    let port = start_tls_server();
+
+    // Waits for the server to start listening.
+    connect_tcp(port).await;
+    let mut plaintext = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    plaintext
+        .write_all(&serialise_packets(&[Packet::ClientPing { nonce: None }]).unwrap())
+        .await
+        .unwrap();
+    let mut response = Vec::new();
+    plaintext.read_to_end(&mut response).await.unwrap();
+    assert_ne!(
+        response,
+        serialise_packets(&[Packet::ServerPong { nonce: None }]).unwrap()
+    );
+
+    let mut client = connect_tls(port).await;
+    client.sync().await;
+}
+
+#[tokio::test]
+async fn stalled_handshakes_over_the_limit_are_closed() {
+    let port = start_tls_server_with_limit(1);
+    // Waits for the server to start listening, and holds the only slot while it handshakes.
+    let _admitted = connect_tcp(port).await;
+
+    // None of these handshake, so each busy reply would wait out the handshake timeout. Only a
+    // few are given the chance, and the rest are closed straight away.
+    let mut stalled = Vec::new();
+    for _ in 0..64 {
+        stalled.push(TcpStream::connect(("127.0.0.1", port)).await.unwrap());
+    }
+    let mut byte = [0u8; 1];
+    let closed = timeout(
+        Duration::from_secs(1),
+        stalled.last_mut().unwrap().read(&mut byte),
+    )
+    .await
+    .expect("The connection was kept open");
+    assert!(matches!(closed, Ok(0) | Err(_)));
+}
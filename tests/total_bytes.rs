@@ -0,0 +1,190 @@
+use fast_stream_db::serialisation::Packet;
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::settings::OverflowPolicy;
+use fast_stream_db::state::ServerState;
+use std::time::Duration;
+
+fn total_bytes(state: &mut ServerState) -> (u64, u32) {
+    let responses = handle_client_packets(state, vec![Packet::ClientGetTotalBytes]).unwrap();
+
+    match responses.as_slice() {
+        [
+            Packet::ServerTotalBytes {
+                total_bytes,
+                stream_count,
+            },
+        ] => (*total_bytes, *stream_count),
+        _ => panic!("Unexpected responses: {:?}", responses),
+    }
+}
+
+#[test]
+fn total_tracks_enqueues_and_fetches() {
+    let mut state = ServerState::new();
+    assert_eq!(total_bytes(&mut state), (0, 0));
+
+    for stream_id in 1..=3 {
+        state.create_new_stream(stream_id).unwrap();
+    }
+    assert_eq!(total_bytes(&mut state), (0, 3));
+
+    handle_client_packets(
+        &mut state,
+        vec![
+            Packet::ClientEnqueueSingle {
+                stream_id: 1,
+                enqueue_data: vec![0; 10].into(),
+            },
+            Packet::ClientEnqueueMultiple {
+                enqueue_data: vec![0; 5].into(),
+                filter_stream_ids: vec![1, 2, 99],
+            },
+            Packet::ClientEnqueueAll {
+                enqueue_data: vec![0; 2].into(),
+            },
+            Packet::ClientEnqueueAllExcept {
+                enqueue_data: vec![0; 1].into(),
+                filter_stream_ids: vec![3],
+            },
+            Packet::ClientEnqueueSeq {
+                stream_id: 3,
+                seq: 1,
+                enqueue_data: vec![0; 4].into(),
+            },
+        ],
+    )
+    .unwrap();
+    // Stream 1: 10 + 5 + 2 + 1, stream 2: 5 + 2 + 1, stream 3: 2 + 4.
+    assert_eq!(total_bytes(&mut state), (32, 3));
+
+    state.fetch_stream_no_clear(1).unwrap();
+    assert_eq!(total_bytes(&mut state), (32, 3));
+
+    state.fetch_stream_contents(1).unwrap();
+    assert_eq!(total_bytes(&mut state), (14, 3));
+
+    state.delete_stream(2).unwrap();
+    assert_eq!(total_bytes(&mut state), (6, 2));
+
+    // Re-creating a stream leaves its buffer intact.
+    state.create_new_stream(3).unwrap();
+    assert_eq!(total_bytes(&mut state), (6, 2));
+}
+
+#[test]
+fn pruning_releases_bytes() {
+    let mut state = ServerState::with_key_expiry(Duration::from_secs(60));
+    state.create_new_stream(1).unwrap();
+    state.enqueue_single(1, &[0; 8]).unwrap();
+    state.get_stream_mut(1).unwrap().last_activity = 0;
+
+    state.prune_expired_streams().unwrap();
+
+    assert_eq!(total_bytes(&mut state), (0, 0));
+}
+
+/// A small deterministic generator, so a failing sequence can be replayed.
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self, bound: u32) -> u32 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        ((self.0 >> 33) % u64::from(bound)) as u32
+    }
+}
+
+fn random_packet(rng: &mut Lcg) -> Packet {
+    let stream_id = rng.next(4);
+    let other_id = rng.next(4);
+    let enqueue_data = vec![0; rng.next(40) as usize];
+    let byte_count = rng.next(30);
+    match rng.next(18) {
+        0 => Packet::ClientCreateNewStream { stream_id },
+        1 => Packet::ClientDeleteStream { stream_id },
+        2 => Packet::ClientEnqueueSingle {
+            stream_id,
+            enqueue_data: enqueue_data.into(),
+        },
+        3 => Packet::ClientEnqueueMultiple {
+            enqueue_data: enqueue_data.into(),
+            filter_stream_ids: vec![stream_id, other_id],
+        },
+        4 => Packet::ClientEnqueueAll {
+            enqueue_data: enqueue_data.into(),
+        },
+        5 => Packet::ClientPrependSingle {
+            stream_id,
+            enqueue_data: enqueue_data.into(),
+        },
+        6 => Packet::ClientEnqueueSeq {
+            stream_id,
+            seq: u64::from(rng.next(1000)),
+            enqueue_data: enqueue_data.into(),
+        },
+        7 => Packet::ClientEnqueueBatch {
+            entries: vec![
+                (stream_id, enqueue_data.clone().into()),
+                (other_id, enqueue_data.into()),
+            ],
+        },
+        8 => Packet::ClientRequestStreamContents { stream_id },
+        9 => Packet::ClientClearStream { stream_id },
+        10 => Packet::ClientDrainPrefix {
+            stream_id,
+            byte_count,
+        },
+        11 => Packet::ClientMoveStreamContents {
+            source_id: stream_id,
+            dest_id: other_id,
+        },
+        12 => Packet::ClientRenameStream {
+            old_id: stream_id,
+            new_id: other_id,
+        },
+        13 => Packet::ClientRegisterConsumer {
+            stream_id,
+            consumer_id: 1,
+        },
+        14 => Packet::ClientAdvanceCursor {
+            stream_id,
+            consumer_id: 1,
+            byte_count,
+        },
+        15 => Packet::ClientCreateMultipleStreams {
+            stream_ids: vec![stream_id, other_id],
+        },
+        16 => Packet::ClientDeleteMultipleStreams {
+            stream_ids: vec![stream_id, other_id],
+        },
+        _ => Packet::ClientUnregisterConsumer {
+            stream_id,
+            consumer_id: 1,
+        },
+    }
+}
+
+#[test]
+fn running_total_never_drifts_from_the_buffers() {
+    for overflow_policy in [OverflowPolicy::Reject, OverflowPolicy::DropOldest] {
+        let mut state = ServerState::new();
+        state.set_stream_limit(64, overflow_policy);
+        let mut rng = Lcg(42);
+
+        for step in 0..10_000 {
+            let packet = random_packet(&mut rng);
+            let description = format!("{:?}", packet);
+            handle_client_packets(&mut state, vec![packet]).unwrap();
+
+            assert_eq!(
+                state.total_bytes(),
+                state.scanned_total_bytes(),
+                "Drifted at step {} after {}",
+                step,
+                description
+            );
+        }
+    }
+}
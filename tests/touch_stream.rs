@@ -0,0 +1,71 @@
+use fast_stream_db::serialisation::Packet;
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::state::ServerState;
+use fast_stream_db::utils;
+use std::time::Duration;
+
+#[test]
+fn touching_keeps_a_stream_from_expiring() {
+    let mut state = ServerState::with_key_expiry(Duration::from_secs(60));
+    state.create_new_stream(1).unwrap();
+    state.enqueue_single(1, b"kept").unwrap();
+    state.get_stream_mut(1).unwrap().last_activity = utils::get_current_timestamp() - 120;
+
+    let responses =
+        handle_client_packets(&mut state, vec![Packet::ClientTouchStream { stream_id: 1 }])
+            .unwrap();
+    state.prune_expired_streams().unwrap();
+
+    assert_eq!(
+        responses,
+        vec![Packet::ServerStreamState {
+            stream_id: 1,
+            is_valid: true,
+        }]
+    );
+    assert_eq!(state.get_stream(1).unwrap().buffer, &b"kept"[..]);
+}
+
+#[test]
+fn read_only_queries_dont_count_as_activity() {
+    let queries = [
+        Packet::ClientCheckStreamState { stream_id: 1 },
+        Packet::ClientCheckStreamStateMultiple {
+            stream_ids: vec![1],
+        },
+        Packet::ClientStreamLength { stream_id: 1 },
+        Packet::ClientStreamInfo { stream_id: 1 },
+        Packet::ClientGetStreamMetadata { stream_id: 1 },
+    ];
+
+    for query in queries {
+        assert!(query.is_read_only_query(), "{:?}", query);
+        let mut state = ServerState::with_key_expiry(Duration::from_secs(60));
+        state.create_new_stream(1).unwrap();
+        state.get_stream_mut(1).unwrap().last_activity = utils::get_current_timestamp() - 120;
+
+        handle_client_packets(&mut state, vec![query.clone()]).unwrap();
+        state.prune_expired_streams().unwrap();
+
+        assert!(!state.stream_exists(1), "{:?}", query);
+    }
+    assert!(!Packet::ClientTouchStream { stream_id: 1 }.is_read_only_query());
+}
+
+#[test]
+fn touching_a_missing_stream_reports_it() {
+    let mut state = ServerState::new();
+
+    let responses =
+        handle_client_packets(&mut state, vec![Packet::ClientTouchStream { stream_id: 1 }])
+            .unwrap();
+
+    assert_eq!(
+        responses,
+        vec![Packet::ServerStreamState {
+            stream_id: 1,
+            is_valid: false,
+        }]
+    );
+    assert!(!state.stream_exists(1));
+}
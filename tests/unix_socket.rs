@@ -0,0 +1,91 @@
+mod common;
+
+use common::{connect_unix, leak_settings, new_state, temp_socket_path};
+use fast_stream_db::server::run_unix_server;
+use fast_stream_db::settings::Settings;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, timeout};
+
+fn unix_settings(path: &str, unix_sock_mode: Option<u32>) -> &'static Settings {
+    leak_settings(Settings {
+        unix_sock_path: path.to_string(),
+        unix_sock_mode,
+        ..Settings::default()
+    })
+}
+
+fn spawn_server(
+    settings: &'static Settings,
+) -> (oneshot::Sender<()>, JoinHandle<anyhow::Result<()>>) {
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let server = tokio::spawn(run_unix_server(settings, new_state(), async {
+        let _ = shutdown_rx.await;
+    }));
+    (shutdown_tx, server)
+}
+
+async fn stop_server(shutdown_tx: oneshot::Sender<()>, server: JoinHandle<anyhow::Result<()>>) {
+    shutdown_tx.send(()).unwrap();
+    timeout(Duration::from_secs(5), server)
+        .await
+        .expect("Server did not shut down")
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn missing_socket_directory_is_created() {
+    let directory = temp_socket_path("missing-directory");
+    let path = format!("{}/nested/fsdb.sock", directory);
+    let (shutdown_tx, server) = spawn_server(unix_settings(&path, Some(0o660)));
+
+    connect_unix(&path).await.sync().await;
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+
+    stop_server(shutdown_tx, server).await;
+    std::fs::remove_dir_all(&directory).unwrap();
+    assert_eq!(mode & 0o777, 0o660);
+}
+
+#[tokio::test]
+async fn socket_held_by_a_running_server_is_not_replaced() {
+    let path = temp_socket_path("in-use");
+    let (shutdown_tx, server) = spawn_server(unix_settings(&path, None));
+    connect_unix(&path).await.sync().await;
+
+    let (_second_tx, second) = spawn_server(unix_settings(&path, None));
+    let error = second.await.unwrap().unwrap_err().to_string();
+    assert!(error.contains("already in use"), "{}", error);
+
+    // The first server is unaffected.
+    connect_unix(&path).await.sync().await;
+    stop_server(shutdown_tx, server).await;
+}
+
+#[tokio::test]
+async fn stale_socket_is_replaced() {
+    let path = temp_socket_path("stale");
+    drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+    assert!(Path::new(&path).exists());
+
+    let (shutdown_tx, server) = spawn_server(unix_settings(&path, None));
+
+    connect_unix(&path).await.sync().await;
+    stop_server(shutdown_tx, server).await;
+}
+
+#[tokio::test]
+async fn regular_file_at_the_socket_path_is_left_alone() {
+    let path = temp_socket_path("regular-file");
+    std::fs::write(&path, b"not a socket").unwrap();
+
+    let (_shutdown_tx, server) = spawn_server(unix_settings(&path, None));
+    let error = server.await.unwrap().unwrap_err().to_string();
+
+    assert_eq!(std::fs::read(&path).unwrap(), b"not a socket");
+    std::fs::remove_file(&path).unwrap();
+    assert!(error.contains("not a socket"), "{}", error);
+}
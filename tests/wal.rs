@@ -0,0 +1,281 @@
+mod common;
+
+use common::enqueue;
+use fast_stream_db::serialisation::{Bytes, Packet};
+use fast_stream_db::server::handle_client_packets;
+use fast_stream_db::settings::OverflowPolicy;
+use fast_stream_db::snapshot::{decode_snapshot, encode_snapshot};
+use fast_stream_db::state::ServerState;
+use fast_stream_db::wal::{WalWriter, remove_wal_files_before, replay_wal, sync_wal};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+fn temp_wal_path(name: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("fsdb-wal-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&directory);
+    std::fs::create_dir_all(&directory).unwrap();
+    directory.join("fsdb.wal")
+}
+
+fn generation_path(path: &std::path::Path, generation: u64) -> PathBuf {
+    PathBuf::from(format!("{}.{}", path.display(), generation))
+}
+
+fn logged_state(path: &std::path::Path) -> (Arc<Mutex<ServerState>>, Arc<Mutex<WalWriter>>) {
+    let mut state = ServerState::new();
+    state.set_stream_limit(16, OverflowPolicy::DropOldest);
+    state.wal_mut().enable();
+    let writer = WalWriter::open(path, state.wal().generation()).unwrap();
+    (Arc::new(Mutex::new(state)), Arc::new(Mutex::new(writer)))
+}
+
+/// Every stream as (ID, buffer, base offset), sorted by ID.
+fn streams(state: &ServerState) -> Vec<(u32, Vec<u8>, u64)> {
+    let mut streams = state
+        .streams()
+        .map(|(stream_id, stream)| (stream_id, stream.buffer.to_vec(), stream.base_offset))
+        .collect::<Vec<_>>();
+    streams.sort_unstable();
+    streams
+}
+
+fn mutate(state: &mut ServerState) {
+    handle_client_packets(
+        state,
+        vec![
+            Packet::ClientCreateNewStream { stream_id: 1 },
+            Packet::ClientCreateNewStream { stream_id: 2 },
+            Packet::ClientCreateNewStream { stream_id: 3 },
+            enqueue(1, b"first "),
+            enqueue(1, b"second"),
+            Packet::ClientEnqueueAll {
+                enqueue_data: Bytes::from_static(b"!"),
+            },
+            Packet::ClientDrainPrefix {
+                stream_id: 1,
+                byte_count: 6,
+            },
+            Packet::ClientPrependSingle {
+                stream_id: 2,
+                enqueue_data: Bytes::from_static(b"front"),
+            },
+            // Past the 16 byte limit, so the oldest bytes are dropped.
+            enqueue(3, b"0123456789abcdefXYZ"),
+            Packet::ClientRequestStreamContents { stream_id: 2 },
+            enqueue(2, b"after fetch"),
+            Packet::ClientCreateNewStream { stream_id: 4 },
+            Packet::ClientDeleteStream { stream_id: 4 },
+            Packet::ClientRenameStream {
+                old_id: 3,
+                new_id: 5,
+            },
+        ],
+    )
+    .unwrap();
+}
+
+#[tokio::test]
+async fn committed_changes_survive_a_crash() {
+    let path = temp_wal_path("crash");
+    let (state, writer) = logged_state(&path);
+
+    mutate(&mut *state.lock().await);
+    sync_wal(&state, &writer).await.unwrap();
+    let committed = streams(&*state.lock().await);
+    // Never synced, so lost in the crash.
+    state.lock().await.enqueue_single(1, b"unsynced").unwrap();
+
+    let mut recovered = ServerState::new();
+    let replayed = replay_wal(&path, &mut recovered).unwrap();
+
+    assert!(replayed > 0);
+    assert_eq!(streams(&recovered), committed);
+    assert_eq!(recovered.total_bytes(), recovered.scanned_total_bytes());
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn replay_starts_from_the_snapshot_generation() {
+    let path = temp_wal_path("snapshot");
+    let (state, writer) = logged_state(&path);
+
+    mutate(&mut *state.lock().await);
+    sync_wal(&state, &writer).await.unwrap();
+    let snapshot = {
+        let mut state = state.lock().await;
+        state.wal_mut().rotate();
+        encode_snapshot(&state)
+    };
+    remove_wal_files_before(&path, 1).unwrap();
+    handle_client_packets(
+        &mut *state.lock().await,
+        vec![
+            enqueue(1, b" more"),
+            Packet::ClientRequestStreamContents { stream_id: 2 },
+            Packet::ClientCreateNewStream { stream_id: 6 },
+        ],
+    )
+    .unwrap();
+    sync_wal(&state, &writer).await.unwrap();
+
+    let mut recovered = ServerState::new();
+    decode_snapshot(&snapshot, &mut recovered).unwrap();
+    replay_wal(&path, &mut recovered).unwrap();
+
+    assert!(!generation_path(&path, 0).exists());
+    assert_eq!(streams(&recovered), streams(&*state.lock().await));
+    assert_eq!(recovered.wal().generation(), 1);
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn torn_record_is_truncated_before_appending() {
+    let path = temp_wal_path("torn");
+    let (state, writer) = logged_state(&path);
+    mutate(&mut *state.lock().await);
+    sync_wal(&state, &writer).await.unwrap();
+    let committed = streams(&*state.lock().await);
+    let committed_len = std::fs::metadata(generation_path(&path, 0)).unwrap().len();
+    // A record cut off partway through its payload.
+    std::fs::OpenOptions::new()
+        .append(true)
+        .open(generation_path(&path, 0))
+        .unwrap()
+        .write_all(&[100, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3, 4, 3])
+        .unwrap();
+
+    let mut recovered = ServerState::new();
+    replay_wal(&path, &mut recovered).unwrap();
+    assert_eq!(streams(&recovered), committed);
+    assert_eq!(
+        std::fs::metadata(generation_path(&path, 0)).unwrap().len(),
+        committed_len
+    );
+
+    // Records written after recovering follow straight on from the last intact one.
+    recovered.wal_mut().enable();
+    let writer = Arc::new(Mutex::new(WalWriter::open(&path, 0).unwrap()));
+    let recovered = Arc::new(Mutex::new(recovered));
+    recovered.lock().await.enqueue_single(5, b"new").unwrap();
+    sync_wal(&recovered, &writer).await.unwrap();
+
+    let mut restarted = ServerState::new();
+    replay_wal(&path, &mut restarted).unwrap();
+    assert_eq!(streams(&restarted), streams(&*recovered.lock().await));
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn stream_settings_and_cursors_survive_a_crash() {
+    let path = temp_wal_path("settings");
+    let (state, writer) = logged_state(&path);
+
+    let named_stream_id = {
+        let mut state = state.lock().await;
+        handle_client_packets(
+            &mut state,
+            vec![
+                Packet::ClientCreateNewStreamWithTtl {
+                    stream_id: 1,
+                    ttl_secs: 0,
+                },
+                Packet::ClientEnqueueSeq {
+                    stream_id: 1,
+                    seq: 7,
+                    enqueue_data: Bytes::from_static(b"0123456789"),
+                },
+                Packet::ClientSetStreamMetadata {
+                    stream_id: 1,
+                    metadata: Bytes::from_static(b"meta"),
+                },
+                Packet::ClientRegisterConsumer {
+                    stream_id: 1,
+                    consumer_id: 1,
+                },
+                Packet::ClientRegisterConsumer {
+                    stream_id: 1,
+                    consumer_id: 2,
+                },
+                Packet::ClientAdvanceCursor {
+                    stream_id: 1,
+                    consumer_id: 1,
+                    byte_count: 4,
+                },
+                Packet::ClientAdvanceCursor {
+                    stream_id: 1,
+                    consumer_id: 2,
+                    byte_count: 6,
+                },
+                Packet::ClientRegisterConsumer {
+                    stream_id: 1,
+                    consumer_id: 3,
+                },
+                Packet::ClientUnregisterConsumer {
+                    stream_id: 1,
+                    consumer_id: 3,
+                },
+            ],
+        )
+        .unwrap();
+        state.create_named_stream(b"session").unwrap()
+    };
+    sync_wal(&state, &writer).await.unwrap();
+
+    let mut recovered = ServerState::new();
+    replay_wal(&path, &mut recovered).unwrap();
+
+    let stream = recovered.get_stream(1).unwrap();
+    assert_eq!(stream.ttl, Some(0));
+    assert_eq!(stream.last_seq, Some(7));
+    assert_eq!(stream.metadata, &b"meta"[..]);
+    assert_eq!(stream.buffer, &b"456789"[..]);
+    assert_eq!(
+        stream.consumer_cursors,
+        state.lock().await.get_stream(1).unwrap().consumer_cursors
+    );
+    assert_eq!(stream.consumer_cursors.len(), 2);
+    assert_eq!(recovered.named_stream_id(b"session"), Some(named_stream_id));
+    assert_eq!(
+        recovered.create_named_stream(b"session").unwrap(),
+        named_stream_id
+    );
+    // A retried sequenced enqueue is still recognised as a duplicate.
+    assert!(!recovered.enqueue_seq(1, 7, b"again"));
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test]
+async fn groups_survive_a_crash() {
+    let path = temp_wal_path("groups");
+    let (state, writer) = logged_state(&path);
+
+    {
+        let mut state = state.lock().await;
+        for stream_id in 1..=4 {
+            state.create_new_stream(stream_id).unwrap();
+        }
+        state.create_group(10);
+        for stream_id in 1..=3 {
+            state.add_to_group(10, stream_id).unwrap();
+        }
+        state.remove_from_group(10, 1).unwrap();
+        state.delete_stream(2).unwrap();
+        state.rename_stream(3, 30).unwrap();
+        state.add_to_group(10, 4).unwrap();
+        state.create_group(11);
+        state.delete_group(11);
+    }
+    sync_wal(&state, &writer).await.unwrap();
+
+    let mut recovered = ServerState::new();
+    replay_wal(&path, &mut recovered).unwrap();
+
+    assert_eq!(recovered.groups(), state.lock().await.groups());
+    assert_eq!(
+        recovered.group_members(10),
+        Some(&[4, 30].into_iter().collect())
+    );
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
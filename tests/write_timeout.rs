@@ -0,0 +1,28 @@
+mod common;
+
+use common::{MockStream, leak_settings, new_state};
+use fast_stream_db::serialisation::{Packet, serialise_packets};
+use fast_stream_db::server::{ConnectionInfo, handle_connection};
+use fast_stream_db::settings::Settings;
+use tokio::time::{Duration, timeout};
+
+#[tokio::test]
+async fn stalled_write_closes_the_connection() {
+    let settings = leak_settings(Settings {
+        write_timeout: Duration::from_secs(1),
+        ..Settings::default()
+    });
+    let stream = MockStream::with_stalled_writes(
+        serialise_packets(&[Packet::ClientPing { nonce: None }]).unwrap(),
+    );
+
+    let result = timeout(
+        Duration::from_secs(5),
+        handle_connection(stream, new_state(), settings, ConnectionInfo::new("test")),
+    )
+    .await
+    .expect("The connection should give up on the write");
+
+    let error = result.unwrap_err();
+    assert!(error.to_string().contains("timed out"), "{}", error);
+}